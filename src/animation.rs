@@ -0,0 +1,347 @@
+use std::{collections::HashMap, time::Duration};
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+	enemy::{BossBody, Minion},
+	game::{DeathEvent, GameState},
+	player::Player,
+	shooting::ShootEvent,
+};
+
+/// Draws the player, the boss' body and minions from sprite sheets instead of their plain sprites, once
+/// a sheet is set in `assets/config/animation.config.ron`. The animation is a child in place of the
+/// hidden sprite, so everything else keeps working on the sprite. It switches between idle, walk and
+/// shoot by the velocity and shots of its entity, and plays death where the entity died
+pub struct AnimationPlugin;
+
+impl Plugin for AnimationPlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(AnimationParams::default())
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(attach_animations)
+					.with_system(switch_animation_states.label("switch_animation_states"))
+					.with_system(spawn_death_animations.after("detect_deaths"))
+					.with_system(animate_sprites.after("switch_animation_states")),
+			);
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AnimationState {
+	Idle,
+	Walk,
+	Shoot,
+	Death,
+}
+
+/// Frames following each other in the sheet, counted row by row
+#[derive(Deserialize, Clone, Debug)]
+pub struct AnimationClip {
+	pub first: usize,
+	pub frames: usize,
+	/// Frames per second
+	pub fps: f32,
+	/// Clips that don't loop stay on their last frame, death despawns instead
+	pub looping: bool,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct AnimationSheet {
+	pub texture: String,
+	/// Pixels of a single frame in the texture
+	pub tile_size: Vec2,
+	pub columns: usize,
+	pub rows: usize,
+	/// Pixels the frames are drawn with in the game
+	pub size: Vec2,
+	pub idle: AnimationClip,
+	pub walk: AnimationClip,
+	pub shoot: AnimationClip,
+	pub death: AnimationClip,
+}
+
+impl AnimationSheet {
+	pub fn clip(&self, state: AnimationState) -> &AnimationClip {
+		match state {
+			AnimationState::Idle => &self.idle,
+			AnimationState::Walk => &self.walk,
+			AnimationState::Shoot => &self.shoot,
+			AnimationState::Death => &self.death,
+		}
+	}
+}
+
+/// Sheets are None while there is no art for them, the plain sprites are drawn then
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct AnimationParams {
+	pub player: Option<AnimationSheet>,
+	pub boss: Option<AnimationSheet>,
+	pub minion: Option<AnimationSheet>,
+	/// Pixels / sec from which on an entity walks instead of standing idle
+	pub walk_speed: f32,
+	/// Seconds the shoot animation plays after every shot
+	pub shoot_secs: f32,
+}
+
+impl Default for AnimationParams {
+	fn default() -> Self {
+		Self {
+			player: None,
+			boss: None,
+			minion: None,
+			walk_speed: 20.0,
+			shoot_secs: 0.25,
+		}
+	}
+}
+
+impl AnimationParams {
+	pub fn get(&self, kind: AnimatedKind) -> Option<&AnimationSheet> {
+		match kind {
+			AnimatedKind::Player => self.player.as_ref(),
+			AnimatedKind::Boss => self.boss.as_ref(),
+			AnimatedKind::Minion => self.minion.as_ref(),
+		}
+	}
+}
+
+/// Which sheet of `AnimationParams` is used
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AnimatedKind {
+	Player,
+	Boss,
+	Minion,
+}
+
+#[derive(Component)]
+pub struct SpriteAnimation {
+	kind: AnimatedKind,
+	/// Entity whose velocity and shots decide the state, the boss for its body
+	host: Entity,
+	pub state: AnimationState,
+	/// Frame within the current clip
+	frame: usize,
+	timer: Timer,
+	/// Seconds the shoot animation keeps playing
+	shooting: f32,
+}
+
+impl SpriteAnimation {
+	fn new(kind: AnimatedKind, host: Entity, state: AnimationState, clip: &AnimationClip) -> Self {
+		Self {
+			kind,
+			host,
+			state,
+			frame: 0,
+			timer: Timer::from_seconds(1.0 / clip.fps.max(0.01), true),
+			shooting: 0.0,
+		}
+	}
+}
+
+/// Hides the sprites of new animated entities and adds the animation as their child. Atlases are kept
+/// until the params change
+fn attach_animations(
+	mut commands: Commands,
+	params: Res<AnimationParams>,
+	asset_server: Res<AssetServer>,
+	mut atlases: ResMut<Assets<TextureAtlas>>,
+	mut atlas_handles: Local<HashMap<AnimatedKind, Handle<TextureAtlas>>>,
+	mut q_new: Query<
+		(
+			Entity,
+			&mut Visibility,
+			Option<&Player>,
+			Option<&Minion>,
+			Option<&Parent>,
+		),
+		Or<(Added<Player>, Added<Minion>, Added<BossBody>)>,
+	>,
+) {
+	if params.is_changed() {
+		atlas_handles.clear();
+	}
+	for (entity, mut visibility, player, minion, parent) in q_new.iter_mut() {
+		let (kind, host) = match (player, minion, parent) {
+			(Some(_), _, _) => (AnimatedKind::Player, entity),
+			(_, Some(_), _) => (AnimatedKind::Minion, entity),
+			(_, _, Some(Parent(boss))) => (AnimatedKind::Boss, *boss),
+			_ => continue,
+		};
+		let sheet = match params.get(kind) {
+			Some(sheet) => sheet,
+			None => continue,
+		};
+		let atlas = atlas_handles
+			.entry(kind)
+			.or_insert_with(|| {
+				atlases.add(TextureAtlas::from_grid(
+					asset_server.load(sheet.texture.as_str()),
+					sheet.tile_size,
+					sheet.columns.max(1),
+					sheet.rows.max(1),
+				))
+			})
+			.clone();
+
+		visibility.is_visible = false;
+		let animation = commands
+			.spawn_bundle(SpriteSheetBundle {
+				texture_atlas: atlas,
+				sprite: TextureAtlasSprite {
+					index: sheet.idle.first,
+					custom_size: Some(sheet.size),
+					..Default::default()
+				},
+				transform: Transform::from_xyz(0.0, 0.0, 0.1),
+				..Default::default()
+			})
+			.insert(SpriteAnimation::new(
+				kind,
+				host,
+				AnimationState::Idle,
+				&sheet.idle,
+			))
+			.id();
+		commands.entity(entity).push_children(&[animation]);
+	}
+}
+
+/// Shooting wins over walking, walking over standing idle
+fn switch_animation_states(
+	mut q_animations: Query<&mut SpriteAnimation>,
+	q_velocity: Query<&RigidBodyVelocityComponent>,
+	mut shoot_er: EventReader<ShootEvent>,
+	params: Res<AnimationParams>,
+	rapier_config: Res<RapierConfiguration>,
+	time: Res<Time>,
+) {
+	let shooters: Vec<Entity> = shoot_er
+		.iter()
+		.filter_map(|ShootEvent(_, _, _, shooter, _, _)| *shooter)
+		.collect();
+	for mut animation in q_animations.iter_mut() {
+		if animation.state == AnimationState::Death {
+			continue;
+		}
+		animation.shooting = if shooters.contains(&animation.host) {
+			params.shoot_secs
+		} else {
+			(animation.shooting - time.delta_seconds()).max(0.0)
+		};
+		let speed = q_velocity.get(animation.host).map_or(0.0, |rb_vel| {
+			Vec2::from(rb_vel.linvel).length() * rapier_config.scale
+		});
+
+		let state = if animation.shooting > 0.0 {
+			AnimationState::Shoot
+		} else if speed >= params.walk_speed {
+			AnimationState::Walk
+		} else {
+			AnimationState::Idle
+		};
+		if animation.state != state {
+			animation.state = state;
+			animation.frame = 0;
+			animation.timer.reset();
+		}
+	}
+}
+
+/// Leaves the death animation behind where an animated entity died, on its own since the entity is
+/// about to be despawned
+fn spawn_death_animations(
+	mut commands: Commands,
+	mut death_er: EventReader<DeathEvent>,
+	params: Res<AnimationParams>,
+	q_animations: Query<(
+		&SpriteAnimation,
+		&Handle<TextureAtlas>,
+		&TextureAtlasSprite,
+		&GlobalTransform,
+	)>,
+) {
+	for DeathEvent(dead) in death_er.iter() {
+		for (animation, atlas, sprite, transform) in q_animations.iter() {
+			if animation.host != *dead || animation.state == AnimationState::Death {
+				continue;
+			}
+			let sheet = match params.get(animation.kind) {
+				Some(sheet) => sheet,
+				None => continue,
+			};
+			commands
+				.spawn_bundle(SpriteSheetBundle {
+					texture_atlas: atlas.clone(),
+					sprite: TextureAtlasSprite {
+						index: sheet.death.first,
+						flip_x: sprite.flip_x,
+						custom_size: sprite.custom_size,
+						..Default::default()
+					},
+					transform: Transform {
+						translation: transform.translation,
+						rotation: transform.rotation,
+						..Default::default()
+					},
+					..Default::default()
+				})
+				.insert(SpriteAnimation::new(
+					animation.kind,
+					*dead,
+					AnimationState::Death,
+					&sheet.death,
+				));
+		}
+	}
+}
+
+/// Steps through the frames of the current clip. The hidden sprite still knows which way its entity
+/// faces, so its flip is copied over
+fn animate_sprites(
+	mut commands: Commands,
+	params: Res<AnimationParams>,
+	mut q_animations: Query<(
+		Entity,
+		&mut SpriteAnimation,
+		&mut TextureAtlasSprite,
+		Option<&Parent>,
+	)>,
+	q_sprites: Query<&Sprite>,
+	time: Res<Time>,
+) {
+	for (entity, mut animation, mut sprite, parent) in q_animations.iter_mut() {
+		let clip = match params.get(animation.kind) {
+			Some(sheet) => sheet.clip(animation.state),
+			None => continue,
+		};
+		let frame_time = Duration::from_secs_f32(1.0 / clip.fps.max(0.01));
+		if animation.timer.duration() != frame_time {
+			animation.timer.set_duration(frame_time);
+		}
+		animation.timer.tick(time.delta());
+		animation.frame += animation.timer.times_finished() as usize;
+
+		let frames = clip.frames.max(1);
+		if animation.frame >= frames {
+			if animation.state == AnimationState::Death {
+				commands.entity(entity).despawn_recursive();
+				continue;
+			}
+			animation.frame = if clip.looping {
+				animation.frame % frames
+			} else {
+				frames - 1
+			};
+		}
+		sprite.index = clip.first + animation.frame;
+		if let Some(Ok(host_sprite)) = parent.map(|Parent(host)| q_sprites.get(*host)) {
+			sprite.flip_x = host_sprite.flip_x;
+		}
+	}
+}
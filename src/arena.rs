@@ -0,0 +1,189 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+	enemy::EnemyParams,
+	game::{GameGlobals, GameState},
+	map::{ArenaMap, MapLayout},
+	physics::PhysicsMaterialParams,
+	player::PlayerParams,
+	scene::{spawn_static_collider, Wall, ARENA_HALF_EXTENTS},
+};
+
+/// Fills the arena with randomly placed obstacles every time a level starts. The layout is seeded
+/// from the level, so the same level always looks the same. The waypoint grid is spawned after the
+/// obstacles, so its edges are built around them
+pub struct ArenaPlugin;
+
+impl Plugin for ArenaPlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(ArenaParams::default()).add_system_set(
+			SystemSet::on_enter(GameState::Playing).with_system(
+				generate_arena
+					.label("arena")
+					.after("map")
+					.after("reset_game_globals"),
+			),
+		);
+	}
+}
+
+pub struct ArenaParams {
+	pub enabled: bool,
+	/// Mixed into the level to pick the layout, change it to get a different set of layouts
+	pub seed: u64,
+	pub base_obstacles: u32,
+	pub obstacles_per_level: u32,
+	pub max_obstacles: u32,
+	/// Pixels obstacles keep from the walls, so minions spawning at the edges don't end up inside them
+	pub wall_margin: f32,
+	/// Pixels obstacles keep from the player and boss spawns
+	pub spawn_clearance: f32,
+	/// Pixels between the bounding circles of two obstacles
+	pub min_gap: f32,
+	pub pillar_size: f32,
+	/// Length and thickness of cover blocks
+	pub cover_size: Vec2,
+	/// Length of each leg and thickness of L-walls
+	pub l_wall_size: Vec2,
+}
+
+impl Default for ArenaParams {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			seed: 0,
+			base_obstacles: 3,
+			obstacles_per_level: 1,
+			max_obstacles: 8,
+			wall_margin: 80.0,
+			spawn_clearance: 80.0,
+			min_gap: 40.0,
+			pillar_size: 30.0,
+			cover_size: Vec2::new(60.0, 15.0),
+			l_wall_size: Vec2::new(70.0, 10.0),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ObstacleKind {
+	Pillar,
+	/// Freestanding block to hide behind, either horizontal or vertical
+	Cover,
+	/// Two legs meeting in a corner, opening in any of the four diagonal directions
+	LWall,
+}
+
+/// Marks the boxes spawned by the generator
+#[derive(Component)]
+pub struct Obstacle;
+
+/// Boxes an obstacle is made of as (center, size), relative to the obstacle's position
+fn obstacle_boxes(kind: ObstacleKind, params: &ArenaParams, rng: &mut StdRng) -> Vec<(Vec2, Vec2)> {
+	match kind {
+		ObstacleKind::Pillar => vec![(Vec2::ZERO, Vec2::splat(params.pillar_size))],
+		ObstacleKind::Cover => {
+			let size = if rng.gen_bool(0.5) {
+				params.cover_size
+			} else {
+				Vec2::new(params.cover_size.y, params.cover_size.x)
+			};
+			vec![(Vec2::ZERO, size)]
+		}
+		ObstacleKind::LWall => {
+			let (length, thickness) = (params.l_wall_size.x, params.l_wall_size.y);
+			let flip = Vec2::new(
+				if rng.gen_bool(0.5) { 1.0 } else { -1.0 },
+				if rng.gen_bool(0.5) { 1.0 } else { -1.0 },
+			);
+			// both legs start at the corner in the obstacle's center
+			let half = (length - thickness) * 0.5;
+			vec![
+				(Vec2::new(half, 0.0) * flip, Vec2::new(length, thickness)),
+				(Vec2::new(0.0, half) * flip, Vec2::new(thickness, length)),
+			]
+		}
+	}
+}
+
+fn generate_arena(
+	mut commands: Commands,
+	params: Res<ArenaParams>,
+	globals: Res<GameGlobals>,
+	player_params: Res<PlayerParams>,
+	enemy_params: Res<EnemyParams>,
+	(map, layouts): (Res<ArenaMap>, Res<Assets<MapLayout>>),
+	rapier_config: Res<RapierConfiguration>,
+	materials: Res<PhysicsMaterialParams>,
+) {
+	if !params.enabled {
+		return;
+	}
+
+	let mut rng = StdRng::seed_from_u64(params.seed ^ globals.level as u64);
+	let count = (params.base_obstacles
+		+ params.obstacles_per_level * globals.level.saturating_sub(1))
+	.min(params.max_obstacles);
+	let area = (ARENA_HALF_EXTENTS - Vec2::splat(params.wall_margin)).max(Vec2::ZERO);
+	let spawns = [player_params.spawn_pos, enemy_params.spawn_pos];
+	debug!(
+		"GENERATE_ARENA level {} with {} obstacles",
+		globals.level, count
+	);
+
+	// bounding circles of the obstacles placed so far, starting with the solids of the map
+	let mut placed: Vec<(Vec2, f32)> = map
+		.layout(&layouts)
+		.map(|layout| {
+			layout
+				.solids
+				.iter()
+				.map(|(center, size)| (*center, size.length() * 0.5))
+				.collect()
+		})
+		.unwrap_or_default();
+	let map_solids = placed.len();
+	let mut attempts = 0;
+	while placed.len() - map_solids < count as usize && attempts < count * 20 {
+		attempts += 1;
+		let kind = match rng.gen_range(0..3) {
+			0 => ObstacleKind::Pillar,
+			1 => ObstacleKind::Cover,
+			_ => ObstacleKind::LWall,
+		};
+		let boxes = obstacle_boxes(kind, &params, &mut rng);
+		let pos = Vec2::new(
+			rng.gen_range(-area.x..=area.x),
+			rng.gen_range(-area.y..=area.y),
+		);
+		let radius = boxes
+			.iter()
+			.map(|(center, size)| center.length() + size.length() * 0.5)
+			.fold(0.0, f32::max);
+
+		let blocks_spawn = spawns
+			.iter()
+			.any(|spawn| spawn.distance(pos) < radius + params.spawn_clearance);
+		let overlaps = placed.iter().any(|(other_pos, other_radius)| {
+			other_pos.distance(pos) < radius + other_radius + params.min_gap
+		});
+		if blocks_spawn || overlaps {
+			continue;
+		}
+
+		placed.push((pos, radius));
+		for (center, size) in boxes {
+			spawn_static_collider(
+				&mut commands,
+				&rapier_config,
+				&materials,
+				pos + center,
+				size,
+			)
+			.insert(Wall)
+			.insert(Obstacle);
+		}
+	}
+}
@@ -0,0 +1,1096 @@
+use std::f32::consts::PI;
+
+use bevy::{math::Vec3Swizzles, prelude::*};
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+	enemy::{Boss, BossPhase, BossPhaseParams, Enemy, EnemyState},
+	feel::CameraShakeEvent,
+	game::{BulletTime, DamageEvent, DamageSource, GameGlobals, GameState, Health, Invulnerable},
+	physics::PhysicsGlobals,
+	player::Player,
+	shooting::{ShootEvent, Team, WeaponKind},
+	telegraph::{CancelTelegraphsEvent, TelegraphEvent, TelegraphShape},
+};
+
+/// Special boss attacks that don't fit into the regular chase and shoot loop
+pub struct AttacksPlugin;
+
+impl Plugin for AttacksPlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(FirePatchParams::default())
+			.insert_resource(RageBeamParams::default())
+			.insert_resource(InterruptParams::default())
+			.insert_resource(OrbitingShieldParams::default())
+			.insert_resource(SpecialAttackParams::default())
+			.insert_resource(SpecialAttackCooldowns::default())
+			.add_system_set(
+				SystemSet::on_enter(GameState::Playing)
+					.with_system(reset_special_attack_cooldowns)
+					.with_system(reset_fire_patch_cooldown)
+					.with_system(reset_rage_beam_cooldown)
+					.with_system(reset_orbiting_shield_cooldown),
+			)
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(spawn_fire_patches)
+					.with_system(update_fire_patches)
+					.with_system(spawn_rage_beam)
+					.with_system(update_rage_beams)
+					.with_system(interrupt_telegraphed_attacks.after("apply_damage"))
+					.with_system(tick_recoil)
+					.with_system(spawn_orbiting_shields)
+					.with_system(update_orbiting_shields)
+					.with_system(start_special_attacks.after("enemy_ai"))
+					.with_system(update_special_attacks.after("enemy_ai")),
+			);
+	}
+}
+
+/// The boss sets the floor around the player on fire. Patches are telegraphed before they start burning
+pub struct FirePatchParams {
+	/// Level from which on the boss uses this attack
+	pub min_level: u32,
+	pub cooldown: Timer,
+	pub count: u32,
+	/// Patches are placed randomly within this distance around the player
+	pub spread: f32,
+	pub size: f32,
+	pub telegraph_secs: f32,
+	pub duration_secs: f32,
+	pub tick_secs: f32,
+	pub damage_per_tick: f32,
+}
+
+impl Default for FirePatchParams {
+	fn default() -> Self {
+		Self {
+			min_level: 2,
+			cooldown: Timer::from_seconds(10.0, false),
+			count: 3,
+			spread: 120.0,
+			size: 60.0,
+			telegraph_secs: 1.0,
+			duration_secs: 5.0,
+			tick_secs: 0.5,
+			damage_per_tick: 3.0,
+		}
+	}
+}
+
+#[derive(Component)]
+struct FirePatch {
+	telegraph: Timer,
+	lifetime: Timer,
+	tick: Timer,
+	damage_per_tick: f32,
+}
+
+const FIRE_TELEGRAPH_COLOR: Color = Color::rgba(1.0, 0.5, 0.0, 0.2);
+const FIRE_COLOR: Color = Color::rgba(1.0, 0.3, 0.0, 0.6);
+
+fn reset_fire_patch_cooldown(mut params: ResMut<FirePatchParams>) {
+	params.cooldown.reset();
+}
+
+fn spawn_fire_patches(
+	mut commands: Commands,
+	mut params: ResMut<FirePatchParams>,
+	globals: Res<GameGlobals>,
+	rapier_config: Res<RapierConfiguration>,
+	physics_globals: Res<PhysicsGlobals>,
+	q_boss: Query<&Boss>,
+	q_player_t: Query<&Transform, With<Player>>,
+	time: Res<Time>,
+) {
+	if globals.level < params.min_level || q_boss.iter().next().is_none() {
+		return;
+	}
+
+	params.cooldown.tick(time.delta());
+	if !params.cooldown.finished() {
+		return;
+	}
+
+	if let Ok(player_t) = q_player_t.get_single() {
+		params.cooldown.reset();
+		for _ in 0..params.count {
+			let offset = Vec2::new(
+				rand::random::<f32>() * 2.0 - 1.0,
+				rand::random::<f32>() * 2.0 - 1.0,
+			) * params.spread;
+			let pos = player_t.translation.xy() + offset;
+
+			commands
+				.spawn_bundle(SpriteBundle {
+					sprite: Sprite {
+						color: FIRE_TELEGRAPH_COLOR,
+						custom_size: Some(Vec2::splat(params.size)),
+						..Default::default()
+					},
+					transform: Transform::from_xyz(pos.x, pos.y, -1.0),
+					..Default::default()
+				})
+				.insert_bundle(ColliderBundle {
+					collider_type: ColliderType::Sensor.into(),
+					position: (pos / rapier_config.scale).into(),
+					shape: ColliderShape::ball(params.size * 0.5 / rapier_config.scale).into(),
+					flags: ColliderFlags {
+						collision_groups: InteractionGroups::new(
+							physics_globals.scene_mask,
+							physics_globals.player_mask,
+						),
+						active_events: ActiveEvents::INTERSECTION_EVENTS,
+						..Default::default()
+					}
+					.into(),
+					..Default::default()
+				})
+				.insert(FirePatch {
+					telegraph: Timer::from_seconds(params.telegraph_secs, false),
+					lifetime: Timer::from_seconds(params.duration_secs, false),
+					tick: Timer::from_seconds(params.tick_secs, true),
+					damage_per_tick: params.damage_per_tick,
+				});
+		}
+	}
+}
+
+/// Ignites telegraphed patches, damages players standing in them and removes burnt out patches
+fn update_fire_patches(
+	mut commands: Commands,
+	mut q_patches: Query<(Entity, &mut FirePatch, &mut Sprite)>,
+	q_players: Query<Entity, With<Player>>,
+	narrow_phase: Res<NarrowPhase>,
+	mut damage_ew: EventWriter<DamageEvent>,
+	time: Res<Time>,
+) {
+	for (entity, mut patch, mut sprite) in q_patches.iter_mut() {
+		if !patch.telegraph.finished() {
+			patch.telegraph.tick(time.delta());
+			if patch.telegraph.just_finished() {
+				sprite.color = FIRE_COLOR;
+			}
+			continue;
+		}
+
+		patch.lifetime.tick(time.delta());
+		if patch.lifetime.finished() {
+			commands.entity(entity).despawn_recursive();
+			continue;
+		}
+
+		patch.tick.tick(time.delta());
+		if !patch.tick.just_finished() {
+			continue;
+		}
+		for player in q_players.iter() {
+			if narrow_phase.intersection_pair(entity.handle(), player.handle()) == Some(true) {
+				damage_ew.send(DamageEvent {
+					target: player,
+					amount: patch.damage_per_tick,
+					source: DamageSource::Attack,
+				});
+			}
+		}
+	}
+}
+
+/// The boss fires a beam that slowly turns towards the player, so standing still isn't an option.
+/// The beam is telegraphed as a thin line before it starts dealing damage. Only phases with
+/// `BossPhaseSpec::rage_beam` use it
+pub struct RageBeamParams {
+	pub cooldown: Timer,
+	/// Radians per second the beam turns towards the player
+	pub turn_rate: f32,
+	pub length: f32,
+	pub width: f32,
+	pub telegraph_secs: f32,
+	pub duration_secs: f32,
+	pub tick_secs: f32,
+	pub damage_per_tick: f32,
+}
+
+impl Default for RageBeamParams {
+	fn default() -> Self {
+		Self {
+			cooldown: Timer::from_seconds(15.0, false),
+			turn_rate: 0.6,
+			length: 800.0,
+			width: 16.0,
+			telegraph_secs: 1.0,
+			duration_secs: 4.0,
+			tick_secs: 0.2,
+			damage_per_tick: 4.0,
+		}
+	}
+}
+
+#[derive(Component)]
+pub struct TrackingBeam {
+	pub turn_rate: f32,
+	pub damage_per_tick: f32,
+	/// Entity the beam is fired from
+	source: Entity,
+	/// Angle in radians
+	angle: f32,
+	length: f32,
+	width: f32,
+	telegraph: Timer,
+	lifetime: Timer,
+	tick: Timer,
+}
+
+const BEAM_TELEGRAPH_COLOR: Color = Color::rgba(1.0, 0.1, 0.1, 0.3);
+const BEAM_COLOR: Color = Color::rgba(1.0, 0.1, 0.1, 0.8);
+/// Width of the beam while it is telegraphed, relative to the full width
+const BEAM_TELEGRAPH_WIDTH: f32 = 0.25;
+
+fn reset_rage_beam_cooldown(mut params: ResMut<RageBeamParams>) {
+	params.cooldown.reset();
+}
+
+fn spawn_rage_beam(
+	mut commands: Commands,
+	mut params: ResMut<RageBeamParams>,
+	phase_params: Res<BossPhaseParams>,
+	q_boss: Query<(Entity, &Transform, Option<&BossPhase>), With<Boss>>,
+	q_player_t: Query<&Transform, With<Player>>,
+	q_beams: Query<&TrackingBeam>,
+	time: Res<Time>,
+) {
+	if !q_beams.is_empty() {
+		return;
+	}
+	let (boss, boss_t) = match q_boss.iter().next() {
+		Some((boss, boss_t, phase))
+			if phase_params
+				.get(phase)
+				.map_or(false, |phase| phase.rage_beam) =>
+		{
+			(boss, boss_t)
+		}
+		_ => return,
+	};
+
+	params.cooldown.tick(time.delta());
+	if !params.cooldown.finished() {
+		return;
+	}
+
+	if let Ok(player_t) = q_player_t.get_single() {
+		params.cooldown.reset();
+		let dir = player_t.translation.xy() - boss_t.translation.xy();
+		commands
+			.spawn_bundle(SpriteBundle {
+				sprite: Sprite {
+					color: BEAM_TELEGRAPH_COLOR,
+					..Default::default()
+				},
+				..Default::default()
+			})
+			.insert(TrackingBeam {
+				turn_rate: params.turn_rate,
+				damage_per_tick: params.damage_per_tick,
+				source: boss,
+				angle: dir.y.atan2(dir.x),
+				length: params.length,
+				width: params.width,
+				telegraph: Timer::from_seconds(params.telegraph_secs, false),
+				lifetime: Timer::from_seconds(params.duration_secs, false),
+				tick: Timer::from_seconds(params.tick_secs, true),
+			});
+	}
+}
+
+/// Turns beams towards the player, cuts them off at walls and damages players they hit.
+/// Beams are removed once they ran out or the boss is gone
+fn update_rage_beams(
+	mut commands: Commands,
+	mut q_beams: Query<(Entity, &mut TrackingBeam, &mut Sprite, &mut Transform)>,
+	q_source_t: Query<&Transform, (With<Boss>, Without<TrackingBeam>)>,
+	q_player_t: Query<&Transform, (With<Player>, Without<TrackingBeam>)>,
+	rapier_config: Res<RapierConfiguration>,
+	physics_globals: Res<PhysicsGlobals>,
+	query_pipeline: Res<QueryPipeline>,
+	collider_query: QueryPipelineColliderComponentsQuery,
+	mut damage_ew: EventWriter<DamageEvent>,
+	time: Res<Time>,
+) {
+	let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+	for (entity, mut beam, mut sprite, mut transform) in q_beams.iter_mut() {
+		let source_pos = match q_source_t.get(beam.source) {
+			Ok(source_t) => source_t.translation.xy(),
+			Err(_) => {
+				commands.entity(entity).despawn_recursive();
+				continue;
+			}
+		};
+
+		let telegraphing = !beam.telegraph.finished();
+		if telegraphing {
+			beam.telegraph.tick(time.delta());
+		} else {
+			beam.lifetime.tick(time.delta());
+			if beam.lifetime.finished() {
+				commands.entity(entity).despawn_recursive();
+				continue;
+			}
+		}
+
+		// turn towards the player, at most by turn_rate
+		if let Ok(player_t) = q_player_t.get_single() {
+			let dir = player_t.translation.xy() - source_pos;
+			let mut diff = dir.y.atan2(dir.x) - beam.angle;
+			diff = (diff + PI).rem_euclid(2.0 * PI) - PI;
+			let max_turn = beam.turn_rate * time.delta_seconds();
+			beam.angle += diff.clamp(-max_turn, max_turn);
+		}
+		let dir = Vec2::new(beam.angle.cos(), beam.angle.sin());
+
+		// the beam ends at the first wall or player it hits
+		let ray = Ray::new((source_pos / rapier_config.scale).into(), dir.into());
+		let hit = query_pipeline.cast_ray(
+			&collider_set,
+			&ray,
+			beam.length / rapier_config.scale,
+			true,
+			InteractionGroups::new(
+				u32::MAX,
+				physics_globals.scene_mask | physics_globals.player_mask,
+			),
+			None,
+		);
+		let length = hit
+			.map(|(_, toi)| toi * rapier_config.scale)
+			.unwrap_or(beam.length);
+
+		let width = if telegraphing {
+			beam.width * BEAM_TELEGRAPH_WIDTH
+		} else {
+			beam.width
+		};
+		sprite.color = if telegraphing {
+			BEAM_TELEGRAPH_COLOR
+		} else {
+			BEAM_COLOR
+		};
+		sprite.custom_size = Some(Vec2::new(length, width));
+		let center = source_pos + dir * length * 0.5;
+		transform.translation = center.extend(-0.5);
+		transform.rotation = Quat::from_rotation_z(beam.angle);
+
+		if telegraphing {
+			continue;
+		}
+		beam.tick.tick(time.delta());
+		if !beam.tick.just_finished() {
+			continue;
+		}
+		if let Some((handle, _)) = hit {
+			let target = handle.entity();
+			if q_player_t.get(target).is_ok() {
+				damage_ew.send(DamageEvent {
+					target,
+					amount: beam.damage_per_tick,
+					source: DamageSource::Attack,
+				});
+			}
+		}
+	}
+}
+
+/// Hitting the boss hard enough while one of its attacks is still telegraphed cancels the attack and
+/// knocks the boss back. Instant attacks like regular shots can't be interrupted
+pub struct InterruptParams {
+	/// Damage the boss has to take while an attack is telegraphed
+	pub threshold: f32,
+	pub recoil_secs: f32,
+	/// Pixels / sec the boss is pushed away from the player
+	pub recoil_speed: f32,
+}
+
+impl Default for InterruptParams {
+	fn default() -> Self {
+		Self {
+			threshold: 15.0,
+			recoil_secs: 0.6,
+			recoil_speed: 200.0,
+		}
+	}
+}
+
+/// The boss neither moves on its own nor shoots while recoiling
+#[derive(Component)]
+pub struct Recoil {
+	pub timer: Timer,
+	/// Pixels / sec
+	pub velocity: Vec2,
+}
+
+/// Damage the boss took since its current telegraphs started
+#[derive(Default)]
+struct InterruptWindow {
+	damage: f32,
+	last_health: Option<f32>,
+}
+
+fn interrupt_telegraphed_attacks(
+	mut commands: Commands,
+	params: Res<InterruptParams>,
+	mut window: Local<InterruptWindow>,
+	q_boss: Query<(Entity, &Health, &Transform), (With<Boss>, Without<Recoil>)>,
+	q_player_t: Query<&Transform, With<Player>>,
+	q_patches: Query<(Entity, &FirePatch)>,
+	q_beams: Query<(Entity, &TrackingBeam)>,
+	q_specials: Query<&SpecialAttack>,
+) {
+	let (boss, Health(health), boss_t) = match q_boss.iter().next() {
+		Some(boss) => boss,
+		None => {
+			*window = InterruptWindow::default();
+			return;
+		}
+	};
+
+	let telegraphed: Vec<Entity> = q_patches
+		.iter()
+		.filter(|(_, patch)| !patch.telegraph.finished())
+		.map(|(entity, _)| entity)
+		.chain(
+			q_beams
+				.iter()
+				.filter(|(_, beam)| !beam.telegraph.finished())
+				.map(|(entity, _)| entity),
+		)
+		.collect();
+
+	// special attacks end themselves once the boss recoils, see `update_special_attacks`
+	let winding_up = q_specials.iter().any(|special| !special.windup.finished());
+
+	if telegraphed.is_empty() && !winding_up {
+		window.damage = 0.0;
+	} else if let Some(last_health) = window.last_health {
+		// healing, e.g. when the boss is upgraded, doesn't count against the damage
+		window.damage += (last_health - health).max(0.0);
+	}
+	window.last_health = Some(*health);
+	if window.damage < params.threshold {
+		return;
+	}
+
+	debug!("BOSS_INTERRUPTED");
+	window.damage = 0.0;
+	for entity in telegraphed {
+		commands.entity(entity).despawn_recursive();
+	}
+	let away = q_player_t.get_single().map_or(Vec2::ZERO, |player_t| {
+		(boss_t.translation.xy() - player_t.translation.xy()).normalize_or_zero()
+	});
+	commands.entity(boss).insert(Recoil {
+		timer: Timer::from_seconds(params.recoil_secs, false),
+		velocity: away * params.recoil_speed,
+	});
+}
+
+fn tick_recoil(
+	mut commands: Commands,
+	mut q_recoil: Query<(Entity, &mut Recoil)>,
+	time: Res<Time>,
+) {
+	for (entity, mut recoil) in q_recoil.iter_mut() {
+		recoil.timer.tick(time.delta());
+		if recoil.timer.finished() {
+			commands.entity(entity).remove::<Recoil>();
+		}
+	}
+}
+
+/// Charge, slam, bullet ring and homing missiles. While attacking the player, the boss picks one of the
+/// attacks that are ready by weight, telegraphs it during the wind-up and stays in `EnemyState::SPECIAL`
+/// until it's over
+pub struct SpecialAttackParams {
+	/// Level from which on the boss uses special attacks
+	pub min_level: u32,
+	/// Seconds between the starts of two special attacks, whichever they are
+	pub global_cooldown: f32,
+	pub attacks: Vec<SpecialAttackSpec>,
+	/// Pixels / sec the boss charges with, it keeps its direction from the start of the wind-up
+	pub charge_speed: f32,
+	pub charge_damage: f32,
+	/// Pixels between the boss' center and a player that count as being run over
+	pub charge_hit_radius: f32,
+	pub slam_radius: f32,
+	pub slam_damage: f32,
+	/// Shake when the slam lands, pixels and seconds
+	pub slam_shake: (f32, f32),
+	/// Pixels / sec a player hit by the charge or the slam is pushed away with
+	pub knockback: f32,
+	/// Bullets per wave of the ring, evenly spaced around the boss
+	pub ring_bullets: u32,
+	/// Waves fired over the ring's duration, every one turned by half the gap between two bullets
+	pub ring_waves: u32,
+	/// Multiplies the bullet speed
+	pub ring_speed: f32,
+	/// Missiles fired at once, fanned out `missile_spacing` radians apart
+	pub missile_count: u32,
+	pub missile_spacing: f32,
+}
+
+pub struct SpecialAttackSpec {
+	pub kind: SpecialAttackKind,
+	/// Chance to be picked relative to the other ready attacks
+	pub weight: f32,
+	/// Seconds before the attack can be picked again
+	pub cooldown: f32,
+	/// Seconds the attack is telegraphed before it goes off
+	pub windup: f32,
+	/// Seconds from going off until the boss returns to attacking
+	pub duration: f32,
+	/// Only picked while the player is between these many pixels away
+	pub min_dist: f32,
+	pub max_dist: f32,
+	/// Index into `BossPhaseParams::phases` from which on the attack is picked
+	pub min_phase: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SpecialAttackKind {
+	/// Runs in a straight line, hurting players in the way
+	Charge,
+	/// Hurts players close to the boss
+	Slam,
+	/// Fires bullets in every direction
+	BulletRing,
+	/// Fires a fan of homing missiles at the player
+	Missiles,
+}
+
+impl Default for SpecialAttackParams {
+	fn default() -> Self {
+		let spec = |kind, weight, cooldown, windup, duration, min_dist, max_dist, min_phase| {
+			SpecialAttackSpec {
+				kind,
+				weight,
+				cooldown,
+				windup,
+				duration,
+				min_dist,
+				max_dist,
+				min_phase,
+			}
+		};
+		Self {
+			min_level: 1,
+			global_cooldown: 4.0,
+			attacks: vec![
+				spec(
+					SpecialAttackKind::Charge,
+					1.0,
+					8.0,
+					0.8,
+					0.6,
+					120.0,
+					450.0,
+					0,
+				),
+				spec(SpecialAttackKind::Slam, 1.5, 6.0, 0.7, 0.3, 0.0, 130.0, 0),
+				spec(
+					SpecialAttackKind::BulletRing,
+					1.0,
+					10.0,
+					1.0,
+					0.8,
+					0.0,
+					600.0,
+					0,
+				),
+				// only from the third phase on, when the boss is at half health
+				spec(
+					SpecialAttackKind::Missiles,
+					1.0,
+					12.0,
+					0.8,
+					0.5,
+					150.0,
+					800.0,
+					2,
+				),
+			],
+			charge_speed: 500.0,
+			charge_damage: 15.0,
+			charge_hit_radius: 60.0,
+			slam_radius: 140.0,
+			slam_damage: 12.0,
+			slam_shake: (10.0, 0.3),
+			knockback: 500.0,
+			ring_bullets: 16,
+			ring_waves: 3,
+			ring_speed: 0.8,
+			missile_count: 3,
+			missile_spacing: 0.5,
+		}
+	}
+}
+
+/// Indexed like `SpecialAttackParams::attacks`
+#[derive(Default)]
+pub struct SpecialAttackCooldowns {
+	global: Timer,
+	attacks: Vec<Timer>,
+}
+
+/// Attached to the boss while it's in `EnemyState::SPECIAL`
+#[derive(Component)]
+pub struct SpecialAttack {
+	pub kind: SpecialAttackKind,
+	target: Entity,
+	/// Direction of the charge
+	dir: Vec2,
+	windup: Timer,
+	active: Timer,
+	/// Waves of the ring fired so far, 1 once the charge hit or the missiles are out
+	progress: u32,
+}
+
+const CHARGE_TELEGRAPH_COLOR: Color = Color::rgba(1.0, 0.2, 0.2, 0.3);
+const SLAM_TELEGRAPH_COLOR: Color = Color::rgba(1.0, 0.5, 0.0, 0.3);
+const RING_TELEGRAPH_COLOR: Color = Color::rgba(1.0, 0.1, 0.6, 0.3);
+/// Pixels, the ring's telegraph covers the boss
+const RING_TELEGRAPH_RADIUS: f32 = 90.0;
+const MISSILE_TELEGRAPH_COLOR: Color = Color::rgba(0.9, 0.9, 0.2, 0.3);
+/// Pixels, missiles turn too much for the telegraph to cover their whole way
+const MISSILE_TELEGRAPH_RANGE: f32 = 200.0;
+
+fn reset_special_attack_cooldowns(
+	params: Res<SpecialAttackParams>,
+	mut cooldowns: ResMut<SpecialAttackCooldowns>,
+) {
+	*cooldowns = SpecialAttackCooldowns {
+		global: Timer::from_seconds(params.global_cooldown, false),
+		// every attack is ready once the global cooldown is over the first time
+		attacks: params
+			.attacks
+			.iter()
+			.map(|spec| {
+				let mut timer = Timer::from_seconds(spec.cooldown, false);
+				timer.tick(timer.duration());
+				timer
+			})
+			.collect(),
+	};
+}
+
+/// Picks a special attack for an attacking boss and starts its wind-up
+fn start_special_attacks(
+	mut commands: Commands,
+	params: Res<SpecialAttackParams>,
+	mut cooldowns: ResMut<SpecialAttackCooldowns>,
+	globals: Res<GameGlobals>,
+	mut q_boss: Query<
+		(Entity, &Transform, &mut Enemy, Option<&BossPhase>),
+		(With<Boss>, Without<SpecialAttack>, Without<Recoil>),
+	>,
+	q_player_t: Query<&Transform, With<Player>>,
+	mut telegraph_ew: EventWriter<TelegraphEvent>,
+	(time, bullet_time): (Res<Time>, Res<BulletTime>),
+) {
+	if globals.level < params.min_level {
+		return;
+	}
+	let delta = time.delta().mul_f32(bullet_time.scale);
+	cooldowns.global.tick(delta);
+	for timer in cooldowns.attacks.iter_mut() {
+		timer.tick(delta);
+	}
+	if !cooldowns.global.finished() {
+		return;
+	}
+
+	for (boss, boss_t, mut enemy, phase) in q_boss.iter_mut() {
+		// only while the boss sees the player
+		let target = match enemy.0 {
+			EnemyState::ATTACK(Some(target)) => target,
+			_ => continue,
+		};
+		let player_t = match q_player_t.get(target) {
+			Ok(player_t) => player_t,
+			Err(_) => continue,
+		};
+		let pos = boss_t.translation.xy();
+		let to_player = player_t.translation.xy() - pos;
+		let dist = to_player.length();
+		let phase = phase.map_or(0, |BossPhase(phase)| *phase);
+
+		let ready: Vec<usize> = params
+			.attacks
+			.iter()
+			.enumerate()
+			.filter(|(i, spec)| {
+				cooldowns.attacks.get(*i).map_or(false, Timer::finished)
+					&& spec.weight > 0.0
+					&& dist >= spec.min_dist
+					&& dist <= spec.max_dist
+					&& phase >= spec.min_phase
+			})
+			.map(|(i, _)| i)
+			.collect();
+		let total: f32 = ready.iter().map(|i| params.attacks[*i].weight).sum();
+		let mut roll = rand::random::<f32>() * total;
+		let picked = ready.iter().find(|i| {
+			roll -= params.attacks[**i].weight;
+			roll <= 0.0
+		});
+		// rounding can leave a bit of the roll over
+		let index = match picked.or_else(|| ready.last()) {
+			Some(index) => *index,
+			None => continue,
+		};
+		let spec = &params.attacks[index];
+
+		debug!("SPECIAL_ATTACK {:?}", spec.kind);
+		let dir = to_player.normalize_or_zero();
+		let (shape, follow, color) = match spec.kind {
+			SpecialAttackKind::Charge => (
+				TelegraphShape::Line {
+					length: params.charge_speed * spec.duration,
+					width: params.charge_hit_radius * 2.0,
+				},
+				false,
+				CHARGE_TELEGRAPH_COLOR,
+			),
+			SpecialAttackKind::Slam => (
+				TelegraphShape::Circle {
+					radius: params.slam_radius,
+				},
+				true,
+				SLAM_TELEGRAPH_COLOR,
+			),
+			SpecialAttackKind::BulletRing => (
+				TelegraphShape::Circle {
+					radius: RING_TELEGRAPH_RADIUS,
+				},
+				true,
+				RING_TELEGRAPH_COLOR,
+			),
+			SpecialAttackKind::Missiles => (
+				TelegraphShape::Cone {
+					range: MISSILE_TELEGRAPH_RANGE,
+					angle: params.missile_spacing * params.missile_count.max(1) as f32,
+				},
+				true,
+				MISSILE_TELEGRAPH_COLOR,
+			),
+		};
+		telegraph_ew.send(TelegraphEvent {
+			owner: boss,
+			shape,
+			pos,
+			dir,
+			secs: spec.windup,
+			follow,
+			color,
+		});
+		commands.entity(boss).insert(SpecialAttack {
+			kind: spec.kind,
+			target,
+			dir,
+			windup: Timer::from_seconds(spec.windup, false),
+			active: Timer::from_seconds(spec.duration, false),
+			progress: 0,
+		});
+		enemy.0 = EnemyState::SPECIAL(Some(target));
+		cooldowns.global.reset();
+		cooldowns.attacks[index].reset();
+	}
+}
+
+/// Carries out the attacks once their wind-up is over. Recoiling ends an attack early
+fn update_special_attacks(
+	mut commands: Commands,
+	params: Res<SpecialAttackParams>,
+	mut q_boss: Query<
+		(
+			Entity,
+			&Transform,
+			&mut Enemy,
+			&mut SpecialAttack,
+			&mut RigidBodyVelocityComponent,
+			Option<&Recoil>,
+		),
+		With<Boss>,
+	>,
+	mut q_players: Query<
+		(Entity, &Transform, &mut RigidBodyVelocityComponent),
+		(With<Player>, Without<Boss>, Without<Invulnerable>),
+	>,
+	q_player_t: Query<&Transform, With<Player>>,
+	mut damage_ew: EventWriter<DamageEvent>,
+	mut shoot_ew: EventWriter<ShootEvent>,
+	mut shake_ew: EventWriter<CameraShakeEvent>,
+	mut cancel_telegraphs_ew: EventWriter<CancelTelegraphsEvent>,
+	rapier_config: Res<RapierConfiguration>,
+	(time, bullet_time): (Res<Time>, Res<BulletTime>),
+) {
+	let delta = time.delta().mul_f32(bullet_time.scale);
+	for (boss, boss_t, mut enemy, mut special, mut rb_vel, recoil) in q_boss.iter_mut() {
+		let pos = boss_t.translation.xy();
+		let done = if recoil.is_some() {
+			cancel_telegraphs_ew.send(CancelTelegraphsEvent(boss));
+			true
+		} else if !special.windup.finished() {
+			special.windup.tick(delta);
+			if special.windup.just_finished() && special.kind == SpecialAttackKind::Slam {
+				debug!("BOSS_SLAM");
+				for (player, player_t, mut player_vel) in q_players.iter_mut() {
+					let away = player_t.translation.xy() - pos;
+					if away.length() > params.slam_radius {
+						continue;
+					}
+					damage_ew.send(DamageEvent {
+						target: player,
+						amount: params.slam_damage,
+						source: DamageSource::Attack,
+					});
+					let push = away.normalize_or_zero() * params.knockback;
+					player_vel.linvel =
+						(Vec2::from(player_vel.linvel) + push / rapier_config.scale).into();
+				}
+				let (magnitude, duration) = params.slam_shake;
+				shake_ew.send(CameraShakeEvent {
+					magnitude,
+					duration,
+				});
+			}
+			false
+		} else {
+			special.active.tick(delta);
+			match special.kind {
+				SpecialAttackKind::Charge => {
+					let velocity = special.dir * params.charge_speed * bullet_time.scale;
+					rb_vel.linvel = (velocity / rapier_config.scale).into();
+					for (player, player_t, mut player_vel) in q_players.iter_mut() {
+						let rel = player_t.translation.xy() - pos;
+						if special.progress > 0 || rel.length() > params.charge_hit_radius {
+							continue;
+						}
+						special.progress = 1;
+						damage_ew.send(DamageEvent {
+							target: player,
+							amount: params.charge_damage,
+							source: DamageSource::Attack,
+						});
+						// thrown aside, out of the way of the charge
+						let side = special.dir.perp();
+						let side = if rel.dot(side) < 0.0 { -side } else { side };
+						player_vel.linvel = (side * params.knockback / rapier_config.scale).into();
+					}
+				}
+				SpecialAttackKind::BulletRing => {
+					let waves = params.ring_waves.max(1);
+					let due = ((special.active.percent() * waves as f32) as u32 + 1).min(waves);
+					let gap = 2.0 * PI / params.ring_bullets.max(1) as f32;
+					while special.progress < due {
+						let offset = special.progress as f32 * gap * 0.5;
+						for i in 0..params.ring_bullets {
+							let angle = offset + i as f32 * gap;
+							shoot_ew.send(ShootEvent(
+								Team::Enemy,
+								pos,
+								Vec2::new(angle.cos(), angle.sin()),
+								Some(boss),
+								params.ring_speed,
+								WeaponKind::Pistol,
+							));
+						}
+						special.progress += 1;
+					}
+				}
+				SpecialAttackKind::Missiles if special.progress == 0 => {
+					// aimed where the player is now, the missiles follow them from there
+					let dir = q_player_t
+						.get(special.target)
+						.map_or(special.dir, |player_t| {
+							(player_t.translation.xy() - pos).normalize_or_zero()
+						});
+					let count = params.missile_count.max(1);
+					for i in 0..count {
+						let angle = (i as f32 - (count - 1) as f32 * 0.5) * params.missile_spacing;
+						shoot_ew.send(ShootEvent(
+							Team::Enemy,
+							pos,
+							Mat2::from_angle(angle) * dir,
+							Some(boss),
+							1.0,
+							WeaponKind::Missile,
+						));
+					}
+					special.progress = 1;
+				}
+				SpecialAttackKind::Missiles | SpecialAttackKind::Slam => {}
+			}
+			special.active.finished()
+		};
+		if !done {
+			continue;
+		}
+
+		commands.entity(boss).remove::<SpecialAttack>();
+		enemy.0 = if q_player_t.get(special.target).is_ok() {
+			EnemyState::ATTACK(Some(special.target))
+		} else {
+			EnemyState::IDLE
+		};
+	}
+}
+
+/// The boss surrounds itself with rotating shield segments that block bullets, so the player has to
+/// shoot through the gaps or wait until they expire. How many segments there are and how they move is
+/// set per phase, see `BossPhaseSpec::orbiting_shield`
+pub struct OrbitingShieldParams {
+	pub cooldown: Timer,
+	/// Size of a segment in pixels, its long side faces away from the boss
+	pub segment_size: Vec2,
+}
+
+pub struct OrbitingShieldStage {
+	pub count: u32,
+	/// Distance from the boss in pixels
+	pub radius: f32,
+	/// Radians per second
+	pub angular_velocity: f32,
+	pub lifetime_secs: f32,
+}
+
+impl Default for OrbitingShieldParams {
+	fn default() -> Self {
+		Self {
+			cooldown: Timer::from_seconds(12.0, false),
+			segment_size: Vec2::new(10.0, 50.0),
+		}
+	}
+}
+
+/// Shield segment circling `boss`. It has no health, bullets hitting it are simply despawned
+#[derive(Component)]
+pub struct OrbitingShield {
+	/// Radians per second
+	pub angular_velocity: f32,
+	/// Pixels
+	pub radius: f32,
+	pub lifetime: Timer,
+	boss: Entity,
+	/// Current angle around the boss in radians
+	angle: f32,
+}
+
+const ORBITING_SHIELD_COLOR: Color = Color::ALICE_BLUE;
+
+fn reset_orbiting_shield_cooldown(mut params: ResMut<OrbitingShieldParams>) {
+	params.cooldown.reset();
+}
+
+fn spawn_orbiting_shields(
+	mut commands: Commands,
+	mut params: ResMut<OrbitingShieldParams>,
+	phase_params: Res<BossPhaseParams>,
+	rapier_config: Res<RapierConfiguration>,
+	physics_globals: Res<PhysicsGlobals>,
+	q_boss: Query<(Entity, &Transform, Option<&BossPhase>), With<Boss>>,
+	q_shields: Query<&OrbitingShield>,
+	time: Res<Time>,
+) {
+	let (boss, boss_t, stage) = match q_boss.iter().next() {
+		Some((boss, boss_t, phase)) => (
+			boss,
+			boss_t,
+			phase_params
+				.get(phase)
+				.and_then(|phase| phase.orbiting_shield.as_ref()),
+		),
+		None => return,
+	};
+	let stage = match stage {
+		Some(stage) if q_shields.is_empty() => stage,
+		_ => return,
+	};
+
+	params.cooldown.tick(time.delta());
+	if !params.cooldown.finished() {
+		return;
+	}
+	params.cooldown.reset();
+
+	let size = params.segment_size;
+	for i in 0..stage.count {
+		let angle = i as f32 * 2.0 * PI / stage.count as f32;
+		let pos = boss_t.translation.xy() + Vec2::new(angle.cos(), angle.sin()) * stage.radius;
+		commands
+			.spawn_bundle(SpriteBundle {
+				sprite: Sprite {
+					color: ORBITING_SHIELD_COLOR,
+					custom_size: Some(size),
+					..Default::default()
+				},
+				transform: Transform::from_translation(pos.extend(0.5))
+					.with_rotation(Quat::from_rotation_z(angle)),
+				..Default::default()
+			})
+			.insert_bundle(ColliderBundle {
+				position: (pos / rapier_config.scale, angle).into(),
+				shape: ColliderShape::cuboid(
+					size.x * 0.5 / rapier_config.scale,
+					size.y * 0.5 / rapier_config.scale,
+				)
+				.into(),
+				flags: ColliderFlags {
+					collision_groups: InteractionGroups::new(
+						physics_globals.enemy_mask,
+						physics_globals.bullet_mask,
+					),
+					..Default::default()
+				}
+				.into(),
+				..Default::default()
+			})
+			.insert(OrbitingShield {
+				angular_velocity: stage.angular_velocity,
+				radius: stage.radius,
+				lifetime: Timer::from_seconds(stage.lifetime_secs, false),
+				boss,
+				angle,
+			});
+	}
+}
+
+/// Moves the segments around the boss and removes them once they expired or the boss is gone
+fn update_orbiting_shields(
+	mut commands: Commands,
+	mut q_shields: Query<(
+		Entity,
+		&mut OrbitingShield,
+		&mut Transform,
+		&mut ColliderPositionComponent,
+	)>,
+	q_boss_t: Query<&Transform, (With<Boss>, Without<OrbitingShield>)>,
+	rapier_config: Res<RapierConfiguration>,
+	time: Res<Time>,
+) {
+	for (entity, mut shield, mut transform, mut collider_pos) in q_shields.iter_mut() {
+		shield.lifetime.tick(time.delta());
+		let boss_t = match q_boss_t.get(shield.boss) {
+			Ok(boss_t) if !shield.lifetime.finished() => boss_t,
+			_ => {
+				commands.entity(entity).despawn_recursive();
+				continue;
+			}
+		};
+
+		shield.angle = (shield.angle + shield.angular_velocity * time.delta_seconds()) % (2.0 * PI);
+		let pos = boss_t.translation.xy()
+			+ Vec2::new(shield.angle.cos(), shield.angle.sin()) * shield.radius;
+		transform.translation = pos.extend(0.5);
+		transform.rotation = Quat::from_rotation_z(shield.angle);
+		collider_pos.0 = (pos / rapier_config.scale, shield.angle).into();
+	}
+}
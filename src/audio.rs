@@ -0,0 +1,448 @@
+use bevy::prelude::*;
+use bevy_kira_audio::{Audio, AudioChannel, AudioSource};
+
+use crate::{
+	enemy::{Boss, BossPhase, BossSpawnEvent, Minion},
+	game::{DamageDealtEvent, DamageSource, DeathEvent, GameState},
+	loading::LoadingAssets,
+	player::Player,
+	scene::MainCamera,
+	settings::Settings,
+	shooting::{ShootEvent, Team},
+};
+
+/// Plays sound effects for `SoundEvent`s and loops background music depending on the game state.
+/// During a run the music intensifies with the boss phase and the number of minions, and it is turned
+/// down in the pause menu.
+/// Most sounds are derived from existing game events, other plugins can send `SoundEvent`s directly.
+/// Sounds with a position are panned and attenuated relative to the player, if enabled in the settings
+pub struct AudioPlugin;
+
+/// Effects are spread over this many channels, since kira can only pan and attenuate whole channels
+const SFX_CHANNELS: usize = 8;
+
+impl Plugin for AudioPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_plugin(bevy_kira_audio::AudioPlugin)
+			.add_event::<SoundEvent>()
+			.insert_resource(AudioChannels {
+				music: AudioChannel::new("music".to_string()),
+				sfx: (0..SFX_CHANNELS)
+					.map(|i| AudioChannel::new(format!("sfx{}", i)))
+					.collect(),
+				next_sfx: 0,
+			})
+			.insert_resource(SpatialAudioParams::default())
+			.insert_resource(Sounds::default())
+			.insert_resource(CurrentMusic::default())
+			.insert_resource(MusicParams::default())
+			.insert_resource(MusicIntensity::default())
+			.add_startup_system(load_sounds)
+			.add_system(apply_music_mix.label("apply_music_mix"))
+			.add_system(play_sounds.label("play_sounds"))
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(
+						sounds_from_game_events
+							.before("play_sounds")
+							.after("detect_deaths"),
+					)
+					.with_system(update_music_intensity.before("apply_music_mix")),
+			)
+			.add_system_set(SystemSet::on_enter(GameState::Playing).with_system(play_game_music))
+			.add_system_set(
+				SystemSet::on_enter(GameState::GameOver).with_system(play_game_over_music),
+			);
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Sound {
+	/// The player fired
+	Shot,
+	Hit,
+	BossRoar,
+	PlayerDeath,
+	EnemyDeath,
+}
+
+pub struct SoundEvent {
+	pub sound: Sound,
+	/// Where in the world the sound comes from, `None` plays it centered at full volume
+	pub pos: Option<Vec2>,
+}
+
+impl SoundEvent {
+	pub fn at(sound: Sound, pos: Vec2) -> Self {
+		Self {
+			sound,
+			pos: Some(pos),
+		}
+	}
+
+	pub fn global(sound: Sound) -> Self {
+		Self { sound, pos: None }
+	}
+}
+
+pub struct SpatialAudioParams {
+	/// Horizontal distance in pixels at which a sound is panned the furthest
+	pub pan_dist: f32,
+	/// Panning at `pan_dist`, 1 would play the sound in one ear only
+	pub max_pan: f32,
+	/// Sounds closer than this many pixels play at full volume
+	pub full_volume_dist: f32,
+	/// Pixels beyond `full_volume_dist` at which the volume is halved
+	pub falloff_dist: f32,
+	/// Far away sounds never get quieter than this, so off-screen enemies stay audible
+	pub min_volume: f32,
+}
+
+impl Default for SpatialAudioParams {
+	fn default() -> Self {
+		Self {
+			pan_dist: 350.0,
+			max_pan: 0.8,
+			full_volume_dist: 150.0,
+			falloff_dist: 300.0,
+			min_volume: 0.25,
+		}
+	}
+}
+
+impl SpatialAudioParams {
+	/// Volume factor and kira panning (0 left, 0.5 centered, 1 right) of a sound `offset` pixels away
+	/// from the listener
+	fn spatialize(&self, offset: Vec2) -> (f32, f32) {
+		let panning = 0.5 + 0.5 * self.max_pan * (offset.x / self.pan_dist).clamp(-1.0, 1.0);
+		let falloff = (offset.length() - self.full_volume_dist).max(0.0) / self.falloff_dist;
+		let volume = (1.0 / (1.0 + falloff)).max(self.min_volume);
+		(volume, panning)
+	}
+}
+
+struct AudioChannels {
+	music: AudioChannel,
+	sfx: Vec<AudioChannel>,
+	/// Effect channel the next sound plays on, they are used in turn so a new sound rarely changes
+	/// the panning of one that is still playing
+	next_sfx: usize,
+}
+
+#[derive(Default)]
+struct Sounds {
+	shot: Handle<AudioSource>,
+	hit: Handle<AudioSource>,
+	roar: Handle<AudioSource>,
+	death: Handle<AudioSource>,
+	game_music: Handle<AudioSource>,
+	game_over_music: Handle<AudioSource>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Music {
+	Game,
+	GameOver,
+}
+
+/// Music that is looping right now, so restarting a run doesn't restart the same track
+#[derive(Default)]
+struct CurrentMusic(Option<Music>);
+
+pub struct MusicParams {
+	/// Game music volume without any pressure, it rises to full volume with the intensity
+	pub calm_volume: f32,
+	/// Playback rate of the game music at full intensity, 1 is the original tempo
+	pub intense_rate: f32,
+	/// Intensity added by every boss phase after the first
+	pub phase_intensity: f32,
+	/// Intensity added by every live minion
+	pub minion_intensity: f32,
+	/// How quickly the music follows the intensity, per second
+	pub fade_speed: f32,
+	/// Music volume in the pause menu, relative to the volume while playing
+	pub pause_volume: f32,
+}
+
+impl Default for MusicParams {
+	fn default() -> Self {
+		Self {
+			calm_volume: 0.6,
+			intense_rate: 1.08,
+			phase_intensity: 0.35,
+			minion_intensity: 0.06,
+			fade_speed: 0.5,
+			pause_volume: 0.3,
+		}
+	}
+}
+
+impl MusicParams {
+	/// Intensity from 0 to 1 of a fight in the given boss phase against that many minions
+	fn intensity(&self, phase: usize, minions: usize) -> f32 {
+		(phase as f32 * self.phase_intensity + minions as f32 * self.minion_intensity).min(1.0)
+	}
+
+	/// Volume factor and playback rate of the game music at the given intensity
+	fn mix(&self, intensity: f32) -> (f32, f32) {
+		(
+			self.calm_volume + (1.0 - self.calm_volume) * intensity,
+			1.0 + (self.intense_rate - 1.0) * intensity,
+		)
+	}
+}
+
+/// Current intensity of the game music, and the volume and rate last sent to the music channel
+#[derive(Default)]
+struct MusicIntensity {
+	intensity: f32,
+	applied: Option<(f32, f32)>,
+}
+
+fn load_sounds(
+	asset_server: Res<AssetServer>,
+	mut sounds: ResMut<Sounds>,
+	mut loading: ResMut<LoadingAssets>,
+) {
+	*sounds = Sounds {
+		shot: asset_server.load("sound/shoot.wav"),
+		hit: asset_server.load("sound/hit.wav"),
+		roar: asset_server.load("sound/roar.wav"),
+		death: asset_server.load("sound/death.wav"),
+		game_music: asset_server.load("sound/MusicFull.wav"),
+		game_over_music: asset_server.load("sound/gameover.wav"),
+	};
+	for handle in [
+		&sounds.shot,
+		&sounds.hit,
+		&sounds.roar,
+		&sounds.death,
+		&sounds.game_music,
+		&sounds.game_over_music,
+	] {
+		loading.track(handle);
+	}
+}
+
+/// Fades the intensity towards how hard the fight is right now
+fn update_music_intensity(
+	q_bosses: Query<&BossPhase, With<Boss>>,
+	q_minions: Query<(), With<Minion>>,
+	params: Res<MusicParams>,
+	time: Res<Time>,
+	mut music: ResMut<MusicIntensity>,
+) {
+	let phase = q_bosses.iter().map(|BossPhase(phase)| *phase).max();
+	let target = params.intensity(phase.unwrap_or(0), q_minions.iter().count());
+	let step = params.fade_speed * time.delta_seconds();
+	music.intensity += (target - music.intensity).clamp(-step, step);
+}
+
+/// Sets the volume and playback rate of the music channel, only sending them to kira when they change
+fn apply_music_mix(
+	settings: Res<Settings>,
+	state: Res<State<GameState>>,
+	params: Res<MusicParams>,
+	current: Res<CurrentMusic>,
+	mut music: ResMut<MusicIntensity>,
+	audio: Res<Audio>,
+	channels: Res<AudioChannels>,
+) {
+	let (mut volume, rate) = match current.0 {
+		Some(Music::Game) => params.mix(music.intensity),
+		_ => (1.0, 1.0),
+	};
+	if *state.current() == GameState::Paused {
+		volume *= params.pause_volume;
+	}
+	volume *= settings.master_volume * settings.music_volume;
+
+	let changed = music
+		.applied
+		.map_or(true, |(applied_volume, applied_rate)| {
+			(volume - applied_volume).abs() > 0.005 || (rate - applied_rate).abs() > 0.001
+		});
+	if changed {
+		audio.set_volume_in_channel(volume, &channels.music);
+		audio.set_playback_rate_in_channel(rate, &channels.music);
+		music.applied = Some((volume, rate));
+	}
+}
+
+/// Turns shots of the player, damage, deaths and boss spawns into sounds
+fn sounds_from_game_events(
+	mut shoot_events: EventReader<ShootEvent>,
+	mut dealt_events: EventReader<DamageDealtEvent>,
+	mut death_events: EventReader<DeathEvent>,
+	mut boss_events: EventReader<BossSpawnEvent>,
+	q_players: Query<(), With<Player>>,
+	// boss parts are children, so only the global transform has their place in the arena
+	q_transforms: Query<&GlobalTransform>,
+	mut sound_ew: EventWriter<SoundEvent>,
+) {
+	let pos_of = |entity: Entity| {
+		q_transforms
+			.get(entity)
+			.ok()
+			.map(|transform| transform.translation.truncate())
+	};
+	// enemies fire too many bullets to give each of them a sound
+	for ShootEvent(team, pos, ..) in shoot_events.iter() {
+		if *team == Team::Player {
+			sound_ew.send(SoundEvent::at(Sound::Shot, *pos));
+		}
+	}
+	// only hits that took health, hazards hurt every tick and would drown out everything else
+	for DamageDealtEvent { target, source, .. } in dealt_events.iter() {
+		if *source == DamageSource::Environment {
+			continue;
+		}
+		sound_ew.send(SoundEvent {
+			sound: Sound::Hit,
+			pos: pos_of(*target),
+		});
+	}
+	for DeathEvent(entity) in death_events.iter() {
+		let sound = if q_players.get(*entity).is_ok() {
+			Sound::PlayerDeath
+		} else {
+			Sound::EnemyDeath
+		};
+		sound_ew.send(SoundEvent {
+			sound,
+			pos: pos_of(*entity),
+		});
+	}
+	for _ in boss_events.iter() {
+		sound_ew.send(SoundEvent::global(Sound::BossRoar));
+	}
+}
+
+/// Plays every kind of sound at most once per frame, so bursts don't stack up into noise. Of a burst
+/// the sound closest to the listener is played
+fn play_sounds(
+	mut sound_events: EventReader<SoundEvent>,
+	audio: Res<Audio>,
+	mut channels: ResMut<AudioChannels>,
+	sounds: Res<Sounds>,
+	settings: Res<Settings>,
+	params: Res<SpatialAudioParams>,
+	q_player: Query<&Transform, With<Player>>,
+	q_camera: Query<&Transform, With<MainCamera>>,
+) {
+	// the player hears the game, without one in the arena the camera does
+	let listener = q_player
+		.iter()
+		.chain(q_camera.iter())
+		.next()
+		.map(|transform| transform.translation.truncate());
+
+	let mut closest: Vec<(Sound, Vec2)> = vec![];
+	for SoundEvent { sound, pos } in sound_events.iter() {
+		let offset = match (settings.spatial_audio, pos, listener) {
+			(true, Some(pos), Some(listener)) => *pos - listener,
+			_ => Vec2::ZERO,
+		};
+		match closest.iter_mut().find(|(played, _)| played == sound) {
+			Some((_, nearest)) if offset.length_squared() < nearest.length_squared() => {
+				*nearest = offset
+			}
+			Some(_) => {}
+			None => closest.push((*sound, offset)),
+		}
+	}
+
+	for (sound, offset) in closest {
+		let handle = match sound {
+			Sound::Shot => &sounds.shot,
+			Sound::Hit => &sounds.hit,
+			Sound::BossRoar => &sounds.roar,
+			Sound::PlayerDeath | Sound::EnemyDeath => &sounds.death,
+		};
+		let (volume, panning) = params.spatialize(offset);
+		let channel = &channels.sfx[channels.next_sfx];
+		audio.set_volume_in_channel(
+			settings.master_volume * settings.sfx_volume * volume,
+			channel,
+		);
+		audio.set_panning_in_channel(panning, channel);
+		audio.play_in_channel(handle.clone(), channel);
+		channels.next_sfx = (channels.next_sfx + 1) % SFX_CHANNELS;
+	}
+}
+
+fn play_music(
+	music: Music,
+	audio: &Audio,
+	channels: &AudioChannels,
+	sounds: &Sounds,
+	current: &mut CurrentMusic,
+) {
+	if current.0 == Some(music) {
+		return;
+	}
+	current.0 = Some(music);
+	let handle = match music {
+		Music::Game => &sounds.game_music,
+		Music::GameOver => &sounds.game_over_music,
+	};
+	audio.stop_channel(&channels.music);
+	audio.play_looped_in_channel(handle.clone(), &channels.music);
+}
+
+/// A new run or level starts calm
+fn play_game_music(
+	audio: Res<Audio>,
+	channels: Res<AudioChannels>,
+	sounds: Res<Sounds>,
+	mut current: ResMut<CurrentMusic>,
+	mut music: ResMut<MusicIntensity>,
+) {
+	music.intensity = 0.0;
+	play_music(Music::Game, &audio, &channels, &sounds, &mut current);
+}
+
+fn play_game_over_music(
+	audio: Res<Audio>,
+	channels: Res<AudioChannels>,
+	sounds: Res<Sounds>,
+	mut current: ResMut<CurrentMusic>,
+) {
+	play_music(Music::GameOver, &audio, &channels, &sounds, &mut current);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn music_intensifies_with_the_fight() {
+		let params = MusicParams::default();
+		assert_eq!(params.intensity(0, 0), 0.0);
+		assert!(params.intensity(1, 0) > params.intensity(0, 0));
+		assert!(params.intensity(1, 3) > params.intensity(1, 0));
+		assert_eq!(params.intensity(2, 100), 1.0);
+
+		assert_eq!(params.mix(0.0), (params.calm_volume, 1.0));
+		assert_eq!(params.mix(1.0), (1.0, params.intense_rate));
+	}
+
+	#[test]
+	fn sounds_pan_and_fade_with_distance() {
+		let params = SpatialAudioParams::default();
+		assert_eq!(params.spatialize(Vec2::ZERO), (1.0, 0.5));
+
+		let (near_volume, near_panning) = params.spatialize(Vec2::new(100.0, 0.0));
+		assert_eq!(near_volume, 1.0);
+		assert!(near_panning > 0.5);
+
+		let (left_volume, left_panning) = params.spatialize(Vec2::new(-300.0, 0.0));
+		assert!(left_volume < 1.0);
+		assert!(left_panning < 0.5);
+
+		// far off-screen sounds are quiet but never silent or in one ear only
+		let (far_volume, far_panning) = params.spatialize(Vec2::new(5000.0, 0.0));
+		assert_eq!(far_volume, params.min_volume);
+		assert!(far_panning < 1.0);
+	}
+}
@@ -0,0 +1,188 @@
+use bevy::{math::Vec3Swizzles, prelude::*};
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+	drops::DamageBoost,
+	game::{DamageEvent, DamageSource, GameState, Health, Invulnerable},
+	input::{InputAction, MousePosition},
+	physics::PhysicsGlobals,
+	player::Player,
+	shooting::{BulletParams, Team, Weapon, WeaponKind, WeaponParams},
+};
+
+/// The beam weapon fires continuously while the fire button is held. A ray is cast from the player
+/// along the aim and the beam is drawn up to the first thing it hits, which takes the weapon's damage
+/// per second. The ray collides with what the player's bullets collide with, apart from other bullets
+pub struct BeamPlugin;
+
+impl Plugin for BeamPlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(BeamParams::default()).add_system_set(
+			SystemSet::on_update(GameState::Playing)
+				.with_system(fire_beam.after("input"))
+				.with_system(update_beams.before("apply_damage")),
+		);
+	}
+}
+
+pub struct BeamParams {
+	/// Pixels from the player's center
+	pub range: f32,
+	/// Pixels
+	pub width: f32,
+	/// Seconds between two damage ticks, the damage per second is `WeaponSpec::damage`
+	pub tick_secs: f32,
+}
+
+impl Default for BeamParams {
+	fn default() -> Self {
+		Self {
+			range: 500.0,
+			width: 6.0,
+			tick_secs: 0.1,
+		}
+	}
+}
+
+/// A beam in progress, removed once its owner lets go of the fire button or switches weapons
+#[derive(Component)]
+struct Beam {
+	owner: Entity,
+	tick: Timer,
+}
+
+const BEAM_COLOR: Color = Color::rgba(0.4, 0.9, 1.0, 0.8);
+
+/// Whether the player holds fire, unlike shots the beam isn't buffered
+fn holds_fire(mouse_input: &Input<MouseButton>, input: &InputAction) -> bool {
+	mouse_input.pressed(MouseButton::Left) || input.gamepad_fire
+}
+
+/// Starts a beam when the player holds fire with the beam weapon and stops it otherwise
+fn fire_beam(
+	mut commands: Commands,
+	mouse_input: Res<Input<MouseButton>>,
+	input: Res<InputAction>,
+	params: Res<BeamParams>,
+	q_player: Query<(Entity, &Weapon), With<Player>>,
+	q_beams: Query<(Entity, &Beam)>,
+) {
+	let firing = holds_fire(&mouse_input, &input);
+	for (player, Weapon(kind)) in q_player.iter() {
+		let beam = q_beams.iter().find(|(_, beam)| beam.owner == player);
+		match (beam, firing && *kind == WeaponKind::Beam) {
+			(None, true) => {
+				debug!("BEAM_START");
+				commands
+					.spawn_bundle(SpriteBundle {
+						sprite: Sprite {
+							color: BEAM_COLOR,
+							custom_size: Some(Vec2::ZERO),
+							..Default::default()
+						},
+						..Default::default()
+					})
+					.insert(Beam {
+						owner: player,
+						tick: Timer::from_seconds(params.tick_secs, true),
+					});
+			}
+			(Some((beam, _)), false) => commands.entity(beam).despawn_recursive(),
+			_ => {}
+		}
+	}
+	// the owner died
+	for (beam, Beam { owner, .. }) in q_beams.iter() {
+		if q_player.get(*owner).is_err() {
+			commands.entity(beam).despawn_recursive();
+		}
+	}
+}
+
+/// Casts the beams' rays, stretches them up to the hit and damages it every `tick_secs`
+fn update_beams(
+	mut q_beams: Query<(&mut Beam, &mut Sprite, &mut Transform)>,
+	q_owner: Query<(&Transform, Option<&DamageBoost>), (With<Player>, Without<Beam>)>,
+	q_health: Query<(), (With<Health>, Without<Invulnerable>)>,
+	q_parent: Query<&Parent>,
+	q_colliders: Query<&ColliderTypeComponent>,
+	mouse_pos: Res<MousePosition>,
+	input: Res<InputAction>,
+	(params, bullet_params, weapons): (Res<BeamParams>, Res<BulletParams>, Res<WeaponParams>),
+	rapier_config: Res<RapierConfiguration>,
+	physics_globals: Res<PhysicsGlobals>,
+	query_pipeline: Res<QueryPipeline>,
+	collider_query: QueryPipelineColliderComponentsQuery,
+	mut damage_ew: EventWriter<DamageEvent>,
+	time: Res<Time>,
+) {
+	let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+	let weapon = weapons.get(WeaponKind::Beam);
+	for (mut beam, mut sprite, mut transform) in q_beams.iter_mut() {
+		let (owner_t, boost) = match q_owner.get(beam.owner) {
+			Ok(owner) => owner,
+			Err(_) => continue,
+		};
+		let pos = owner_t.translation.xy();
+		let dir = input.aim.unwrap_or(mouse_pos.0 - pos).normalize_or_zero();
+		if dir == Vec2::ZERO {
+			continue;
+		}
+
+		// same masks as the player's bullets, but bullets, sensors and the owner don't stop the beam
+		let ignore_mask =
+			Team::Player.ignored_mask(bullet_params.friendly_fire(), &physics_globals);
+		let owner = beam.owner;
+		let filter = |handle: ColliderHandle| {
+			let entity = handle.entity();
+			entity != owner
+				&& q_colliders.get(entity).map_or(true, |collider_type| {
+					matches!(collider_type.0, ColliderType::Solid)
+				})
+		};
+		let ray = Ray::new((pos / rapier_config.scale).into(), dir.into());
+		let hit = query_pipeline.cast_ray(
+			&collider_set,
+			&ray,
+			params.range / rapier_config.scale,
+			true,
+			InteractionGroups::new(
+				physics_globals.bullet_mask,
+				u32::MAX - ignore_mask - physics_globals.bullet_mask,
+			),
+			Some(&filter),
+		);
+		let length = hit
+			.map(|(_, toi)| toi * rapier_config.scale)
+			.unwrap_or(params.range);
+
+		sprite.custom_size = Some(Vec2::new(length, params.width));
+		transform.translation = (pos + dir * length * 0.5).extend(-0.5);
+		transform.rotation = Quat::from_rotation_z(dir.y.atan2(dir.x));
+
+		beam.tick.tick(time.delta());
+		if !beam.tick.just_finished() {
+			continue;
+		}
+		// the hit collider either has health itself or is a part of a parent with health
+		let target = hit.map(|(handle, _)| handle.entity()).and_then(|hit_e| {
+			if q_health.get(hit_e).is_ok() {
+				Some(hit_e)
+			} else {
+				q_parent
+					.get(hit_e)
+					.ok()
+					.map(|Parent(parent_e)| *parent_e)
+					.filter(|parent_e| q_health.get(*parent_e).is_ok())
+			}
+		});
+		if let Some(target) = target {
+			let boost = boost.map_or(1.0, |boost| boost.multiplier);
+			damage_ew.send(DamageEvent {
+				target,
+				amount: weapon.damage * boost * beam.tick.duration().as_secs_f32(),
+				source: DamageSource::Bullet,
+			});
+		}
+	}
+}
@@ -0,0 +1,291 @@
+use std::f32::consts::PI;
+
+use bevy::{math::Vec3Swizzles, prelude::*};
+
+use crate::{
+	game::GameState,
+	player::Player,
+	shooting::{ShootEvent, Team, WeaponKind},
+};
+
+/// A chaos meter fills up during a run. Once full, a random disruptive event is telegraphed, runs for
+/// a while and is reverted again
+pub struct ChaosPlugin;
+
+impl Plugin for ChaosPlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(ChaosParams::default())
+			.insert_resource(ChaosDirector::default())
+			.insert_resource(ChaosEffects::default())
+			.add_system_set(
+				SystemSet::on_enter(GameState::Playing)
+					.with_system(reset_chaos)
+					.with_system(spawn_chaos_text),
+			)
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(direct_chaos.before("player_movement"))
+					.with_system(update_chaos_text),
+			);
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChaosEventKind {
+	/// Movement keys move the player the opposite way
+	InvertControls,
+	/// The player accelerates and brakes much slower
+	SlipperyFloor,
+	/// A ring of bullets closes in on the player
+	BulletStorm,
+	/// The walls move in, the player takes damage outside like in sudden death
+	ShrinkArena,
+}
+
+impl ChaosEventKind {
+	fn name(&self) -> &'static str {
+		match self {
+			ChaosEventKind::InvertControls => "CONTROLS INVERTED",
+			ChaosEventKind::SlipperyFloor => "SLIPPERY FLOOR",
+			ChaosEventKind::BulletStorm => "BULLET STORM",
+			ChaosEventKind::ShrinkArena => "ARENA SHRINKING",
+		}
+	}
+}
+
+pub struct ChaosEventSpec {
+	pub kind: ChaosEventKind,
+	/// Relative chance of being picked
+	pub weight: f32,
+	pub telegraph_secs: f32,
+	pub duration_secs: f32,
+}
+
+pub struct ChaosParams {
+	/// Seconds until the meter is full, it starts filling again once an event is over
+	pub fill_secs: f32,
+	pub events: Vec<ChaosEventSpec>,
+	/// Player acceleration and deceleration multiplier on a slippery floor
+	pub slippery_scale: f32,
+	pub storm_bullets: u32,
+	/// Distance in pixels from the player the storm's bullets start at
+	pub storm_radius: f32,
+	pub storm_speed: f32,
+	/// Pixels the walls move in, limited by `SuddenDeathParams::min_half_extents`
+	pub shrink_amount: f32,
+}
+
+impl Default for ChaosParams {
+	fn default() -> Self {
+		Self {
+			fill_secs: 45.0,
+			events: vec![
+				ChaosEventSpec {
+					kind: ChaosEventKind::InvertControls,
+					weight: 1.0,
+					telegraph_secs: 2.0,
+					duration_secs: 5.0,
+				},
+				ChaosEventSpec {
+					kind: ChaosEventKind::SlipperyFloor,
+					weight: 1.0,
+					telegraph_secs: 1.5,
+					duration_secs: 8.0,
+				},
+				ChaosEventSpec {
+					kind: ChaosEventKind::BulletStorm,
+					weight: 0.5,
+					telegraph_secs: 2.0,
+					duration_secs: 0.0,
+				},
+				ChaosEventSpec {
+					kind: ChaosEventKind::ShrinkArena,
+					weight: 0.5,
+					telegraph_secs: 2.0,
+					duration_secs: 6.0,
+				},
+			],
+			slippery_scale: 0.15,
+			storm_bullets: 24,
+			storm_radius: 180.0,
+			storm_speed: 0.6,
+			shrink_amount: 80.0,
+		}
+	}
+}
+
+/// Current state of the chaos meter and the running event
+#[derive(Default)]
+pub struct ChaosDirector {
+	/// 0 to 1
+	pub meter: f32,
+	pub event: Option<ChaosEvent>,
+}
+
+pub struct ChaosEvent {
+	pub kind: ChaosEventKind,
+	pub telegraph: Timer,
+	pub duration: Timer,
+}
+
+/// Modifiers other systems apply while an event runs. The default is the normal game
+pub struct ChaosEffects {
+	pub inverted_controls: bool,
+	pub acceleration_scale: f32,
+	/// Pixels the arena walls are moved in
+	pub arena_shrink: f32,
+}
+
+impl Default for ChaosEffects {
+	fn default() -> Self {
+		Self {
+			inverted_controls: false,
+			acceleration_scale: 1.0,
+			arena_shrink: 0.0,
+		}
+	}
+}
+
+/// Picks a random event by weight, None if there is nothing to pick from
+fn pick_event(events: &[ChaosEventSpec]) -> Option<&ChaosEventSpec> {
+	let total: f32 = events.iter().map(|spec| spec.weight.max(0.0)).sum();
+	if total <= 0.0 {
+		return None;
+	}
+	let mut roll = rand::random::<f32>() * total;
+	for spec in events.iter() {
+		roll -= spec.weight.max(0.0);
+		if roll < 0.0 {
+			return Some(spec);
+		}
+	}
+	events.last()
+}
+
+fn reset_chaos(mut director: ResMut<ChaosDirector>, mut effects: ResMut<ChaosEffects>) {
+	*director = ChaosDirector::default();
+	*effects = ChaosEffects::default();
+}
+
+/// Fills the meter, starts events once it is full and applies and reverts their effects
+fn direct_chaos(
+	params: Res<ChaosParams>,
+	mut director: ResMut<ChaosDirector>,
+	mut effects: ResMut<ChaosEffects>,
+	q_player_t: Query<&Transform, With<Player>>,
+	mut ev_shoot_writer: EventWriter<ShootEvent>,
+	time: Res<Time>,
+) {
+	let director = &mut *director;
+	let event = match director.event.as_mut() {
+		Some(event) => event,
+		None => {
+			director.meter += time.delta_seconds() / params.fill_secs.max(f32::EPSILON);
+			if director.meter >= 1.0 {
+				director.meter = 0.0;
+				if let Some(spec) = pick_event(&params.events) {
+					debug!("CHAOS {:?}", spec.kind);
+					director.event = Some(ChaosEvent {
+						kind: spec.kind,
+						telegraph: Timer::from_seconds(spec.telegraph_secs, false),
+						duration: Timer::from_seconds(spec.duration_secs, false),
+					});
+				}
+			}
+			return;
+		}
+	};
+
+	if !event.telegraph.finished() {
+		event.telegraph.tick(time.delta());
+		if !event.telegraph.just_finished() {
+			return;
+		}
+		match event.kind {
+			ChaosEventKind::InvertControls => effects.inverted_controls = true,
+			ChaosEventKind::SlipperyFloor => effects.acceleration_scale = params.slippery_scale,
+			ChaosEventKind::ShrinkArena => effects.arena_shrink = params.shrink_amount,
+			ChaosEventKind::BulletStorm => {
+				if let Ok(player_t) = q_player_t.get_single() {
+					let center = player_t.translation.xy();
+					for i in 0..params.storm_bullets {
+						let angle = i as f32 * 2.0 * PI / params.storm_bullets as f32;
+						let offset = Vec2::new(angle.cos(), angle.sin()) * params.storm_radius;
+						ev_shoot_writer.send(ShootEvent(
+							Team::Neutral,
+							center + offset,
+							-offset,
+							None,
+							params.storm_speed,
+							WeaponKind::Pistol,
+						));
+					}
+				}
+			}
+		}
+	}
+
+	event.duration.tick(time.delta());
+	if event.duration.finished() {
+		*effects = ChaosEffects::default();
+		director.event = None;
+	}
+}
+
+#[derive(Component)]
+struct ChaosText;
+
+/// Bottom center text with the meter, or the event that is coming up or running
+fn spawn_chaos_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+	commands
+		.spawn_bundle(NodeBundle {
+			style: Style {
+				size: Size::new(Val::Percent(100.0), Val::Auto),
+				position_type: PositionType::Absolute,
+				position: Rect {
+					bottom: Val::Px(20.0),
+					..Default::default()
+				},
+				justify_content: JustifyContent::Center,
+				..Default::default()
+			},
+			color: Color::NONE.into(),
+			..Default::default()
+		})
+		.with_children(|parent| {
+			parent
+				.spawn_bundle(TextBundle {
+					text: Text::with_section(
+						"",
+						TextStyle {
+							font: asset_server.load("fonts/PressStart2P-Regular.ttf"),
+							font_size: 12.0,
+							color: Color::rgb(0.9, 0.9, 0.9),
+						},
+						Default::default(),
+					),
+					..Default::default()
+				})
+				.insert(ChaosText);
+		});
+}
+
+fn update_chaos_text(director: Res<ChaosDirector>, mut q_text: Query<&mut Text, With<ChaosText>>) {
+	let (value, color) = match &director.event {
+		Some(event) if !event.telegraph.finished() => (
+			format!("{} INCOMING", event.kind.name()),
+			Color::rgb(1.0, 1.0, 0.3),
+		),
+		Some(event) => (event.kind.name().to_string(), Color::rgb(1.0, 0.3, 1.0)),
+		None => (
+			format!("CHAOS {:>3}%", (director.meter * 100.0) as u32),
+			Color::rgb(0.9, 0.9, 0.9),
+		),
+	};
+	for mut text in q_text.iter_mut() {
+		if text.sections[0].value != value {
+			text.sections[0].value = value.clone();
+			text.sections[0].style.color = color;
+		}
+	}
+}
@@ -0,0 +1,103 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_prototype_debug_lines::{DebugLines, DebugLinesPlugin};
+use bevy_rapier2d::prelude::*;
+
+use crate::settings::Settings;
+
+/// Draws the outline of every collider at its position in the physics world, toggled with F3.
+/// Only compiled with the `debug` feature
+pub struct ColliderDebugPlugin;
+
+impl Plugin for ColliderDebugPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_plugin(DebugLinesPlugin::default())
+			.insert_resource(ColliderDebug::default())
+			.add_system(toggle_collider_debug)
+			.add_system_to_stage(CoreStage::Last, draw_colliders);
+	}
+}
+
+#[derive(Default)]
+struct ColliderDebug {
+	enabled: bool,
+}
+
+/// Segments used to draw balls
+const BALL_SEGMENTS: usize = 16;
+
+/// The debug overlays setting switches all overlays at once, the keys each one on its own
+fn toggle_collider_debug(
+	keyboard_input: Res<Input<KeyCode>>,
+	settings: Res<Settings>,
+	mut debug: ResMut<ColliderDebug>,
+) {
+	if settings.is_changed() {
+		debug.enabled = settings.debug_overlays;
+	}
+	if keyboard_input.just_pressed(KeyCode::F3) {
+		debug.enabled = !debug.enabled;
+	}
+}
+
+/// Uses the collider positions instead of transforms, so wrong offsets between the two show up
+fn draw_colliders(
+	debug: Res<ColliderDebug>,
+	rapier_config: Res<RapierConfiguration>,
+	q_colliders: Query<(
+		&ColliderShapeComponent,
+		&ColliderPositionComponent,
+		&ColliderTypeComponent,
+	)>,
+	mut lines: ResMut<DebugLines>,
+) {
+	if !debug.enabled {
+		return;
+	}
+
+	for (shape, position, collider_type) in q_colliders.iter() {
+		let color = match collider_type.0 {
+			ColliderType::Solid => Color::GREEN,
+			ColliderType::Sensor => Color::YELLOW,
+		};
+		let iso = position.0 .0;
+		// local outline in physics units, closed by connecting the last point to the first
+		let outline: Vec<Vec2> = if let Some(cuboid) = shape.as_cuboid() {
+			let half = cuboid.half_extents;
+			vec![
+				Vec2::new(-half.x, -half.y),
+				Vec2::new(half.x, -half.y),
+				Vec2::new(half.x, half.y),
+				Vec2::new(-half.x, half.y),
+			]
+		} else if let Some(ball) = shape.as_ball() {
+			(0..BALL_SEGMENTS)
+				.map(|i| {
+					let angle = i as f32 / BALL_SEGMENTS as f32 * 2.0 * PI;
+					Vec2::new(angle.cos(), angle.sin()) * ball.radius
+				})
+				.collect()
+		} else {
+			// anything else only gets its bounding box
+			let aabb = shape.compute_local_aabb();
+			vec![
+				Vec2::new(aabb.mins.x, aabb.mins.y),
+				Vec2::new(aabb.maxs.x, aabb.mins.y),
+				Vec2::new(aabb.maxs.x, aabb.maxs.y),
+				Vec2::new(aabb.mins.x, aabb.maxs.y),
+			]
+		};
+
+		let world: Vec<Vec3> = outline
+			.iter()
+			.map(|local| {
+				let point = iso * Point::new(local.x, local.y);
+				Vec3::new(point.x, point.y, 0.0) * rapier_config.scale
+			})
+			.collect();
+		for i in 0..world.len() {
+			lines.line_colored(world[i], world[(i + 1) % world.len()], 0.0, color);
+		}
+	}
+}
@@ -0,0 +1,134 @@
+use bevy::{
+	asset::{AssetLoader, LoadContext, LoadedAsset},
+	prelude::*,
+	reflect::TypeUuid,
+	utils::BoxedFuture,
+};
+use serde::de::DeserializeOwned;
+
+use crate::{
+	animation::AnimationParams,
+	enemy::{EnemyParams, MinionParams},
+	loading::LoadingAssets,
+	player::PlayerParams,
+	shooting::BulletParams,
+};
+
+/// Loads the tuning params from `assets/config/*.config.ron`, so they can be balanced without
+/// recompiling. Files are reloaded when they change on disk. Fields missing in a file keep their
+/// built-in default, a file that fails to parse leaves the params untouched
+pub struct ConfigPlugin;
+
+impl Plugin for ConfigPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_asset::<ConfigFile>()
+			.init_asset_loader::<ConfigLoader>()
+			.insert_resource(ConfigHandles::default())
+			.add_startup_system(load_config)
+			.add_system(apply_config);
+	}
+}
+
+/// Raw text of a config file. It is only parsed once it's known which params it belongs to
+#[derive(TypeUuid)]
+#[uuid = "c3a1e7d4-6f2b-4b8e-9a57-1d0c4e8b3f92"]
+pub struct ConfigFile(String);
+
+#[derive(Default)]
+struct ConfigLoader;
+
+impl AssetLoader for ConfigLoader {
+	fn load<'a>(
+		&'a self,
+		bytes: &'a [u8],
+		load_context: &'a mut LoadContext,
+	) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+		Box::pin(async move {
+			let text = std::str::from_utf8(bytes)?.to_string();
+			load_context.set_default_asset(LoadedAsset::new(ConfigFile(text)));
+			Ok(())
+		})
+	}
+
+	fn extensions(&self) -> &[&str] {
+		&["config.ron"]
+	}
+}
+
+#[derive(Default)]
+struct ConfigHandles {
+	enemy: Handle<ConfigFile>,
+	minion: Handle<ConfigFile>,
+	bullet: Handle<ConfigFile>,
+	player: Handle<ConfigFile>,
+	animation: Handle<ConfigFile>,
+}
+
+fn load_config(
+	asset_server: Res<AssetServer>,
+	mut handles: ResMut<ConfigHandles>,
+	mut loading: ResMut<LoadingAssets>,
+) {
+	handles.enemy = asset_server.load("config/enemy.config.ron");
+	handles.minion = asset_server.load("config/minion.config.ron");
+	handles.bullet = asset_server.load("config/bullet.config.ron");
+	handles.player = asset_server.load("config/player.config.ron");
+	handles.animation = asset_server.load("config/animation.config.ron");
+	for handle in [
+		&handles.enemy,
+		&handles.minion,
+		&handles.bullet,
+		&handles.player,
+		&handles.animation,
+	] {
+		loading.track(handle);
+	}
+}
+
+/// Replaces the params of every config file that was loaded or changed
+fn apply_config(
+	mut asset_events: EventReader<AssetEvent<ConfigFile>>,
+	files: Res<Assets<ConfigFile>>,
+	handles: Res<ConfigHandles>,
+	mut enemy_params: ResMut<EnemyParams>,
+	mut minion_params: ResMut<MinionParams>,
+	mut bullet_params: ResMut<BulletParams>,
+	mut player_params: ResMut<PlayerParams>,
+	mut animation_params: ResMut<AnimationParams>,
+) {
+	for event in asset_events.iter() {
+		let handle = match event {
+			AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+			AssetEvent::Removed { .. } => continue,
+		};
+		let file = match files.get(handle) {
+			Some(file) => file,
+			None => continue,
+		};
+
+		if *handle == handles.enemy {
+			parse_params(file, "enemy", &mut *enemy_params);
+		} else if *handle == handles.minion {
+			parse_params(file, "minion", &mut *minion_params);
+		} else if *handle == handles.bullet {
+			parse_params(file, "bullet", &mut *bullet_params);
+		} else if *handle == handles.player {
+			parse_params(file, "player", &mut *player_params);
+		} else if *handle == handles.animation {
+			parse_params(file, "animation", &mut *animation_params);
+		}
+	}
+}
+
+fn parse_params<T: DeserializeOwned>(file: &ConfigFile, name: &str, params: &mut T) {
+	match ron::de::from_str(&file.0) {
+		Ok(parsed) => {
+			debug!("LOADED {} CONFIG", name.to_uppercase());
+			*params = parsed;
+		}
+		Err(err) => warn!(
+			"Keeping the current {} params, config failed to parse: {}",
+			name, err
+		),
+	}
+}
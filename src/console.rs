@@ -0,0 +1,176 @@
+use bevy::{prelude::*, window::ReceivedCharacter};
+
+use crate::{
+	enemy::{EnemyParams, Minion, MinionParams, WaveSpawner},
+	game::GameState,
+	settings::Settings,
+	shooting::{BulletParams, WeaponParams},
+	status_effects::StatusEffectParams,
+};
+
+/// Developer console to tweak params at runtime. Toggled with backtick, pauses the game while open
+/// with its own `GameState::Console`, so the settings screen doesn't take the keys.
+/// Only compiled with the `debug` feature
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(Console::default())
+			.add_system(toggle_console.label("toggle_console"))
+			.add_system(console_input.after("toggle_console"))
+			.add_system_set(SystemSet::on_exit(GameState::Console).with_system(close_console));
+	}
+}
+
+#[derive(Default)]
+struct Console {
+	open: bool,
+	input: String,
+	output: String,
+	text: Option<Entity>,
+}
+
+impl Console {
+	fn close(&mut self, commands: &mut Commands) {
+		self.open = false;
+		if let Some(text) = self.text.take() {
+			commands.entity(text).despawn_recursive();
+		}
+	}
+}
+
+const HELP: &str =
+	"set <enemy|minion|bullet|weapon|settings>.<field> <value> | spawn minion <count> | clear minions";
+
+fn toggle_console(
+	mut commands: Commands,
+	keyboard_input: Res<Input<KeyCode>>,
+	asset_server: Res<AssetServer>,
+	mut console: ResMut<Console>,
+	mut state: ResMut<State<GameState>>,
+) {
+	if !keyboard_input.just_pressed(KeyCode::Grave) {
+		return;
+	}
+
+	console.open = !console.open;
+	if console.open {
+		if matches!(state.current(), GameState::Playing | GameState::Paused) {
+			let _ = state.push(GameState::Console);
+		}
+		// spawned on demand, teardown despawns everything between runs
+		console.text = Some(
+			commands
+				.spawn_bundle(TextBundle {
+					style: Style {
+						position_type: PositionType::Absolute,
+						position: Rect {
+							top: Val::Px(10.0),
+							left: Val::Px(10.0),
+							..Default::default()
+						},
+						..Default::default()
+					},
+					text: Text::with_section(
+						"",
+						TextStyle {
+							font: asset_server.load("fonts/PressStart2P-Regular.ttf"),
+							font_size: 14.0,
+							color: Color::rgb(0.9, 0.9, 0.9),
+						},
+						Default::default(),
+					),
+					..Default::default()
+				})
+				.id(),
+		);
+		console.output = HELP.to_string();
+	} else if *state.current() == GameState::Console {
+		// `close_console` cleans up once the state is popped
+		let _ = state.pop();
+	} else {
+		console.close(&mut commands);
+	}
+}
+
+/// Also when something else than the console's key leaves the state
+fn close_console(mut commands: Commands, mut console: ResMut<Console>) {
+	console.close(&mut commands);
+}
+
+fn console_input(
+	mut commands: Commands,
+	mut char_events: EventReader<ReceivedCharacter>,
+	keyboard_input: Res<Input<KeyCode>>,
+	mut console: ResMut<Console>,
+	mut enemy_params: ResMut<EnemyParams>,
+	mut minion_params: ResMut<MinionParams>,
+	mut bullet_params: ResMut<BulletParams>,
+	mut weapon_params: ResMut<WeaponParams>,
+	mut status_params: ResMut<StatusEffectParams>,
+	mut settings: ResMut<Settings>,
+	mut wave_spawner: ResMut<WaveSpawner>,
+	q_minions: Query<Entity, With<Minion>>,
+	mut q_text: Query<&mut Text>,
+) {
+	if !console.open {
+		// drop whatever was typed while closed
+		for _ in char_events.iter() {}
+		return;
+	}
+
+	for ev in char_events.iter() {
+		if ev.char != '`' && !ev.char.is_control() {
+			console.input.push(ev.char);
+		}
+	}
+	if keyboard_input.just_pressed(KeyCode::Back) {
+		console.input.pop();
+	}
+	if keyboard_input.just_pressed(KeyCode::Return) {
+		let line = std::mem::take(&mut console.input);
+		let parts: Vec<&str> = line.split_whitespace().collect();
+		let result = match parts.as_slice() {
+			["set", path, value] => value
+				.parse::<f32>()
+				.map_err(|_| format!("not a number: {}", value))
+				.and_then(|value| {
+					let (target, field) = path
+						.split_once('.')
+						.ok_or_else(|| format!("expected <target>.<field>, got {}", path))?;
+					match target {
+						"enemy" => enemy_params.set_param(field, value),
+						"minion" => minion_params.set_param(field, value),
+						"bullet" => bullet_params.set_param(field, value),
+						"weapon" => weapon_params.set_param(field, value),
+						"status" => status_params.set_param(field, value),
+						"settings" => settings.set_param(field, value),
+						_ => Err(format!("unknown target {}", target)),
+					}
+					.map(|_| format!("{} = {}", path, value))
+				}),
+			["spawn", "minion", count] => count
+				.parse::<u32>()
+				.map_err(|_| format!("not a count: {}", count))
+				.map(|count| {
+					// queued like the minions of a wave
+					wave_spawner.pending += count;
+					format!("spawning {} minions", count)
+				}),
+			["clear", "minions"] => {
+				for minion in q_minions.iter() {
+					commands.entity(minion).despawn_recursive();
+				}
+				wave_spawner.pending = 0;
+				Ok("minions cleared".to_string())
+			}
+			["help"] => Ok(HELP.to_string()),
+			_ => Err(format!("unknown command: {}", line)),
+		};
+		console.output = result.unwrap_or_else(|err| err);
+	}
+
+	if let Some(mut text) = console.text.and_then(|text| q_text.get_mut(text).ok()) {
+		text.sections[0].value = format!("{}\n> {}_", console.output, console.input);
+	}
+}
@@ -0,0 +1,131 @@
+use bevy::{math::Vec3Swizzles, prelude::*};
+
+use crate::{
+	game::{DamageDealtEvent, DamageSource, GameState},
+	player::Player,
+	ui::UIParams,
+};
+
+/// Floating numbers over everything hit by a bullet that rise and fade out. Can be turned off with
+/// `UIParams::damage_numbers`
+pub struct DamageNumberPlugin;
+
+impl Plugin for DamageNumberPlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(DamageNumberParams::default())
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(spawn_damage_numbers.after("apply_damage"))
+					.with_system(animate_damage_numbers),
+			);
+	}
+}
+
+pub struct DamageNumberParams {
+	pub lifetime_secs: f32,
+	/// Pixels / sec the numbers rise with
+	pub rise_speed: f32,
+	pub font_size: f32,
+	/// Pixels above the hit entity the number starts at
+	pub offset: f32,
+}
+
+impl Default for DamageNumberParams {
+	fn default() -> Self {
+		Self {
+			lifetime_secs: 0.5,
+			rise_speed: 60.0,
+			font_size: 10.0,
+			offset: 12.0,
+		}
+	}
+}
+
+#[derive(Component)]
+struct DamageNumber {
+	lifetime: Timer,
+}
+
+fn spawn_damage_numbers(
+	mut commands: Commands,
+	mut dealt_events: EventReader<DamageDealtEvent>,
+	asset_server: Res<AssetServer>,
+	ui_params: Res<UIParams>,
+	params: Res<DamageNumberParams>,
+	q_transform: Query<&GlobalTransform>,
+	q_players: Query<&Player>,
+) {
+	if !ui_params.damage_numbers {
+		// drop the hits while turned off, so they don't all pop up once turned on
+		for _ in dealt_events.iter() {}
+		return;
+	}
+
+	for DamageDealtEvent {
+		target,
+		amount,
+		source,
+	} in dealt_events.iter()
+	{
+		if !matches!(
+			source,
+			DamageSource::Bullet
+				| DamageSource::Ricochet
+				| DamageSource::Melee
+				| DamageSource::Status
+		) || *amount <= 0.0
+		{
+			continue;
+		}
+		let pos = match q_transform.get(*target) {
+			Ok(transform) => transform.translation.xy(),
+			Err(_) => continue,
+		};
+		// damage the player takes stands out from damage dealt
+		let color = if q_players.get(*target).is_ok() {
+			Color::rgb(1.0, 0.3, 0.3)
+		} else {
+			Color::rgb(1.0, 1.0, 0.6)
+		};
+		commands
+			.spawn_bundle(Text2dBundle {
+				text: Text::with_section(
+					format!("{}", amount.round() as i32),
+					TextStyle {
+						font: asset_server.load("fonts/PressStart2P-Regular.ttf"),
+						font_size: params.font_size,
+						color,
+					},
+					TextAlignment {
+						vertical: VerticalAlign::Center,
+						horizontal: HorizontalAlign::Center,
+					},
+				),
+				transform: Transform::from_xyz(pos.x, pos.y + params.offset, 10.0),
+				..Default::default()
+			})
+			.insert(DamageNumber {
+				lifetime: Timer::from_seconds(params.lifetime_secs, false),
+			});
+	}
+}
+
+fn animate_damage_numbers(
+	mut commands: Commands,
+	mut q_numbers: Query<(Entity, &mut DamageNumber, &mut Transform, &mut Text)>,
+	params: Res<DamageNumberParams>,
+	time: Res<Time>,
+) {
+	for (entity, mut number, mut transform, mut text) in q_numbers.iter_mut() {
+		number.lifetime.tick(time.delta());
+		if number.lifetime.finished() {
+			commands.entity(entity).despawn();
+			continue;
+		}
+		transform.translation.y += params.rise_speed * time.delta_seconds();
+		text.sections[0]
+			.style
+			.color
+			.set_a(number.lifetime.percent_left());
+	}
+}
@@ -0,0 +1,311 @@
+use bevy::{math::Vec3Swizzles, prelude::*};
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+	enemy::Minion,
+	game::{DeathEvent, GameState, Health},
+	inventory::PlayerInventory,
+	physics::PhysicsGlobals,
+	player::{Player, PlayerParams},
+	shooting::{Ammo, WeaponKind, WeaponParams},
+	status_effects::{StatusEffectKind, StatusEffects},
+};
+
+/// Dying minions sometimes leave a pickup behind: a health pack, ammo, a temporary damage boost, a
+/// cleanse curing burning, slow and poison or a weapon for the inventory.
+/// Pickups are sensors, the player collects them by touching them
+pub struct DropsPlugin;
+
+impl Plugin for DropsPlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(DropParams::default()).add_system_set(
+			SystemSet::on_update(GameState::Playing)
+				.with_system(spawn_drops.after("detect_deaths"))
+				.with_system(collect_pickups)
+				.with_system(expire_pickups)
+				.with_system(tick_damage_boosts),
+		);
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PickupKind {
+	Health,
+	Ammo,
+	DamageBoost,
+	Cleanse,
+	/// Goes into a free inventory slot, stays on the ground while the inventory is full
+	Weapon(WeaponKind),
+}
+
+pub struct DropParams {
+	/// Chance from 0 to 1 that a dying minion drops anything
+	pub drop_chance: f32,
+	/// Relative chance of every pickup once something drops
+	pub health_weight: f32,
+	pub ammo_weight: f32,
+	pub damage_boost_weight: f32,
+	pub cleanse_weight: f32,
+	/// One of `WeaponKind::LOADOUTS`, picked at random
+	pub weapon_weight: f32,
+	/// Health restored, never above the player's start health
+	pub heal_amount: f32,
+	/// Full magazines added to the reserve of every weapon
+	pub ammo_amount: u32,
+	pub damage_boost_multiplier: f32,
+	/// Seconds
+	pub damage_boost_duration: f32,
+	/// Seconds before an uncollected pickup disappears
+	pub pickup_lifetime: f32,
+	/// In pixels
+	pub pickup_size: f32,
+}
+
+impl Default for DropParams {
+	fn default() -> Self {
+		Self {
+			drop_chance: 0.25,
+			health_weight: 3.0,
+			ammo_weight: 2.0,
+			damage_boost_weight: 1.0,
+			cleanse_weight: 1.0,
+			weapon_weight: 0.5,
+			heal_amount: 20.0,
+			ammo_amount: 3,
+			damage_boost_multiplier: 1.5,
+			damage_boost_duration: 8.0,
+			pickup_lifetime: 10.0,
+			pickup_size: 10.0,
+		}
+	}
+}
+
+impl DropParams {
+	/// Rolls the drop table, None when nothing drops
+	fn roll(&self) -> Option<PickupKind> {
+		if rand::random::<f32>() >= self.drop_chance {
+			return None;
+		}
+		let table = [
+			(PickupKind::Health, self.health_weight),
+			(PickupKind::Ammo, self.ammo_weight),
+			(PickupKind::DamageBoost, self.damage_boost_weight),
+			(PickupKind::Cleanse, self.cleanse_weight),
+			(
+				PickupKind::Weapon(
+					WeaponKind::LOADOUTS[rand::random::<usize>() % WeaponKind::LOADOUTS.len()],
+				),
+				self.weapon_weight,
+			),
+		];
+		let total: f32 = table.iter().map(|(_, weight)| weight.max(0.0)).sum();
+		let mut roll = rand::random::<f32>() * total;
+		for (kind, weight) in table {
+			if roll < weight.max(0.0) {
+				return Some(kind);
+			}
+			roll -= weight.max(0.0);
+		}
+		None
+	}
+}
+
+#[derive(Component)]
+pub struct Pickup {
+	pub kind: PickupKind,
+	lifetime: Timer,
+}
+
+/// Multiplies the damage of the player's bullets until the timer runs out
+#[derive(Component)]
+pub struct DamageBoost {
+	pub multiplier: f32,
+	timer: Timer,
+}
+
+fn spawn_drops(
+	mut commands: Commands,
+	mut death_events: EventReader<DeathEvent>,
+	q_minions: Query<&Transform, With<Minion>>,
+	params: Res<DropParams>,
+	rapier_config: Res<RapierConfiguration>,
+	physics_globals: Res<PhysicsGlobals>,
+) {
+	let mut dropped: Vec<Entity> = vec![];
+	for DeathEvent(entity) in death_events.iter() {
+		let transform = match q_minions.get(*entity) {
+			Ok(transform) if !dropped.contains(entity) => transform,
+			_ => continue,
+		};
+		dropped.push(*entity);
+		let kind = match params.roll() {
+			Some(kind) => kind,
+			None => continue,
+		};
+
+		debug!("DROP {:?}", kind);
+		spawn_pickup(
+			&mut commands,
+			kind,
+			transform.translation.xy(),
+			&params,
+			rapier_config.scale,
+			&physics_globals,
+		);
+	}
+}
+
+/// Spawns a pickup sensor at `pos` in pixels, also used for weapons the player drops
+pub fn spawn_pickup(
+	commands: &mut Commands,
+	kind: PickupKind,
+	pos: Vec2,
+	params: &DropParams,
+	rapier_scale: f32,
+	physics_globals: &PhysicsGlobals,
+) {
+	let color = match kind {
+		PickupKind::Health => Color::rgb(0.2, 0.9, 0.3),
+		PickupKind::Ammo => Color::rgb(0.9, 0.6, 0.2),
+		PickupKind::DamageBoost => Color::rgb(0.9, 0.2, 0.9),
+		PickupKind::Cleanse => Color::rgb(0.3, 0.8, 0.9),
+		PickupKind::Weapon(_) => Color::rgb(0.9, 0.9, 0.9),
+	};
+	commands
+		.spawn_bundle(SpriteBundle {
+			sprite: Sprite {
+				color,
+				custom_size: Some(Vec2::splat(params.pickup_size)),
+				..Default::default()
+			},
+			transform: Transform::from_xyz(pos.x, pos.y, -0.5),
+			..Default::default()
+		})
+		.insert_bundle(ColliderBundle {
+			collider_type: ColliderType::Sensor.into(),
+			position: (pos / rapier_scale).into(),
+			shape: ColliderShape::ball(params.pickup_size * 0.5 / rapier_scale).into(),
+			flags: ColliderFlags {
+				collision_groups: InteractionGroups::new(
+					physics_globals.scene_mask,
+					physics_globals.player_mask,
+				),
+				active_events: ActiveEvents::INTERSECTION_EVENTS,
+				..Default::default()
+			}
+			.into(),
+			..Default::default()
+		})
+		.insert(Pickup {
+			kind,
+			lifetime: Timer::from_seconds(params.pickup_lifetime, false),
+		});
+}
+
+/// Applies the effect of every pickup the player started touching and removes it
+fn collect_pickups(
+	mut commands: Commands,
+	mut intersection_events: EventReader<IntersectionEvent>,
+	q_pickups: Query<&Pickup>,
+	mut q_player: Query<
+		(
+			&mut Health,
+			&mut Ammo,
+			Option<&mut StatusEffects>,
+			Option<&mut PlayerInventory>,
+		),
+		With<Player>,
+	>,
+	params: Res<DropParams>,
+	player_params: Res<PlayerParams>,
+	weapons: Res<WeaponParams>,
+) {
+	let mut collected: Vec<Entity> = vec![];
+	for IntersectionEvent {
+		collider1,
+		collider2,
+		intersecting,
+	} in intersection_events.iter()
+	{
+		if !intersecting {
+			continue;
+		}
+		let (pickup_e, player_e) = if q_pickups.get(collider1.entity()).is_ok() {
+			(collider1.entity(), collider2.entity())
+		} else {
+			(collider2.entity(), collider1.entity())
+		};
+		let (pickup, (mut health, mut ammo, effects, inventory)) =
+			match (q_pickups.get(pickup_e), q_player.get_mut(player_e)) {
+				(Ok(pickup), Ok(player)) if !collected.contains(&pickup_e) => (pickup, player),
+				_ => continue,
+			};
+
+		debug!("PICKUP {:?}", pickup.kind);
+		match pickup.kind {
+			PickupKind::Health => {
+				health.0 = (health.0 + params.heal_amount).min(player_params.start_health);
+			}
+			PickupKind::Ammo => ammo.refill(&weapons, params.ammo_amount),
+			// collecting another boost restarts it
+			PickupKind::DamageBoost => {
+				commands.entity(player_e).insert(DamageBoost {
+					multiplier: params.damage_boost_multiplier,
+					timer: Timer::from_seconds(params.damage_boost_duration, false),
+				});
+			}
+			PickupKind::Cleanse => {
+				if let Some(mut effects) = effects {
+					effects.clear(&[
+						StatusEffectKind::Burn,
+						StatusEffectKind::Slow,
+						StatusEffectKind::Poison,
+					]);
+				}
+			}
+			// a weapon the player already carries counts as ammo
+			PickupKind::Weapon(kind) => match inventory {
+				Some(inventory) if inventory.contains(kind) => {
+					ammo.refill(&weapons, params.ammo_amount)
+				}
+				Some(mut inventory) => {
+					if !inventory.add(kind) {
+						continue;
+					}
+				}
+				None => continue,
+			},
+		}
+		collected.push(pickup_e);
+		commands.entity(pickup_e).despawn();
+	}
+}
+
+/// Pickups blink for their last 2 seconds and then disappear
+fn expire_pickups(
+	mut commands: Commands,
+	mut q_pickups: Query<(Entity, &mut Pickup, &mut Visibility)>,
+	time: Res<Time>,
+) {
+	for (entity, mut pickup, mut visibility) in q_pickups.iter_mut() {
+		pickup.lifetime.tick(time.delta());
+		if pickup.lifetime.finished() {
+			commands.entity(entity).despawn();
+			continue;
+		}
+		let left = pickup.lifetime.duration().as_secs_f32() - pickup.lifetime.elapsed_secs();
+		visibility.is_visible = left > 2.0 || (left * 8.0) as u32 % 2 == 0;
+	}
+}
+
+fn tick_damage_boosts(
+	mut commands: Commands,
+	mut q_boosts: Query<(Entity, &mut DamageBoost)>,
+	time: Res<Time>,
+) {
+	for (entity, mut boost) in q_boosts.iter_mut() {
+		if boost.timer.tick(time.delta()).finished() {
+			commands.entity(entity).remove::<DamageBoost>();
+		}
+	}
+}
@@ -1,15 +1,27 @@
-use std::f32::consts::PI;
+use std::{f32::consts::PI, time::Duration};
 
-use bevy::{math::Vec3Swizzles, prelude::*};
+use bevy::{math::Vec3Swizzles, prelude::*, sprite::MaterialMesh2dBundle};
 use bevy_inspector_egui::Inspectable;
 use bevy_rapier2d::{na::UnitComplex, prelude::*};
+use serde::Deserialize;
 
 use crate::{
-	game::{GameGlobals, GameState, Health},
-	physics::PhysicsGlobals,
+	attacks::{OrbitingShieldStage, Recoil},
+	audio::{Sound, SoundEvent},
+	game::{
+		BulletTime, DamageEvent, DamageImmunity, DamageSource, DeathEvent, GameClock, GameGlobals,
+		GameState, Health, Invulnerable,
+	},
+	input::{InputAction, MousePosition},
+	patterns::{BulletPattern, BulletPatterns},
+	physics::{approach_velocity, PhysicsGlobals, PhysicsMaterialParams},
 	player::Player,
-	shooting::ShootEvent,
-	waypoints::{CreatePathEvent, NextWaypoint},
+	scene::ArenaBounds,
+	score::{ScoreEvent, ScoreKind, ScoreParams},
+	shooting::{BulletParams, ShootEvent, Team, Weapon, WeaponKind, WeaponParams},
+	status_effects::{StatusEffectParams, StatusEffects},
+	telegraph::{TelegraphEvent, TelegraphShape, TELEGRAPH_COLOR},
+	waypoints::{CreatePathEvent, NextWaypoint, PathRequestThrottle, Waypoint},
 };
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
@@ -21,27 +33,70 @@ pub struct EnemyPlugin;
 
 impl Plugin for EnemyPlugin {
 	fn build(&self, app: &mut App) {
-		app.add_system_set(SystemSet::on_enter(GameState::Playing).with_system(spawn_boss))
-			.add_event::<BossSpawnEvent>()
-			.add_system_set(
-				SystemSet::on_update(GameState::Playing)
-					.with_system(enemy_movement)
-					.with_system(enemy_state_control)
-					.with_system(spawn_minions),
-			)
-			.insert_resource(EnemyParams::default())
-			.insert_resource(MinionParams::default());
+		app.add_system_set(
+			SystemSet::on_enter(GameState::Playing)
+				.with_system(spawn_boss.after("map"))
+				.with_system(reset_player_habits)
+				.with_system(reset_waves),
+		)
+		.add_event::<BossSpawnEvent>()
+		.add_event::<WaveStartedEvent>()
+		.add_system_set(
+			SystemSet::on_update(GameState::Playing)
+				.with_system(update_aggression.before("enemy_ai"))
+				.with_system(learn_player_habits.before("enemy_ai"))
+				.with_system(update_boss_phase.after("apply_damage").before("enemy_ai"))
+				.with_system(enemy_movement.label("enemy_ai"))
+				.with_system(enemy_state_control.label("enemy_ai"))
+				.with_system(flee_to_cover.label("enemy_ai"))
+				.with_system(predictive_dodge.before("enemy_ai"))
+				.with_system(unstick_enemies.after("enemy_ai"))
+				.with_system(teleport_enemies)
+				.with_system(tick_spawn_grace)
+				.with_system(cycle_boss_immunity.before("apply_damage"))
+				.with_system(run_waves.before("spawn_minions"))
+				.with_system(spawn_minions.label("spawn_minions"))
+				.with_system(minion_contacts.before("apply_damage"))
+				.with_system(contact_damage.before("apply_damage"))
+				.with_system(handle_enemy_deaths.after("detect_deaths"))
+				.with_system(destroy_boss_parts.after("detect_deaths")),
+		)
+		.insert_resource(EnemyParams::default())
+		.insert_resource(AggressionParams::default())
+		.insert_resource(Aggression::default())
+		.insert_resource(AdaptationParams::default())
+		.insert_resource(PlayerHabits::default())
+		.insert_resource(BossPhaseParams::default())
+		.insert_resource(MinionParams::default())
+		.insert_resource(WaveParams::default())
+		.insert_resource(WaveSpawner::default());
 		//.register_inspectable::<Enemy>()
 		//.add_plugin(InspectorPlugin::<EnemyParams>::new())
 	}
 }
 
 /// Values we might want to tweak and that are used to define specific properties of the entities.
-#[derive(Inspectable)]
+#[derive(Inspectable, Deserialize)]
+#[serde(default)]
 pub struct EnemyParams {
 	speed: f32,
+	/// Radians / sec enemies turn at most
+	turn_rate: f32,
+	/// Name of the bullet pattern in `assets/patterns/enemy.patterns.ron` the boss attacks with
+	attack_pattern: String,
+	/// Volleys of the pattern fired per attack, `attack_burst_interval` seconds apart.
+	/// The pattern's interval is the time between the starts of two bursts
+	attack_burst: u32,
+	attack_burst_interval: f32,
+	/// Seconds the first burst after the boss starts attacking is telegraphed, 0 fires right away
+	attack_windup: f32,
+	/// Pattern, burst and wind-up of ranged minions
+	minion_attack_pattern: String,
+	minion_attack_burst: u32,
+	minion_attack_burst_interval: f32,
+	minion_attack_windup: f32,
 	rot_offset: f32,
-	spawn_pos: Vec2,
+	pub spawn_pos: Vec2,
 	follow_threshold: f32,
 	attack_dist: f32,
 	visibility_dist: f32,
@@ -63,12 +118,92 @@ pub struct EnemyParams {
 	left_weapon_scale: Vec2,
 	right_weapon_pos: Vec2,
 	right_weapon_scale: Vec2,
+	/// Health of every arm, shield and weapon, see `BossPart`
+	arm_health: f32,
+	shield_health: f32,
+	weapon_health: f32,
+	/// Score for destroying a part
+	part_score: u32,
+	/// Only in the phases with `BossPhaseSpec::teleport`
+	teleport_cooldown: f32,
+	/// Seconds for the whole teleport, half fading out and half fading back in
+	teleport_duration: f32,
+	teleport_min_dist: f32,
+	teleport_max_dist: f32,
+	movement_mode: BossMovementMode,
+	/// Distance in pixels the boss keeps from the arena bounds while mirroring
+	mirror_wall_margin: f32,
+	/// Minions the boss splits into when killed, 0 keeps the boss alive and upgrades it instead
+	split_count: u32,
+	kill_score: u32,
+	/// Seconds the boss ignores damage after spawning
+	intro_invulnerability: f32,
+	/// Damage the boss deals to players it rams, at most once every `contact_cooldown` seconds
+	contact_damage: f32,
+	contact_cooldown: f32,
+	/// Pixels / sec a rammed player is pushed away with
+	contact_knockback: f32,
+	/// Seconds a freshly spawned boss stays idle before it can go after the player
+	spawn_grace: f32,
+	/// Pixels / sec² towards the target velocity, 0 for instant movement
+	acceleration: f32,
+	/// Pixels / sec² when slowing down, 0 for instantly stopping
+	deceleration: f32,
+	/// Pixels / sec below which a moving enemy counts as stuck
+	stuck_speed: f32,
+	/// Seconds an enemy has to be stuck before it gets nudged
+	stuck_time: f32,
+	/// Pixels / sec of the random push that frees a stuck enemy
+	nudge_strength: f32,
+	/// Minions aim where a moving player will be when the bullet arrives instead of where the player is.
+	/// The boss leads its shots per phase, see `BossPhaseSpec::lead_targets`
+	lead_targets: bool,
+	/// Pixels / sec an enemy bullet flies with before the speed of its pattern, weapon, minion level and
+	/// bullet time are applied. Every shot is led with its bullet's actual speed
+	lead_bullet_speed: f32,
+	/// 1 leads the target perfectly, lower values randomly under- or overshoot so shots stay dodgeable
+	accuracy: f32,
+	/// Level from which on the boss sidesteps when the player aims at it
+	dodge_min_level: u32,
+	/// Chance per second to dodge while being aimed at
+	dodge_chance: f32,
+	/// Radians between the player's aim and the boss that count as aiming at it
+	dodge_aim_angle: f32,
+	dodge_speed: f32,
+	dodge_duration: f32,
+	/// Seconds after a dodge before the boss can dodge again
+	dodge_cooldown: f32,
+	/// Level from which on the boss periodically becomes immune to everything but the current phase's
+	/// `BossPhaseSpec::immunity_except`
+	immunity_min_level: u32,
+	/// Seconds between immune phases
+	immunity_cooldown: f32,
+	immunity_duration: f32,
+	/// Idle enemies start chasing players closer than this many pixels
+	aggro_dist: f32,
+	/// Idle enemies patrol between waypoints within this many pixels of where they spawned
+	patrol_radius: f32,
+	/// Speed while patrolling, relative to `speed`
+	patrol_speed: f32,
+	/// Seconds between path requests of chasing enemies while their target stays put
+	path_interval: f32,
+	/// Pixels the target has to move since the last path request to request a new one right away
+	path_retarget_dist: f32,
 }
 
 impl Default for EnemyParams {
 	fn default() -> Self {
 		Self {
 			speed: 80.0,
+			turn_rate: 2.0 * PI,
+			attack_pattern: "single".to_string(),
+			attack_burst: 1,
+			attack_burst_interval: 0.1,
+			attack_windup: 0.5,
+			minion_attack_pattern: "aimed".to_string(),
+			minion_attack_burst: 3,
+			minion_attack_burst_interval: 0.1,
+			minion_attack_windup: 0.4,
 			rot_offset: -PI / 2.0,
 			attack_dist: 200.0,
 			start_health: 100.0,
@@ -95,56 +230,827 @@ impl Default for EnemyParams {
 			left_weapon_scale: Vec2::new(10.0, 30.0),
 			right_weapon_pos: Vec2::new(75.0, 20.0),
 			right_weapon_scale: Vec2::new(10.0, 30.0),
+			// destructible parts
+			arm_health: 40.0,
+			shield_health: 30.0,
+			weapon_health: 30.0,
+			part_score: 20,
+			// teleport
+			teleport_cooldown: 8.0,
+			teleport_duration: 1.2,
+			teleport_min_dist: 120.0,
+			teleport_max_dist: 250.0,
+			// movement
+			movement_mode: BossMovementMode::Pathfinding,
+			mirror_wall_margin: 100.0,
+			split_count: 0,
+			kill_score: 100,
+			intro_invulnerability: 2.0,
+			contact_damage: 10.0,
+			contact_cooldown: 0.75,
+			contact_knockback: 400.0,
+			spawn_grace: 1.5,
+			acceleration: 1500.0,
+			deceleration: 1500.0,
+			// anti-stuck
+			stuck_speed: 5.0,
+			stuck_time: 0.5,
+			nudge_strength: 150.0,
+			// target leading
+			lead_targets: false,
+			lead_bullet_speed: 600.0,
+			accuracy: 0.7,
+			// predictive dodge
+			dodge_min_level: 4,
+			dodge_chance: 0.8,
+			dodge_aim_angle: 0.15,
+			dodge_speed: 250.0,
+			dodge_duration: 0.3,
+			dodge_cooldown: 1.5,
+			// damage immunity, lure the boss into hazards to hurt it
+			immunity_min_level: 5,
+			immunity_cooldown: 20.0,
+			immunity_duration: 8.0,
+			// patrolling
+			aggro_dist: 300.0,
+			patrol_radius: 150.0,
+			patrol_speed: 0.5,
+			// path request throttling
+			path_interval: 0.25,
+			path_retarget_dist: 30.0,
 		}
 	}
 }
 
-#[derive(Inspectable)]
-struct MinionParams {
-	speed: f32,
+/// Sets numeric fields by name, used by the dev console
+#[cfg(feature = "debug")]
+impl EnemyParams {
+	pub fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+		match name {
+			"speed" => self.speed = value,
+			"turn_rate" => self.turn_rate = value,
+			"follow_threshold" => self.follow_threshold = value,
+			"attack_dist" => self.attack_dist = value,
+			"visibility_dist" => self.visibility_dist = value,
+			"start_health" => self.start_health = value,
+			"attack_burst" => self.attack_burst = value as u32,
+			"attack_burst_interval" => self.attack_burst_interval = value,
+			"minion_attack_burst" => self.minion_attack_burst = value as u32,
+			"minion_attack_burst_interval" => self.minion_attack_burst_interval = value,
+			"attack_windup" => self.attack_windup = value,
+			"minion_attack_windup" => self.minion_attack_windup = value,
+			"arm_health" => self.arm_health = value,
+			"shield_health" => self.shield_health = value,
+			"weapon_health" => self.weapon_health = value,
+			"part_score" => self.part_score = value as u32,
+			"teleport_cooldown" => self.teleport_cooldown = value,
+			"teleport_duration" => self.teleport_duration = value,
+			"teleport_min_dist" => self.teleport_min_dist = value,
+			"teleport_max_dist" => self.teleport_max_dist = value,
+			"split_count" => self.split_count = value as u32,
+			"kill_score" => self.kill_score = value as u32,
+			"intro_invulnerability" => self.intro_invulnerability = value,
+			"contact_damage" => self.contact_damage = value,
+			"contact_cooldown" => self.contact_cooldown = value,
+			"contact_knockback" => self.contact_knockback = value,
+			"spawn_grace" => self.spawn_grace = value,
+			"acceleration" => self.acceleration = value,
+			"deceleration" => self.deceleration = value,
+			"stuck_speed" => self.stuck_speed = value,
+			"stuck_time" => self.stuck_time = value,
+			"nudge_strength" => self.nudge_strength = value,
+			"lead_targets" => self.lead_targets = value != 0.0,
+			"lead_bullet_speed" => self.lead_bullet_speed = value,
+			"accuracy" => self.accuracy = value,
+			"dodge_min_level" => self.dodge_min_level = value as u32,
+			"dodge_chance" => self.dodge_chance = value,
+			"dodge_aim_angle" => self.dodge_aim_angle = value,
+			"dodge_speed" => self.dodge_speed = value,
+			"dodge_duration" => self.dodge_duration = value,
+			"dodge_cooldown" => self.dodge_cooldown = value,
+			"immunity_min_level" => self.immunity_min_level = value as u32,
+			"immunity_cooldown" => self.immunity_cooldown = value,
+			"immunity_duration" => self.immunity_duration = value,
+			"aggro_dist" => self.aggro_dist = value,
+			"patrol_radius" => self.patrol_radius = value,
+			"patrol_speed" => self.patrol_speed = value,
+			"path_interval" => self.path_interval = value,
+			"path_retarget_dist" => self.path_retarget_dist = value,
+			_ => return Err(format!("unknown field enemy.{}", name)),
+		}
+		Ok(())
+	}
+}
+
+#[derive(Inspectable, Deserialize)]
+#[serde(default)]
+pub struct MinionParams {
 	rot_offset: f32,
-	spawn_pos: Vec2,
 	follow_threshold: f32,
-	attack_dist: f32,
 	visibility_dist: f32,
-	start_health: f32,
-	body_scale: Vec2,
+	/// Kinds spawned minions are picked from by weight, see `MinionKind`
+	#[inspectable(ignore)]
+	kinds: Vec<MinionKindSpec>,
 	weapon_pos: Vec2,
 	weapon_scale: Vec2,
+	/// Max speed in pixels / sec minions scatter with when a boss splits, on top of the boss's velocity
+	split_scatter_speed: f32,
+	/// Minions run for cover once their health drops below this fraction of their start health
+	flee_health_ratio: f32,
+	/// Health / sec fleeing minions regenerate
+	flee_regen: f32,
+	/// Fleeing minions go back to fighting once healed up to this fraction of their start health
+	reengage_health_ratio: f32,
+	/// Every nth minion is a support minion shielding nearby enemies, 0 disables them
+	support_every: u32,
+	support_radius: f32,
+	/// Fraction of damage nearby enemies don't take
+	support_reduction: f32,
+	kill_score: u32,
+	/// Seconds a freshly spawned minion stays idle before it can go after the player
+	spawn_grace: f32,
+	/// Seconds between two hits of a melee minion touching the player
+	contact_cooldown: f32,
+	/// Pixels / sec a player hit by a melee minion is pushed away with
+	contact_knockback: f32,
 }
 
 impl Default for MinionParams {
 	fn default() -> Self {
 		Self {
-			speed: 160.0,
 			rot_offset: -PI / 2.0,
-			attack_dist: 140.0,
-			start_health: 50.0,
 			follow_threshold: 30.0,
 			visibility_dist: 400.0,
-			spawn_pos: Vec2::new(150.0, 0.0),
-			body_scale: Vec2::new(50.0, 50.0),
+			kinds: vec![
+				MinionKindSpec {
+					kind: MinionKind::Ranged,
+					weight: 2.0,
+					color: Color::RED,
+					body_scale: Vec2::new(50.0, 50.0),
+					speed: 80.0,
+					start_health: 50.0,
+					attack_dist: 200.0,
+					keep_dist: 120.0,
+					contact_damage: 0.0,
+					blast_radius: 0.0,
+				},
+				MinionKindSpec {
+					kind: MinionKind::Melee,
+					weight: 1.0,
+					color: Color::rgb(1.0, 0.5, 0.1),
+					body_scale: Vec2::new(40.0, 40.0),
+					speed: 150.0,
+					start_health: 70.0,
+					attack_dist: 120.0,
+					keep_dist: 0.0,
+					contact_damage: 15.0,
+					blast_radius: 0.0,
+				},
+				MinionKindSpec {
+					kind: MinionKind::Kamikaze,
+					weight: 1.0,
+					color: Color::rgb(1.0, 1.0, 0.2),
+					body_scale: Vec2::new(30.0, 30.0),
+					speed: 190.0,
+					start_health: 25.0,
+					attack_dist: 150.0,
+					keep_dist: 0.0,
+					contact_damage: 30.0,
+					blast_radius: 80.0,
+				},
+			],
 			weapon_pos: Vec2::new(-75.0, 20.0),
 			weapon_scale: Vec2::new(10.0, 30.0),
+			split_scatter_speed: 200.0,
+			flee_health_ratio: 0.3,
+			flee_regen: 5.0,
+			reengage_health_ratio: 0.8,
+			support_every: 4,
+			support_radius: 120.0,
+			support_reduction: 0.5,
+			kill_score: 10,
+			spawn_grace: 1.0,
+			contact_cooldown: 0.5,
+			contact_knockback: 250.0,
+		}
+	}
+}
+
+impl MinionParams {
+	/// Falls back to the first kind, or the built-in one, if `kind` isn't in the table
+	pub fn kind(&self, kind: MinionKind) -> &MinionKindSpec {
+		self.kinds
+			.iter()
+			.find(|spec| spec.kind == kind)
+			.or_else(|| self.kinds.first())
+			.unwrap_or(&DEFAULT_MINION_KIND)
+	}
+
+	/// Random kind by weight, ranged if there is nothing to pick from
+	fn pick_kind(&self) -> MinionKind {
+		let total: f32 = self.kinds.iter().map(|spec| spec.weight.max(0.0)).sum();
+		if total <= 0.0 {
+			return MinionKind::Ranged;
+		}
+		let mut roll = rand::random::<f32>() * total;
+		for spec in self.kinds.iter() {
+			roll -= spec.weight.max(0.0);
+			if roll < 0.0 {
+				return spec.kind;
+			}
+		}
+		self.kinds
+			.last()
+			.map_or(MinionKind::Ranged, |spec| spec.kind)
+	}
+}
+
+/// What a minion does once it is close enough to attack
+#[derive(Component, Inspectable, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum MinionKind {
+	/// Keeps its distance and shoots
+	Ranged,
+	/// Charges into the player and hurts on contact
+	Melee,
+	/// Charges into the player and explodes on contact, hurting everything around it
+	Kamikaze,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct MinionKindSpec {
+	pub kind: MinionKind,
+	/// Relative chance of being picked
+	pub weight: f32,
+	pub color: Color,
+	pub body_scale: Vec2,
+	/// Pixels / sec
+	pub speed: f32,
+	pub start_health: f32,
+	/// Distance in pixels from which on the minion attacks
+	pub attack_dist: f32,
+	/// Ranged minions back off from players closer than this many pixels
+	pub keep_dist: f32,
+	/// Damage on touching a player, for kamikazes the damage of the explosion
+	pub contact_damage: f32,
+	/// Pixels around an exploding kamikaze that take its damage, 0 only hurts the touched player
+	pub blast_radius: f32,
+}
+
+const DEFAULT_MINION_KIND: MinionKindSpec = MinionKindSpec {
+	kind: MinionKind::Ranged,
+	weight: 1.0,
+	color: Color::RED,
+	body_scale: Vec2::new(50.0, 50.0),
+	speed: 80.0,
+	start_health: 50.0,
+	attack_dist: 200.0,
+	keep_dist: 120.0,
+	contact_damage: 0.0,
+	blast_radius: 0.0,
+};
+
+/// Minions come in timed waves that grow with the level and with every wave of a run
+pub struct WaveParams {
+	/// Seconds from the start of a run until the first wave
+	pub first_wave_secs: f32,
+	/// Seconds between waves
+	pub interval_secs: f32,
+	pub base_size: u32,
+	/// Extra minions per wave for every level
+	pub size_per_level: u32,
+	/// Extra minions for every wave that came before in this run
+	pub size_per_wave: u32,
+	/// Minions waiting to spawn stay queued while this many are alive
+	pub max_alive: u32,
+	/// Distance in pixels of the spawn points to the arena bounds
+	pub edge_margin: f32,
+	/// Spawn points closer than this many pixels to the player are avoided
+	pub min_player_dist: f32,
+}
+
+impl Default for WaveParams {
+	fn default() -> Self {
+		Self {
+			first_wave_secs: 5.0,
+			interval_secs: 20.0,
+			base_size: 0,
+			size_per_level: 2,
+			size_per_wave: 1,
+			max_alive: 20,
+			edge_margin: 40.0,
+			min_player_dist: 150.0,
+		}
+	}
+}
+
+impl WaveParams {
+	fn size(&self, level: u32, wave: u32) -> u32 {
+		self.base_size + self.size_per_level * level + self.size_per_wave * wave
+	}
+}
+
+/// Progress of the waves in the current run
+pub struct WaveSpawner {
+	/// Waves started so far
+	pub wave: u32,
+	/// Minions of started waves that haven't spawned yet
+	pub pending: u32,
+	timer: Timer,
+}
+
+impl Default for WaveSpawner {
+	fn default() -> Self {
+		Self {
+			wave: 0,
+			pending: 0,
+			timer: Timer::from_seconds(WaveParams::default().first_wave_secs, false),
+		}
+	}
+}
+
+pub struct WaveStartedEvent {
+	/// Starts at 1
+	pub wave: u32,
+	pub size: u32,
+}
+
+/// How the boss moves while chasing or attacking the player
+#[derive(Inspectable, Deserialize, Clone, Copy, PartialEq)]
+pub enum BossMovementMode {
+	/// Follows the player along the waypoint graph
+	Pathfinding,
+	/// Moves in the opposite direction of the player
+	MirrorOpposite,
+	/// Mirrors the player's movement on the vertical axis, moving left when the player moves right
+	MirrorHorizontal,
+	/// Mirrors the player's movement on the horizontal axis, moving up when the player moves down
+	MirrorVertical,
+}
+
+/// Makes an enemy mirror the player's movement instead of pathfinding towards them
+#[derive(Component)]
+struct MirrorMovement(BossMovementMode);
+
+impl MirrorMovement {
+	fn apply(&self, player_vel: Vec2) -> Vec2 {
+		match self.0 {
+			BossMovementMode::Pathfinding => Vec2::ZERO,
+			BossMovementMode::MirrorOpposite => -player_vel,
+			BossMovementMode::MirrorHorizontal => Vec2::new(-player_vel.x, player_vel.y),
+			BossMovementMode::MirrorVertical => Vec2::new(player_vel.x, -player_vel.y),
+		}
+	}
+}
+
+/// How the boss' aggression rises over the fight
+#[derive(Inspectable)]
+pub enum AggressionCurve {
+	/// Rises steadily, reaching full aggression after `1 / rate` seconds
+	Linear,
+	/// Starts slow and speeds up, reaching full aggression after `ln(2) / rate` seconds
+	Exponential,
+}
+
+/// Values at full aggression. They are blended in from the base values while aggression rises
+#[derive(Inspectable)]
+pub struct AggressionParams {
+	curve: AggressionCurve,
+	rate: f32,
+	speed_multiplier: f32,
+	fire_rate_multiplier: f32,
+	/// Maximum angle in radians the boss' shots deviate from aiming straight at the player
+	max_spread: f32,
+}
+
+impl Default for AggressionParams {
+	fn default() -> Self {
+		Self {
+			curve: AggressionCurve::Linear,
+			rate: 1.0 / 180.0,
+			speed_multiplier: 1.5,
+			fire_rate_multiplier: 2.0,
+			max_spread: 0.3,
+		}
+	}
+}
+
+impl AggressionParams {
+	fn speed(&self, aggression: f32) -> f32 {
+		1.0 + (self.speed_multiplier - 1.0) * aggression
+	}
+
+	fn fire_rate(&self, aggression: f32) -> f32 {
+		1.0 + (self.fire_rate_multiplier - 1.0) * aggression
+	}
+
+	fn spread(&self, aggression: f32) -> f32 {
+		self.max_spread * aggression
+	}
+}
+
+/// How aggressive the boss currently is, from 0 at the start of a run up to 1
+#[derive(Default)]
+pub struct Aggression(pub f32);
+
+/// The boss gets faster, attacks from further away, shoots more often and switches to nastier bullet
+/// patterns the more health it loses
+pub struct BossPhaseParams {
+	/// Sorted by descending `health_ratio`, the first phase is the one the boss starts in
+	pub phases: Vec<BossPhaseSpec>,
+}
+
+pub struct BossPhaseSpec {
+	/// The phase starts once the boss' health drops to this fraction of `EnemyParams::start_health`
+	pub health_ratio: f32,
+	/// Multipliers of the boss' `EnemyParams`
+	pub speed: f32,
+	pub attack_dist: f32,
+	pub fire_rate: f32,
+	/// Bullet pattern replacing `EnemyParams::attack_pattern`
+	pub attack_pattern: Option<String>,
+	/// Whether the boss aims where a moving player will be, and how accurately, like
+	/// `EnemyParams::lead_targets` and `EnemyParams::accuracy` do for minions
+	pub lead_targets: bool,
+	pub accuracy: f32,
+	/// The only damage source that still hurts the boss while it's immune
+	pub immunity_except: DamageSource,
+	/// Whether the boss fires its rage beam, see `RageBeamParams`
+	pub rage_beam: bool,
+	/// Whether the boss teleports next to the player, see `TeleportAbility`
+	pub teleport: bool,
+	/// Segments the boss surrounds itself with, None for no orbiting shields
+	pub orbiting_shield: Option<OrbitingShieldStage>,
+}
+
+impl Default for BossPhaseParams {
+	fn default() -> Self {
+		let phase = |health_ratio, speed, attack_dist, fire_rate, attack_pattern: Option<&str>| {
+			BossPhaseSpec {
+				health_ratio,
+				speed,
+				attack_dist,
+				fire_rate,
+				attack_pattern: attack_pattern.map(String::from),
+				lead_targets: false,
+				accuracy: 0.7,
+				immunity_except: DamageSource::Environment,
+				rage_beam: false,
+				teleport: false,
+				orbiting_shield: None,
+			}
+		};
+		let shield = |count, radius, angular_velocity, lifetime_secs| {
+			Some(OrbitingShieldStage {
+				count,
+				radius,
+				angular_velocity,
+				lifetime_secs,
+			})
+		};
+		Self {
+			phases: vec![
+				phase(1.0, 1.0, 1.0, 1.0, None),
+				BossPhaseSpec {
+					orbiting_shield: shield(3, 110.0, 1.0, 5.0),
+					..phase(0.75, 1.15, 1.1, 1.2, Some("spread"))
+				},
+				BossPhaseSpec {
+					lead_targets: true,
+					teleport: true,
+					orbiting_shield: shield(3, 110.0, 1.0, 5.0),
+					..phase(0.5, 1.3, 1.2, 1.4, Some("burst"))
+				},
+				BossPhaseSpec {
+					lead_targets: true,
+					accuracy: 0.85,
+					immunity_except: DamageSource::Melee,
+					rage_beam: true,
+					teleport: true,
+					orbiting_shield: shield(5, 130.0, 1.6, 6.0),
+					..phase(0.25, 1.5, 1.3, 1.7, Some("spiral"))
+				},
+			],
+		}
+	}
+}
+
+impl BossPhaseParams {
+	pub fn get(&self, phase: Option<&BossPhase>) -> Option<&BossPhaseSpec> {
+		phase.and_then(|BossPhase(i)| self.phases.get(*i))
+	}
+
+	fn speed(&self, phase: Option<&BossPhase>) -> f32 {
+		self.get(phase).map_or(1.0, |spec| spec.speed)
+	}
+}
+
+/// Index into `BossPhaseParams::phases`
+#[derive(Component, Default)]
+pub struct BossPhase(pub usize);
+
+/// The boss learns where the player tends to move and shifts its aim that way
+#[derive(Inspectable)]
+pub struct AdaptationParams {
+	/// How quickly the learned habits follow the player's recent movement, per second
+	rate: f32,
+	/// 1 aims exactly where the player's usual movement would take them by the time the bullet arrives
+	strength: f32,
+	/// Maximum angle in radians the aim is shifted, so the player can still outplay the boss
+	max_bias: f32,
+}
+
+impl Default for AdaptationParams {
+	fn default() -> Self {
+		Self {
+			rate: 0.2,
+			strength: 0.8,
+			max_bias: 0.25,
+		}
+	}
+}
+
+impl AdaptationParams {
+	/// Shifts `dir` towards where the player usually moves, by at most `max_bias`
+	fn bias(&self, habits: &PlayerHabits, dir: Vec2, bullet_speed: f32) -> Vec2 {
+		let flight_time = dir.length() / bullet_speed.max(1.0);
+		let biased = dir + habits.velocity * flight_time * self.strength;
+		let angle = dir
+			.angle_between(biased)
+			.clamp(-self.max_bias, self.max_bias);
+		if angle.is_nan() {
+			return dir;
+		}
+		Mat2::from_angle(angle) * dir
+	}
+}
+
+/// The player's average velocity in pixels / sec over the run, weighted towards recent movement
+#[derive(Default)]
+pub struct PlayerHabits {
+	pub velocity: Vec2,
+}
+
+#[cfg(feature = "debug")]
+impl MinionParams {
+	/// Fields of a minion kind are set as `<kind>.<field>`, e.g. `kamikaze.blast_radius`
+	pub fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+		if let Some((kind, field)) = name.split_once('.') {
+			let kind = match kind {
+				"ranged" => MinionKind::Ranged,
+				"melee" => MinionKind::Melee,
+				"kamikaze" => MinionKind::Kamikaze,
+				_ => return Err(format!("unknown minion kind {}", kind)),
+			};
+			let spec = self
+				.kinds
+				.iter_mut()
+				.find(|spec| spec.kind == kind)
+				.ok_or_else(|| format!("no {:?} minions configured", kind))?;
+			match field {
+				"weight" => spec.weight = value,
+				"speed" => spec.speed = value,
+				"start_health" => spec.start_health = value,
+				"attack_dist" => spec.attack_dist = value,
+				"keep_dist" => spec.keep_dist = value,
+				"contact_damage" => spec.contact_damage = value,
+				"blast_radius" => spec.blast_radius = value,
+				_ => return Err(format!("unknown field minion.{}", name)),
+			}
+			return Ok(());
 		}
+		match name {
+			"follow_threshold" => self.follow_threshold = value,
+			"visibility_dist" => self.visibility_dist = value,
+			"flee_health_ratio" => self.flee_health_ratio = value,
+			"flee_regen" => self.flee_regen = value,
+			"reengage_health_ratio" => self.reengage_health_ratio = value,
+			"support_every" => self.support_every = value as u32,
+			"support_radius" => self.support_radius = value,
+			"support_reduction" => self.support_reduction = value,
+			"spawn_grace" => self.spawn_grace = value,
+			"contact_cooldown" => self.contact_cooldown = value,
+			"contact_knockback" => self.contact_knockback = value,
+			_ => return Err(format!("unknown field minion.{}", name)),
+		}
+		Ok(())
 	}
 }
 
 #[derive(Component, Inspectable)]
-pub struct Enemy(EnemyState);
+pub struct Enemy(pub EnemyState);
+
+/// Limits how often an enemy shoots while attacking. Every time the timer finishes, a burst of
+/// volleys of the enemy's bullet pattern is fired, `burst_interval` seconds apart.
+/// Enemies without it never shoot
+#[derive(Component)]
+struct FireControl {
+	/// Runs for the pattern's interval between the starts of two bursts
+	timer: Timer,
+	burst_timer: Timer,
+	/// Volleys left in the current burst
+	burst_left: u32,
+	/// Volleys fired so far, used to rotate the pattern
+	volleys: u32,
+	/// Whether the enemy kept attacking since the last wind-up
+	engaged: bool,
+	/// Runs while the first burst is telegraphed, it goes off once the timer finished
+	windup: Option<Timer>,
+}
+
+impl Default for FireControl {
+	fn default() -> Self {
+		Self {
+			// the durations are set from the params when attacking
+			timer: Timer::from_seconds(1.0, true),
+			burst_timer: Timer::from_seconds(0.1, false),
+			burst_left: 0,
+			volleys: 0,
+			engaged: false,
+			windup: None,
+		}
+	}
+}
+
+impl FireControl {
+	/// Holds fire for `secs`, then starts a burst right away
+	fn wind_up(&mut self, secs: f32) {
+		self.burst_left = 0;
+		self.windup = Some(Timer::from_seconds(secs, false));
+	}
+
+	fn start_burst(&mut self, burst: u32) {
+		self.burst_left = burst.max(1);
+		// the first volley of a burst goes off right away
+		let duration = self.burst_timer.duration();
+		self.burst_timer.tick(duration);
+	}
+
+	/// Advances the timers by `delta`, true when the next volley is due
+	fn tick(&mut self, delta: Duration, interval: f32, burst: u32, burst_interval: f32) -> bool {
+		self.timer.set_duration(Duration::from_secs_f32(interval));
+		self.burst_timer
+			.set_duration(Duration::from_secs_f32(burst_interval));
+		if let Some(windup) = self.windup.as_mut() {
+			windup.tick(delta);
+			if !windup.finished() {
+				return false;
+			}
+			self.windup = None;
+			self.timer.reset();
+			self.start_burst(burst);
+		} else {
+			self.timer.tick(delta);
+			self.burst_timer.tick(delta);
+			if self.timer.just_finished() {
+				self.start_burst(burst);
+			}
+		}
+		if self.burst_left > 0 && self.burst_timer.finished() {
+			self.burst_left -= 1;
+			self.burst_timer.reset();
+			return true;
+		}
+		false
+	}
+}
 
 #[derive(Component)]
 pub struct Boss;
 
+/// Child of the boss with its own health. Bullets hitting a part damage the part instead of the boss,
+/// destroyed parts are removed and score
+#[derive(Component, Clone, Copy, PartialEq, Debug)]
+pub enum BossPart {
+	LeftArm,
+	RightArm,
+	LeftShield,
+	RightShield,
+	LeftWeapon,
+	RightWeapon,
+}
+
+impl BossPart {
+	/// Weapon an arm holds, it is destroyed along with the arm
+	fn weapon(&self) -> Option<BossPart> {
+		match self {
+			BossPart::LeftArm => Some(BossPart::LeftWeapon),
+			BossPart::RightArm => Some(BossPart::RightWeapon),
+			_ => None,
+		}
+	}
+}
+
+/// Child of the boss drawing its body, the parts are attached around it
+#[derive(Component)]
+pub struct BossBody;
+
 #[derive(Component)]
 pub struct Minion;
 
+/// Instead of dying, the enemy splits into `count` minions that keep fighting
+#[derive(Component)]
+pub struct SplitOnDeath {
+	pub count: u32,
+}
+
+/// Other enemies within `radius` pixels take `reduction` (0..1) less damage while this enemy lives
+#[derive(Component)]
+pub struct SupportAura {
+	pub radius: f32,
+	pub reduction: f32,
+}
+
+/// Sidestep out of the player's aim. The enemy moves with `velocity` (pixels / sec) until `moving` finished,
+/// the component is removed once `cooldown` finished as well
+#[derive(Component)]
+struct Dodge {
+	velocity: Vec2,
+	moving: Timer,
+	cooldown: Timer,
+}
+
+/// Tracks how long an enemy that should be moving barely moved
+#[derive(Component, Default)]
+struct StuckDetector {
+	last_pos: Option<Vec2>,
+	stuck_for: f32,
+}
+
+impl StuckDetector {
+	/// Takes the enemy's position after `dt` more seconds, and whether it should be moving. Returns
+	/// whether it moved slower than `stuck_speed` for `stuck_time` seconds and needs a nudge, which
+	/// starts the timer over
+	fn update(
+		&mut self,
+		pos: Vec2,
+		moving: bool,
+		dt: f32,
+		stuck_speed: f32,
+		stuck_time: f32,
+	) -> bool {
+		let last_pos = self.last_pos.replace(pos);
+		let moved = last_pos.map_or(f32::MAX, |last_pos| last_pos.distance(pos));
+		if !moving || dt <= 0.0 || moved / dt >= stuck_speed {
+			self.stuck_for = 0.0;
+			return false;
+		}
+
+		self.stuck_for += dt;
+		if self.stuck_for < stuck_time {
+			return false;
+		}
+		self.stuck_for = 0.0;
+		true
+	}
+}
+
+/// Cover waypoint a fleeing enemy is heading to. Without one, the enemy runs straight away from the player
+#[derive(Component)]
+struct Fleeing {
+	cover: Option<Vec2>,
+}
+
+/// Minion spawned by a `SplitOnDeath` enemy. The level is only over once all of them are dead
+#[derive(Component)]
+struct SplitMinion;
+
+/// Lets an enemy teleport next to its target once the cooldown finished
+#[derive(Component)]
+struct TeleportAbility {
+	cooldown: Timer,
+}
+
+/// Attached while an enemy is teleporting. The destination is telegraphed by a separate sprite entity
+#[derive(Component)]
+struct Teleport {
+	timer: Timer,
+	destination: Vec2,
+	telegraph: Entity,
+	relocated: bool,
+}
+
+/// Idle enemies walk between random waypoints around `home` until a player comes into aggro range
+#[derive(Component)]
+pub struct Patrol {
+	home: Vec2,
+	target: Option<Vec2>,
+}
+
+impl Patrol {
+	fn new(home: Vec2) -> Self {
+		Self { home, target: None }
+	}
+}
+
 #[derive(Inspectable, Debug)]
 pub enum EnemyState {
 	IDLE,
-	FLEEING,
+	FLEEING(Option<Entity>),
 	CHASING(Option<Entity>),
 	ATTACK(Option<Entity>),
+	TELEPORTING(Option<Entity>),
+	/// Busy with one of the boss' special attacks, see `attacks::SpecialAttack`
+	SPECIAL(Option<Entity>),
 }
 
 impl Default for EnemyState {
@@ -153,20 +1059,38 @@ impl Default for EnemyState {
 	}
 }
 
-fn spawn_boss(
+pub fn spawn_boss(
 	mut commands: Commands,
 	params: Res<EnemyParams>,
 	rapier_config: ResMut<RapierConfiguration>,
 	physics_globals: Res<PhysicsGlobals>,
+	materials: Res<PhysicsMaterialParams>,
 	mut ev_writer: EventWriter<BossSpawnEvent>,
 ) {
+	build_boss(
+		&mut commands,
+		&params,
+		&rapier_config,
+		&physics_globals,
+		&materials,
+	);
+	ev_writer.send(BossSpawnEvent);
+}
+
+fn build_boss(
+	commands: &mut Commands,
+	params: &EnemyParams,
+	rapier_config: &RapierConfiguration,
+	physics_globals: &PhysicsGlobals,
+	materials: &PhysicsMaterialParams,
+) -> Entity {
 	let collider_flags = ColliderFlags {
 		collision_groups: InteractionGroups::new(physics_globals.enemy_mask, u32::MAX),
 		..Default::default()
 	};
 
-	info!("SPAWN_BOSS");
-	commands
+	debug!("SPAWN_BOSS");
+	let boss = commands
 		.spawn_bundle(RigidBodyBundle {
 			position: (params.spawn_pos / rapier_config.scale).into(),
 			..Default::default()
@@ -189,9 +1113,11 @@ fn spawn_boss(
 					},
 					..Default::default()
 				})
+				.insert(BossBody)
 				.insert(ColliderPositionSync::Discrete)
 				.insert_bundle(ColliderBundle {
 					flags: collider_flags.clone().into(),
+					material: materials.entity(),
 					position: Vec2::ZERO.into(),
 					// Since the physics world is scaled, we divide pixel size by it to get the collider size
 					shape: ColliderShapeComponent(ColliderShape::cuboid(
@@ -214,6 +1140,7 @@ fn spawn_boss(
 				.insert(ColliderPositionSync::Discrete)
 				.insert_bundle(ColliderBundle {
 					flags: collider_flags.clone().into(),
+					material: materials.entity(),
 					position: (
 						params.left_arm_pos / rapier_config.scale,
 						params.left_arm_rot,
@@ -225,7 +1152,9 @@ fn spawn_boss(
 						params.left_arm_scale.y * 0.5 / rapier_config.scale,
 					)),
 					..Default::default()
-				});
+				})
+				.insert(BossPart::LeftArm)
+				.insert(Health(params.arm_health));
 
 			// right arm
 			parent
@@ -240,6 +1169,7 @@ fn spawn_boss(
 				.insert(ColliderPositionSync::Discrete)
 				.insert_bundle(ColliderBundle {
 					flags: collider_flags.clone().into(),
+					material: materials.entity(),
 					position: (
 						params.right_arm_pos / rapier_config.scale,
 						params.right_arm_rot,
@@ -251,7 +1181,9 @@ fn spawn_boss(
 						params.right_arm_scale.y * 0.5 / rapier_config.scale,
 					)),
 					..Default::default()
-				});
+				})
+				.insert(BossPart::RightArm)
+				.insert(Health(params.arm_health));
 
 			// left shield
 			parent
@@ -266,6 +1198,7 @@ fn spawn_boss(
 				.insert(ColliderPositionSync::Discrete)
 				.insert_bundle(ColliderBundle {
 					flags: collider_flags.clone().into(),
+					material: materials.entity(),
 					position: (
 						params.left_shield_pos / rapier_config.scale,
 						params.left_shield_rot,
@@ -277,7 +1210,9 @@ fn spawn_boss(
 						params.left_shield_scale.y * 0.5 / rapier_config.scale,
 					)),
 					..Default::default()
-				});
+				})
+				.insert(BossPart::LeftShield)
+				.insert(Health(params.shield_health));
 
 			// right shield
 			parent
@@ -292,6 +1227,7 @@ fn spawn_boss(
 				.insert(ColliderPositionSync::Discrete)
 				.insert_bundle(ColliderBundle {
 					flags: collider_flags.clone().into(),
+					material: materials.entity(),
 					position: (
 						params.right_shield_pos / rapier_config.scale,
 						params.right_shield_rot,
@@ -303,7 +1239,9 @@ fn spawn_boss(
 						params.right_shield_scale.y * 0.5 / rapier_config.scale,
 					)),
 					..Default::default()
-				});
+				})
+				.insert(BossPart::RightShield)
+				.insert(Health(params.shield_health));
 
 			// left weapon
 			parent
@@ -318,6 +1256,7 @@ fn spawn_boss(
 				.insert(ColliderPositionSync::Discrete)
 				.insert_bundle(ColliderBundle {
 					flags: collider_flags.clone().into(),
+					material: materials.entity(),
 					position: (params.left_weapon_pos / rapier_config.scale).into(),
 					// Since the physics world is scaled, we divide pixel size by it to get the collider size
 					shape: ColliderShapeComponent(ColliderShape::cuboid(
@@ -325,7 +1264,9 @@ fn spawn_boss(
 						params.left_weapon_scale.y * 0.5 / rapier_config.scale,
 					)),
 					..Default::default()
-				});
+				})
+				.insert(BossPart::LeftWeapon)
+				.insert(Health(params.weapon_health));
 
 			// right weapon
 			parent
@@ -340,6 +1281,7 @@ fn spawn_boss(
 				.insert(ColliderPositionSync::Discrete)
 				.insert_bundle(ColliderBundle {
 					flags: collider_flags.into(),
+					material: materials.entity(),
 					position: (params.right_weapon_pos / rapier_config.scale).into(),
 					// Since the physics world is scaled, we divide pixel size by it to get the collider size
 					shape: ColliderShapeComponent(ColliderShape::cuboid(
@@ -347,39 +1289,209 @@ fn spawn_boss(
 						params.right_weapon_scale.y * 0.5 / rapier_config.scale,
 					)),
 					..Default::default()
-				});
+				})
+				.insert(BossPart::RightWeapon)
+				.insert(Health(params.weapon_health));
 		})
 		.insert(Enemy(EnemyState::IDLE))
+		.insert(Patrol::new(params.spawn_pos))
+		.insert(StuckDetector::default())
+		.insert(PathRequestThrottle::default())
+		.insert(FireControl::default())
 		.insert(Boss)
+		.insert(BossPhase::default())
 		.insert(Health(params.start_health))
+		.insert(ContactDamage::new(
+			params.contact_damage,
+			params.contact_cooldown,
+			params.contact_knockback,
+		))
+		.insert(TeleportAbility {
+			cooldown: Timer::from_seconds(params.teleport_cooldown, false),
+		})
+		.insert(ImmunityCycle {
+			timer: Timer::from_seconds(params.immunity_cooldown, false),
+		})
 		.id();
 
-	ev_writer.send(BossSpawnEvent);
+	if params.movement_mode != BossMovementMode::Pathfinding {
+		commands
+			.entity(boss)
+			.insert(MirrorMovement(params.movement_mode));
+	}
+
+	if params.intro_invulnerability > 0.0 {
+		let shield = commands
+			.spawn_bundle(SpriteBundle {
+				sprite: Sprite {
+					custom_size: Some(params.body_scale * 2.0),
+					color: Color::rgba(0.3, 0.6, 1.0, 0.5),
+					..Default::default()
+				},
+				transform: Transform::from_xyz(0.0, 0.0, 1.0),
+				..Default::default()
+			})
+			.id();
+		commands
+			.entity(boss)
+			.push_children(&[shield])
+			.insert(Invulnerable {
+				timer: Timer::from_seconds(params.intro_invulnerability, false),
+				shield: Some(shield),
+			});
+	}
+
+	add_spawn_grace(&mut commands, boss, params.spawn_grace, params.body_scale);
+
+	if params.split_count > 0 {
+		commands.entity(boss).insert(SplitOnDeath {
+			count: params.split_count,
+		});
+	}
+
+	boss
+}
+
+fn reset_waves(mut spawner: ResMut<WaveSpawner>, params: Res<WaveParams>) {
+	*spawner = WaveSpawner {
+		wave: 0,
+		pending: 0,
+		timer: Timer::from_seconds(params.first_wave_secs, false),
+	};
+}
+
+/// Starts a new wave whenever the timer runs out, its minions are queued for `spawn_minions`
+fn run_waves(
+	mut spawner: ResMut<WaveSpawner>,
+	params: Res<WaveParams>,
+	game_globals: Res<GameGlobals>,
+	mut wave_ew: EventWriter<WaveStartedEvent>,
+	time: Res<Time>,
+) {
+	spawner.timer.tick(time.delta());
+	if !spawner.timer.finished() {
+		return;
+	}
+	let size = params.size(game_globals.level, spawner.wave);
+	spawner.wave += 1;
+	spawner.pending += size;
+	spawner.timer = Timer::from_seconds(params.interval_secs, false);
+	debug!("WAVE {} WITH {} MINIONS", spawner.wave, size);
+	wave_ew.send(WaveStartedEvent {
+		wave: spawner.wave,
+		size,
+	});
+}
+
+/// Random point along the arena edges that isn't right next to the player, if one is found
+fn edge_spawn_point(half_extents: Vec2, params: &WaveParams, player: Option<Vec2>) -> Vec2 {
+	let extents = (half_extents - Vec2::splat(params.edge_margin)).max(Vec2::ZERO);
+	let mut point = Vec2::ZERO;
+	for _ in 0..8 {
+		let t = rand::random::<f32>() * 2.0 - 1.0;
+		point = match rand::random::<u8>() % 4 {
+			0 => Vec2::new(-extents.x, t * extents.y),
+			1 => Vec2::new(extents.x, t * extents.y),
+			2 => Vec2::new(t * extents.x, -extents.y),
+			_ => Vec2::new(t * extents.x, extents.y),
+		};
+		if player.map_or(true, |player| {
+			player.distance(point) >= params.min_player_dist
+		}) {
+			break;
+		}
+	}
+	point
 }
 
+/// Spawns one queued minion per frame at the arena edges
 fn spawn_minions(
 	mut commands: Commands,
 	params: Res<MinionParams>,
-	game_globals: Res<GameGlobals>,
+	(mut spawner, wave_params, bounds): (ResMut<WaveSpawner>, Res<WaveParams>, Res<ArenaBounds>),
 	rapier_config: ResMut<RapierConfiguration>,
 	physics_globals: Res<PhysicsGlobals>,
-	q_minions: Query<&Minion>,
+	materials: Res<PhysicsMaterialParams>,
+	mut meshes: ResMut<Assets<Mesh>>,
+	mut color_materials: ResMut<Assets<ColorMaterial>>,
+	q_minions: Query<Option<&SupportAura>, With<Minion>>,
+	q_player_t: Query<&Transform, With<Player>>,
 	mut ev_writer: EventWriter<BossSpawnEvent>,
-	_time: Res<Time>,
 ) {
-	if q_minions.iter().count() as u32 >= game_globals.minions {
+	let count = q_minions.iter().count() as u32;
+	if spawner.pending == 0 || count >= wave_params.max_alive {
 		return;
 	}
+	spawner.pending -= 1;
+
+	let player_pos = q_player_t.get_single().ok().map(|t| t.translation.xy());
+	let pos = edge_spawn_point(bounds.half_extents, &wave_params, player_pos);
+	let minion = build_minion(
+		&mut commands,
+		&params,
+		params.pick_kind(),
+		&rapier_config,
+		&physics_globals,
+		&materials,
+		pos,
+		Vec2::ZERO,
+	);
+
+	let supports = q_minions.iter().flatten().count() as u32;
+	if params.support_every > 0 && supports < (count + 1) / params.support_every {
+		debug!("SPAWN_SUPPORT");
+		// seen from above, a sphere is the easiest circle to get
+		let aura = commands
+			.spawn_bundle(MaterialMesh2dBundle {
+				mesh: meshes
+					.add(Mesh::from(shape::Icosphere {
+						radius: params.support_radius,
+						subdivisions: 3,
+					}))
+					.into(),
+				material: color_materials.add(Color::rgba(0.2, 1.0, 0.4, 0.15).into()),
+				transform: Transform::from_xyz(0.0, 0.0, -1.0),
+				..Default::default()
+			})
+			.id();
+		commands
+			.entity(minion)
+			.push_children(&[aura])
+			.insert(SupportAura {
+				radius: params.support_radius,
+				reduction: params.support_reduction,
+			});
+	}
+
+	ev_writer.send(BossSpawnEvent);
+}
 
+/// `pos` is in pixels, `linvel` in physics units
+fn build_minion(
+	commands: &mut Commands,
+	params: &MinionParams,
+	kind: MinionKind,
+	rapier_config: &RapierConfiguration,
+	physics_globals: &PhysicsGlobals,
+	materials: &PhysicsMaterialParams,
+	pos: Vec2,
+	linvel: Vec2,
+) -> Entity {
 	let collider_flags = ColliderFlags {
 		collision_groups: InteractionGroups::new(physics_globals.enemy_mask, u32::MAX),
 		..Default::default()
 	};
 
-	info!("SPAWN_MINION");
-	commands
+	let spec = params.kind(kind);
+	debug!("SPAWN_MINION {:?}", kind);
+	let minion = commands
 		.spawn_bundle(RigidBodyBundle {
-			position: (params.spawn_pos / rapier_config.scale).into(),
+			position: (pos / rapier_config.scale).into(),
+			velocity: RigidBodyVelocity {
+				linvel: linvel.into(),
+				..Default::default()
+			}
+			.into(),
 			..Default::default()
 		})
 		.insert(Transform::from_rotation(Quat::from_euler(
@@ -390,8 +1502,8 @@ fn spawn_minions(
 		)))
 		.insert_bundle(SpriteBundle {
 			sprite: Sprite {
-				custom_size: Some(params.body_scale),
-				color: Color::RED,
+				custom_size: Some(spec.body_scale),
+				color: spec.color,
 				..Default::default()
 			},
 			..Default::default()
@@ -399,51 +1511,598 @@ fn spawn_minions(
 		.insert(ColliderPositionSync::Discrete)
 		.insert_bundle(ColliderBundle {
 			flags: collider_flags.clone().into(),
+			material: materials.entity(),
 			position: Vec2::ZERO.into(),
 			// Since the physics world is scaled, we divide pixel size by it to get the collider size
 			shape: ColliderShapeComponent(ColliderShape::cuboid(
-				params.body_scale.x * 0.5 / rapier_config.scale,
-				params.body_scale.y * 0.5 / rapier_config.scale,
+				spec.body_scale.x * 0.5 / rapier_config.scale,
+				spec.body_scale.y * 0.5 / rapier_config.scale,
 			)),
 			..Default::default()
 		})
 		.insert(Enemy(EnemyState::IDLE))
+		.insert(Patrol::new(pos))
+		.insert(StuckDetector::default())
+		.insert(PathRequestThrottle::default())
 		.insert(Minion)
-		.insert(Health(params.start_health))
+		.insert(kind)
+		.insert(Health(spec.start_health))
 		.id();
+	if kind == MinionKind::Ranged {
+		commands.entity(minion).insert(FireControl::default());
+	}
+	// kamikazes explode on contact instead, see `minion_contacts`
+	if kind == MinionKind::Melee {
+		commands.entity(minion).insert(ContactDamage::new(
+			spec.contact_damage,
+			params.contact_cooldown,
+			params.contact_knockback,
+		));
+	}
+	add_spawn_grace(commands, minion, params.spawn_grace, spec.body_scale);
+	minion
+}
 
-	ev_writer.send(BossSpawnEvent);
+/// Freshly spawned enemies stay idle for a moment, so players aren't swarmed the moment something spawns
+#[derive(Component)]
+pub struct SpawnGrace(pub Timer);
+
+/// Pulsing marker around an enemy during its spawn grace
+#[derive(Component)]
+struct SpawnIndicator;
+
+fn add_spawn_grace(commands: &mut Commands, enemy: Entity, secs: f32, size: Vec2) {
+	if secs <= 0.0 {
+		return;
+	}
+	let indicator = commands
+		.spawn_bundle(SpriteBundle {
+			sprite: Sprite {
+				custom_size: Some(size * 1.5),
+				color: Color::rgba(1.0, 1.0, 1.0, 0.3),
+				..Default::default()
+			},
+			transform: Transform::from_xyz(0.0, 0.0, -0.1),
+			..Default::default()
+		})
+		.insert(SpawnIndicator)
+		.id();
+	commands
+		.entity(enemy)
+		.push_children(&[indicator])
+		.insert(SpawnGrace(Timer::from_seconds(secs, false)));
 }
 
-fn enemy_movement(
-	mut q_enemy: Query<
-		(
-			&Transform,
-			&mut RigidBodyVelocityComponent,
-			&mut RigidBodyPositionComponent,
-			&NextWaypoint,
-			&Enemy,
+fn tick_spawn_grace(
+	mut commands: Commands,
+	mut q_grace: Query<(Entity, &mut SpawnGrace, &Children)>,
+	mut q_indicators: Query<&mut Sprite, With<SpawnIndicator>>,
+	time: Res<Time>,
+) {
+	for (entity, mut grace, children) in q_grace.iter_mut() {
+		grace.0.tick(time.delta());
+		let finished = grace.0.finished();
+		for &child in children.iter() {
+			if let Ok(mut sprite) = q_indicators.get_mut(child) {
+				if finished {
+					commands.entity(child).despawn_recursive();
+				} else {
+					sprite
+						.color
+						.set_a(0.3 + 0.2 * (grace.0.elapsed_secs() * 12.0).sin());
+				}
+			}
+		}
+		if finished {
+			commands.entity(entity).remove::<SpawnGrace>();
+		}
+	}
+}
+
+/// Despawns dead minions and splits up dead `SplitOnDeath` bosses. Regular bosses never die, they
+/// are upgraded in `update_level_over_time` instead
+fn handle_enemy_deaths(
+	mut commands: Commands,
+	mut death_events: EventReader<DeathEvent>,
+	q_dying: Query<(
+		&Transform,
+		&RigidBodyVelocityComponent,
+		Option<&SplitOnDeath>,
+		Option<&Minion>,
+	)>,
+	q_split_minions: Query<Entity, With<SplitMinion>>,
+	params: Res<EnemyParams>,
+	minion_params: Res<MinionParams>,
+	mut globals: ResMut<GameGlobals>,
+	rapier_config: Res<RapierConfiguration>,
+	physics_globals: Res<PhysicsGlobals>,
+	materials: Res<PhysicsMaterialParams>,
+	mut ev_writer: EventWriter<BossSpawnEvent>,
+	mut score_ew: EventWriter<ScoreEvent>,
+) {
+	let mut dead: Vec<Entity> = vec![];
+	for DeathEvent(entity) in death_events.iter() {
+		if !dead.contains(entity) {
+			dead.push(*entity);
+		}
+	}
+
+	let mut split = false;
+	for entity in dead.iter() {
+		let (transform, rb_vel, split_on_death, minion) = match q_dying.get(*entity) {
+			Ok(dying) => dying,
+			Err(_) => continue,
+		};
+
+		if let Some(SplitOnDeath { count }) = split_on_death {
+			debug!("SPLIT_BOSS");
+			let pos = transform.translation.xy();
+			let vel: Vec2 = rb_vel.linvel.into();
+			for _ in 0..*count {
+				let scatter = Mat2::from_angle(rand::random::<f32>() * 2.0 * PI)
+					* Vec2::X * rand::random::<f32>()
+					* minion_params.split_scatter_speed
+					/ rapier_config.scale;
+				let split_minion = build_minion(
+					&mut commands,
+					&minion_params,
+					minion_params.pick_kind(),
+					&rapier_config,
+					&physics_globals,
+					&materials,
+					pos,
+					vel + scatter,
+				);
+				commands.entity(split_minion).insert(SplitMinion);
+			}
+			score_ew.send(ScoreEvent {
+				kind: ScoreKind::BossKill,
+				points: params.kill_score,
+			});
+			commands.entity(*entity).despawn_recursive();
+			split = true;
+		} else if minion.is_some() {
+			score_ew.send(ScoreEvent {
+				kind: ScoreKind::MinionKill,
+				points: minion_params.kill_score,
+			});
+			commands.entity(*entity).despawn_recursive();
+		}
+	}
+
+	// the last split minion died, so the boss comes back a level stronger
+	let killed_split_minion = dead.iter().any(|e| q_split_minions.get(*e).is_ok());
+	if !split && killed_split_minion && q_split_minions.iter().all(|e| dead.contains(&e)) {
+		globals.level += 1;
+		build_boss(
+			&mut commands,
+			&params,
+			&rapier_config,
+			&physics_globals,
+			&materials,
+		);
+		ev_writer.send(BossSpawnEvent);
+	}
+}
+
+/// Destroyed parts fall off the boss and score. An arm takes its weapon with it
+fn destroy_boss_parts(
+	mut commands: Commands,
+	mut death_events: EventReader<DeathEvent>,
+	q_parts: Query<(Entity, &BossPart, &Parent)>,
+	params: Res<EnemyParams>,
+	mut score_ew: EventWriter<ScoreEvent>,
+) {
+	let mut destroyed: Vec<Entity> = vec![];
+	for DeathEvent(entity) in death_events.iter() {
+		let (part, Parent(boss)) = match q_parts.get(*entity) {
+			Ok((_, part, parent)) if !destroyed.contains(entity) => (part, parent),
+			_ => continue,
+		};
+		debug!("DESTROY_BOSS_PART {:?}", part);
+		score_ew.send(ScoreEvent {
+			kind: ScoreKind::PartBreak,
+			points: params.part_score,
+		});
+		destroyed.push(*entity);
+		commands.entity(*entity).despawn_recursive();
+
+		if let Some(weapon) = part.weapon() {
+			for (weapon_e, other, Parent(owner)) in q_parts.iter() {
+				if *other == weapon && owner == boss && !destroyed.contains(&weapon_e) {
+					destroyed.push(weapon_e);
+					commands.entity(weapon_e).despawn_recursive();
+				}
+			}
+		}
+	}
+}
+
+/// Kamikazes touching a player explode, hurting everything with health within their blast radius,
+/// and die in the process
+fn minion_contacts(
+	mut contact_events: EventReader<ContactEvent>,
+	q_minions: Query<(&MinionKind, &Transform), With<Minion>>,
+	q_players: Query<(), With<Player>>,
+	q_targets: Query<(Entity, &GlobalTransform), With<Health>>,
+	mut q_health: Query<&mut Health, With<Minion>>,
+	mut damage_ew: EventWriter<DamageEvent>,
+	params: Res<MinionParams>,
+) {
+	let mut exploded: Vec<Entity> = vec![];
+	for contact_event in contact_events.iter() {
+		let (h1, h2) = match contact_event {
+			ContactEvent::Started(h1, h2) => (h1.entity(), h2.entity()),
+			ContactEvent::Stopped(..) => continue,
+		};
+		let (minion, player) = if q_players.get(h2).is_ok() {
+			(h1, h2)
+		} else if q_players.get(h1).is_ok() {
+			(h2, h1)
+		} else {
+			continue;
+		};
+		let (kind, minion_t) = match q_minions.get(minion) {
+			Ok(minion) => minion,
+			Err(_) => continue,
+		};
+		let spec = params.kind(*kind);
+		match kind {
+			// melee minions hurt through their `ContactDamage`
+			MinionKind::Ranged | MinionKind::Melee => {}
+			MinionKind::Kamikaze if !exploded.contains(&minion) => {
+				debug!("KAMIKAZE_EXPLOSION");
+				exploded.push(minion);
+				let pos = minion_t.translation.xy();
+				for (target, target_t) in q_targets.iter() {
+					let in_blast = target_t.translation.xy().distance(pos) <= spec.blast_radius;
+					if target != minion && (target == player || in_blast) {
+						damage_ew.send(DamageEvent {
+							target,
+							amount: spec.contact_damage,
+							source: DamageSource::Attack,
+						});
+					}
+				}
+				if let Ok(mut health) = q_health.get_mut(minion) {
+					health.0 = 0.0;
+				}
+			}
+			MinionKind::Kamikaze => {}
+		}
+	}
+}
+
+/// Enemies that hurt players by touching them, e.g. the boss ramming into them
+#[derive(Component)]
+pub struct ContactDamage {
+	pub damage: f32,
+	/// Pixels / sec the player is pushed away with
+	pub knockback: f32,
+	cooldown: Timer,
+}
+
+impl ContactDamage {
+	pub fn new(damage: f32, cooldown_secs: f32, knockback: f32) -> Self {
+		let mut cooldown = Timer::from_seconds(cooldown_secs, false);
+		// the first touch hurts right away
+		cooldown.tick(cooldown.duration());
+		Self {
+			damage,
+			knockback,
+			cooldown,
+		}
+	}
+}
+
+/// Hurts and knocks back players for as long as they touch an enemy with `ContactDamage`, once per
+/// cooldown. `touching` holds the colliders in contact with a player, boss parts count for the boss
+fn contact_damage(
+	mut contact_events: EventReader<ContactEvent>,
+	mut touching: Local<Vec<(Entity, Entity)>>,
+	mut q_contact: Query<(&mut ContactDamage, &Transform)>,
+	q_parent: Query<&Parent>,
+	q_players: Query<(), With<Player>>,
+	mut q_targets: Query<
+		(&Transform, &mut RigidBodyVelocityComponent),
+		(With<Player>, Without<Invulnerable>, Without<ContactDamage>),
+	>,
+	mut damage_ew: EventWriter<DamageEvent>,
+	rapier_config: Res<RapierConfiguration>,
+	time: Res<Time>,
+) {
+	for contact_event in contact_events.iter() {
+		let (h1, h2, started) = match contact_event {
+			ContactEvent::Started(h1, h2) => (h1.entity(), h2.entity(), true),
+			ContactEvent::Stopped(h1, h2) => (h1.entity(), h2.entity(), false),
+		};
+		let pair = if q_players.get(h2).is_ok() {
+			(h1, h2)
+		} else if q_players.get(h1).is_ok() {
+			(h2, h1)
+		} else {
+			continue;
+		};
+		touching.retain(|touching_pair| *touching_pair != pair);
+		if started {
+			touching.push(pair);
+		}
+	}
+	// despawned colliders don't report the contact stopping
+	touching.retain(|(collider, _)| {
+		q_contact.get(*collider).is_ok() || q_parent.get(*collider).is_ok()
+	});
+
+	for (mut contact, _) in q_contact.iter_mut() {
+		contact.cooldown.tick(time.delta());
+	}
+
+	for (collider, player) in touching.iter() {
+		let enemy = if q_contact.get(*collider).is_ok() {
+			*collider
+		} else {
+			match q_parent.get(*collider) {
+				Ok(Parent(parent)) => *parent,
+				Err(_) => continue,
+			}
+		};
+		let (mut contact, enemy_t) = match q_contact.get_mut(enemy) {
+			Ok(contact) if contact.cooldown.finished() && contact.damage > 0.0 => contact,
+			_ => continue,
+		};
+		let (player_t, mut rb_vel) = match q_targets.get_mut(*player) {
+			Ok(target) => target,
+			Err(_) => continue,
+		};
+
+		damage_ew.send(DamageEvent {
+			target: *player,
+			amount: contact.damage,
+			source: DamageSource::Attack,
+		});
+		let away = (player_t.translation.xy() - enemy_t.translation.xy()).normalize_or_zero();
+		let linvel = Vec2::from(rb_vel.linvel) + away * contact.knockback / rapier_config.scale;
+		rb_vel.linvel = linvel.into();
+		contact.cooldown.reset();
+	}
+}
+
+/// Moves the boss to the phase of its current health. Upgrading the boss heals it, which starts over
+/// from the first phase
+fn update_boss_phase(
+	mut q_boss: Query<(&Transform, &Health, &mut BossPhase), With<Boss>>,
+	params: Res<EnemyParams>,
+	phase_params: Res<BossPhaseParams>,
+	mut sound_ew: EventWriter<SoundEvent>,
+	(mut score_ew, score_params): (EventWriter<ScoreEvent>, Res<ScoreParams>),
+) {
+	for (transform, Health(health), mut phase) in q_boss.iter_mut() {
+		let ratio = health / params.start_health;
+		let current = phase_params
+			.phases
+			.iter()
+			.rposition(|spec| ratio <= spec.health_ratio)
+			.unwrap_or(0);
+		if phase.0 != current {
+			debug!("BOSS_PHASE {}", current);
+			// falling back to an earlier phase after an upgrade doesn't score
+			if current > phase.0 {
+				score_ew.send(ScoreEvent {
+					kind: ScoreKind::PhaseReached,
+					points: score_params.phase_score * (current - phase.0) as u32,
+				});
+			}
+			phase.0 = current;
+			sound_ew.send(SoundEvent::at(
+				Sound::BossRoar,
+				transform.translation.truncate(),
+			));
+		}
+	}
+}
+
+fn update_aggression(
+	clock: Res<GameClock>,
+	params: Res<AggressionParams>,
+	mut aggression: ResMut<Aggression>,
+) {
+	let t = clock.elapsed_secs();
+	let value = match params.curve {
+		AggressionCurve::Linear => t * params.rate,
+		AggressionCurve::Exponential => (t * params.rate).exp() - 1.0,
+	};
+	aggression.0 = value.clamp(0.0, 1.0);
+}
+
+/// Alternates between vulnerable and immune phases, the timer holds the duration of the current one
+#[derive(Component)]
+pub struct ImmunityCycle {
+	timer: Timer,
+}
+
+fn cycle_boss_immunity(
+	mut commands: Commands,
+	mut q_boss: Query<
+		(
+			Entity,
+			&mut ImmunityCycle,
+			Option<&mut DamageImmunity>,
+			Option<&BossPhase>,
+		),
+		With<Boss>,
+	>,
+	params: Res<EnemyParams>,
+	phase_params: Res<BossPhaseParams>,
+	game_globals: Res<GameGlobals>,
+	time: Res<Time>,
+) {
+	for (entity, mut cycle, immunity, phase) in q_boss.iter_mut() {
+		if game_globals.level < params.immunity_min_level {
+			continue;
+		}
+		let except = phase_params
+			.get(phase)
+			.map_or(DamageSource::Environment, |phase| phase.immunity_except);
+
+		cycle.timer.tick(time.delta());
+		if !cycle.timer.finished() {
+			// a new phase while immune changes what still hurts the boss
+			if let Some(mut immunity) = immunity {
+				if immunity.except != except {
+					immunity.except = except;
+				}
+			}
+			continue;
+		}
+		let duration = if immunity.is_some() {
+			debug!("BOSS_VULNERABLE");
+			commands.entity(entity).remove::<DamageImmunity>();
+			params.immunity_cooldown
+		} else {
+			debug!("BOSS_IMMUNE");
+			commands.entity(entity).insert(DamageImmunity { except });
+			params.immunity_duration
+		};
+		cycle.timer.set_duration(Duration::from_secs_f32(duration));
+		cycle.timer.reset();
+	}
+}
+
+fn reset_player_habits(mut habits: ResMut<PlayerHabits>) {
+	*habits = PlayerHabits::default();
+}
+
+fn learn_player_habits(
+	q_player: Query<&RigidBodyVelocityComponent, With<Player>>,
+	params: Res<AdaptationParams>,
+	mut habits: ResMut<PlayerHabits>,
+	rapier_config: Res<RapierConfiguration>,
+	time: Res<Time>,
+) {
+	if let Ok(rb_vel) = q_player.get_single() {
+		let velocity = Vec2::from(rb_vel.linvel) * rapier_config.scale;
+		// exponential moving average, independent of the frame rate
+		let blend = 1.0 - (-params.rate * time.delta_seconds()).exp();
+		habits.velocity = habits.velocity.lerp(velocity, blend);
+	}
+}
+
+fn enemy_movement(
+	mut q_enemy: Query<
+		(
+			Option<&Boss>,
+			&Transform,
+			&mut RigidBodyVelocityComponent,
+			&mut RigidBodyPositionComponent,
+			&NextWaypoint,
+			&Enemy,
+			ChangeTrackers<Enemy>,
+			Option<&MirrorMovement>,
+			Option<&Fleeing>,
+			Option<&Dodge>,
+			Option<&Patrol>,
+			Option<&Recoil>,
+			Option<&BossPhase>,
+			Option<&MinionKind>,
+			Option<&StatusEffects>,
 		),
 		With<Enemy>,
 	>,
 	q_player_t: Query<&Transform, With<Player>>,
+	q_player_vel: Query<&RigidBodyVelocityComponent, (With<Player>, Without<Enemy>)>,
+	bounds: Res<ArenaBounds>,
 	params: Res<EnemyParams>,
+	minion_params: Res<MinionParams>,
+	aggression_params: Res<AggressionParams>,
+	aggression: Res<Aggression>,
+	phase_params: Res<BossPhaseParams>,
 	rapier_parameters: Res<RapierConfiguration>,
 	physics_globals: Res<PhysicsGlobals>,
+	status_params: Res<StatusEffectParams>,
 	query_pipeline: Res<QueryPipeline>,
 	collider_query: QueryPipelineColliderComponentsQuery,
-	_time: Res<Time>,
+	bullet_time: Res<BulletTime>,
+	time: Res<Time>,
 ) {
 	let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
-	for (transform, mut rb_vel, mut rb_pos, next_wp, Enemy(state)) in q_enemy.iter_mut() {
+	let max_turn = params.turn_rate * time.delta_seconds() * bullet_time.scale;
+	for (
+		boss,
+		transform,
+		mut rb_vel,
+		mut rb_pos,
+		next_wp,
+		Enemy(state),
+		enemy_tracker,
+		mirror,
+		fleeing,
+		dodge,
+		patrol,
+		recoil,
+		phase,
+		kind,
+		status,
+	) in q_enemy.iter_mut()
+	{
 		let pos = transform.translation.xy();
+		let current_vel: Vec2 = rb_vel.linvel.into();
+		if let Some(recoil) = recoil {
+			rb_vel.linvel = (recoil.velocity * bullet_time.scale / rapier_parameters.scale).into();
+			continue;
+		}
+		if let Some(dodge) = dodge.filter(|dodge| !dodge.moving.finished()) {
+			rb_vel.linvel = (dodge.velocity * bullet_time.scale / rapier_parameters.scale).into();
+			continue;
+		}
+		let minion_spec = kind.map(|kind| minion_params.kind(*kind));
+		let speed = if boss.is_some() {
+			params.speed * aggression_params.speed(aggression.0) * phase_params.speed(phase)
+		} else if let Some(spec) = minion_spec {
+			spec.speed
+		} else {
+			params.speed
+		} * status.map_or(1.0, |status| status.speed_multiplier(&status_params))
+			* bullet_time.scale;
+
+		if let (
+			Some(mirror),
+			EnemyState::CHASING(Some(entity)) | EnemyState::ATTACK(Some(entity)),
+		) = (mirror, state)
+		{
+			if let (Ok(player_vel), Ok(player_t)) =
+				(q_player_vel.get(*entity), q_player_t.get(*entity))
+			{
+				let mut vel = mirror.apply(player_vel.linvel.into()) * bullet_time.scale;
+
+				// slide along the arena bounds instead of pushing into them
+				let limit = bounds.half_extents - Vec2::splat(params.mirror_wall_margin);
+				if (pos.x >= limit.x && vel.x > 0.0) || (pos.x <= -limit.x && vel.x < 0.0) {
+					vel.x = 0.0;
+				}
+				if (pos.y >= limit.y && vel.y > 0.0) || (pos.y <= -limit.y && vel.y < 0.0) {
+					vel.y = 0.0;
+				}
+				rb_vel.linvel = vel.into();
+
+				let dir_player = player_t.translation.xy() - pos;
+				turn_towards(
+					&mut rb_pos,
+					params.rot_offset - dir_player.angle_between(Vec2::X),
+					max_turn,
+				);
+				continue;
+			}
+		}
 		match state {
 			EnemyState::CHASING(Some(entity)) => {
 				let target_pos = next_wp.0 .0;
-				let player_pos = q_player_t.get(*entity).unwrap().translation.xy();
+				let player_pos = match q_player_t.get(*entity) {
+					Ok(player_t) => player_t.translation.xy(),
+					Err(_) => continue,
+				};
 				let dir = target_pos - pos;
 				let dir_player = player_pos - pos;
-				let move_delta = dir.normalize() * params.speed / rapier_parameters.scale;
+				let move_delta = dir.normalize() * speed / rapier_parameters.scale;
 
 				rb_vel.linvel = move_delta.into();
 
@@ -460,26 +2119,139 @@ fn enemy_movement(
 					move_delta.angle_between(Vec2::X)
 				};
 
-				rb_pos.0.position.rotation = UnitComplex::from_angle(params.rot_offset - angle);
+				turn_towards(&mut rb_pos, params.rot_offset - angle, max_turn);
 			}
 			EnemyState::ATTACK(Some(entity)) => {
-				let player_pos = q_player_t.get(*entity).unwrap().translation.xy();
+				let player_pos = match q_player_t.get(*entity) {
+					Ok(player_t) => player_t.translation.xy(),
+					Err(_) => continue,
+				};
 				let dir = player_pos - transform.translation.xy();
 				let move_delta = dir.normalize() * params.speed / rapier_parameters.scale;
 
-				rb_vel.linvel = Vec2::ZERO.into();
-				rb_pos.0.position.rotation =
-					UnitComplex::from_angle(params.rot_offset - move_delta.angle_between(Vec2::X));
+				// melee and kamikaze minions charge, ranged ones back off from players too close
+				rb_vel.linvel = match minion_spec {
+					Some(spec) if spec.kind != MinionKind::Ranged => {
+						dir.normalize_or_zero() * speed / rapier_parameters.scale
+					}
+					Some(spec) if dir.length() < spec.keep_dist => {
+						-dir.normalize_or_zero() * speed / rapier_parameters.scale
+					}
+					_ => Vec2::ZERO,
+				}
+				.into();
+				turn_towards(
+					&mut rb_pos,
+					params.rot_offset - move_delta.angle_between(Vec2::X),
+					max_turn,
+				);
+			}
+			EnemyState::FLEEING(Some(entity)) => {
+				// the player might be gone, e.g. despawned for a restart
+				let player_pos = match q_player_t.get(*entity) {
+					Ok(player_t) => player_t.translation.xy(),
+					Err(_) => continue,
+				};
+				let dir = match fleeing.and_then(|fleeing| fleeing.cover) {
+					// stay hidden once the cover is reached
+					Some(cover_pos) if cover_pos.distance(pos) < params.follow_threshold => {
+						Vec2::ZERO
+					}
+					Some(_) => next_wp.0 .0 - pos,
+					None => pos - player_pos,
+				};
+				let move_delta = dir.normalize_or_zero() * speed / rapier_parameters.scale;
+
+				rb_vel.linvel = move_delta.into();
+				if move_delta != Vec2::ZERO {
+					turn_towards(
+						&mut rb_pos,
+						params.rot_offset - move_delta.angle_between(Vec2::X),
+						max_turn,
+					);
+				}
+			}
+			EnemyState::IDLE if patrol.and_then(|patrol| patrol.target).is_some() => {
+				let dir = next_wp.0 .0 - pos;
+				let move_delta =
+					dir.normalize_or_zero() * speed * params.patrol_speed / rapier_parameters.scale;
+
+				rb_vel.linvel = move_delta.into();
+				if move_delta != Vec2::ZERO {
+					turn_towards(
+						&mut rb_pos,
+						params.rot_offset - move_delta.angle_between(Vec2::X),
+						max_turn,
+					);
+				}
 			}
 			_ => {
 				rb_vel.linvel = Vec2::ZERO.into();
-				info!("Not moving because in state: {:?}", state);
+				// only log when the state changed, this would spam the console every frame otherwise
+				if enemy_tracker.is_changed() {
+					trace!("Not moving because in state: {:?}", state);
+				}
 			}
 		}
+
+		// the branches above set the target velocity, dodging and mirroring the already smooth player skip this
+		rb_vel.linvel = approach_velocity(
+			current_vel,
+			rb_vel.linvel.into(),
+			params.acceleration / rapier_parameters.scale,
+			params.deceleration / rapier_parameters.scale,
+			time.delta_seconds() * bullet_time.scale,
+		)
+		.into();
 	}
 }
 
-fn raycast_between(
+/// Rotates the body towards `angle` by at most `max_delta` radians, the shortest way around
+fn turn_towards(rb_pos: &mut RigidBodyPositionComponent, angle: f32, max_delta: f32) {
+	let current = rb_pos.0.position.rotation.angle();
+	let diff = (angle - current + PI).rem_euclid(2.0 * PI) - PI;
+	rb_pos.0.position.rotation =
+		UnitComplex::from_angle(current + diff.clamp(-max_delta, max_delta));
+}
+
+/// Where a bullet fired from `pos` with `bullet_speed` meets a target moving with constant `target_vel`.
+/// Falls back to the target's current position when the bullet can't catch up
+fn lead_target(pos: Vec2, target_pos: Vec2, target_vel: Vec2, bullet_speed: f32) -> Vec2 {
+	// solve |to_target + target_vel * t| = bullet_speed * t for the smallest positive t
+	let to_target = target_pos - pos;
+	let a = target_vel.length_squared() - bullet_speed * bullet_speed;
+	let b = 2.0 * to_target.dot(target_vel);
+	let c = to_target.length_squared();
+
+	let t = if a.abs() < f32::EPSILON {
+		// as fast as the bullet, the equation is linear
+		if b.abs() < f32::EPSILON {
+			None
+		} else {
+			Some(-c / b)
+		}
+	} else {
+		let discriminant = b * b - 4.0 * a * c;
+		if discriminant < 0.0 {
+			None
+		} else {
+			let root = discriminant.sqrt();
+			[(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)]
+				.iter()
+				.copied()
+				.filter(|t| *t > 0.0)
+				.reduce(f32::min)
+		}
+	};
+
+	match t {
+		Some(t) if t > 0.0 => target_pos + target_vel * t,
+		_ => target_pos,
+	}
+}
+
+/// Whether something other than players and enemies blocks the line between `pos` and `target`
+pub fn raycast_between(
 	pos: Vec2,
 	target: Vec2,
 	query_pipeline: &Res<QueryPipeline>,
@@ -506,34 +2278,179 @@ fn raycast_between(
 }
 
 fn enemy_state_control(
-	mut q_enemy: Query<(Entity, &Transform, &mut Enemy)>,
-	q_player: Query<(Entity, &Transform), With<Player>>,
-	mut ev_shoot_writer: EventWriter<ShootEvent>,
+	mut commands: Commands,
+	mut q_enemy: Query<(
+		Entity,
+		&Transform,
+		&mut Enemy,
+		Option<&mut FireControl>,
+		Option<&Boss>,
+		Option<&mut Patrol>,
+		Option<&SpawnGrace>,
+		Option<&BossPhase>,
+		Option<&Weapon>,
+		Option<&MinionKind>,
+		Option<&mut PathRequestThrottle>,
+	)>,
+	(q_recoil, q_parts): (Query<&Recoil>, Query<(&BossPart, &Parent)>),
+	mut q_teleport: Query<&mut TeleportAbility>,
+	q_player: Query<(Entity, &Transform, &RigidBodyVelocityComponent), With<Player>>,
+	q_waypoints: Query<(&Waypoint, Entity)>,
+	(mut ev_shoot_writer, mut telegraph_ew): (EventWriter<ShootEvent>, EventWriter<TelegraphEvent>),
 	mut create_path_ew: EventWriter<CreatePathEvent>,
-	query_pipeline: Res<QueryPipeline>,
-	physics_globals: Res<PhysicsGlobals>,
+	(query_pipeline, physics_globals, rapier_config): (
+		Res<QueryPipeline>,
+		Res<PhysicsGlobals>,
+		Res<RapierConfiguration>,
+	),
 	params: Res<EnemyParams>,
+	game_globals: Res<GameGlobals>,
+	(aggression_params, aggression): (Res<AggressionParams>, Res<Aggression>),
+	(adaptation, habits): (Res<AdaptationParams>, Res<PlayerHabits>),
+	(patterns, phase_params, minion_params, bullet_params, weapons): (
+		Res<BulletPatterns>,
+		Res<BossPhaseParams>,
+		Res<MinionParams>,
+		Res<BulletParams>,
+		Res<WeaponParams>,
+	),
 	collider_query: QueryPipelineColliderComponentsQuery,
-	_time: Res<Time>,
+	(time, bullet_time): (Res<Time>, Res<BulletTime>),
 ) {
+	let delta = time.delta().mul_f32(bullet_time.scale);
 	let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
-	for (entity, transform, mut enemy) in q_enemy.iter_mut() {
+	for (
+		entity,
+		transform,
+		mut enemy,
+		mut fire_control,
+		boss,
+		mut patrol,
+		spawn_grace,
+		phase,
+		weapon,
+		kind,
+		mut path_throttle,
+	) in q_enemy.iter_mut()
+	{
+		// an interrupted boss gathers itself before doing anything else
+		if q_recoil.get(entity).is_ok() {
+			continue;
+		}
+		let aggression = if boss.is_some() { aggression.0 } else { 0.0 };
+		let phase = phase_params.get(phase);
+		let minion_spec = kind.map(|kind| minion_params.kind(*kind));
+		let attack_dist = match minion_spec {
+			Some(spec) => spec.attack_dist,
+			None => params.attack_dist * phase.map_or(1.0, |phase| phase.attack_dist),
+		};
+		let shoots = minion_spec.map_or(true, |spec| spec.kind == MinionKind::Ranged);
+		let weapon = weapon.map_or(WeaponKind::Pistol, |weapon| weapon.0);
+		// the next time the enemy attacks, its first burst is telegraphed again
+		if let Some(control) = fire_control.as_mut() {
+			if control.engaged && !matches!(enemy.0, EnemyState::ATTACK(_)) {
+				control.engaged = false;
+			}
+		}
+
+		// teleport next to the target when the ability is ready
+		if let EnemyState::CHASING(Some(target)) | EnemyState::ATTACK(Some(target)) = enemy.0 {
+			if let Ok(mut ability) = q_teleport.get_mut(entity) {
+				ability.cooldown.tick(delta);
+				if ability.cooldown.finished() && phase.map_or(false, |phase| phase.teleport) {
+					if let Ok((_, player_t, _)) = q_player.get(target) {
+						let player_pos = player_t.translation.xy();
+						if let Some((wp, _)) = Waypoint::find_in_range(
+							q_waypoints.iter(),
+							&player_pos,
+							params.teleport_min_dist,
+							params.teleport_max_dist,
+						) {
+							let destination = wp.0;
+							let telegraph = commands
+								.spawn_bundle(SpriteBundle {
+									sprite: Sprite {
+										custom_size: Some(params.body_scale),
+										color: Color::rgba(1.0, 0.0, 0.0, 0.3),
+										..Default::default()
+									},
+									transform: Transform::from_xyz(
+										destination.x,
+										destination.y,
+										0.0,
+									),
+									..Default::default()
+								})
+								.id();
+							commands.entity(entity).insert(Teleport {
+								timer: Timer::from_seconds(params.teleport_duration, false),
+								destination,
+								telegraph,
+								relocated: false,
+							});
+							ability.cooldown.reset();
+							enemy.0 = EnemyState::TELEPORTING(Some(target));
+							continue;
+						}
+					}
+				}
+			}
+		}
+
 		match enemy.0 {
 			EnemyState::IDLE => {
-				if let Ok((player, _)) = q_player.get_single() {
-					enemy.0 = EnemyState::CHASING(Some(player));
+				let pos = transform.translation.xy();
+				if let Ok((player, player_t, _)) = q_player.get_single() {
+					let in_range = player_t.translation.xy().distance(pos) < params.aggro_dist;
+					if in_range && spawn_grace.is_none() {
+						if let Some(patrol) = patrol.as_mut() {
+							patrol.target = None;
+						}
+						enemy.0 = EnemyState::CHASING(Some(player));
+						continue;
+					}
+				}
+
+				// walk to another waypoint near home once the last one is reached
+				if let Some(patrol) = patrol.as_mut() {
+					let arrived = patrol.target.map_or(true, |target| {
+						target.distance(pos) < params.follow_threshold
+					});
+					if arrived {
+						patrol.target = Waypoint::find_in_range(
+							q_waypoints.iter(),
+							&patrol.home,
+							0.0,
+							params.patrol_radius,
+						)
+						.map(|(waypoint, _)| waypoint.0);
+						if let Some(target) = patrol.target {
+							create_path_ew.send(CreatePathEvent(pos, target, entity));
+						}
+					}
 				}
 			}
-			EnemyState::FLEEING => todo!(),
+			// handled in `flee_to_cover`
+			EnemyState::FLEEING(_) => {}
 			EnemyState::CHASING(Some(target)) => {
-				if let Ok((player, player_t)) = q_player.get(target) {
+				if let Ok((player, player_t, _)) = q_player.get(target) {
 					let player_pos = player_t.translation.xy();
 					let pos = transform.translation.xy();
 					let dist = player_pos.distance(pos);
 
-					create_path_ew.send(CreatePathEvent(pos, player_pos, entity));
+					let request_path = path_throttle.as_mut().map_or(true, |throttle| {
+						throttle.ready(
+							delta,
+							player_pos,
+							params.path_interval,
+							params.path_retarget_dist,
+						)
+					});
+					if request_path {
+						create_path_ew.send(CreatePathEvent(pos, player_pos, entity));
+					}
 
-					if dist < params.attack_dist {
+					if dist < attack_dist {
 						if !raycast_between(
 							pos,
 							player_pos,
@@ -546,14 +2463,133 @@ fn enemy_state_control(
 					}
 				}
 			}
+			// melee and kamikaze minions hurt by touching, see `minion_contacts`
+			EnemyState::ATTACK(Some(target)) if !shoots => {
+				if let Ok((player, player_t, _)) = q_player.get(target) {
+					if player_t.translation.distance(transform.translation) > attack_dist {
+						enemy.0 = EnemyState::CHASING(Some(player));
+					}
+				}
+			}
 			EnemyState::ATTACK(Some(target)) => {
-				if let Ok((player, player_t)) = q_player.get(target) {
+				if let Ok((player, player_t, player_vel)) = q_player.get(target) {
 					let pos = transform.translation.xy();
-					let dir = player_t.translation.xy() - pos;
-					ev_shoot_writer.send(ShootEvent(false, pos, dir));
+					let player_pos = player_t.translation.xy();
+					let player_vel = Vec2::from(player_vel.linvel) * rapier_config.scale;
+					// the boss leads its shots per phase, minions all the same
+					let (lead_targets, accuracy) = phase
+						.map_or((params.lead_targets, params.accuracy), |phase| {
+							(phase.lead_targets, phase.accuracy)
+						});
+					// inaccurate enemies under- or overshoot the lead, the same way for a whole frame
+					let lead = 1.0 + (1.0 - accuracy) * (rand::random::<f32>() * 2.0 - 1.0);
+					// every bullet is led with the speed it actually flies with
+					let weapon_spec = weapons.get(weapon);
+					let minion_level = Some(game_globals.level).filter(|_| kind.is_some());
+					let speed_scale = bullet_params.speed_scale(
+						Team::Enemy,
+						weapon_spec,
+						minion_level,
+						bullet_time.scale,
+					);
+					let aim = |pattern_speed: f32| {
+						let bullet_speed = params.lead_bullet_speed * pattern_speed * speed_scale;
+						let dir = if lead_targets {
+							let predicted = lead_target(pos, player_pos, player_vel, bullet_speed);
+							player_pos + (predicted - player_pos) * lead - pos
+						} else {
+							player_pos - pos
+						};
+						// the boss counters the player's habits
+						if boss.is_some() {
+							adaptation.bias(&habits, dir, bullet_speed)
+						} else {
+							dir
+						}
+					};
+					let dir = aim(1.0);
+
+					// the more aggressive, the wider the shots spread
+					let spread =
+						aggression_params.spread(aggression) * (rand::random::<f32>() * 2.0 - 1.0);
+					if let Some(control) = fire_control.as_mut() {
+						let (pattern, burst, burst_interval, windup) = if boss.is_some() {
+							let pattern = phase
+								.and_then(|phase| phase.attack_pattern.as_ref())
+								.unwrap_or(&params.attack_pattern);
+							(
+								patterns.get(pattern),
+								params.attack_burst,
+								params.attack_burst_interval,
+								params.attack_windup,
+							)
+						} else {
+							(
+								patterns.get(&params.minion_attack_pattern),
+								params.minion_attack_burst,
+								params.minion_attack_burst_interval,
+								params.minion_attack_windup,
+							)
+						};
+						let fire_rate = aggression_params.fire_rate(aggression)
+							* phase.map_or(1.0, |phase| phase.fire_rate);
+
+						if !control.engaged {
+							control.engaged = true;
+							if windup > 0.0 {
+								control.wind_up(windup);
+								telegraph_ew.send(TelegraphEvent {
+									owner: entity,
+									shape: volley_telegraph(
+										pattern,
+										control.volleys,
+										aggression_params.spread(aggression),
+										attack_dist,
+									),
+									pos,
+									dir,
+									// the wind-up runs faster with the fire rate
+									secs: windup / fire_rate,
+									follow: true,
+									color: TELEGRAPH_COLOR,
+								});
+							}
+						}
+						if control.tick(
+							delta.mul_f32(fire_rate),
+							pattern.interval(),
+							burst,
+							burst_interval,
+						) {
+							// boss volleys alternate between the weapons, the ones of a destroyed weapon are skipped
+							let weapon_part = if control.volleys % 2 == 0 {
+								BossPart::LeftWeapon
+							} else {
+								BossPart::RightWeapon
+							};
+							let armed = boss.is_none()
+								|| q_parts.iter().any(|(part, Parent(owner))| {
+									*owner == entity && *part == weapon_part
+								});
+							if armed {
+								for (angle, speed) in pattern.bullets(control.volleys) {
+									let dir = Mat2::from_angle(angle + spread) * aim(speed);
+									ev_shoot_writer.send(ShootEvent(
+										Team::Enemy,
+										pos,
+										dir,
+										Some(entity),
+										speed,
+										weapon,
+									));
+								}
+							}
+							control.volleys += 1;
+						}
+					}
 
 					let dist = player_t.translation.distance(transform.translation);
-					if dist > params.attack_dist {
+					if dist > attack_dist {
 						enemy.0 = EnemyState::CHASING(Some(player));
 					}
 				}
@@ -562,3 +2598,363 @@ fn enemy_state_control(
 		}
 	}
 }
+
+/// Covers the bullets of the `volley`th volley and the most they can spread. Patterns going all around
+/// the enemy get a circle
+fn volley_telegraph(
+	pattern: &BulletPattern,
+	volley: u32,
+	spread: f32,
+	range: f32,
+) -> TelegraphShape {
+	let half_angle = pattern
+		.bullets(volley)
+		.map(|(angle, _)| ((angle + PI).rem_euclid(2.0 * PI) - PI).abs())
+		.fold(0.0, f32::max)
+		+ spread;
+	// a bit wider than the bullets, they have a size too
+	let angle = half_angle * 2.0 + 0.15;
+	if angle >= 1.5 * PI {
+		TelegraphShape::Circle { radius: range }
+	} else {
+		TelegraphShape::Cone { range, angle }
+	}
+}
+
+/// Enemies wedged on a corner while chasing or fleeing get a random push and a fresh path.
+/// Attacking enemies stand still on purpose, so they are never stuck
+fn unstick_enemies(
+	mut q_enemy: Query<(
+		Entity,
+		&Transform,
+		&Enemy,
+		&mut StuckDetector,
+		&mut RigidBodyVelocityComponent,
+		Option<&Fleeing>,
+	)>,
+	q_player_t: Query<&Transform, With<Player>>,
+	mut create_path_ew: EventWriter<CreatePathEvent>,
+	params: Res<EnemyParams>,
+	rapier_config: Res<RapierConfiguration>,
+	time: Res<Time>,
+) {
+	let dt = time.delta_seconds();
+	for (entity, transform, Enemy(state), mut detector, mut rb_vel, fleeing) in q_enemy.iter_mut() {
+		let pos = transform.translation.xy();
+		let target = match state {
+			EnemyState::CHASING(Some(target)) | EnemyState::FLEEING(Some(target)) => Some(*target),
+			_ => None,
+		};
+		let stuck = detector.update(
+			pos,
+			target.is_some(),
+			dt,
+			params.stuck_speed,
+			params.stuck_time,
+		);
+		let target = match target {
+			Some(target) if stuck => target,
+			_ => continue,
+		};
+
+		debug!("UNSTICK_ENEMY");
+		let nudge =
+			Mat2::from_angle(rand::random::<f32>() * 2.0 * PI) * Vec2::X * params.nudge_strength;
+		rb_vel.linvel = (Vec2::from(rb_vel.linvel) + nudge / rapier_config.scale).into();
+
+		// fleeing enemies head to their cover, everyone else to the player
+		let destination = match fleeing.and_then(|fleeing| fleeing.cover) {
+			Some(cover_pos) => Some(cover_pos),
+			None => q_player_t
+				.get(target)
+				.ok()
+				.map(|player_t| player_t.translation.xy()),
+		};
+		if let Some(destination) = destination {
+			create_path_ew.send(CreatePathEvent(pos, destination, entity));
+		}
+	}
+}
+
+/// From `dodge_min_level` on, the boss reads where the player aims and sometimes sidesteps out of the line of fire
+/// before any bullet is fired
+fn predictive_dodge(
+	mut commands: Commands,
+	mut q_boss: Query<(Entity, &Transform, &Enemy, Option<&mut Dodge>), With<Boss>>,
+	q_player_t: Query<&Transform, With<Player>>,
+	(mouse_pos, input): (Res<MousePosition>, Res<InputAction>),
+	params: Res<EnemyParams>,
+	game_globals: Res<GameGlobals>,
+	bullet_time: Res<BulletTime>,
+	time: Res<Time>,
+) {
+	let delta = time.delta().mul_f32(bullet_time.scale);
+	for (entity, transform, Enemy(state), dodge) in q_boss.iter_mut() {
+		if let Some(mut dodge) = dodge {
+			dodge.moving.tick(delta);
+			if dodge.moving.finished() {
+				dodge.cooldown.tick(delta);
+				if dodge.cooldown.finished() {
+					commands.entity(entity).remove::<Dodge>();
+				}
+			}
+			continue;
+		}
+
+		let target = match state {
+			EnemyState::CHASING(Some(target)) | EnemyState::ATTACK(Some(target)) => *target,
+			_ => continue,
+		};
+		if game_globals.level < params.dodge_min_level {
+			continue;
+		}
+		let player_pos = match q_player_t.get(target) {
+			Ok(player_t) => player_t.translation.xy(),
+			Err(_) => continue,
+		};
+
+		let aim = input.aim.unwrap_or(mouse_pos.0 - player_pos);
+		let to_boss = transform.translation.xy() - player_pos;
+		if aim == Vec2::ZERO || aim.angle_between(to_boss).abs() > params.dodge_aim_angle {
+			continue;
+		}
+
+		if rand::random::<f32>() < params.dodge_chance * delta.as_secs_f32() {
+			// step to the side the boss already is on, so it leaves the line of fire the fastest way
+			let side = aim.perp().normalize();
+			let side = if side.dot(to_boss) < 0.0 { -side } else { side };
+			commands.entity(entity).insert(Dodge {
+				velocity: side * params.dodge_speed,
+				moving: Timer::from_seconds(params.dodge_duration, false),
+				cooldown: Timer::from_seconds(params.dodge_cooldown, false),
+			});
+		}
+	}
+}
+
+/// Badly hurt minions stop fighting and run to the nearest waypoint the player can't see, or the one
+/// furthest from the player if there is none. A new cover is only searched once the current one became
+/// visible. Fleeing minions heal over time and re-engage once healed up
+fn flee_to_cover(
+	mut commands: Commands,
+	mut q_enemy: Query<
+		(
+			Entity,
+			&Transform,
+			&mut Health,
+			&mut Enemy,
+			&MinionKind,
+			Option<&Fleeing>,
+		),
+		With<Minion>,
+	>,
+	q_player_t: Query<&Transform, With<Player>>,
+	q_waypoints: Query<(&Waypoint, Entity)>,
+	mut create_path_ew: EventWriter<CreatePathEvent>,
+	query_pipeline: Res<QueryPipeline>,
+	physics_globals: Res<PhysicsGlobals>,
+	minion_params: Res<MinionParams>,
+	collider_query: QueryPipelineColliderComponentsQuery,
+	(time, bullet_time): (Res<Time>, Res<BulletTime>),
+) {
+	let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+	for (entity, transform, mut health, mut enemy, kind, fleeing) in q_enemy.iter_mut() {
+		let start_health = minion_params.kind(*kind).start_health;
+		let target = match enemy.0 {
+			EnemyState::CHASING(Some(target)) | EnemyState::ATTACK(Some(target))
+				if health.0 < start_health * minion_params.flee_health_ratio =>
+			{
+				enemy.0 = EnemyState::FLEEING(Some(target));
+				target
+			}
+			// dead minions are despawned by `handle_enemy_deaths`, they don't heal back up
+			EnemyState::FLEEING(Some(target)) if health.0 > 0.0 => {
+				health.0 = (health.0
+					+ minion_params.flee_regen * time.delta_seconds() * bullet_time.scale)
+					.min(start_health);
+				if health.0 >= start_health * minion_params.reengage_health_ratio {
+					enemy.0 = EnemyState::CHASING(Some(target));
+					commands.entity(entity).remove::<Fleeing>();
+					continue;
+				}
+				target
+			}
+			_ => continue,
+		};
+		let player_pos = match q_player_t.get(target) {
+			Ok(player_t) => player_t.translation.xy(),
+			Err(_) => continue,
+		};
+		let pos = transform.translation.xy();
+
+		let cover_hidden = fleeing
+			.and_then(|fleeing| fleeing.cover)
+			.map_or(false, |cover_pos| {
+				raycast_between(
+					cover_pos,
+					player_pos,
+					&query_pipeline,
+					&physics_globals,
+					&collider_set,
+				)
+			});
+		let cover = if cover_hidden {
+			fleeing.and_then(|fleeing| fleeing.cover)
+		} else {
+			let cover = Waypoint::find_nearest_hidden(
+				q_waypoints.iter(),
+				&pos,
+				player_pos,
+				&query_pipeline,
+				&physics_globals,
+				&collider_set,
+			)
+			.or_else(|| Waypoint::find_furthest(q_waypoints.iter(), &player_pos))
+			.map(|(wp, _)| wp.0);
+			commands.entity(entity).insert(Fleeing { cover });
+			cover
+		};
+
+		if let Some(cover_pos) = cover {
+			create_path_ew.send(CreatePathEvent(pos, cover_pos, entity));
+		}
+	}
+}
+
+/// Fades teleporting enemies out, moves them to their destination and fades them back in. Colliders are disabled
+/// for the whole teleport, afterwards the enemy attacks right away
+fn teleport_enemies(
+	mut commands: Commands,
+	mut q_teleport: Query<(
+		Entity,
+		&mut Teleport,
+		&mut Enemy,
+		&mut RigidBodyPositionComponent,
+		&Children,
+	)>,
+	mut q_parts: Query<(&mut ColliderFlagsComponent, &mut Sprite)>,
+	q_player_t: Query<&Transform, With<Player>>,
+	mut create_path_ew: EventWriter<CreatePathEvent>,
+	physics_globals: Res<PhysicsGlobals>,
+	rapier_config: Res<RapierConfiguration>,
+	bullet_time: Res<BulletTime>,
+	time: Res<Time>,
+) {
+	for (entity, mut teleport, mut enemy, mut rb_pos, children) in q_teleport.iter_mut() {
+		teleport.timer.tick(time.delta().mul_f32(bullet_time.scale));
+
+		// fade out during the first half and back in during the second one
+		let progress = teleport.timer.percent();
+		let alpha = (progress * 2.0 - 1.0).abs();
+		let collision_groups = if teleport.timer.finished() {
+			InteractionGroups::new(physics_globals.enemy_mask, u32::MAX)
+		} else {
+			InteractionGroups::none()
+		};
+		for &child in children.iter() {
+			if let Ok((mut flags, mut sprite)) = q_parts.get_mut(child) {
+				flags.collision_groups = collision_groups;
+				sprite.color.set_a(alpha);
+			}
+		}
+
+		let target = match enemy.0 {
+			EnemyState::TELEPORTING(target) => target,
+			_ => None,
+		};
+
+		if progress >= 0.5 && !teleport.relocated {
+			rb_pos.0.position.translation.vector =
+				(teleport.destination / rapier_config.scale).into();
+			rb_pos.0.next_position = rb_pos.0.position;
+			teleport.relocated = true;
+
+			// the old path starts where the enemy was, so request a new one right away
+			if let Some(player_t) = target.and_then(|target| q_player_t.get(target).ok()) {
+				create_path_ew.send(CreatePathEvent(
+					teleport.destination,
+					player_t.translation.xy(),
+					entity,
+				));
+			}
+		}
+
+		if teleport.timer.finished() {
+			commands.entity(teleport.telegraph).despawn();
+			commands.entity(entity).remove::<Teleport>();
+			enemy.0 = match target {
+				Some(target) if q_player_t.get(target).is_ok() => EnemyState::ATTACK(Some(target)),
+				_ => EnemyState::IDLE,
+			};
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn leads_moving_target_to_intercept() {
+		let pos = Vec2::ZERO;
+		let target_pos = Vec2::new(100.0, 0.0);
+		let target_vel = Vec2::new(0.0, 50.0);
+		let predicted = lead_target(pos, target_pos, target_vel, 100.0);
+
+		// bullet and target arrive at the predicted position at the same time
+		let t = (predicted - target_pos).length() / target_vel.length();
+		assert!((predicted.distance(pos) - 100.0 * t).abs() < 1e-3);
+		assert!((t - (4.0f32 / 3.0).sqrt()).abs() < 1e-3);
+		assert!(predicted.y > 0.0);
+	}
+
+	#[test]
+	fn aims_at_standing_target() {
+		let target_pos = Vec2::new(30.0, -40.0);
+		assert_eq!(
+			lead_target(Vec2::ZERO, target_pos, Vec2::ZERO, 600.0),
+			target_pos
+		);
+	}
+
+	#[test]
+	fn aims_at_target_too_fast_to_catch() {
+		let target_pos = Vec2::new(100.0, 0.0);
+		let fleeing = Vec2::new(200.0, 0.0);
+		assert_eq!(
+			lead_target(Vec2::ZERO, target_pos, fleeing, 100.0),
+			target_pos
+		);
+	}
+
+	#[test]
+	fn stuck_enemy_gets_nudged_and_recovers() {
+		let (dt, stuck_speed, stuck_time) = (0.25, 5.0, 0.5);
+		let mut detector = StuckDetector::default();
+		let mut pos = Vec2::ZERO;
+		assert!(!detector.update(pos, true, dt, stuck_speed, stuck_time));
+
+		// wedged on a corner, barely moving while chasing, until it was stuck for `stuck_time`
+		let nudged: Vec<bool> = (0..2)
+			.map(|_| {
+				pos += Vec2::X * 0.1;
+				detector.update(pos, true, dt, stuck_speed, stuck_time)
+			})
+			.collect();
+		assert_eq!(nudged, vec![false, true]);
+
+		// moving freely after the nudge never counts as stuck
+		for _ in 0..10 {
+			pos += Vec2::X * 10.0;
+			assert!(!detector.update(pos, true, dt, stuck_speed, stuck_time));
+		}
+	}
+
+	#[test]
+	fn enemy_standing_still_on_purpose_isnt_stuck() {
+		let mut detector = StuckDetector::default();
+		for _ in 0..20 {
+			assert!(!detector.update(Vec2::ZERO, false, 0.1, 5.0, 0.5));
+		}
+	}
+}
@@ -0,0 +1,165 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::{game::GameState, scene::MainCamera, settings::Settings};
+
+/// Game feel: shakes the camera on `CameraShakeEvent`s and briefly freezes the physics world on
+/// `HitStopEvent`s, so heavy hits are felt
+pub struct FeelPlugin;
+
+impl Plugin for FeelPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_event::<CameraShakeEvent>()
+			.add_event::<HitStopEvent>()
+			.insert_resource(FeelParams::default())
+			.insert_resource(CameraShake::default())
+			.insert_resource(HitStop::default())
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(remove_camera_shake.before("camera_follow"))
+					.with_system(apply_camera_shake.after("camera_follow"))
+					.with_system(apply_hit_stop),
+			)
+			.add_system_set(SystemSet::on_exit(GameState::Playing).with_system(reset_feel));
+	}
+}
+
+pub struct FeelParams {
+	pub enabled: bool,
+	/// Shake when the player takes a bullet, pixels and seconds
+	pub player_hit_shake: (f32, f32),
+	/// Shake when a boss part breaks off, pixels and seconds
+	pub part_break_shake: (f32, f32),
+	/// Shake when firing a weapon with a kick, pixels per pixel / sec of `WeaponSpec::kick` and seconds
+	pub kick_shake: (f32, f32),
+	/// Seconds the world freezes for
+	pub hit_stop_secs: f32,
+}
+
+impl Default for FeelParams {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			player_hit_shake: (4.0, 0.2),
+			part_break_shake: (8.0, 0.35),
+			kick_shake: (0.02, 0.15),
+			hit_stop_secs: 0.06,
+		}
+	}
+}
+
+/// Offsets the camera by up to `magnitude` pixels, decaying to zero over `duration` seconds
+pub struct CameraShakeEvent {
+	pub magnitude: f32,
+	pub duration: f32,
+}
+
+/// Freezes the physics world for the given seconds
+pub struct HitStopEvent(pub f32);
+
+/// The strongest running shake and the offset currently applied to the camera
+#[derive(Default)]
+struct CameraShake {
+	magnitude: f32,
+	timer: Timer,
+	offset: Vec2,
+}
+
+#[derive(Default)]
+struct HitStop(Option<Timer>);
+
+/// Takes the last frame's offset back out, so the camera follow only sees the real position
+fn remove_camera_shake(
+	mut shake: ResMut<CameraShake>,
+	mut q_camera: Query<&mut Transform, With<MainCamera>>,
+) {
+	for mut transform in q_camera.iter_mut() {
+		transform.translation -= shake.offset.extend(0.0);
+	}
+	shake.offset = Vec2::ZERO;
+}
+
+fn apply_camera_shake(
+	mut shake_events: EventReader<CameraShakeEvent>,
+	mut shake: ResMut<CameraShake>,
+	params: Res<FeelParams>,
+	settings: Res<Settings>,
+	time: Res<Time>,
+	mut q_camera: Query<&mut Transform, With<MainCamera>>,
+) {
+	for CameraShakeEvent {
+		magnitude,
+		duration,
+	} in shake_events.iter()
+	{
+		// a weaker shake doesn't cut a stronger one short
+		let current = if shake.timer.finished() {
+			0.0
+		} else {
+			shake.magnitude * shake.timer.percent_left()
+		};
+		if *magnitude >= current {
+			shake.magnitude = *magnitude;
+			shake.timer = Timer::from_seconds(*duration, false);
+		}
+	}
+
+	shake.timer.tick(time.delta());
+	if !params.enabled || !settings.screen_shake || shake.timer.finished() {
+		return;
+	}
+
+	let strength = shake.magnitude * shake.timer.percent_left();
+	shake.offset = Vec2::new(
+		rand::random::<f32>() * 2.0 - 1.0,
+		rand::random::<f32>() * 2.0 - 1.0,
+	) * strength;
+	for mut transform in q_camera.iter_mut() {
+		transform.translation += shake.offset.extend(0.0);
+	}
+}
+
+/// Pauses the physics pipeline while a hit stop runs. Only the physics freeze, so timers and input keep going
+fn apply_hit_stop(
+	mut hit_stop_events: EventReader<HitStopEvent>,
+	mut hit_stop: ResMut<HitStop>,
+	params: Res<FeelParams>,
+	time: Res<Time>,
+	mut rapier_config: ResMut<RapierConfiguration>,
+) {
+	for HitStopEvent(secs) in hit_stop_events.iter() {
+		let remaining = hit_stop.0.as_ref().map_or(0.0, |timer| {
+			timer.duration().as_secs_f32() - timer.elapsed_secs()
+		});
+		if params.enabled && *secs > remaining {
+			hit_stop.0 = Some(Timer::from_seconds(*secs, false));
+		}
+	}
+
+	let stopped = match hit_stop.0.as_mut() {
+		Some(timer) => !timer.tick(time.delta()).finished(),
+		None => false,
+	};
+	if !stopped {
+		hit_stop.0 = None;
+	}
+	// only touch the config when it changes, so its change detection stays meaningful
+	if rapier_config.physics_pipeline_active == stopped {
+		rapier_config.physics_pipeline_active = !stopped;
+	}
+}
+
+/// A pause or game over mid shake or hit stop must not leave the camera offset or the physics frozen
+fn reset_feel(
+	mut shake: ResMut<CameraShake>,
+	mut hit_stop: ResMut<HitStop>,
+	mut rapier_config: ResMut<RapierConfiguration>,
+	mut q_camera: Query<&mut Transform, With<MainCamera>>,
+) {
+	for mut transform in q_camera.iter_mut() {
+		transform.translation -= shake.offset.extend(0.0);
+	}
+	*shake = CameraShake::default();
+	*hit_stop = HitStop::default();
+	rapier_config.physics_pipeline_active = true;
+}
@@ -1,18 +1,23 @@
 use std::time::Duration;
 
 use bevy::{
-	ecs::schedule::ShouldRun,
+	math::Vec3Swizzles,
 	prelude::*,
 	tasks::{AsyncComputeTaskPool, Task},
+	window::WindowFocused,
 };
 use futures_lite::future;
 use serde::{Deserialize, Serialize};
 use wasm_bindgen_futures::JsFuture;
 use web_sys::Response;
 
+use bevy_inspector_egui::Inspectable;
+use bevy_rapier2d::prelude::*;
+
 use crate::{
-	enemy::{Boss, EnemyParams},
+	enemy::{Boss, BossPart, Enemy, EnemyParams, SplitOnDeath, SupportAura},
 	player::Player,
+	settings::Settings,
 };
 
 /// Plugin that handles when game restarts and tracks the player's score.
@@ -20,151 +25,732 @@ use crate::{
 /// Score is how long the player stays alive, given the current upgrade level of the boss (inc. enemy)
 pub struct GamePlugin;
 
+/// Loads the leaderboard again right away, e.g. from the retry button after it failed
 pub struct LeaderboardEvent;
 
 impl Plugin for GamePlugin {
 	fn build(&self, app: &mut App) {
 		app.insert_resource(GameGlobals {
 			level: 1,
-			minions: 0,
 			min_upgrade_health: 20.0,
 			time_until_restart: Duration::from_secs(15),
+			auto_restart: true,
+			start_lives: 3,
+			victory_level: 5,
+			victory_bonus: 100,
+			time_until_continue: Duration::from_secs(5),
 			scores: vec![],
 			..Default::default()
 		})
+		.insert_resource(GameClock::default())
+		.insert_resource(Lives(0))
+		.insert_resource(BulletTimeParams::default())
+		.insert_resource(BulletTime::default())
+		.insert_resource(LogParams::default())
+		.insert_resource(LeaderboardStatus::default())
 		.add_event::<LeaderboardEvent>()
-		.add_state(GameState::Playing)
+		.add_event::<DamageEvent>()
+		.add_event::<DamageDealtEvent>()
+		.add_event::<DeathEvent>()
+		.add_event::<PlayerRespawnEvent>()
+		.add_state(GameState::Loading)
+		.add_system(toggle_pause.label("toggle_pause"))
+		.add_system(pause_on_focus_loss)
+		.add_system(toggle_verbose_logs)
 		.add_system_set(
 			SystemSet::on_update(GameState::Playing)
-				.with_system(restart_game_when_player_dies)
-				.with_system(update_score)
-				.with_system(update_level_over_time),
+				.with_system(tick_game_clock.before("update_score"))
+				.with_system(tick_invulnerability.before("apply_damage"))
+				.with_system(tick_bullet_time.before("apply_damage"))
+				.with_system(apply_damage.label("apply_damage"))
+				.with_system(
+					restart_game_when_player_dies
+						.label("player_death")
+						.after("apply_damage"),
+				)
+				.with_system(detect_deaths.label("detect_deaths").after("apply_damage"))
+				.with_system(update_score.label("update_score"))
+				.with_system(update_level_over_time)
+				.with_system(
+					win_when_boss_dies
+						.after("detect_deaths")
+						.after("player_death"),
+				),
+		)
+		.add_system_set(
+			SystemSet::on_enter(GameState::Playing)
+				.with_system(reset_game_globals.label("reset_game_globals")),
 		)
-		.add_system_set(SystemSet::on_enter(GameState::Playing).with_system(reset_game_globals))
 		.add_system_set(SystemSet::on_exit(GameState::Playing).with_system(teardown))
+		// whatever is pushed on top of the run, the pause or the console
+		.add_system_set(SystemSet::on_pause(GameState::Playing).with_system(pause_physics))
+		.add_system_set(SystemSet::on_resume(GameState::Playing).with_system(resume_physics))
 		.add_system_set(
 			SystemSet::on_enter(GameState::GameOver)
-				.with_system(upload_highscores)
-				.with_system(display_highscores_when_loaded),
+				.with_system(reset_game_over_clock)
+				.with_system(upload_highscores),
+		)
+		.add_system_set(
+			SystemSet::on_update(GameState::GameOver)
+				.with_system(display_highscores_when_loaded)
+				.with_system(retry_leaderboard)
+				.with_system(tick_game_over_clock.before("restart_game_timer"))
+				.with_system(restart_game_timer.label("restart_game_timer"))
+				.with_system(restart_game_on_input),
+		)
+		.add_system_set(SystemSet::on_exit(GameState::GameOver).with_system(teardown))
+		.add_system_set(SystemSet::on_enter(GameState::Victory).with_system(reset_game_over_clock))
+		.add_system_set(
+			SystemSet::on_update(GameState::Victory)
+				.with_system(tick_game_over_clock.before("continue_after_victory"))
+				.with_system(continue_after_victory.label("continue_after_victory")),
 		)
-		.add_system_set(SystemSet::on_update(GameState::GameOver).with_system(restart_game_timer))
-		.add_system_set(SystemSet::on_exit(GameState::GameOver).with_system(teardown));
+		.add_system_set(SystemSet::on_exit(GameState::Victory).with_system(teardown));
 	}
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 pub enum GameState {
+	/// Preloads assets before the first run, see `LoadingPlugin`
+	Loading,
 	Playing,
+	/// Pushed on top of `Playing`, so the run continues where it left off when popped
+	Paused,
+	/// Dev console of the `debug` feature, pushed on top of `Playing` or `Paused` like a pause without
+	/// the settings screen
+	Console,
 	GameOver,
+	/// The boss was killed, the run continues a level higher afterwards
+	Victory,
 }
 
 #[derive(Default)]
 pub struct GameGlobals {
 	pub level: u32,
 	pub score: u32,
-	pub time_started: Duration,
-	pub minions: u32,
 	pub min_upgrade_health: f32,
 	pub scores: Vec<LeaderboardScore>,
 	pub time_stopped: Duration,
+	/// Only used when `auto_restart` is on
 	pub time_until_restart: Duration,
+	/// When off, the game over screen stays up until the player restarts manually
+	pub auto_restart: bool,
+	/// Score for killed enemies, added on top of the time based score
+	pub kill_score: u32,
+	/// Lives at the start of a run, 1 ends the run on the first death
+	pub start_lives: u32,
+	/// From this level on the boss isn't upgraded anymore and killing it wins
+	pub victory_level: u32,
+	/// Score per boss level for killing the boss
+	pub victory_bonus: u32,
+	/// Time on the victory screen before the next level starts
+	pub time_until_continue: Duration,
+	/// Set by a victory, so the next `Playing` continues the run instead of starting over
+	pub continue_run: bool,
 }
 
+/// Remaining lives of the player in the current run
+pub struct Lives(pub u32);
+
+/// Sent when the player died but has lives left, the player is then reset instead of ending the run
+pub struct PlayerRespawnEvent(pub Entity);
+
 #[derive(Component)]
 pub struct Health(pub f32);
 
-fn reset_game_globals(mut globals: ResMut<GameGlobals>, time: Res<Time>) {
-	globals.time_started = time.time_since_startup();
-	globals.level = 1;
-	globals.score = 0;
-	globals.minions = 0;
+/// Damage dealt to an entity with `Health`. All damage goes through this event, so rules like
+/// invulnerability only have to be handled in `apply_damage`
+pub struct DamageEvent {
+	pub target: Entity,
+	pub amount: f32,
+	pub source: DamageSource,
 }
 
-pub fn run_when_enter_playing_state(
-	state: Res<State<GameState>>,
-	globals: Res<GameGlobals>,
+/// Sent by `apply_damage` for every `DamageEvent` that actually took health, with the amount after
+/// reductions
+pub struct DamageDealtEvent {
+	pub target: Entity,
+	pub amount: f32,
+	pub source: DamageSource,
+}
+
+/// What dealt the damage, so some entities can be made immune to everything but one source
+#[derive(Inspectable, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum DamageSource {
+	Bullet,
+	/// Bullet that bounced off a wall before hitting
+	Ricochet,
+	/// Boss attacks like fire patches and the rage beam, and minions touching the player
+	Attack,
+	/// Arena hazards and the sudden death zone
+	Environment,
+	/// The player's melee swing
+	Melee,
+	/// Burning and poison, see `StatusEffects`
+	Status,
+}
+
+impl DamageSource {
+	/// Shown to the player, e.g. when the boss is immune to everything else
+	pub fn name(&self) -> &'static str {
+		match self {
+			DamageSource::Bullet => "bullets",
+			DamageSource::Ricochet => "bounced bullets",
+			DamageSource::Attack => "boss attacks",
+			DamageSource::Environment => "hazards",
+			DamageSource::Melee => "melee attacks",
+			DamageSource::Status => "burning and poison",
+		}
+	}
+}
+
+/// Sent for every non-player entity whose health dropped to zero. Players end the run instead,
+/// see `restart_game_when_player_dies`
+pub struct DeathEvent(pub Entity);
+
+/// Entities with this component ignore all damage until the timer finishes. The optional shield
+/// entity is pulsed while it lasts and despawned afterwards
+#[derive(Component)]
+pub struct Invulnerable {
+	pub timer: Timer,
+	pub shield: Option<Entity>,
+}
+
+/// Entities with this component only take damage from the `except` source
+#[derive(Component)]
+pub struct DamageImmunity {
+	pub except: DamageSource,
+}
+
+/// Bullet time kicks in when the player's health drops below `health_threshold`, at most once per `cooldown`
+pub struct BulletTimeParams {
+	pub health_threshold: f32,
+	/// Seconds
+	pub duration: f32,
+	/// Seconds from the end of one bullet time to the start of the next
+	pub cooldown: f32,
+	/// Speed of enemies and their bullets during bullet time
+	pub time_scale: f32,
+}
+
+impl Default for BulletTimeParams {
+	fn default() -> Self {
+		Self {
+			health_threshold: 25.0,
+			duration: 2.0,
+			cooldown: 20.0,
+			time_scale: 0.3,
+		}
+	}
+}
+
+/// Slows enemies and their bullets down while the player keeps moving at normal speed.
+/// Enemy systems multiply their deltas and speeds by `scale`
+pub struct BulletTime {
+	pub scale: f32,
+	active: Option<Timer>,
+	cooldown: Option<Timer>,
+}
+
+impl Default for BulletTime {
+	fn default() -> Self {
+		Self {
+			scale: 1.0,
+			active: None,
+			cooldown: None,
+		}
+	}
+}
+
+fn tick_bullet_time(
+	mut bullet_time: ResMut<BulletTime>,
+	params: Res<BulletTimeParams>,
 	time: Res<Time>,
-) -> ShouldRun {
-	if *state.current() == GameState::Playing && time.time_since_startup() == globals.time_started {
-		ShouldRun::YesAndCheckAgain
-	} else {
-		ShouldRun::NoAndCheckAgain
+) {
+	if let Some(active) = bullet_time.active.as_mut() {
+		active.tick(time.delta());
+		if active.finished() {
+			bullet_time.active = None;
+			bullet_time.cooldown = Some(Timer::from_seconds(params.cooldown, false));
+			bullet_time.scale = 1.0;
+		}
+	} else if let Some(cooldown) = bullet_time.cooldown.as_mut() {
+		cooldown.tick(time.delta());
+		if cooldown.finished() {
+			bullet_time.cooldown = None;
+		}
+	}
+}
+
+fn apply_damage(
+	mut damage_events: EventReader<DamageEvent>,
+	mut q_health: Query<&mut Health, Without<Invulnerable>>,
+	q_immunity: Query<&DamageImmunity>,
+	(q_parts, q_invulnerable): (
+		Query<&Parent, With<BossPart>>,
+		Query<(), With<Invulnerable>>,
+	),
+	q_enemy_t: Query<&Transform, With<Enemy>>,
+	q_auras: Query<(Entity, &Transform, &SupportAura)>,
+	q_players: Query<&Player>,
+	mut bullet_time: ResMut<BulletTime>,
+	bullet_time_params: Res<BulletTimeParams>,
+	log_params: Res<LogParams>,
+	mut dealt_ew: EventWriter<DamageDealtEvent>,
+) {
+	for DamageEvent {
+		target,
+		amount,
+		source,
+	} in damage_events.iter()
+	{
+		// boss parts share the boss' immunity and invulnerability
+		let owner = q_parts.get(*target).map_or(*target, |Parent(boss)| *boss);
+		if matches!(q_immunity.get(owner), Ok(immunity) if immunity.except != *source) {
+			continue;
+		}
+		if owner != *target && q_invulnerable.get(owner).is_ok() {
+			continue;
+		}
+		if let Ok(mut health) = q_health.get_mut(*target) {
+			// enemies near a support minion take less damage, the strongest aura wins. Supports don't protect themselves
+			let reduction = q_enemy_t.get(*target).map_or(0.0, |target_t| {
+				q_auras
+					.iter()
+					.filter(|(aura_e, aura_t, aura)| {
+						aura_e != target
+							&& aura_t.translation.xy().distance(target_t.translation.xy())
+								<= aura.radius
+					})
+					.fold(0.0, |reduction: f32, (_, _, aura)| {
+						reduction.max(aura.reduction)
+					})
+			});
+			let dealt = amount * (1.0 - reduction.clamp(0.0, 1.0));
+			health.0 -= dealt;
+			dealt_ew.send(DamageDealtEvent {
+				target: *target,
+				amount: dealt,
+				source: *source,
+			});
+
+			// give a player that's about to die a chance to dodge
+			let ready = bullet_time.active.is_none() && bullet_time.cooldown.is_none();
+			if ready
+				&& q_players.get(*target).is_ok()
+				&& health.0 > 0.0
+				&& health.0 < bullet_time_params.health_threshold
+			{
+				debug!("BULLET_TIME");
+				bullet_time.active = Some(Timer::from_seconds(bullet_time_params.duration, false));
+				bullet_time.scale = bullet_time_params.time_scale;
+			}
+			if log_params.verbose {
+				info!("DAMAGE -> HEALTH {}", health.0);
+			}
+		}
+	}
+}
+
+/// Only ticks while playing and doesn't depend on any animation, so invulnerability always ends
+fn tick_invulnerability(
+	mut commands: Commands,
+	mut q_invulnerable: Query<(Entity, &mut Invulnerable)>,
+	mut q_sprites: Query<&mut Sprite>,
+	time: Res<Time>,
+) {
+	for (entity, mut invulnerable) in q_invulnerable.iter_mut() {
+		invulnerable.timer.tick(time.delta());
+
+		if invulnerable.timer.finished() {
+			if let Some(shield) = invulnerable.shield {
+				commands.entity(shield).despawn_recursive();
+			}
+			commands.entity(entity).remove::<Invulnerable>();
+		} else if let Some(Ok(mut sprite)) = invulnerable.shield.map(|e| q_sprites.get_mut(e)) {
+			// pulse faster towards the end so players know when to start shooting
+			let pulse = invulnerable.timer.percent() * invulnerable.timer.percent() * 40.0;
+			sprite.color.set_a(0.3 + 0.2 * pulse.sin());
+		}
+	}
+}
+
+fn detect_deaths(
+	q_health: Query<(Entity, &Health), Without<Player>>,
+	mut death_ew: EventWriter<DeathEvent>,
+) {
+	for (entity, Health(health)) in q_health.iter() {
+		if *health <= 0.0 {
+			death_ew.send(DeathEvent(entity));
+		}
+	}
+}
+
+/// Game time of the current run. Unlike `Time::time_since_startup()` it only advances while `Playing`,
+/// so pausing doesn't count towards the score or any other time based gameplay
+#[derive(Default)]
+pub struct GameClock {
+	elapsed: Duration,
+	game_over_elapsed: Duration,
+}
+
+impl GameClock {
+	/// Playing time until all colliders are in the query pipeline and the waypoint graph can be built
+	pub const WARM_UP: Duration = Duration::from_secs(3);
+
+	/// Time spent playing since the run started
+	pub fn elapsed(&self) -> Duration {
+		self.elapsed
+	}
+
+	pub fn elapsed_secs(&self) -> f32 {
+		self.elapsed.as_secs_f32()
+	}
+
+	/// Time spent on the game over or victory screen
+	pub fn game_over_elapsed(&self) -> Duration {
+		self.game_over_elapsed
+	}
+
+	pub fn warmed_up(&self) -> bool {
+		self.elapsed >= Self::WARM_UP
+	}
+
+	fn tick(&mut self, delta: Duration) {
+		self.elapsed += delta;
+	}
+}
+
+fn tick_game_clock(mut clock: ResMut<GameClock>, time: Res<Time>) {
+	clock.tick(time.delta());
+}
+
+fn tick_game_over_clock(mut clock: ResMut<GameClock>, time: Res<Time>) {
+	clock.game_over_elapsed += time.delta();
+}
+
+fn reset_game_over_clock(mut clock: ResMut<GameClock>) {
+	clock.game_over_elapsed = Duration::ZERO;
+}
+
+/// Pauses when the window loses focus and resumes when it gets it back, unless the player paused
+/// themselves in the meantime. The game clock doesn't advance while paused
+fn pause_on_focus_loss(
+	mut focus_events: EventReader<WindowFocused>,
+	settings: Res<Settings>,
+	mut state: ResMut<State<GameState>>,
+	mut auto_paused: Local<bool>,
+) {
+	for WindowFocused { focused, .. } in focus_events.iter() {
+		if !settings.pause_on_focus_loss {
+			continue;
+		}
+		match (*focused, state.current()) {
+			(false, GameState::Playing) => {
+				if state.push(GameState::Paused).is_ok() {
+					*auto_paused = true;
+				}
+			}
+			(true, GameState::Paused) if *auto_paused => {
+				let _ = state.pop();
+				*auto_paused = false;
+			}
+			_ => {}
+		}
+	}
+	// unpausing by hand takes over
+	if *auto_paused && *state.current() != GameState::Paused {
+		*auto_paused = false;
+	}
+}
+
+/// Runtime switch for logs that would otherwise fire many times per frame, like damage dealt
+/// or failed path requests. Toggled with F2. They log at info level, so they show up in release
+/// builds too
+#[derive(Default)]
+pub struct LogParams {
+	pub verbose: bool,
+}
+
+fn toggle_verbose_logs(keyboard_input: Res<Input<KeyCode>>, mut log_params: ResMut<LogParams>) {
+	if keyboard_input.just_pressed(KeyCode::F2) {
+		log_params.verbose = !log_params.verbose;
+		info!("Verbose logs: {}", log_params.verbose);
 	}
 }
 
+/// Pauses or resumes the run when pressing the pause key, escape by default
+fn toggle_pause(
+	keyboard_input: Res<Input<KeyCode>>,
+	settings: Res<Settings>,
+	mut state: ResMut<State<GameState>>,
+) {
+	if !keyboard_input.just_pressed(settings.key_bindings.pause) {
+		return;
+	}
+
+	let _ = match state.current().clone() {
+		GameState::Playing => state.push(GameState::Paused),
+		GameState::Paused => state.pop(),
+		// the console closes with its own key
+		GameState::Loading | GameState::GameOver | GameState::Victory | GameState::Console => {
+			Ok(())
+		}
+	};
+}
+
+fn pause_physics(mut rapier_config: ResMut<RapierConfiguration>) {
+	rapier_config.physics_pipeline_active = false;
+}
+
+fn resume_physics(mut rapier_config: ResMut<RapierConfiguration>) {
+	rapier_config.physics_pipeline_active = true;
+}
+
+fn reset_game_globals(
+	mut globals: ResMut<GameGlobals>,
+	mut clock: ResMut<GameClock>,
+	mut lives: ResMut<Lives>,
+	mut bullet_time: ResMut<BulletTime>,
+) {
+	*clock = GameClock::default();
+	*bullet_time = BulletTime::default();
+	// after a victory the score of the finished level is already in `kill_score`, since the clock restarts
+	if globals.continue_run {
+		globals.continue_run = false;
+		globals.level += 1;
+		globals.score = globals.kill_score;
+		return;
+	}
+	lives.0 = globals.start_lives.max(1);
+	globals.level = 1;
+	globals.score = 0;
+	globals.kill_score = 0;
+}
+
+/// Bosses that split on death are not upgraded, they die and the next level starts once their minions are cleared.
+/// From `victory_level` on the boss isn't upgraded anymore either, so it can be killed
 fn update_level_over_time(
-	mut q_health: Query<&mut Health, With<Boss>>,
+	mut q_health: Query<&mut Health, (With<Boss>, Without<SplitOnDeath>)>,
 	enemy_params: ResMut<EnemyParams>,
 	mut state: ResMut<State<GameState>>,
 	time: Res<Time>,
 	mut globals: ResMut<GameGlobals>,
 ) {
+	if globals.level >= globals.victory_level {
+		return;
+	}
 	if let Ok(mut health) = q_health.get_single_mut() {
 		if health.0 < globals.min_upgrade_health {
 			health.0 = enemy_params.start_health;
 			globals.level += 1;
-			globals.minions += globals.level;
 		}
 	}
 }
 
+/// Every death costs a life, the run is only over once none are left
 fn restart_game_when_player_dies(
-	q_player: Query<&Health, With<Player>>,
+	q_player: Query<(Entity, &Health), With<Player>>,
 	mut state: ResMut<State<GameState>>,
-	time: Res<Time>,
+	clock: Res<GameClock>,
 	mut globals: ResMut<GameGlobals>,
+	mut lives: ResMut<Lives>,
+	mut respawn_ew: EventWriter<PlayerRespawnEvent>,
 ) {
-	for Health(health) in q_player.iter() {
+	for (entity, Health(health)) in q_player.iter() {
 		if *health <= 0.0 {
-			let _ = state.overwrite_set(GameState::GameOver);
-			globals.time_stopped = time.time_since_startup();
+			lives.0 = lives.0.saturating_sub(1);
+			if lives.0 > 0 {
+				respawn_ew.send(PlayerRespawnEvent(entity));
+			} else {
+				let _ = state.overwrite_set(GameState::GameOver);
+				globals.time_stopped = clock.elapsed();
+			}
+		}
+	}
+}
+
+/// Killing a boss that isn't upgraded anymore wins the level. Bosses that split on death are beaten
+/// by killing their minions and come back a level stronger instead
+fn win_when_boss_dies(
+	q_boss: Query<&Health, (With<Boss>, Without<SplitOnDeath>)>,
+	mut state: ResMut<State<GameState>>,
+	clock: Res<GameClock>,
+	mut globals: ResMut<GameGlobals>,
+) {
+	if let Ok(Health(health)) = q_boss.get_single() {
+		if *health <= 0.0 && *state.current() == GameState::Playing {
+			debug!("VICTORY");
+			let bonus = globals.victory_bonus * globals.level;
+			globals.score =
+				(clock.elapsed().as_secs() as u32) * globals.level + globals.kill_score + bonus;
+			globals.kill_score = globals.score;
+			globals.continue_run = true;
+			globals.time_stopped = clock.elapsed();
+			let _ = state.overwrite_set(GameState::Victory);
 		}
 	}
 }
 
-fn upload_highscores(globals: Res<GameGlobals>, thread_pool: Res<AsyncComputeTaskPool>) {
+/// Starts the next level after a while or when pressing enter or space
+fn continue_after_victory(
+	keyboard_input: Res<Input<KeyCode>>,
+	clock: Res<GameClock>,
+	globals: Res<GameGlobals>,
+	mut state: ResMut<State<GameState>>,
+) {
+	if clock.game_over_elapsed() > globals.time_until_continue
+		|| keyboard_input.any_just_pressed([KeyCode::Return, KeyCode::Space])
+	{
+		let _ = state.overwrite_set(GameState::Playing);
+	}
+}
+
+/// Whether the leaderboard shown on the game over screen is still loading, loaded or failed to load
+#[derive(Default)]
+pub struct LeaderboardStatus {
+	pub state: LeaderboardState,
+	/// Failed attempts in a row, every one doubles the wait before the next retry
+	attempts: u32,
+	/// Counts down to the next retry after a failure
+	retry: Timer,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum LeaderboardState {
+	Loading,
+	Loaded,
+	/// Why it failed
+	Failed(String),
+}
+
+impl Default for LeaderboardState {
+	fn default() -> Self {
+		LeaderboardState::Loading
+	}
+}
+
+impl LeaderboardStatus {
+	/// Seconds until the next automatic retry
+	pub fn retry_in(&self) -> f32 {
+		self.retry.duration().as_secs_f32() - self.retry.elapsed_secs()
+	}
+}
+
+/// Seconds before the first retry and the longest wait between retries
+const LEADERBOARD_RETRY_SECS: (f32, f32) = (2.0, 60.0);
+
+#[derive(Component)]
+struct LeaderboardTask(Task<Result<Vec<LeaderboardScore>, String>>);
+
+fn upload_highscores(
+	mut commands: Commands,
+	globals: Res<GameGlobals>,
+	mut status: ResMut<LeaderboardStatus>,
+	thread_pool: Res<AsyncComputeTaskPool>,
+) {
+	*status = LeaderboardStatus::default();
 	// publish highscores to web api
 	let score = globals.score;
-	thread_pool.spawn(async move {
+	let task = thread_pool.spawn(async move {
 		let _ = Leaderboard::add_score(score, format!("player-{}", rand::random::<u32>()).as_str())
 			.await;
-		let res = Leaderboard::leaderboard().await.unwrap();
-		res.scores
+		fetch_leaderboard().await
 	});
+	commands.spawn().insert(LeaderboardTask(task));
+}
+
+/// JS errors can't leave the task, so they are turned into their description
+async fn fetch_leaderboard() -> Result<Vec<LeaderboardScore>, String> {
+	Leaderboard::leaderboard()
+		.await
+		.map(|res| res.scores)
+		.map_err(|err| format!("{:?}", err))
 }
 
 fn display_highscores_when_loaded(
 	mut commands: Commands,
 	mut globals: ResMut<GameGlobals>,
-	mut transform_tasks: Query<(Entity, &mut Task<Vec<LeaderboardScore>>)>,
+	mut status: ResMut<LeaderboardStatus>,
+	mut tasks: Query<(Entity, &mut LeaderboardTask)>,
 ) {
-	for (entity, mut task) in transform_tasks.iter_mut() {
-		if let Some(scores) = future::block_on(future::poll_once(&mut *task)) {
-			globals.scores = scores;
-			// Task is complete, so remove task component from entity
-			commands.entity(entity).remove::<Task<Transform>>();
+	for (entity, mut task) in tasks.iter_mut() {
+		let result = match future::block_on(future::poll_once(&mut task.0)) {
+			Some(result) => result,
+			None => continue,
+		};
+		// Task is complete, so remove the entity holding it
+		commands.entity(entity).despawn();
+		match result {
+			Ok(scores) => {
+				globals.scores = scores;
+				status.state = LeaderboardState::Loaded;
+				status.attempts = 0;
+			}
+			Err(err) => {
+				warn!("Couldn't load leaderboard: {}", err);
+				status.attempts += 1;
+				let (first, max) = LEADERBOARD_RETRY_SECS;
+				let wait = (first * 2f32.powi(status.attempts as i32 - 1)).min(max);
+				status.retry = Timer::from_seconds(wait, false);
+				status.state = LeaderboardState::Failed(err);
+			}
 		}
 	}
 }
 
-fn restart_game_timer(
+/// Loads the leaderboard again once the backoff ran out or when asked to. The score was already uploaded
+fn retry_leaderboard(
+	mut commands: Commands,
+	mut retry_events: EventReader<LeaderboardEvent>,
+	mut status: ResMut<LeaderboardStatus>,
+	thread_pool: Res<AsyncComputeTaskPool>,
 	time: Res<Time>,
+) {
+	let requested = retry_events.iter().count() > 0;
+	if !matches!(status.state, LeaderboardState::Failed(_)) {
+		return;
+	}
+	status.retry.tick(time.delta());
+	if !requested && !status.retry.finished() {
+		return;
+	}
+
+	debug!("RETRY_LEADERBOARD {}", status.attempts);
+	status.state = LeaderboardState::Loading;
+	let task = thread_pool.spawn(fetch_leaderboard());
+	commands.spawn().insert(LeaderboardTask(task));
+}
+
+fn restart_game_timer(
+	clock: Res<GameClock>,
 	globals: Res<GameGlobals>,
 	mut state: ResMut<State<GameState>>,
 ) {
-	if time.time_since_startup() > (globals.time_stopped + globals.time_until_restart) {
+	if globals.auto_restart && clock.game_over_elapsed() > globals.time_until_restart {
+		let _ = state.overwrite_set(GameState::Playing);
+	}
+}
+
+/// Restarts when pressing enter or space on the game over screen, if the game doesn't restart by itself
+fn restart_game_on_input(
+	keyboard_input: Res<Input<KeyCode>>,
+	globals: Res<GameGlobals>,
+	mut state: ResMut<State<GameState>>,
+) {
+	if !globals.auto_restart && keyboard_input.any_just_pressed([KeyCode::Return, KeyCode::Space]) {
 		let _ = state.overwrite_set(GameState::Playing);
 	}
 }
 
 /// updates score when player is there
-fn update_score(time: Res<Time>, mut globals: ResMut<GameGlobals>) {
-	globals.score =
-		((time.time_since_startup() - globals.time_started).as_secs() as u32) * globals.level;
+fn update_score(clock: Res<GameClock>, mut globals: ResMut<GameGlobals>) {
+	globals.score = (clock.elapsed().as_secs() as u32) * globals.level + globals.kill_score;
 }
 
-/// remove all entities that are not a camera
-fn teardown(mut commands: Commands, entities: Query<Entity, Without<Camera>>) {
+/// remove all entities that are not a camera.
+/// Children are despawned with their parents, despawning them on their own would despawn them twice
+fn teardown(mut commands: Commands, entities: Query<Entity, (Without<Camera>, Without<Parent>)>) {
 	for entity in entities.iter() {
 		commands.entity(entity).despawn_recursive();
 	}
@@ -200,7 +786,9 @@ impl Leaderboard {
 	pub async fn leaderboard() -> Result<LeaderboardResponse, JsValue> {
 		let res = Self::fetch_api("/scores", Some(format!("table_id={}", TABLE_ID))).await?;
 		let json = JsFuture::from(res.json()?).await?;
-		let leaderboard: LeaderboardJSON = json.into_serde().unwrap();
+		let leaderboard: LeaderboardJSON = json
+			.into_serde()
+			.map_err(|err| JsValue::from_str(&err.to_string()))?;
 		Ok(leaderboard.response)
 	}
 
@@ -230,8 +818,185 @@ impl Leaderboard {
 		let signature = format!("{:x}", &md5::compute(format!("{}{}", url, PRIVATE_KEY)));
 		let url = format!("{}&signature={}", url, signature);
 
-		JsFuture::from(window.fetch_with_str(&url))
+		let res = JsFuture::from(window.fetch_with_str(&url))
 			.await?
-			.dyn_into::<Response>()
+			.dyn_into::<Response>()?;
+		if !res.ok() {
+			return Err(JsValue::from_str(&format!("HTTP {}", res.status())));
+		}
+		Ok(res)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bevy::{
+		asset::AssetPlugin,
+		tasks::{IoTaskPool, TaskPool},
+		utils::Instant,
+	};
+
+	use super::*;
+	use crate::{
+		enemy::{spawn_boss, BossPhase, BossSpawnEvent},
+		physics::SetupPhysicsPlugin,
+		player::{spawn_player, PlayerParams, PlayerSpawnEvent},
+		shooting::WeaponParams,
+	};
+
+	/// Runs `secs` seconds of frames 100 ms apart. The time is stepped by hand, so tests don't depend
+	/// on how fast they run
+	fn run_for(app: &mut App, secs: f32) {
+		for _ in 0..(secs * 10.0).round() as u32 {
+			{
+				let mut time = app.world.get_resource_mut::<Time>().unwrap();
+				let now = time.last_update().unwrap_or_else(Instant::now);
+				time.update_with_instant(now + Duration::from_millis(100));
+			}
+			app.update();
+		}
+	}
+
+	/// The systems of a run's start, the player's death, the game over screen and the restart.
+	/// Without rendering, physics or the leaderboard
+	fn restart_app() -> App {
+		let mut app = App::new();
+		app.insert_resource(IoTaskPool(TaskPool::new()))
+			.add_plugin(AssetPlugin)
+			.add_plugin(SetupPhysicsPlugin)
+			.insert_resource(Time::default())
+			.insert_resource(GameGlobals {
+				start_lives: 1,
+				auto_restart: true,
+				time_until_restart: Duration::from_secs(1),
+				..Default::default()
+			})
+			.insert_resource(GameClock::default())
+			.insert_resource(Lives(0))
+			.insert_resource(BulletTime::default())
+			.insert_resource(Settings::default())
+			.insert_resource(PlayerParams::default())
+			.insert_resource(WeaponParams::default())
+			.insert_resource(EnemyParams::default())
+			.add_event::<PlayerRespawnEvent>()
+			.add_event::<PlayerSpawnEvent>()
+			.add_event::<BossSpawnEvent>()
+			.add_state(GameState::Playing)
+			.add_system_set(
+				SystemSet::on_enter(GameState::Playing)
+					.with_system(reset_game_globals)
+					.with_system(spawn_player)
+					.with_system(spawn_boss),
+			)
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(tick_game_clock)
+					.with_system(restart_game_when_player_dies),
+			)
+			.add_system_set(SystemSet::on_exit(GameState::Playing).with_system(teardown))
+			.add_system_set(
+				SystemSet::on_enter(GameState::GameOver).with_system(reset_game_over_clock),
+			)
+			.add_system_set(
+				SystemSet::on_update(GameState::GameOver)
+					.with_system(tick_game_over_clock.before("restart_game_timer"))
+					.with_system(restart_game_timer.label("restart_game_timer")),
+			)
+			.add_system_set(SystemSet::on_exit(GameState::GameOver).with_system(teardown));
+		app
+	}
+
+	fn current_state(app: &App) -> GameState {
+		app.world
+			.get_resource::<State<GameState>>()
+			.unwrap()
+			.current()
+			.clone()
+	}
+
+	#[test]
+	fn restart_after_death_spawns_a_fresh_player_and_boss() {
+		let mut app = restart_app();
+		run_for(&mut app, 0.5);
+
+		// the boss was hurt into a later phase and the player's last life runs out
+		let mut q_boss = app
+			.world
+			.query_filtered::<(&mut Health, &mut BossPhase), With<Boss>>();
+		for (mut health, mut phase) in q_boss.iter_mut(&mut app.world) {
+			health.0 = 10.0;
+			phase.0 = 2;
+		}
+		let mut q_player = app.world.query_filtered::<&mut Health, With<Player>>();
+		for mut health in q_player.iter_mut(&mut app.world) {
+			health.0 = 0.0;
+		}
+		run_for(&mut app, 0.1);
+		assert_eq!(current_state(&app), GameState::GameOver);
+
+		run_for(&mut app, 1.5);
+		assert_eq!(current_state(&app), GameState::Playing);
+
+		let player_health = PlayerParams::default().start_health;
+		let mut q_player = app.world.query_filtered::<&Health, With<Player>>();
+		let players: Vec<f32> = q_player.iter(&app.world).map(|health| health.0).collect();
+		assert_eq!(players, vec![player_health]);
+
+		let boss_health = EnemyParams::default().start_health;
+		let mut q_boss = app
+			.world
+			.query_filtered::<(&Health, &BossPhase), With<Boss>>();
+		let bosses: Vec<(f32, usize)> = q_boss
+			.iter(&app.world)
+			.map(|(health, phase)| (health.0, phase.0))
+			.collect();
+		assert_eq!(bosses, vec![(boss_health, 0)]);
+	}
+
+	#[test]
+	fn clock_warms_up_after_three_seconds() {
+		let mut clock = GameClock::default();
+		assert!(!clock.warmed_up());
+		clock.tick(Duration::from_millis(2900));
+		assert!(!clock.warmed_up());
+		clock.tick(Duration::from_millis(100));
+		assert!(clock.warmed_up());
+
+		// a new run starts cold again
+		clock = GameClock::default();
+		assert!(!clock.warmed_up());
+	}
+
+	#[test]
+	fn clock_stops_while_paused() {
+		let mut app = App::new();
+		app.insert_resource(Time::default())
+			.insert_resource(GameClock::default())
+			.add_state(GameState::Playing)
+			.add_system_set(SystemSet::on_update(GameState::Playing).with_system(tick_game_clock));
+		run_for(&mut app, 1.0);
+		let elapsed = app.world.get_resource::<GameClock>().unwrap().elapsed();
+		assert!(elapsed > Duration::ZERO);
+
+		app.world
+			.get_resource_mut::<State<GameState>>()
+			.unwrap()
+			.push(GameState::Paused)
+			.unwrap();
+		run_for(&mut app, 5.0);
+		assert_eq!(current_state(&app), GameState::Paused);
+		assert_eq!(
+			app.world.get_resource::<GameClock>().unwrap().elapsed(),
+			elapsed
+		);
+
+		// and it goes on where it stopped after resuming
+		app.world
+			.get_resource_mut::<State<GameState>>()
+			.unwrap()
+			.pop()
+			.unwrap();
+		run_for(&mut app, 1.0);
+		assert!(app.world.get_resource::<GameClock>().unwrap().elapsed() > elapsed);
 	}
 }
@@ -0,0 +1,190 @@
+use bevy::{math::Vec3Swizzles, prelude::*};
+#[cfg(not(target_arch = "wasm32"))]
+use gilrs::{
+	ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Replay, Ticks},
+	Gilrs,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::settings::Settings;
+
+use crate::{
+	game::{DamageDealtEvent, GameState},
+	input::InputAction,
+	player::Player,
+	shooting::{ShootEvent, Team, WeaponParams},
+};
+
+/// Gamepad feedback: a reticle in the world shows where the right stick aims, since there's no cursor,
+/// and the gamepad rumbles when the player gets hurt or fires a heavy weapon
+pub struct GamepadFeedbackPlugin;
+
+impl Plugin for GamepadFeedbackPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_event::<RumbleEvent>()
+			.insert_resource(RumbleParams::default())
+			.add_system_set(SystemSet::on_enter(GameState::Playing).with_system(spawn_reticle))
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(update_reticle.after("player_movement"))
+					.with_system(rumble_on_damage.after("apply_damage"))
+					.with_system(rumble_on_heavy_fire),
+			);
+
+		// force feedback isn't available in the browser
+		#[cfg(not(target_arch = "wasm32"))]
+		app.insert_non_send_resource(RumbleEffects::default())
+			.add_system_to_stage(CoreStage::PostUpdate, play_rumble);
+	}
+}
+
+pub struct RumbleParams {
+	/// Pixels between the player and the reticle
+	pub reticle_distance: f32,
+	pub reticle_size: f32,
+	/// Rumble when the player takes damage, strength from 0 to 1 and seconds
+	pub damage_rumble: (f32, f32),
+	/// Rumble when the player fires a weapon with `WeaponSpec::kick`, strength from 0 to 1 and seconds
+	pub heavy_fire_rumble: (f32, f32),
+}
+
+impl Default for RumbleParams {
+	fn default() -> Self {
+		Self {
+			reticle_distance: 120.0,
+			reticle_size: 10.0,
+			damage_rumble: (0.7, 0.25),
+			heavy_fire_rumble: (0.4, 0.12),
+		}
+	}
+}
+
+/// Rumbles all connected gamepads, scaled by the rumble setting
+pub struct RumbleEvent {
+	pub strength: f32,
+	pub duration: f32,
+}
+
+#[derive(Component)]
+struct AimReticle;
+
+fn spawn_reticle(mut commands: Commands, params: Res<RumbleParams>) {
+	commands
+		.spawn_bundle(SpriteBundle {
+			sprite: Sprite {
+				color: Color::rgba(1.0, 1.0, 1.0, 0.7),
+				custom_size: Some(Vec2::splat(params.reticle_size)),
+				..Default::default()
+			},
+			transform: Transform::from_xyz(0.0, 0.0, 5.0)
+				.with_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_4)),
+			visibility: Visibility { is_visible: false },
+			..Default::default()
+		})
+		.insert(AimReticle);
+}
+
+/// Only shown while aiming with the stick, the mouse has its cursor
+fn update_reticle(
+	input: Res<InputAction>,
+	params: Res<RumbleParams>,
+	q_player: Query<&Transform, (With<Player>, Without<AimReticle>)>,
+	mut q_reticle: Query<(&mut Transform, &mut Visibility), With<AimReticle>>,
+) {
+	let player_pos = q_player.get_single().ok().map(|t| t.translation.xy());
+	for (mut transform, mut visibility) in q_reticle.iter_mut() {
+		match (input.aim, player_pos) {
+			(Some(aim), Some(player_pos)) => {
+				let pos = player_pos + aim * params.reticle_distance;
+				transform.translation = pos.extend(transform.translation.z);
+				visibility.is_visible = true;
+			}
+			_ => visibility.is_visible = false,
+		}
+	}
+}
+
+fn rumble_on_damage(
+	mut damage_er: EventReader<DamageDealtEvent>,
+	mut rumble_ew: EventWriter<RumbleEvent>,
+	params: Res<RumbleParams>,
+	q_player: Query<(), With<Player>>,
+) {
+	if damage_er
+		.iter()
+		.any(|event| q_player.get(event.target).is_ok())
+	{
+		let (strength, duration) = params.damage_rumble;
+		rumble_ew.send(RumbleEvent { strength, duration });
+	}
+}
+
+fn rumble_on_heavy_fire(
+	mut shoot_er: EventReader<ShootEvent>,
+	mut rumble_ew: EventWriter<RumbleEvent>,
+	params: Res<RumbleParams>,
+	weapons: Res<WeaponParams>,
+) {
+	let heavy = shoot_er.iter().any(|ShootEvent(team, _, _, _, _, kind)| {
+		*team == Team::Player && weapons.get(*kind).kick > 0.0
+	});
+	if heavy {
+		let (strength, duration) = params.heavy_fire_rumble;
+		rumble_ew.send(RumbleEvent { strength, duration });
+	}
+}
+
+/// Running effects with the time they end at. Dropping an effect stops it, so they are kept until then
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct RumbleEffects(Vec<(Effect, f64)>);
+
+/// Gilrs is owned by bevy's gamepad plugin and missing when no gamepad backend is available
+#[cfg(not(target_arch = "wasm32"))]
+fn play_rumble(
+	mut rumble_er: EventReader<RumbleEvent>,
+	gilrs: Option<NonSendMut<Gilrs>>,
+	mut effects: NonSendMut<RumbleEffects>,
+	settings: Res<Settings>,
+	time: Res<Time>,
+) {
+	let now = time.seconds_since_startup();
+	effects.0.retain(|(_, end)| *end > now);
+
+	let mut gilrs = match gilrs {
+		Some(gilrs) => gilrs,
+		None => return,
+	};
+	for event in rumble_er.iter() {
+		let strength = (event.strength * settings.rumble).clamp(0.0, 1.0);
+		if strength <= 0.0 {
+			continue;
+		}
+		let gamepads: Vec<_> = gilrs
+			.gamepads()
+			.filter(|(_, gamepad)| gamepad.is_ff_supported())
+			.map(|(id, _)| id)
+			.collect();
+		if gamepads.is_empty() {
+			return;
+		}
+
+		let effect = EffectBuilder::new()
+			.add_effect(BaseEffect {
+				kind: BaseEffectType::Strong {
+					magnitude: (strength * u16::MAX as f32) as u16,
+				},
+				scheduling: Replay {
+					play_for: Ticks::from_ms((event.duration * 1000.0) as u32),
+					..Default::default()
+				},
+				..Default::default()
+			})
+			.gamepads(&gamepads)
+			.finish(&mut *gilrs);
+		match effect.and_then(|effect| effect.play().map(|_| effect)) {
+			Ok(effect) => effects.0.push((effect, now + event.duration as f64)),
+			Err(err) => warn!("Couldn't play rumble: {}", err),
+		}
+	}
+}
@@ -0,0 +1,328 @@
+use bevy::{
+	asset::{AssetLoader, LoadContext, LoadedAsset},
+	prelude::*,
+	reflect::TypeUuid,
+	utils::BoxedFuture,
+};
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+	game::{DamageEvent, DamageSource, GameClock, GameState, Health},
+	loading::LoadingAssets,
+	physics::PhysicsGlobals,
+};
+
+/// Environmental hazards that hurt players and enemies alike, independent of the boss.
+/// Their schedule is loaded from `assets/hazards/arena.hazards.ron`. Hazards follow the game clock,
+/// so every run starts with the same schedule
+pub struct HazardsPlugin;
+
+impl Plugin for HazardsPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_asset::<HazardScheduleFile>()
+			.init_asset_loader::<HazardScheduleLoader>()
+			.insert_resource(HazardSchedule::default())
+			.add_startup_system(load_hazard_schedule)
+			.add_system(update_hazard_schedule)
+			.add_system_set(
+				SystemSet::on_enter(GameState::Playing).with_system(spawn_hazards.after("scene")),
+			)
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(update_hazards.before("apply_damage")),
+			);
+	}
+}
+
+#[derive(Deserialize, Clone)]
+pub enum HazardKind {
+	/// Rectangular patch of floor that periodically pushes out spikes
+	SpikeTrap,
+}
+
+/// One hazard and its cycle: telegraphed for `telegraph` seconds, then dangerous for `active` seconds,
+/// repeating every `period` seconds after `start_delay`
+#[derive(Deserialize, Clone)]
+pub struct HazardSpec {
+	kind: HazardKind,
+	/// Center in pixels
+	pos: Vec2,
+	/// Size in pixels
+	size: Vec2,
+	#[serde(default)]
+	start_delay: f32,
+	period: f32,
+	telegraph: f32,
+	active: f32,
+	/// Damage dealt every `tick` seconds to everything with health standing in the hazard while it is active
+	damage: f32,
+	tick: f32,
+}
+
+impl HazardSpec {
+	fn validate(&self) -> Result<(), String> {
+		if self.size.x <= 0.0 || self.size.y <= 0.0 {
+			return Err("size has to be positive".to_string());
+		}
+		if self.period <= 0.0 || self.tick <= 0.0 {
+			return Err("period and tick have to be positive".to_string());
+		}
+		if self.telegraph < 0.0 || self.active <= 0.0 || self.telegraph + self.active > self.period
+		{
+			return Err(format!(
+				"telegraph {} and active {} don't fit into the period {}",
+				self.telegraph, self.active, self.period
+			));
+		}
+		Ok(())
+	}
+
+	/// Phase of the hazard's cycle at `elapsed` seconds into the run
+	fn phase(&self, elapsed: f32) -> HazardPhase {
+		if elapsed < self.start_delay {
+			return HazardPhase::Idle;
+		}
+		let t = (elapsed - self.start_delay) % self.period;
+		if t < self.telegraph {
+			HazardPhase::Telegraph
+		} else if t < self.telegraph + self.active {
+			HazardPhase::Active
+		} else {
+			HazardPhase::Idle
+		}
+	}
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum HazardPhase {
+	Idle,
+	Telegraph,
+	Active,
+}
+
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "9f4d2c71-6a3b-4e8f-b5c2-1d7e0a9b3f46"]
+pub struct HazardScheduleFile {
+	hazards: Vec<HazardSpec>,
+}
+
+#[derive(Default)]
+struct HazardScheduleLoader;
+
+impl AssetLoader for HazardScheduleLoader {
+	fn load<'a>(
+		&'a self,
+		bytes: &'a [u8],
+		load_context: &'a mut LoadContext,
+	) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+		Box::pin(async move {
+			let file: HazardScheduleFile = ron::de::from_bytes(bytes)?;
+			load_context.set_default_asset(LoadedAsset::new(file));
+			Ok(())
+		})
+	}
+
+	fn extensions(&self) -> &[&str] {
+		&["hazards.ron"]
+	}
+}
+
+/// Valid hazards of the loaded file. Empty until the file is loaded
+#[derive(Default)]
+pub struct HazardSchedule {
+	handle: Handle<HazardScheduleFile>,
+	hazards: Vec<HazardSpec>,
+}
+
+#[derive(Component)]
+struct Hazard {
+	spec: HazardSpec,
+	tick: Timer,
+}
+
+const HAZARD_IDLE_COLOR: Color = Color::rgba(0.5, 0.5, 0.5, 0.15);
+const HAZARD_TELEGRAPH_COLOR: Color = Color::rgba(1.0, 1.0, 0.0, 0.3);
+const SPIKES_COLOR: Color = Color::rgba(0.8, 0.8, 0.9, 0.8);
+
+fn load_hazard_schedule(
+	asset_server: Res<AssetServer>,
+	mut schedule: ResMut<HazardSchedule>,
+	mut loading: ResMut<LoadingAssets>,
+) {
+	schedule.handle = asset_server.load("hazards/arena.hazards.ron");
+	loading.track(&schedule.handle);
+}
+
+/// Copies the hazards over once the file is loaded or was changed, skipping invalid ones.
+/// Hazards of a running game are replaced right away
+fn update_hazard_schedule(
+	mut commands: Commands,
+	mut asset_events: EventReader<AssetEvent<HazardScheduleFile>>,
+	files: Res<Assets<HazardScheduleFile>>,
+	mut schedule: ResMut<HazardSchedule>,
+	state: Res<State<GameState>>,
+	q_hazards: Query<Entity, With<Hazard>>,
+	rapier_config: Res<RapierConfiguration>,
+	physics_globals: Res<PhysicsGlobals>,
+) {
+	for event in asset_events.iter() {
+		let handle = match event {
+			AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+			AssetEvent::Removed { .. } => continue,
+		};
+		if *handle != schedule.handle {
+			continue;
+		}
+
+		if let Some(file) = files.get(handle) {
+			schedule.hazards = file
+				.hazards
+				.iter()
+				.enumerate()
+				.filter_map(|(i, hazard)| match hazard.validate() {
+					Ok(()) => Some(hazard.clone()),
+					Err(err) => {
+						warn!("Skipping hazard {}: {}", i, err);
+						None
+					}
+				})
+				.collect();
+			debug!("LOADED {} HAZARDS", schedule.hazards.len());
+
+			if *state.current() == GameState::Playing {
+				for entity in q_hazards.iter() {
+					commands.entity(entity).despawn_recursive();
+				}
+				for spec in schedule.hazards.iter() {
+					spawn_hazard(&mut commands, spec, &rapier_config, &physics_globals);
+				}
+			}
+		}
+	}
+}
+
+fn spawn_hazards(
+	mut commands: Commands,
+	schedule: Res<HazardSchedule>,
+	rapier_config: Res<RapierConfiguration>,
+	physics_globals: Res<PhysicsGlobals>,
+) {
+	for spec in schedule.hazards.iter() {
+		spawn_hazard(&mut commands, spec, &rapier_config, &physics_globals);
+	}
+}
+
+fn spawn_hazard(
+	commands: &mut Commands,
+	spec: &HazardSpec,
+	rapier_config: &RapierConfiguration,
+	physics_globals: &PhysicsGlobals,
+) {
+	commands
+		.spawn_bundle(SpriteBundle {
+			sprite: Sprite {
+				color: HAZARD_IDLE_COLOR,
+				custom_size: Some(spec.size),
+				..Default::default()
+			},
+			transform: Transform::from_xyz(spec.pos.x, spec.pos.y, -1.0),
+			..Default::default()
+		})
+		.insert_bundle(ColliderBundle {
+			collider_type: ColliderType::Sensor.into(),
+			position: (spec.pos / rapier_config.scale).into(),
+			shape: ColliderShape::cuboid(
+				spec.size.x * 0.5 / rapier_config.scale,
+				spec.size.y * 0.5 / rapier_config.scale,
+			)
+			.into(),
+			flags: ColliderFlags {
+				collision_groups: InteractionGroups::new(
+					physics_globals.scene_mask,
+					physics_globals.player_mask | physics_globals.enemy_mask,
+				),
+				active_events: ActiveEvents::INTERSECTION_EVENTS,
+				..Default::default()
+			}
+			.into(),
+			..Default::default()
+		})
+		.insert(Hazard {
+			spec: spec.clone(),
+			tick: Timer::from_seconds(spec.tick, true),
+		});
+}
+
+/// Shows the phase of every hazard and damages everything with health overlapping an active one
+fn update_hazards(
+	mut q_hazards: Query<(Entity, &mut Hazard, &mut Sprite)>,
+	q_health: Query<&Health>,
+	q_parent: Query<&Parent>,
+	narrow_phase: Res<NarrowPhase>,
+	clock: Res<GameClock>,
+	mut damage_ew: EventWriter<DamageEvent>,
+	time: Res<Time>,
+) {
+	let elapsed = clock.elapsed().as_secs_f32();
+	for (entity, mut hazard, mut sprite) in q_hazards.iter_mut() {
+		let active_color = match hazard.spec.kind {
+			HazardKind::SpikeTrap => SPIKES_COLOR,
+		};
+
+		let phase = hazard.spec.phase(elapsed);
+		let color = match phase {
+			HazardPhase::Idle => HAZARD_IDLE_COLOR,
+			HazardPhase::Telegraph => HAZARD_TELEGRAPH_COLOR,
+			HazardPhase::Active => active_color,
+		};
+		if sprite.color != color {
+			sprite.color = color;
+		}
+		if phase != HazardPhase::Active {
+			// the first tick lands right when the spikes come out
+			hazard.tick.reset();
+			continue;
+		}
+
+		let first_tick = hazard.tick.elapsed_secs() == 0.0;
+		hazard.tick.tick(time.delta());
+		if !first_tick && !hazard.tick.just_finished() {
+			continue;
+		}
+
+		// colliders can be children of the entity with health, every entity only takes damage once
+		let mut targets: Vec<Entity> = vec![];
+		for (h1, h2, intersecting) in narrow_phase.intersections_with(entity.handle()) {
+			if !intersecting {
+				continue;
+			}
+			let other = if h1.entity() == entity {
+				h2.entity()
+			} else {
+				h1.entity()
+			};
+			let target = if q_health.get(other).is_ok() {
+				Some(other)
+			} else {
+				q_parent
+					.get(other)
+					.ok()
+					.map(|Parent(parent_e)| *parent_e)
+					.filter(|parent_e| q_health.get(*parent_e).is_ok())
+			};
+			if let Some(target) = target {
+				if !targets.contains(&target) {
+					targets.push(target);
+				}
+			}
+		}
+		for target in targets {
+			damage_ew.send(DamageEvent {
+				target,
+				amount: hazard.spec.damage,
+				source: DamageSource::Environment,
+			});
+		}
+	}
+}
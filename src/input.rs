@@ -1,15 +1,20 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap, window::CursorMoved};
 
-use crate::{game::GameState, scene::MainCamera};
+use crate::{game::GameState, scene::MainCamera, settings::Settings};
 
 pub struct InputPlugin;
 
 impl Plugin for InputPlugin {
 	fn build(&self, app: &mut App) {
 		app.insert_resource(MousePosition(Vec2::new(0.0, 0.0)))
+			.insert_resource(InputBuffer::default())
+			.insert_resource(InputAction::default())
+			.insert_resource(GamepadParams::default())
 			.add_system_set(
 				SystemSet::on_update(GameState::Playing)
-					.with_system(update_mouse_position.label("input")),
+					.with_system(update_mouse_position.label("input"))
+					.with_system(update_input_action.label("input").label("input_action"))
+					.with_system(buffer_actions.label("input").after("input_action")),
 			);
 	}
 }
@@ -18,6 +23,155 @@ impl Plugin for InputPlugin {
 /// use it in other parts of the game
 pub struct MousePosition(pub Vec2);
 
+/// Input of the current frame, whether it comes from keyboard and mouse or a gamepad (left stick moves,
+/// right stick aims and fires). Gameplay reads this instead of the devices
+#[derive(Default)]
+pub struct InputAction {
+	/// Length from 0 to 1, analog sticks can move slower than full speed
+	pub movement: Vec2,
+	/// Direction the right stick aims in. None while aiming with the mouse
+	pub aim: Option<Vec2>,
+	/// Whether the gamepad wants to shoot, the mouse is handled in `buffer_actions`
+	pub gamepad_fire: bool,
+	/// Whether a bumper was just pressed, Space is handled in `buffer_actions`
+	pub gamepad_dash: bool,
+	/// Whether the left trigger was just pressed, the right mouse button is handled in `buffer_actions`
+	pub gamepad_melee: bool,
+}
+
+pub struct GamepadParams {
+	/// Stick deflection below which the stick counts as centered
+	pub deadzone: f32,
+	/// Right stick deflection from which on the player shoots
+	pub fire_threshold: f32,
+}
+
+impl Default for GamepadParams {
+	fn default() -> Self {
+		Self {
+			deadzone: 0.2,
+			fire_threshold: 0.5,
+		}
+	}
+}
+
+/// Keyboard movement wins over the left stick. The right stick keeps aiming where it was last pushed
+/// until the mouse moves
+fn update_input_action(
+	mut input: ResMut<InputAction>,
+	keyboard_input: Res<Input<KeyCode>>,
+	mut cursor_events: EventReader<CursorMoved>,
+	gamepads: Res<Gamepads>,
+	axes: Res<Axis<GamepadAxis>>,
+	buttons: Res<Input<GamepadButton>>,
+	params: Res<GamepadParams>,
+	settings: Res<Settings>,
+) {
+	let keys = settings.key_bindings;
+	let up = keyboard_input.any_pressed([keys.up, KeyCode::Up]);
+	let down = keyboard_input.any_pressed([keys.down, KeyCode::Down]);
+	let left = keyboard_input.any_pressed([keys.left, KeyCode::Left]);
+	let right = keyboard_input.any_pressed([keys.right, KeyCode::Right]);
+	let x_axis = -(left as i8) + right as i8;
+	let y_axis = -(down as i8) + up as i8;
+	input.movement = Vec2::new(x_axis as f32, y_axis as f32).normalize_or_zero();
+	input.gamepad_fire = false;
+	input.gamepad_dash = false;
+	input.gamepad_melee = false;
+	if cursor_events.iter().count() > 0 {
+		input.aim = None;
+	}
+
+	let gamepad = match gamepads.iter().next() {
+		Some(gamepad) => *gamepad,
+		None => return,
+	};
+	let stick = |x, y| {
+		Vec2::new(
+			axes.get(GamepadAxis(gamepad, x)).unwrap_or(0.0),
+			axes.get(GamepadAxis(gamepad, y)).unwrap_or(0.0),
+		)
+	};
+
+	let left_stick = stick(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY);
+	if input.movement == Vec2::ZERO && left_stick.length() > params.deadzone {
+		input.movement = left_stick.clamp_length_max(1.0);
+	}
+
+	let right_stick = stick(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY);
+	if right_stick.length() > params.deadzone {
+		input.aim = Some(right_stick.normalize());
+	}
+	let trigger = buttons.pressed(GamepadButton(gamepad, GamepadButtonType::RightTrigger2));
+	input.gamepad_fire = right_stick.length() > params.fire_threshold || trigger;
+	input.gamepad_dash = buttons.any_just_pressed([
+		GamepadButton(gamepad, GamepadButtonType::LeftTrigger),
+		GamepadButton(gamepad, GamepadButtonType::RightTrigger),
+	]);
+	input.gamepad_melee =
+		buttons.just_pressed(GamepadButton(gamepad, GamepadButtonType::LeftTrigger2));
+}
+
+/// Actions that are buffered when pressed, see `InputBuffer`
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+	Shoot,
+	Dash,
+	Melee,
+}
+
+/// Remembers when actions were last pressed, so a press shortly before the action is available again
+/// isn't dropped but executed as soon as it is
+pub struct InputBuffer {
+	/// Seconds a press stays buffered
+	pub window: f32,
+	presses: HashMap<Action, f64>,
+}
+
+impl Default for InputBuffer {
+	fn default() -> Self {
+		Self {
+			window: 0.1,
+			presses: HashMap::default(),
+		}
+	}
+}
+
+impl InputBuffer {
+	pub fn press(&mut self, action: Action, now: f64) {
+		self.presses.insert(action, now);
+	}
+
+	/// Whether `action` was pressed within the buffer window. The press is used up, so every press
+	/// only triggers the action once
+	pub fn consume(&mut self, action: Action, now: f64) -> bool {
+		match self.presses.remove(&action) {
+			Some(pressed) => now - pressed <= self.window as f64,
+			None => false,
+		}
+	}
+}
+
+/// Holding fire on the gamepad keeps the press buffered, so it shoots whenever the cooldown allows
+fn buffer_actions(
+	mut buffer: ResMut<InputBuffer>,
+	mouse_input: Res<Input<MouseButton>>,
+	keyboard_input: Res<Input<KeyCode>>,
+	input: Res<InputAction>,
+	settings: Res<Settings>,
+	time: Res<Time>,
+) {
+	if mouse_input.just_pressed(MouseButton::Left) || input.gamepad_fire {
+		buffer.press(Action::Shoot, time.seconds_since_startup());
+	}
+	if keyboard_input.just_pressed(settings.key_bindings.dash) || input.gamepad_dash {
+		buffer.press(Action::Dash, time.seconds_since_startup());
+	}
+	if mouse_input.just_pressed(MouseButton::Right) || input.gamepad_melee {
+		buffer.press(Action::Melee, time.seconds_since_startup());
+	}
+}
+
 /// System that updates the MousePosition resource, so that it is available for the entire app to use
 fn update_mouse_position(
 	mut mouse_pos: ResMut<MousePosition>,
@@ -27,12 +181,69 @@ fn update_mouse_position(
 	let (camera, camera_transform) = q_camera.single();
 	let wnd = windows_info.get(camera.window).unwrap();
 
+	// cursor position and window size are both in logical pixels, so the window's scale factor cancels
+	// out. Mixing in physical sizes would offset the aim on high-DPI screens and scaled WASM canvases
 	if let Some(screen_pos) = wnd.cursor_position() {
-		let window_size = Vec2::new(wnd.width() as f32, wnd.height() as f32);
-		let ndc = (screen_pos / window_size) * 2.0 - Vec2::ONE;
+		let window_size = Vec2::new(wnd.width(), wnd.height());
+		// the projection matrix already contains the scaling mode and zoom, so this holds for any window size
 		let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix.inverse();
-		let world_pos = ndc_to_world.project_point3(ndc.extend(-1.0));
-		let world_pos: Vec2 = world_pos.truncate();
-		mouse_pos.0 = world_pos;
+		mouse_pos.0 = screen_to_world(screen_pos, window_size, ndc_to_world);
+	}
+}
+
+/// Projects a position in logical pixels from the bottom left corner of the window into the world
+fn screen_to_world(screen_pos: Vec2, window_size: Vec2, ndc_to_world: Mat4) -> Vec2 {
+	let ndc = (screen_pos / window_size) * 2.0 - Vec2::ONE;
+	ndc_to_world.project_point3(ndc.extend(-1.0)).truncate()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Camera at the origin showing 800 x 600 logical pixels of the world
+	fn ndc_to_world() -> Mat4 {
+		Mat4::orthographic_rh(-400.0, 400.0, -300.0, 300.0, 0.0, 1000.0).inverse()
+	}
+
+	#[test]
+	fn projects_cursor_at_scale_factor_1() {
+		let window_size = Vec2::new(800.0, 600.0);
+		let world = screen_to_world(Vec2::new(600.0, 450.0), window_size, ndc_to_world());
+		assert!(world.abs_diff_eq(Vec2::new(200.0, 150.0), 1e-3));
+		let center = screen_to_world(window_size * 0.5, window_size, ndc_to_world());
+		assert!(center.abs_diff_eq(Vec2::ZERO, 1e-3));
+	}
+
+	#[test]
+	fn projects_cursor_at_scale_factor_2() {
+		// a 1600 x 1200 physical window is 800 x 600 logical pixels, the cursor is logical as well
+		let scale_factor = 2.0;
+		let window_size = Vec2::new(1600.0, 1200.0) / scale_factor;
+		let cursor = Vec2::new(1200.0, 900.0) / scale_factor;
+		let world = screen_to_world(cursor, window_size, ndc_to_world());
+		assert!(world.abs_diff_eq(Vec2::new(200.0, 150.0), 1e-3));
+	}
+
+	#[test]
+	fn buffered_shoot_fires_when_cooldown_ends() {
+		let mut buffer = InputBuffer::default();
+		let cooldown_end = 1.0;
+		// pressed shortly before the cooldown ends, while shooting isn't possible yet
+		buffer.press(Action::Shoot, cooldown_end - 0.05);
+		assert!(buffer.consume(Action::Shoot, cooldown_end));
+		// every press only shoots once
+		assert!(!buffer.consume(Action::Shoot, cooldown_end + 0.01));
+	}
+
+	#[test]
+	fn press_expires_after_buffer_window() {
+		let mut buffer = InputBuffer::default();
+		buffer.press(Action::Dash, 1.0);
+		assert!(!buffer.consume(Action::Dash, 1.0 + buffer.window as f64 + 0.01));
+		// other actions aren't buffered by the press
+		buffer.press(Action::Dash, 2.0);
+		assert!(!buffer.consume(Action::Shoot, 2.0));
+		assert!(buffer.consume(Action::Dash, 2.0));
 	}
 }
@@ -0,0 +1,212 @@
+use bevy::{math::Vec3Swizzles, prelude::*};
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+	drops::{spawn_pickup, DropParams, PickupKind},
+	game::GameState,
+	input::{InputAction, MousePosition},
+	physics::PhysicsGlobals,
+	player::Player,
+	settings::Settings,
+	shooting::{Weapon, WeaponKind},
+};
+
+/// The player carries up to `SLOTS` weapons. The number keys select a slot (see `switch_weapon`), minions
+/// drop weapons as pickups (see `PickupKind::Weapon`) and the drop key throws the active weapon away.
+/// The hotbar in `UIPlugin` shows the slots
+pub struct PlayerInventoryPlugin;
+
+impl Plugin for PlayerInventoryPlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(InventoryParams::default())
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing).with_system(drop_weapon.after("input")),
+			);
+	}
+}
+
+/// One per number key
+pub const SLOTS: usize = 5;
+
+pub struct InventoryParams {
+	/// Distance in pixels in front of the player a dropped weapon lands at
+	pub drop_distance: f32,
+}
+
+impl Default for InventoryParams {
+	fn default() -> Self {
+		Self {
+			drop_distance: 40.0,
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ItemSlot {
+	Empty,
+	Weapon(WeaponKind),
+}
+
+/// Weapons the player carries. The player's `Weapon` is always the one in the active slot
+#[derive(Component, Debug)]
+pub struct PlayerInventory {
+	slots: [ItemSlot; SLOTS],
+	active: usize,
+}
+
+impl PlayerInventory {
+	/// Starts with the `loadout` weapon active and the pistol next to it
+	pub fn new(loadout: WeaponKind) -> Self {
+		let mut inventory = Self {
+			slots: [ItemSlot::Empty; SLOTS],
+			active: 0,
+		};
+		inventory.slots[0] = ItemSlot::Weapon(loadout);
+		inventory.add(WeaponKind::Pistol);
+		inventory
+	}
+
+	pub fn slots(&self) -> &[ItemSlot] {
+		&self.slots
+	}
+
+	pub fn active(&self) -> usize {
+		self.active
+	}
+
+	pub fn contains(&self, kind: WeaponKind) -> bool {
+		self.slots.contains(&ItemSlot::Weapon(kind))
+	}
+
+	/// Puts the weapon into the first empty slot. False when it is already carried or there is no room
+	pub fn add(&mut self, kind: WeaponKind) -> bool {
+		if self.contains(kind) {
+			return false;
+		}
+		match self.slots.iter().position(|slot| *slot == ItemSlot::Empty) {
+			Some(i) => {
+				self.slots[i] = ItemSlot::Weapon(kind);
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Makes `slot` the active one, None when it is empty or doesn't exist
+	pub fn select(&mut self, slot: usize) -> Option<WeaponKind> {
+		match self.slots.get(slot) {
+			Some(ItemSlot::Weapon(kind)) => {
+				self.active = slot;
+				Some(*kind)
+			}
+			_ => None,
+		}
+	}
+
+	/// Empties the active slot and selects the next weapon. The last weapon can't be dropped
+	pub fn drop_active(&mut self) -> Option<WeaponKind> {
+		let kind = match self.slots[self.active] {
+			ItemSlot::Weapon(kind) => kind,
+			ItemSlot::Empty => return None,
+		};
+		let next = (1..SLOTS)
+			.map(|offset| (self.active + offset) % SLOTS)
+			.find(|i| self.slots[*i] != ItemSlot::Empty)?;
+		self.slots[self.active] = ItemSlot::Empty;
+		self.active = next;
+		Some(kind)
+	}
+}
+
+/// Throws the active weapon in the aim direction as a pickup and switches to the next one
+fn drop_weapon(
+	mut commands: Commands,
+	keyboard_input: Res<Input<KeyCode>>,
+	settings: Res<Settings>,
+	mouse_pos: Res<MousePosition>,
+	input: Res<InputAction>,
+	mut q_player: Query<(&Transform, &mut Weapon, &mut PlayerInventory), With<Player>>,
+	params: Res<InventoryParams>,
+	drop_params: Res<DropParams>,
+	rapier_config: Res<RapierConfiguration>,
+	physics_globals: Res<PhysicsGlobals>,
+) {
+	if !keyboard_input.just_pressed(settings.key_bindings.drop) {
+		return;
+	}
+	for (transform, mut weapon, mut inventory) in q_player.iter_mut() {
+		let kind = match inventory.drop_active() {
+			Some(kind) => kind,
+			None => continue,
+		};
+		if let ItemSlot::Weapon(next) = inventory.slots()[inventory.active()] {
+			weapon.0 = next;
+		}
+		debug!("DROP_WEAPON {:?}", kind);
+		let player_pos = transform.translation.xy();
+		let aim = input
+			.aim
+			.unwrap_or(mouse_pos.0 - player_pos)
+			.normalize_or_zero();
+		spawn_pickup(
+			&mut commands,
+			PickupKind::Weapon(kind),
+			player_pos + aim * params.drop_distance,
+			&drop_params,
+			rapier_config.scale,
+			&physics_globals,
+		);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn starts_with_loadout_and_pistol() {
+		let inventory = PlayerInventory::new(WeaponKind::Rocket);
+		assert_eq!(inventory.active(), 0);
+		assert_eq!(inventory.slots()[0], ItemSlot::Weapon(WeaponKind::Rocket));
+		assert_eq!(inventory.slots()[1], ItemSlot::Weapon(WeaponKind::Pistol));
+
+		let inventory = PlayerInventory::new(WeaponKind::Pistol);
+		assert_eq!(inventory.slots()[1], ItemSlot::Empty);
+	}
+
+	#[test]
+	fn adds_until_full() {
+		let mut inventory = PlayerInventory::new(WeaponKind::Pistol);
+		assert!(!inventory.add(WeaponKind::Pistol));
+		for kind in [
+			WeaponKind::Shotgun,
+			WeaponKind::Laser,
+			WeaponKind::Rocket,
+			WeaponKind::Beam,
+		] {
+			assert!(inventory.add(kind));
+		}
+		assert!(!inventory.add(WeaponKind::Missile));
+		assert!(!inventory.contains(WeaponKind::Missile));
+	}
+
+	#[test]
+	fn selects_only_filled_slots() {
+		let mut inventory = PlayerInventory::new(WeaponKind::Shotgun);
+		assert_eq!(inventory.select(1), Some(WeaponKind::Pistol));
+		assert_eq!(inventory.active(), 1);
+		assert_eq!(inventory.select(3), None);
+		assert_eq!(inventory.select(SLOTS), None);
+		assert_eq!(inventory.active(), 1);
+	}
+
+	#[test]
+	fn keeps_the_last_weapon() {
+		let mut inventory = PlayerInventory::new(WeaponKind::Laser);
+		assert_eq!(inventory.drop_active(), Some(WeaponKind::Laser));
+		assert_eq!(inventory.slots()[0], ItemSlot::Empty);
+		assert_eq!(inventory.active(), 1);
+		assert_eq!(inventory.drop_active(), None);
+		assert!(inventory.contains(WeaponKind::Pistol));
+	}
+}
@@ -0,0 +1,123 @@
+use bevy::{asset::LoadState, prelude::*};
+
+use crate::{game::GameState, shooting::BulletParams};
+
+/// Preloads everything the game needs before the first run starts, so nothing pops in on WASM where
+/// loading is slow. Other plugins add the assets they load themselves with `LoadingAssets::track`
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(LoadingAssets::default())
+			.add_startup_system(preload_assets)
+			.add_system_set(SystemSet::on_enter(GameState::Loading).with_system(spawn_loading_text))
+			.add_system_set(SystemSet::on_update(GameState::Loading).with_system(check_loading))
+			.add_system_set(
+				SystemSet::on_exit(GameState::Loading).with_system(despawn_loading_text),
+			);
+	}
+}
+
+/// Handles of all assets that have to be loaded before playing. Keeping them here also keeps the
+/// assets from being unloaded between runs
+#[derive(Default)]
+pub struct LoadingAssets {
+	handles: Vec<HandleUntyped>,
+}
+
+impl LoadingAssets {
+	pub fn track<T: bevy::asset::Asset>(&mut self, handle: &Handle<T>) {
+		self.handles.push(handle.clone_untyped());
+	}
+}
+
+#[derive(Component)]
+struct LoadingText;
+
+const FONT: &str = "fonts/PressStart2P-Regular.ttf";
+
+fn preload_assets(
+	asset_server: Res<AssetServer>,
+	bullet_params: Res<BulletParams>,
+	mut loading: ResMut<LoadingAssets>,
+) {
+	loading.track(&asset_server.load::<Font, _>(FONT));
+	loading.track(&asset_server.load::<Image, _>("physics_example/player.png"));
+	for texture in bullet_params.textures() {
+		loading.track(&asset_server.load::<Image, _>(texture));
+	}
+}
+
+fn spawn_loading_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+	commands
+		.spawn_bundle(NodeBundle {
+			style: Style {
+				size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+				justify_content: JustifyContent::Center,
+				align_items: AlignItems::Center,
+				..Default::default()
+			},
+			color: Color::NONE.into(),
+			..Default::default()
+		})
+		.insert(LoadingText)
+		.with_children(|parent| {
+			parent
+				.spawn_bundle(TextBundle {
+					text: Text::with_section(
+						"Loading...",
+						TextStyle {
+							font: asset_server.load(FONT),
+							font_size: 24.0,
+							color: Color::rgb(0.9, 0.9, 0.9),
+						},
+						Default::default(),
+					),
+					..Default::default()
+				})
+				.insert(LoadingText);
+		});
+}
+
+/// Starts the first run once everything is loaded. Failed assets are listed instead, the game
+/// stays on the loading screen then
+fn check_loading(
+	asset_server: Res<AssetServer>,
+	loading: Res<LoadingAssets>,
+	mut state: ResMut<State<GameState>>,
+	mut q_text: Query<&mut Text, With<LoadingText>>,
+) {
+	match asset_server.get_group_load_state(loading.handles.iter().map(|handle| handle.id)) {
+		LoadState::Loaded => {
+			debug!("LOADED {} ASSETS", loading.handles.len());
+			let _ = state.overwrite_set(GameState::Playing);
+		}
+		LoadState::Failed => {
+			let failed: Vec<String> = loading
+				.handles
+				.iter()
+				.filter(|handle| asset_server.get_load_state(*handle) == LoadState::Failed)
+				.filter_map(|handle| asset_server.get_handle_path(handle))
+				.map(|path| path.path().display().to_string())
+				.collect();
+			let value = format!("Failed to load:\n{}", failed.join("\n"));
+			for mut text in q_text.iter_mut() {
+				if text.sections[0].value != value {
+					error!("{}", value);
+					text.sections[0].value = value.clone();
+					text.sections[0].style.color = Color::rgb(1.0, 0.3, 0.3);
+				}
+			}
+		}
+		_ => {}
+	}
+}
+
+fn despawn_loading_text(
+	mut commands: Commands,
+	q_text: Query<Entity, (With<LoadingText>, Without<Parent>)>,
+) {
+	for entity in q_text.iter() {
+		commands.entity(entity).despawn_recursive();
+	}
+}
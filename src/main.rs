@@ -1,13 +1,43 @@
-use bevy::prelude::*;
+use bevy::{
+	asset::AssetServerSettings,
+	log::{Level, LogSettings},
+	prelude::*,
+};
 use bevy_rapier2d::prelude::*;
 
+mod animation;
+mod arena;
+mod attacks;
+mod audio;
+mod beam;
+mod chaos;
+#[cfg(feature = "debug")]
+mod collider_debug;
+mod config;
+#[cfg(feature = "debug")]
+mod console;
+mod damage_numbers;
+mod drops;
 mod enemy;
+mod feel;
 mod game;
+mod gamepad;
+mod hazards;
 mod input;
+mod inventory;
+mod loading;
+mod map;
+mod melee;
+mod patterns;
 mod physics;
 mod player;
 mod scene;
+mod score;
+mod settings;
 mod shooting;
+mod stats;
+mod status_effects;
+mod telegraph;
 mod ui;
 mod waypoints;
 
@@ -15,18 +45,60 @@ fn main() {
 	// When building for WASM, print panics to the browser console
 	#[cfg(target_arch = "wasm32")]
 	console_error_panic_hook::set_once();
-	App::new()
+	let mut app = App::new();
+	app
+		// debug logs (spawns, damage, pathfinding) are only shown in debug builds
+		.insert_resource(LogSettings {
+			level: if cfg!(debug_assertions) {
+				Level::DEBUG
+			} else {
+				Level::INFO
+			},
+			..Default::default()
+		})
+		// reload changed assets like the config files while the game runs, not supported on WASM
+		.insert_resource(AssetServerSettings {
+			watch_for_changes: cfg!(not(target_arch = "wasm32")),
+			..Default::default()
+		})
+		// before the plugins, so they see the stored settings from the start
+		.insert_resource(settings::load_settings())
 		.add_plugins(DefaultPlugins)
 		//.add_plugin(bevy_inspector_egui::WorldInspectorPlugin::default())
 		.add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
 		.add_plugin(game::GamePlugin)
+		.add_plugin(loading::LoadingPlugin)
 		.add_plugin(input::InputPlugin)
 		.add_plugin(ui::UIPlugin)
+		.add_plugin(damage_numbers::DamageNumberPlugin)
 		.add_plugin(physics::SetupPhysicsPlugin)
 		.add_plugin(scene::SetupScenePlugin)
 		.add_plugin(shooting::ShootingPlugin)
 		.add_plugin(player::PlayerPlugin)
 		.add_plugin(enemy::EnemyPlugin)
+		.add_plugin(attacks::AttacksPlugin)
+		.add_plugin(chaos::ChaosPlugin)
+		.add_plugin(patterns::PatternsPlugin)
+		.add_plugin(config::ConfigPlugin)
+		.add_plugin(stats::StatsPlugin)
+		.add_plugin(audio::AudioPlugin)
+		.add_plugin(hazards::HazardsPlugin)
 		.add_plugin(waypoints::WaypointsPlugin)
-		.run();
+		.add_plugin(feel::FeelPlugin)
+		.add_plugin(settings::SettingsPlugin)
+		.add_plugin(drops::DropsPlugin)
+		.add_plugin(inventory::PlayerInventoryPlugin)
+		.add_plugin(arena::ArenaPlugin)
+		.add_plugin(map::MapLoaderPlugin)
+		.add_plugin(gamepad::GamepadFeedbackPlugin)
+		.add_plugin(melee::MeleePlugin)
+		.add_plugin(score::ScorePlugin)
+		.add_plugin(telegraph::TelegraphPlugin)
+		.add_plugin(status_effects::StatusEffectsPlugin)
+		.add_plugin(beam::BeamPlugin)
+		.add_plugin(animation::AnimationPlugin);
+	#[cfg(feature = "debug")]
+	app.add_plugin(console::ConsolePlugin)
+		.add_plugin(collider_debug::ColliderDebugPlugin);
+	app.run();
 }
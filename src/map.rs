@@ -0,0 +1,333 @@
+use bevy::{
+	asset::{AssetLoader, LoadContext, LoadedAsset},
+	prelude::*,
+	reflect::TypeUuid,
+	utils::BoxedFuture,
+};
+
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+	enemy::EnemyParams,
+	game::GameState,
+	loading::LoadingAssets,
+	physics::PhysicsMaterialParams,
+	player::PlayerParams,
+	scene::{spawn_static_collider, Wall},
+	waypoints::Waypoint,
+};
+
+/// Loads the arena layout from an LDtk file, `assets/maps/arena.ldtk` by default. The first level of
+/// the file is used, centered on the origin:
+/// - every IntGrid layer is solid, neighbouring cells in a row are merged into one static collider
+/// - tile layers are drawn with their tileset, which is expected to have no spacing or padding
+/// - `PlayerSpawn` and `BossSpawn` entities move the spawns, `Waypoint` entities add pathfinding waypoints
+///
+/// The arena walls stay in `scene.rs`, since sudden death moves them
+pub struct MapLoaderPlugin;
+
+impl Plugin for MapLoaderPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_asset::<MapLayout>()
+			.init_asset_loader::<LdtkLoader>()
+			.insert_resource(MapParams::default())
+			.insert_resource(ArenaMap::default())
+			.add_startup_system(load_map)
+			.add_system_set(
+				SystemSet::on_enter(GameState::Playing)
+					.with_system(spawn_map.label("map").after("scene")),
+			);
+	}
+}
+
+pub struct MapParams {
+	/// Asset path of the map, None keeps the built-in scene
+	pub map: Option<String>,
+}
+
+impl Default for MapParams {
+	fn default() -> Self {
+		Self {
+			map: Some("maps/arena.ldtk".to_string()),
+		}
+	}
+}
+
+#[derive(Default)]
+pub struct ArenaMap {
+	handle: Handle<MapLayout>,
+}
+
+impl ArenaMap {
+	/// The layout once it is loaded, None without a map
+	pub fn layout<'a>(&self, layouts: &'a Assets<MapLayout>) -> Option<&'a MapLayout> {
+		layouts.get(&self.handle)
+	}
+}
+
+/// Everything the game needs from a map, in world coordinates
+#[derive(TypeUuid, Default)]
+#[uuid = "8e2f4d61-3b7a-4c59-a1d8-6f0e2b9c7a34"]
+pub struct MapLayout {
+	/// (center, size) of every solid box
+	pub solids: Vec<(Vec2, Vec2)>,
+	pub player_spawn: Option<Vec2>,
+	pub boss_spawn: Option<Vec2>,
+	pub waypoints: Vec<Vec2>,
+	tile_layers: Vec<TileLayer>,
+}
+
+struct TileLayer {
+	/// Asset path of the tileset image
+	tileset: String,
+	tileset_size: Vec2,
+	grid_size: f32,
+	/// (center, tile index in the tileset)
+	tiles: Vec<(Vec2, usize)>,
+}
+
+// the parts of the LDtk format the game reads, everything else is ignored
+#[derive(Deserialize)]
+struct LdtkFile {
+	defs: LdtkDefs,
+	levels: Vec<LdtkLevel>,
+}
+
+#[derive(Deserialize)]
+struct LdtkDefs {
+	#[serde(default)]
+	tilesets: Vec<LdtkTileset>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkTileset {
+	uid: i64,
+	px_wid: i32,
+	px_hei: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkLevel {
+	px_wid: i32,
+	px_hei: i32,
+	#[serde(default)]
+	layer_instances: Option<Vec<LdtkLayer>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkLayer {
+	#[serde(rename = "__type")]
+	kind: String,
+	#[serde(rename = "__gridSize")]
+	grid_size: i32,
+	#[serde(rename = "__cWid")]
+	c_wid: i32,
+	#[serde(rename = "__tilesetDefUid")]
+	tileset_def_uid: Option<i64>,
+	#[serde(rename = "__tilesetRelPath")]
+	tileset_rel_path: Option<String>,
+	#[serde(default)]
+	int_grid_csv: Vec<i32>,
+	#[serde(default)]
+	grid_tiles: Vec<LdtkTile>,
+	#[serde(default)]
+	auto_layer_tiles: Vec<LdtkTile>,
+	#[serde(default)]
+	entity_instances: Vec<LdtkEntity>,
+}
+
+#[derive(Deserialize)]
+struct LdtkTile {
+	px: [i32; 2],
+	src: [i32; 2],
+}
+
+#[derive(Deserialize)]
+struct LdtkEntity {
+	#[serde(rename = "__identifier")]
+	identifier: String,
+	px: [i32; 2],
+}
+
+#[derive(Default)]
+struct LdtkLoader;
+
+impl AssetLoader for LdtkLoader {
+	fn load<'a>(
+		&'a self,
+		bytes: &'a [u8],
+		load_context: &'a mut LoadContext,
+	) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+		Box::pin(async move {
+			let file: LdtkFile = serde_json::from_slice(bytes)?;
+			// tilesets are relative to the map file
+			let dir = load_context
+				.path()
+				.parent()
+				.map(|dir| dir.to_path_buf())
+				.unwrap_or_default();
+			let layout = to_layout(&file, |rel_path| {
+				dir.join(rel_path).to_string_lossy().replace('\\', "/")
+			})?;
+			load_context.set_default_asset(LoadedAsset::new(layout));
+			Ok(())
+		})
+	}
+
+	fn extensions(&self) -> &[&str] {
+		&["ldtk"]
+	}
+}
+
+fn to_layout(
+	file: &LdtkFile,
+	asset_path: impl Fn(&str) -> String,
+) -> Result<MapLayout, anyhow::Error> {
+	let level = file
+		.levels
+		.first()
+		.ok_or_else(|| anyhow::anyhow!("the map has no levels"))?;
+	let half_size = Vec2::new(level.px_wid as f32, level.px_hei as f32) * 0.5;
+	// LDtk counts pixels from the top left corner downwards
+	let to_world = |x: f32, y: f32| Vec2::new(x - half_size.x, half_size.y - y);
+
+	let mut layout = MapLayout::default();
+	// layers are listed from the top down, so the last one is drawn first
+	for layer in level.layer_instances.iter().flatten().rev() {
+		let grid = layer.grid_size as f32;
+		match layer.kind.as_str() {
+			"IntGrid" => {
+				if layer.c_wid <= 0 {
+					continue;
+				}
+				for (row, cells) in layer.int_grid_csv.chunks(layer.c_wid as usize).enumerate() {
+					// merge runs of solid cells into one box
+					let mut start: Option<usize> = None;
+					for col in 0..=cells.len() {
+						let solid = cells.get(col).map_or(false, |value| *value > 0);
+						match (solid, start) {
+							(true, None) => start = Some(col),
+							(false, Some(first)) => {
+								let width = (col - first) as f32 * grid;
+								let center = to_world(
+									first as f32 * grid + width * 0.5,
+									(row as f32 + 0.5) * grid,
+								);
+								layout.solids.push((center, Vec2::new(width, grid)));
+								start = None;
+							}
+							_ => {}
+						}
+					}
+				}
+			}
+			"Entities" => {
+				for entity in layer.entity_instances.iter() {
+					let pos = to_world(entity.px[0] as f32, entity.px[1] as f32);
+					match entity.identifier.as_str() {
+						"PlayerSpawn" => layout.player_spawn = Some(pos),
+						"BossSpawn" => layout.boss_spawn = Some(pos),
+						"Waypoint" => layout.waypoints.push(pos),
+						other => warn!("Ignoring unknown map entity {}", other),
+					}
+				}
+			}
+			_ => {}
+		}
+
+		let tiles = layer.grid_tiles.iter().chain(layer.auto_layer_tiles.iter());
+		let tileset = layer
+			.tileset_def_uid
+			.and_then(|uid| file.defs.tilesets.iter().find(|tileset| tileset.uid == uid));
+		if let (Some(tileset), Some(rel_path)) = (tileset, layer.tileset_rel_path.as_ref()) {
+			let columns = (tileset.px_wid / layer.grid_size.max(1)).max(1);
+			layout.tile_layers.push(TileLayer {
+				tileset: asset_path(rel_path),
+				tileset_size: Vec2::new(tileset.px_wid as f32, tileset.px_hei as f32),
+				grid_size: grid,
+				tiles: tiles
+					.map(|tile| {
+						let center = to_world(
+							tile.px[0] as f32 + grid * 0.5,
+							tile.px[1] as f32 + grid * 0.5,
+						);
+						let index = (tile.src[1] / layer.grid_size.max(1)) * columns
+							+ tile.src[0] / layer.grid_size.max(1);
+						(center, index as usize)
+					})
+					.collect(),
+			});
+		}
+	}
+	Ok(layout)
+}
+
+fn load_map(
+	asset_server: Res<AssetServer>,
+	params: Res<MapParams>,
+	mut map: ResMut<ArenaMap>,
+	mut loading: ResMut<LoadingAssets>,
+) {
+	if let Some(path) = params.map.as_ref() {
+		map.handle = asset_server.load(path.as_str());
+		loading.track(&map.handle);
+	}
+}
+
+/// Spawns the map at the start of every run and moves the spawns to its markers
+fn spawn_map(
+	mut commands: Commands,
+	map: Res<ArenaMap>,
+	layouts: Res<Assets<MapLayout>>,
+	asset_server: Res<AssetServer>,
+	mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+	(mut player_params, mut enemy_params): (ResMut<PlayerParams>, ResMut<EnemyParams>),
+	rapier_config: Res<RapierConfiguration>,
+	materials: Res<PhysicsMaterialParams>,
+) {
+	let layout = match map.layout(&layouts) {
+		Some(layout) => layout,
+		None => return,
+	};
+	debug!("SPAWN_MAP");
+
+	for (i, layer) in layout.tile_layers.iter().enumerate() {
+		let columns = (layer.tileset_size.x / layer.grid_size) as usize;
+		let rows = (layer.tileset_size.y / layer.grid_size) as usize;
+		let atlas = texture_atlases.add(TextureAtlas::from_grid(
+			asset_server.load(layer.tileset.as_str()),
+			Vec2::splat(layer.grid_size),
+			columns.max(1),
+			rows.max(1),
+		));
+		for (pos, index) in layer.tiles.iter() {
+			commands.spawn_bundle(SpriteSheetBundle {
+				texture_atlas: atlas.clone(),
+				sprite: TextureAtlasSprite::new(*index),
+				// below everything else, later layers on top
+				transform: Transform::from_xyz(pos.x, pos.y, -10.0 + i as f32 * 0.1),
+				..Default::default()
+			});
+		}
+	}
+
+	for (pos, size) in layout.solids.iter() {
+		spawn_static_collider(&mut commands, &rapier_config, &materials, *pos, *size).insert(Wall);
+	}
+
+	// waypoints are connected with the rest of the grid in `construct_edges`
+	for pos in layout.waypoints.iter() {
+		commands.spawn().insert(Waypoint::new(*pos));
+	}
+
+	if let Some(pos) = layout.player_spawn {
+		player_params.spawn_pos = pos;
+	}
+	if let Some(pos) = layout.boss_spawn {
+		enemy_params.spawn_pos = pos;
+	}
+}
@@ -0,0 +1,237 @@
+use std::f32::consts::PI;
+
+use bevy::{math::Vec3Swizzles, prelude::*};
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+	game::{DamageEvent, DamageSource, GameState, Health, Invulnerable},
+	input::{Action, InputAction, InputBuffer, MousePosition},
+	physics::PhysicsGlobals,
+	player::Player,
+	shooting::{Bullet, DeflectEvent, Team},
+};
+
+/// The player swings a blade on right click or the left trigger. The swing is a short-lived sensor in
+/// the shape of an arc in front of the player, it damages enemies and sends enemy bullets back
+pub struct MeleePlugin;
+
+impl Plugin for MeleePlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(MeleeParams::default()).add_system_set(
+			SystemSet::on_update(GameState::Playing)
+				.with_system(swing_melee.after("input"))
+				.with_system(melee_hits.before("apply_damage"))
+				.with_system(animate_swings),
+		);
+	}
+}
+
+pub struct MeleeParams {
+	/// Seconds between swings
+	pub cooldown: f32,
+	/// Seconds the arc stays out
+	pub duration: f32,
+	/// Pixels from the player's center
+	pub range: f32,
+	/// Radians the arc spans, centered on the aim
+	pub arc: f32,
+	pub damage: f32,
+	/// Deflected bullets fly this much faster
+	pub deflect_speed_scale: f32,
+}
+
+impl Default for MeleeParams {
+	fn default() -> Self {
+		Self {
+			cooldown: 0.6,
+			duration: 0.15,
+			range: 45.0,
+			arc: PI * 0.6,
+			damage: 25.0,
+			deflect_speed_scale: 1.2,
+		}
+	}
+}
+
+/// A swing in progress. Everything is only hit once per swing
+#[derive(Component)]
+struct MeleeSwing {
+	owner: Entity,
+	origin: Vec2,
+	/// Direction the swing is centered on
+	aim: Vec2,
+	timer: Timer,
+	hit: Vec<Entity>,
+}
+
+fn swing_melee(
+	mut commands: Commands,
+	mut input_buffer: ResMut<InputBuffer>,
+	input: Res<InputAction>,
+	mouse_pos: Res<MousePosition>,
+	q_player: Query<(Entity, &Transform), With<Player>>,
+	params: Res<MeleeParams>,
+	physics_globals: Res<PhysicsGlobals>,
+	rapier_config: Res<RapierConfiguration>,
+	mut last_swing: Local<Option<f64>>,
+	time: Res<Time>,
+) {
+	let (player, player_t) = match q_player.get_single() {
+		Ok(player) => player,
+		Err(_) => return,
+	};
+	let now = time.seconds_since_startup();
+	let ready = last_swing.map_or(true, |last_swing| {
+		now - last_swing >= params.cooldown as f64
+	});
+	// the press stays buffered until the cooldown is over
+	if !ready || !input_buffer.consume(Action::Melee, now) {
+		return;
+	}
+	*last_swing = Some(now);
+
+	let origin = player_t.translation.xy();
+	let aim = input
+		.aim
+		.unwrap_or(mouse_pos.0 - origin)
+		.normalize_or_zero();
+	let aim = if aim == Vec2::ZERO { Vec2::X } else { aim };
+
+	// a fan of points along the arc, plus the center
+	let segments = 6;
+	let mut points = vec![Point::origin()];
+	for i in 0..=segments {
+		let angle = (i as f32 / segments as f32 - 0.5) * params.arc;
+		let point = Mat2::from_angle(angle) * aim * params.range / rapier_config.scale;
+		points.push(Point::new(point.x, point.y));
+	}
+	let shape = match ColliderShape::convex_hull(&points) {
+		Some(shape) => shape,
+		None => return,
+	};
+
+	debug!("MELEE_SWING");
+	commands
+		.spawn_bundle(SpriteBundle {
+			sprite: Sprite {
+				color: Color::rgba(1.0, 1.0, 1.0, 0.8),
+				custom_size: Some(Vec2::new(params.range, 4.0)),
+				..Default::default()
+			},
+			transform: Transform::from_xyz(origin.x, origin.y, 1.0),
+			..Default::default()
+		})
+		.insert_bundle(ColliderBundle {
+			collider_type: ColliderType::Sensor.into(),
+			position: (origin / rapier_config.scale).into(),
+			shape: shape.into(),
+			flags: ColliderFlags {
+				collision_groups: InteractionGroups::new(
+					physics_globals.player_mask,
+					physics_globals.enemy_mask | physics_globals.bullet_mask,
+				),
+				active_events: ActiveEvents::INTERSECTION_EVENTS,
+				..Default::default()
+			}
+			.into(),
+			..Default::default()
+		})
+		.insert(MeleeSwing {
+			owner: player,
+			origin,
+			aim,
+			timer: Timer::from_seconds(params.duration, false),
+			hit: vec![],
+		});
+}
+
+/// Damages enemies and deflects enemy bullets the swing starts touching
+fn melee_hits(
+	mut intersection_events: EventReader<IntersectionEvent>,
+	mut q_swings: Query<&mut MeleeSwing>,
+	q_health: Query<(), (With<Health>, Without<Player>, Without<Invulnerable>)>,
+	q_parent: Query<&Parent>,
+	q_bullets: Query<&Bullet>,
+	mut damage_ew: EventWriter<DamageEvent>,
+	mut deflect_ew: EventWriter<DeflectEvent>,
+	params: Res<MeleeParams>,
+) {
+	for IntersectionEvent {
+		collider1,
+		collider2,
+		intersecting,
+	} in intersection_events.iter()
+	{
+		if !intersecting {
+			continue;
+		}
+		let (swing_e, other) = if q_swings.get(collider1.entity()).is_ok() {
+			(collider1.entity(), collider2.entity())
+		} else {
+			(collider2.entity(), collider1.entity())
+		};
+		let mut swing = match q_swings.get_mut(swing_e) {
+			Ok(swing) => swing,
+			Err(_) => continue,
+		};
+
+		if let Ok(bullet) = q_bullets.get(other) {
+			if bullet.team() == Team::Enemy && !swing.hit.contains(&other) {
+				swing.hit.push(other);
+				// back where the swing aimed, not straight back at the shooter
+				deflect_ew.send(DeflectEvent {
+					bullet: other,
+					owner: swing.owner,
+					dir: swing.aim,
+					speed_scale: params.deflect_speed_scale,
+				});
+			}
+			continue;
+		}
+
+		// boss parts don't have health themselves, their boss does
+		let target = if q_health.get(other).is_ok() {
+			Some(other)
+		} else {
+			q_parent
+				.get(other)
+				.ok()
+				.map(|Parent(parent)| *parent)
+				.filter(|parent| q_health.get(*parent).is_ok())
+		};
+		if let Some(target) = target {
+			if !swing.hit.contains(&target) {
+				swing.hit.push(target);
+				damage_ew.send(DamageEvent {
+					target,
+					amount: params.damage,
+					source: DamageSource::Melee,
+				});
+			}
+		}
+	}
+}
+
+/// Sweeps the blade across the arc and fades it out, then removes the swing
+fn animate_swings(
+	mut commands: Commands,
+	mut q_swings: Query<(Entity, &mut MeleeSwing, &mut Transform, &mut Sprite)>,
+	params: Res<MeleeParams>,
+	time: Res<Time>,
+) {
+	for (entity, mut swing, mut transform, mut sprite) in q_swings.iter_mut() {
+		swing.timer.tick(time.delta());
+		if swing.timer.finished() {
+			commands.entity(entity).despawn();
+			continue;
+		}
+
+		let progress = swing.timer.percent();
+		let dir = Mat2::from_angle((progress - 0.5) * params.arc) * swing.aim;
+		// the sprite is centered, so it sits halfway out to reach from the player to the tip
+		let pos = swing.origin + dir * params.range * 0.5;
+		transform.translation = pos.extend(transform.translation.z);
+		transform.rotation = Quat::from_rotation_z(dir.y.atan2(dir.x));
+		sprite.color.set_a(0.8 * (1.0 - progress));
+	}
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use bevy::{
+	asset::{AssetLoader, LoadContext, LoadedAsset},
+	prelude::*,
+	reflect::TypeUuid,
+	utils::BoxedFuture,
+};
+use serde::Deserialize;
+
+use crate::loading::LoadingAssets;
+
+/// Loads the enemy bullet patterns from `assets/patterns/enemy.patterns.ron`, so they can be authored
+/// without recompiling. Until the file is loaded, or if it fails to parse, the built-in default is used
+pub struct PatternsPlugin;
+
+impl Plugin for PatternsPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_asset::<BulletPatternFile>()
+			.init_asset_loader::<BulletPatternLoader>()
+			.insert_resource(BulletPatterns::default())
+			.add_startup_system(load_bullet_patterns)
+			.add_system(update_bullet_patterns);
+	}
+}
+
+/// A volley of bullets fired at once. Every volley is rotated by `rotation` relative to the previous one
+#[derive(Deserialize, Clone)]
+pub struct BulletPattern {
+	shots: Vec<PatternShot>,
+	/// Seconds between volleys
+	interval: f32,
+	/// Degrees
+	#[serde(default)]
+	rotation: f32,
+}
+
+/// `count` bullets fanned out around `angle`, `spacing` degrees apart
+#[derive(Deserialize, Clone)]
+struct PatternShot {
+	/// Degrees relative to the direction of the target
+	angle: f32,
+	#[serde(default = "default_count")]
+	count: u32,
+	#[serde(default)]
+	spacing: f32,
+	/// Multiplier of the bullet speed
+	#[serde(default = "default_speed")]
+	speed: f32,
+}
+
+fn default_count() -> u32 {
+	1
+}
+
+fn default_speed() -> f32 {
+	1.0
+}
+
+impl Default for BulletPattern {
+	/// A single bullet straight at the target, 10 times a second
+	fn default() -> Self {
+		Self {
+			shots: vec![PatternShot {
+				angle: 0.0,
+				count: 1,
+				spacing: 0.0,
+				speed: 1.0,
+			}],
+			interval: 0.1,
+			rotation: 0.0,
+		}
+	}
+}
+
+impl BulletPattern {
+	pub fn interval(&self) -> f32 {
+		self.interval
+	}
+
+	/// Angle in radians relative to the target and speed multiplier of every bullet of the `volley`th volley
+	pub fn bullets(&self, volley: u32) -> impl Iterator<Item = (f32, f32)> + '_ {
+		let rotation = self.rotation * volley as f32;
+		self.shots.iter().flat_map(move |shot| {
+			let first = shot.angle - shot.spacing * (shot.count - 1) as f32 * 0.5;
+			(0..shot.count).map(move |i| {
+				(
+					(rotation + first + shot.spacing * i as f32).to_radians(),
+					shot.speed,
+				)
+			})
+		})
+	}
+
+	fn validate(&self) -> Result<(), String> {
+		if self.shots.is_empty() {
+			return Err("no shots".to_string());
+		}
+		if self.interval <= 0.0 {
+			return Err(format!("interval {} isn't positive", self.interval));
+		}
+		if let Some(shot) = self
+			.shots
+			.iter()
+			.find(|shot| shot.count == 0 || shot.speed <= 0.0)
+		{
+			return Err(format!(
+				"shot at {} degrees needs a count and speed above 0",
+				shot.angle
+			));
+		}
+		Ok(())
+	}
+}
+
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "5b0c6b6e-2a47-4c1e-9d0e-4f3e7d2c8a61"]
+pub struct BulletPatternFile {
+	patterns: HashMap<String, BulletPattern>,
+}
+
+#[derive(Default)]
+struct BulletPatternLoader;
+
+impl AssetLoader for BulletPatternLoader {
+	fn load<'a>(
+		&'a self,
+		bytes: &'a [u8],
+		load_context: &'a mut LoadContext,
+	) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+		Box::pin(async move {
+			let file: BulletPatternFile = ron::de::from_bytes(bytes)?;
+			load_context.set_default_asset(LoadedAsset::new(file));
+			Ok(())
+		})
+	}
+
+	fn extensions(&self) -> &[&str] {
+		&["patterns.ron"]
+	}
+}
+
+/// Valid patterns of the loaded file by name
+#[derive(Default)]
+pub struct BulletPatterns {
+	handle: Handle<BulletPatternFile>,
+	patterns: HashMap<String, BulletPattern>,
+	default: BulletPattern,
+}
+
+impl BulletPatterns {
+	/// Falls back to the built-in default for unknown names
+	pub fn get(&self, name: &str) -> &BulletPattern {
+		self.patterns.get(name).unwrap_or(&self.default)
+	}
+}
+
+fn load_bullet_patterns(
+	asset_server: Res<AssetServer>,
+	mut patterns: ResMut<BulletPatterns>,
+	mut loading: ResMut<LoadingAssets>,
+) {
+	patterns.handle = asset_server.load("patterns/enemy.patterns.ron");
+	loading.track(&patterns.handle);
+}
+
+/// Copies the patterns over once the file is loaded or was changed, skipping invalid ones
+fn update_bullet_patterns(
+	mut asset_events: EventReader<AssetEvent<BulletPatternFile>>,
+	files: Res<Assets<BulletPatternFile>>,
+	mut patterns: ResMut<BulletPatterns>,
+) {
+	for event in asset_events.iter() {
+		let handle = match event {
+			AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+			AssetEvent::Removed { .. } => continue,
+		};
+		if *handle != patterns.handle {
+			continue;
+		}
+
+		if let Some(file) = files.get(handle) {
+			let mut valid = HashMap::new();
+			for (name, pattern) in file.patterns.iter() {
+				match pattern.validate() {
+					Ok(()) => {
+						valid.insert(name.clone(), pattern.clone());
+					}
+					Err(err) => warn!("Skipping bullet pattern {}: {}", name, err),
+				}
+			}
+			debug!("LOADED {} BULLET PATTERNS", valid.len());
+			patterns.patterns = valid;
+		}
+	}
+}
@@ -1,6 +1,8 @@
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 
+use crate::{enemy::Enemy, game::GameState, player::Player, shooting::Bullet};
+
 pub struct SetupPhysicsPlugin;
 
 impl Plugin for SetupPhysicsPlugin {
@@ -16,7 +18,13 @@ impl Plugin for SetupPhysicsPlugin {
 			enemy_mask: 0b00000010,
 			scene_mask: 0b00000100,
 			bullet_mask: 0b00001000,
-		});
+		})
+		.insert_resource(PhysicsMaterialParams::default())
+		.insert_resource(VelocityLimits::default())
+		.add_system_set(
+			SystemSet::on_update(GameState::Playing)
+				.with_system(clamp_velocities.after("player_movement").after("enemy_ai")),
+		);
 	}
 }
 
@@ -26,3 +34,257 @@ pub struct PhysicsGlobals {
 	pub scene_mask: u32,
 	pub bullet_mask: u32,
 }
+
+/// Friction and restitution used when building colliders, grouped by what the collider belongs to.
+/// Set `bullet_restitution` (and `wall_restitution`) above zero to let bullets bounce off the arena.
+/// Ricochet bullets bounce by themselves and ignore both
+pub struct PhysicsMaterialParams {
+	pub wall_friction: f32,
+	pub wall_restitution: f32,
+	pub entity_friction: f32,
+	pub entity_restitution: f32,
+	pub bullet_friction: f32,
+	pub bullet_restitution: f32,
+}
+
+impl Default for PhysicsMaterialParams {
+	fn default() -> Self {
+		Self {
+			wall_friction: 0.5,
+			wall_restitution: 0.0,
+			// no friction so enemies slide along walls instead of sticking to them
+			entity_friction: 0.0,
+			entity_restitution: 0.0,
+			bullet_friction: 0.0,
+			bullet_restitution: 0.0,
+		}
+	}
+}
+
+impl PhysicsMaterialParams {
+	pub fn wall(&self) -> ColliderMaterialComponent {
+		ColliderMaterial::new(self.wall_friction, self.wall_restitution).into()
+	}
+
+	pub fn entity(&self) -> ColliderMaterialComponent {
+		ColliderMaterial::new(self.entity_friction, self.entity_restitution).into()
+	}
+
+	pub fn bullet(&self) -> ColliderMaterialComponent {
+		ColliderMaterial::new(self.bullet_friction, self.bullet_restitution).into()
+	}
+
+	/// The minimum of zero always wins, so the solver only stops ricochet bullets and they bounce off
+	/// walls themselves
+	pub fn ricochet(&self) -> ColliderMaterialComponent {
+		ColliderMaterial {
+			friction: 0.0,
+			restitution: 0.0,
+			friction_combine_rule: CoefficientCombineRule::Min,
+			restitution_combine_rule: CoefficientCombineRule::Min,
+		}
+		.into()
+	}
+}
+
+/// Max speed in pixels / sec per kind of body, so stacked collisions can't fling anything through walls.
+/// 0 disables the limit
+pub struct VelocityLimits {
+	pub player: f32,
+	pub enemy: f32,
+	pub bullet: f32,
+}
+
+impl Default for VelocityLimits {
+	fn default() -> Self {
+		Self {
+			player: 600.0,
+			enemy: 600.0,
+			// bullets are only pushed by forces, so they aren't limited by default
+			bullet: 0.0,
+		}
+	}
+}
+
+/// Moves `current` towards `target` by at most `acceleration * dt` while speeding up and `deceleration * dt`
+/// while slowing down. A rate of 0 or less snaps to the target right away
+pub fn approach_velocity(
+	current: Vec2,
+	target: Vec2,
+	acceleration: f32,
+	deceleration: f32,
+	dt: f32,
+) -> Vec2 {
+	let rate = if target.length_squared() >= current.length_squared() {
+		acceleration
+	} else {
+		deceleration
+	};
+	if rate <= 0.0 {
+		return target;
+	}
+
+	let diff = target - current;
+	let max_delta = rate * dt;
+	if diff.length() <= max_delta {
+		target
+	} else {
+		current + diff.normalize() * max_delta
+	}
+}
+
+fn clamp_velocities(
+	limits: Res<VelocityLimits>,
+	rapier_config: Res<RapierConfiguration>,
+	mut q_bodies: Query<(
+		&mut RigidBodyVelocityComponent,
+		Option<&Player>,
+		Option<&Enemy>,
+		Option<&Bullet>,
+	)>,
+) {
+	for (mut rb_vel, player, enemy, bullet) in q_bodies.iter_mut() {
+		let max_speed = match (player, enemy, bullet) {
+			(Some(_), _, _) => limits.player,
+			(_, Some(_), _) => limits.enemy,
+			(_, _, Some(_)) => limits.bullet,
+			_ => continue,
+		};
+		if max_speed <= 0.0 {
+			continue;
+		}
+
+		let vel = Vec2::from(rb_vel.linvel);
+		let max_speed = max_speed / rapier_config.scale;
+		if vel.length() > max_speed {
+			rb_vel.linvel = vel.clamp_length_max(max_speed).into();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn zero_rates_snap_to_target_velocity() {
+		let target = Vec2::new(200.0, 0.0);
+		assert_eq!(
+			approach_velocity(Vec2::ZERO, target, 0.0, 0.0, 0.016),
+			target
+		);
+		// stopping snaps too
+		assert_eq!(
+			approach_velocity(target, Vec2::ZERO, 0.0, 0.0, 0.016),
+			Vec2::ZERO
+		);
+	}
+
+	#[test]
+	fn rates_limit_the_velocity_change() {
+		// speeding up is limited by the acceleration
+		let vel = approach_velocity(Vec2::ZERO, Vec2::new(200.0, 0.0), 1000.0, 4000.0, 0.1);
+		assert!(vel.abs_diff_eq(Vec2::new(100.0, 0.0), 1e-4));
+		// slowing down by the deceleration
+		let vel = approach_velocity(Vec2::new(200.0, 0.0), Vec2::ZERO, 1000.0, 500.0, 0.1);
+		assert!(vel.abs_diff_eq(Vec2::new(150.0, 0.0), 1e-4));
+		// the target isn't overshot
+		let vel = approach_velocity(
+			Vec2::new(190.0, 0.0),
+			Vec2::new(200.0, 0.0),
+			1000.0,
+			0.0,
+			0.1,
+		);
+		assert_eq!(vel, Vec2::new(200.0, 0.0));
+	}
+
+	#[test]
+	fn materials_use_configured_restitution() {
+		let params = PhysicsMaterialParams {
+			wall_restitution: 0.8,
+			bullet_restitution: 0.5,
+			..Default::default()
+		};
+		assert_eq!(params.wall().restitution, 0.8);
+		assert_eq!(params.bullet().restitution, 0.5);
+
+		// ricochet bullets bounce themselves, so the solver must not bounce them off the wall
+		let ricochet = params.ricochet();
+		assert_eq!(ricochet.restitution, 0.0);
+		assert_eq!(
+			ricochet.restitution_combine_rule,
+			CoefficientCombineRule::Min
+		);
+	}
+
+	/// Speed of a bullet that hit a wall head-on at 10 physics units per second, after bouncing off it.
+	/// Steps a plain rapier world, with the materials the arena and the bullets are built with
+	fn bounce_speed(params: &PhysicsMaterialParams) -> f32 {
+		let (wall, bullet) = (params.wall(), params.bullet());
+		let mut bodies = RigidBodySet::new();
+		let mut colliders = ColliderSet::new();
+		colliders.insert(
+			ColliderBuilder::cuboid(0.5, 5.0)
+				.friction(wall.friction)
+				.restitution(wall.restitution)
+				.build(),
+		);
+		let body = bodies.insert(
+			RigidBodyBuilder::new_dynamic()
+				.translation(Vec2::new(-2.0, 0.0).into())
+				.linvel(Vec2::new(10.0, 0.0).into())
+				.build(),
+		);
+		colliders.insert_with_parent(
+			ColliderBuilder::ball(0.2)
+				.friction(bullet.friction)
+				.restitution(bullet.restitution)
+				.build(),
+			body,
+			&mut bodies,
+		);
+
+		let mut pipeline = PhysicsPipeline::new();
+		let mut islands = IslandManager::new();
+		let mut broad_phase = BroadPhase::new();
+		let mut narrow_phase = NarrowPhase::new();
+		let mut joints = JointSet::new();
+		let mut ccd_solver = CCDSolver::new();
+		for _ in 0..60 {
+			pipeline.step(
+				&Vec2::ZERO.into(),
+				&IntegrationParameters::default(),
+				&mut islands,
+				&mut broad_phase,
+				&mut narrow_phase,
+				&mut bodies,
+				&mut colliders,
+				&mut joints,
+				&mut ccd_solver,
+				&(),
+				&(),
+			);
+		}
+		-bodies[body].linvel().x
+	}
+
+	#[test]
+	fn bullets_bounce_with_configured_restitution() {
+		let bounce = |restitution: f32| {
+			bounce_speed(&PhysicsMaterialParams {
+				wall_restitution: restitution,
+				bullet_restitution: restitution,
+				..Default::default()
+			})
+		};
+		// the default doesn't bounce at all
+		assert!(bounce_speed(&PhysicsMaterialParams::default()).abs() < 0.5);
+
+		let half = bounce(0.5);
+		let full = bounce(1.0);
+		assert!((half - 5.0).abs() < 1.0, "bounced back at {}", half);
+		assert!((full - 10.0).abs() < 1.0, "bounced back at {}", full);
+		assert!(full > half);
+	}
+}
@@ -1,9 +1,16 @@
 use bevy::prelude::*;
-use bevy_rapier2d::prelude::*;
+use bevy_rapier2d::{na::UnitComplex, prelude::*};
+use serde::Deserialize;
 
 use crate::{
-	game::{GameState, Health},
-	physics::PhysicsGlobals,
+	chaos::ChaosEffects,
+	game::{GameState, Health, Invulnerable, PlayerRespawnEvent},
+	input::{Action, InputAction, InputBuffer, MousePosition},
+	inventory::PlayerInventory,
+	physics::{approach_velocity, PhysicsGlobals, PhysicsMaterialParams},
+	settings::Settings,
+	shooting::{Ammo, Spread, Weapon, WeaponParams},
+	status_effects::{StatusEffectParams, StatusEffects},
 };
 
 pub struct PlayerSpawnEvent;
@@ -12,12 +19,21 @@ pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
 	fn build(&self, app: &mut App) {
-		app.insert_resource(PlayerParams {
-			start_health: 100.0,
-		})
-		.add_event::<PlayerSpawnEvent>()
-		.add_system_set(SystemSet::on_enter(GameState::Playing).with_system(spawn_player))
-		.add_system_set(SystemSet::on_update(GameState::Playing).with_system(player_movement));
+		app.insert_resource(PlayerParams::default())
+			.insert_resource(PlayerCollisionMode::default())
+			.insert_resource(PlayerFacing::default())
+			.add_event::<PlayerSpawnEvent>()
+			.add_system_set(
+				SystemSet::on_enter(GameState::Playing).with_system(spawn_player.after("map")),
+			)
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(player_movement.label("player_movement").after("input"))
+					.with_system(player_dash.after("player_movement"))
+					.with_system(respawn_player.after("player_death"))
+					.with_system(apply_player_collision_mode)
+					.with_system(face_player.after("player_movement").after("input")),
+			);
 	}
 }
 
@@ -27,19 +43,89 @@ const PLAYER_SPEED_VALUE: f32 = 300.0; // Pixels / sec
 #[derive(Component)]
 pub struct Player(pub f32);
 
-struct PlayerParams {
-	start_health: f32,
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PlayerParams {
+	pub start_health: f32,
+	/// In pixels
+	pub spawn_pos: Vec2,
+	/// Seconds the player can't be hurt after losing a life
+	respawn_invulnerability: f32,
+	/// Pixels / sec² towards the input direction, 0 for instant movement
+	acceleration: f32,
+	/// Pixels / sec² when slowing down, 0 for instantly stopping
+	deceleration: f32,
+	/// Pixels / sec while dashing
+	dash_speed: f32,
+	dash_duration: f32,
+	/// Seconds from the start of a dash until the next one
+	dash_cooldown: f32,
+	/// Seconds the player can't be hurt from the start of a dash
+	dash_invulnerability: f32,
+}
+
+impl Default for PlayerParams {
+	fn default() -> Self {
+		Self {
+			start_health: 100.0,
+			spawn_pos: Vec2::new(-200.0, 0.0),
+			respawn_invulnerability: 2.0,
+			acceleration: 3000.0,
+			deceleration: 2500.0,
+			dash_speed: 900.0,
+			dash_duration: 0.15,
+			dash_cooldown: 1.0,
+			dash_invulnerability: 0.25,
+		}
+	}
+}
+
+/// How the player and enemies physically respond to touching each other. Walls always block the player
+#[derive(Clone, Copy, PartialEq)]
+pub enum PlayerCollisionMode {
+	/// Player and enemies push each other
+	Solid,
+	/// Player and enemies overlap, the contact is still detected but nobody gets pushed
+	Sensor,
+	/// The player pushes enemies but enemies can't push the player
+	OneWay,
+}
+
+impl Default for PlayerCollisionMode {
+	fn default() -> Self {
+		PlayerCollisionMode::Solid
+	}
+}
+
+/// Which way the player sprite faces
+#[derive(Clone, Copy, PartialEq)]
+pub enum PlayerFacing {
+	/// Physics alone rotates the player
+	None,
+	/// Mirrors the sprite horizontally when moving left, the body is kept upright
+	Flip,
+	/// Turns the body towards the mouse, or where the right stick aims
+	RotateToMouse,
+}
+
+impl Default for PlayerFacing {
+	fn default() -> Self {
+		PlayerFacing::Flip
+	}
 }
 
-fn spawn_player(
+pub fn spawn_player(
 	mut commands: Commands,
 	asset_server: Res<AssetServer>,
 	rapier_config: Res<RapierConfiguration>,
 	physics_globals: Res<PhysicsGlobals>,
+	materials: Res<PhysicsMaterialParams>,
 	params: Res<PlayerParams>,
+	weapons: Res<WeaponParams>,
+	settings: Res<Settings>,
 	mut ev_writer: EventWriter<PlayerSpawnEvent>,
 ) {
-	info!("SPAWN_PLAYER");
+	debug!("SPAWN_PLAYER");
 
 	// spawn player sprite with physics attached
 	commands
@@ -52,50 +138,274 @@ fn spawn_player(
 			..Default::default()
 		})
 		.insert_bundle(RigidBodyBundle {
-			position: Vec2::new(-10.0, 0.0).into(),
+			position: (params.spawn_pos / rapier_config.scale).into(),
 
 			..Default::default()
 		})
 		.insert(ColliderPositionSync::Discrete)
 		.insert_bundle(ColliderBundle {
 			position: Vec2::ZERO.into(),
+			material: materials.entity(),
 			// Since the physics world is scaled, we divide pixel size by it to get the collider size
 			shape: ColliderShapeComponent(ColliderShape::ball(10.0 / rapier_config.scale)),
 			flags: ColliderFlags {
 				collision_groups: InteractionGroups::new(physics_globals.player_mask, u32::MAX),
+				// enemies touching the player hurt it, see `contact_damage` and `minion_contacts`
+				active_events: ActiveEvents::CONTACT_EVENTS,
 				..Default::default()
 			}
 			.into(),
 			..Default::default()
 		})
 		.insert(Player(PLAYER_SPEED_VALUE))
+		.insert(Spread::default())
+		.insert(Weapon(settings.loadout))
+		.insert(PlayerInventory::new(settings.loadout))
+		.insert(Ammo::new(&weapons))
+		.insert(Dash::new(&params))
 		.insert(Health(params.start_health));
 
 	ev_writer.send(PlayerSpawnEvent);
 }
 
+/// Puts a player that lost a life back to the spawn with full health and a short invulnerability
+fn respawn_player(
+	mut commands: Commands,
+	mut respawn_events: EventReader<PlayerRespawnEvent>,
+	mut q_player: Query<
+		(
+			&mut Health,
+			&mut RigidBodyPositionComponent,
+			&mut RigidBodyVelocityComponent,
+		),
+		With<Player>,
+	>,
+	params: Res<PlayerParams>,
+	rapier_config: Res<RapierConfiguration>,
+) {
+	for PlayerRespawnEvent(entity) in respawn_events.iter() {
+		if let Ok((mut health, mut rb_pos, mut rb_vel)) = q_player.get_mut(*entity) {
+			debug!("RESPAWN_PLAYER");
+			health.0 = params.start_health;
+			rb_pos.0.position.translation.vector = (params.spawn_pos / rapier_config.scale).into();
+			rb_pos.0.next_position = rb_pos.0.position;
+			rb_vel.linvel = Vec2::ZERO.into();
+			// a fresh life starts without burning or poison
+			commands.entity(*entity).remove::<StatusEffects>();
+
+			let shield = commands
+				.spawn_bundle(SpriteBundle {
+					sprite: Sprite {
+						custom_size: Some(Vec2::splat(30.0)),
+						color: Color::rgba(0.3, 0.6, 1.0, 0.5),
+						..Default::default()
+					},
+					transform: Transform::from_xyz(0.0, 0.0, 1.0),
+					..Default::default()
+				})
+				.id();
+			commands
+				.entity(*entity)
+				.push_children(&[shield])
+				.insert(Invulnerable {
+					timer: Timer::from_seconds(params.respawn_invulnerability, false),
+					shield: Some(shield),
+				});
+		}
+	}
+}
+
+/// Applies the collision mode to freshly spawned players and to all players whenever the mode changes
+fn apply_player_collision_mode(
+	mode: Res<PlayerCollisionMode>,
+	physics_globals: Res<PhysicsGlobals>,
+	mut q_player: Query<
+		(
+			&mut ColliderFlagsComponent,
+			&mut RigidBodyDominanceComponent,
+			ChangeTrackers<Player>,
+		),
+		With<Player>,
+	>,
+) {
+	for (mut flags, mut dominance, player_tracker) in q_player.iter_mut() {
+		if !mode.is_changed() && !player_tracker.is_added() {
+			continue;
+		}
+
+		// the solver groups only decide whether contact forces are applied, contacts are still reported
+		flags.solver_groups = match *mode {
+			PlayerCollisionMode::Sensor => InteractionGroups::new(
+				physics_globals.player_mask,
+				u32::MAX - physics_globals.enemy_mask,
+			),
+			_ => InteractionGroups::all(),
+		};
+		// bodies of a higher dominance group act like they had infinite mass for lower ones
+		dominance.0 = RigidBodyDominance(match *mode {
+			PlayerCollisionMode::OneWay => 1,
+			_ => 0,
+		});
+	}
+}
+
+/// Sets the player's rotation directly and stops it from spinning, so physics doesn't turn it any other way
+fn face_player(
+	facing: Res<PlayerFacing>,
+	mouse_pos: Res<MousePosition>,
+	input: Res<InputAction>,
+	rapier_config: Res<RapierConfiguration>,
+	mut q_player: Query<
+		(
+			&mut Sprite,
+			&mut RigidBodyPositionComponent,
+			&mut RigidBodyVelocityComponent,
+		),
+		With<Player>,
+	>,
+) {
+	for (mut sprite, mut rb_pos, mut rb_vel) in q_player.iter_mut() {
+		let angle = match *facing {
+			PlayerFacing::None => {
+				if facing.is_changed() && sprite.flip_x {
+					sprite.flip_x = false;
+				}
+				continue;
+			}
+			PlayerFacing::Flip => {
+				// keep facing the same way while not moving sideways
+				let vel_x = rb_vel.linvel.x;
+				if vel_x.abs() > f32::EPSILON && sprite.flip_x != (vel_x < 0.0) {
+					sprite.flip_x = vel_x < 0.0;
+				}
+				0.0
+			}
+			PlayerFacing::RotateToMouse => {
+				if sprite.flip_x {
+					sprite.flip_x = false;
+				}
+				let pos = Vec2::from(rb_pos.0.position.translation.vector) * rapier_config.scale;
+				let dir = input.aim.unwrap_or(mouse_pos.0 - pos);
+				dir.y.atan2(dir.x)
+			}
+		};
+		rb_pos.0.position.rotation = UnitComplex::from_angle(angle);
+		rb_vel.angvel = 0.0;
+	}
+}
+
+/// Short burst of speed in the movement direction with a few frames of invulnerability
+#[derive(Component)]
+pub struct Dash {
+	pub cooldown: Timer,
+	duration: Timer,
+	direction: Vec2,
+}
+
+impl Dash {
+	fn new(params: &PlayerParams) -> Self {
+		// ready right away and not dashing yet
+		let finished = |secs: f32| {
+			let mut timer = Timer::from_seconds(secs, false);
+			timer.tick(timer.duration());
+			timer
+		};
+		Self {
+			cooldown: finished(params.dash_cooldown),
+			duration: finished(params.dash_duration),
+			direction: Vec2::ZERO,
+		}
+	}
+}
+
+/// Starts a buffered dash once the cooldown is over and overrides the movement velocity while dashing.
+/// Standing still dashes in the direction the player is still drifting in, or not at all
+fn player_dash(
+	mut commands: Commands,
+	input: Res<InputAction>,
+	mut input_buffer: ResMut<InputBuffer>,
+	rapier_parameters: Res<RapierConfiguration>,
+	params: Res<PlayerParams>,
+	chaos: Res<ChaosEffects>,
+	time: Res<Time>,
+	mut q_player: Query<
+		(
+			Entity,
+			&mut Dash,
+			&mut RigidBodyVelocityComponent,
+			Option<&Invulnerable>,
+		),
+		With<Player>,
+	>,
+) {
+	for (entity, mut dash, mut rb_vel, invulnerable) in q_player.iter_mut() {
+		dash.cooldown.tick(time.delta());
+		dash.duration.tick(time.delta());
+
+		let now = time.seconds_since_startup();
+		if dash.cooldown.finished() && input_buffer.consume(Action::Dash, now) {
+			let mut direction = input.movement;
+			if chaos.inverted_controls {
+				direction = -direction;
+			}
+			if direction == Vec2::ZERO {
+				direction = rb_vel.linvel.into();
+			}
+			if direction != Vec2::ZERO {
+				debug!("DASH");
+				dash.direction = direction.normalize();
+				dash.cooldown = Timer::from_seconds(params.dash_cooldown, false);
+				dash.duration = Timer::from_seconds(params.dash_duration, false);
+				// a longer invulnerability, e.g. after respawning, is kept
+				let remaining = invulnerable.map_or(0.0, |invulnerable| {
+					invulnerable.timer.duration().as_secs_f32() - invulnerable.timer.elapsed_secs()
+				});
+				if remaining < params.dash_invulnerability {
+					commands.entity(entity).insert(Invulnerable {
+						timer: Timer::from_seconds(params.dash_invulnerability, false),
+						shield: invulnerable.and_then(|invulnerable| invulnerable.shield),
+					});
+				}
+			}
+		}
+
+		if !dash.duration.finished() {
+			rb_vel.linvel = (dash.direction * params.dash_speed / rapier_parameters.scale).into();
+		}
+	}
+}
+
 /// System that simply updated the player's velocity if buttons to move the player are pressed
 pub fn player_movement(
-	keyboard_input: Res<Input<KeyCode>>,
+	input: Res<InputAction>,
 	rapier_parameters: Res<RapierConfiguration>,
-	mut player_info: Query<(&Player, &mut RigidBodyVelocityComponent)>,
+	params: Res<PlayerParams>,
+	chaos: Res<ChaosEffects>,
+	status_params: Res<StatusEffectParams>,
+	time: Res<Time>,
+	mut player_info: Query<(
+		&Player,
+		&mut RigidBodyVelocityComponent,
+		Option<&StatusEffects>,
+	)>,
 ) {
-	for (player, mut rb_vels) in player_info.iter_mut() {
-		let up = keyboard_input.any_pressed([KeyCode::W, KeyCode::Up]);
-		let down = keyboard_input.any_pressed([KeyCode::S, KeyCode::Down]);
-		let left = keyboard_input.any_pressed([KeyCode::A, KeyCode::Left]);
-		let right = keyboard_input.any_pressed([KeyCode::D, KeyCode::Right]);
-
-		let x_axis = -(left as i8) + right as i8;
-		let y_axis = -(down as i8) + up as i8;
-
-		let mut move_delta = Vec2::new(x_axis as f32, y_axis as f32);
-		if move_delta != Vec2::ZERO {
-			// multiply with scale to transform pixels/sec to physical units/sec
-			move_delta /= move_delta.length() * rapier_parameters.scale;
+	for (player, mut rb_vels, status) in player_info.iter_mut() {
+		let mut move_delta = input.movement;
+		if chaos.inverted_controls {
+			move_delta = -move_delta;
 		}
+		// multiply with scale to transform pixels/sec to physical units/sec
+		move_delta /= rapier_parameters.scale;
 
 		// update velocity
-		rb_vels.linvel = (move_delta * player.0).into();
+		rb_vels.linvel = approach_velocity(
+			rb_vels.linvel.into(),
+			move_delta
+				* player.0 * status.map_or(1.0, |status| status.speed_multiplier(&status_params)),
+			params.acceleration * chaos.acceleration_scale / rapier_parameters.scale,
+			params.deceleration * chaos.acceleration_scale / rapier_parameters.scale,
+			time.delta_seconds(),
+		)
+		.into();
 	}
 }
@@ -1,35 +1,322 @@
-use bevy::prelude::*;
+use bevy::{
+	ecs::system::EntityCommands,
+	math::Vec3Swizzles,
+	prelude::*,
+	render::camera::ScalingMode,
+	window::{WindowCreated, WindowResized},
+};
 use bevy_rapier2d::prelude::*;
 
-use crate::game::GameState;
+use crate::{
+	chaos::ChaosEffects,
+	game::{DamageEvent, DamageSource, GameClock, GameState},
+	map::{ArenaMap, MapLayout},
+	physics::PhysicsMaterialParams,
+	player::Player,
+};
 
 pub struct SetupScenePlugin;
 
 impl Plugin for SetupScenePlugin {
 	fn build(&self, app: &mut App) {
 		app.insert_resource(WindowDescriptor::default())
+			.insert_resource(CameraParams::default())
+			.insert_resource(SuddenDeathParams::default())
+			.insert_resource(ArenaBounds::default())
 			.add_startup_system(spawn_camera)
+			.add_system(fit_camera_to_window)
 			.add_system_set(
-				SystemSet::on_enter(GameState::Playing).with_system(spawn_scene.label("scene")),
+				SystemSet::on_enter(GameState::Playing)
+					.with_system(spawn_scene.label("scene"))
+					.with_system(reset_arena_bounds)
+					.with_system(reset_camera),
+			)
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(shrink_arena)
+					.with_system(
+						camera_follow
+							.label("camera_follow")
+							.after("player_movement"),
+					),
 			);
 	}
 }
 
+/// Half of the width and height of the area enclosed by the walls
+pub const ARENA_HALF_EXTENTS: Vec2 = Vec2::new(345.0, 200.0);
+
+/// Optional mode where the walls close in over time. The player takes damage while outside the safe zone
+pub struct SuddenDeathParams {
+	pub enabled: bool,
+	/// Seconds before the walls start moving
+	pub start_delay: f32,
+	/// Pixels / sec each wall moves inwards
+	pub shrink_rate: f32,
+	pub min_half_extents: Vec2,
+	/// Damage / sec while outside the safe zone
+	pub outside_damage: f32,
+}
+
+impl Default for SuddenDeathParams {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			start_delay: 20.0,
+			shrink_rate: 2.0,
+			min_half_extents: Vec2::new(150.0, 100.0),
+			outside_damage: 10.0,
+		}
+	}
+}
+
+/// The area the player is safe in, centered around the origin. Shrinks when sudden death is enabled
+pub struct ArenaBounds {
+	pub half_extents: Vec2,
+}
+
+impl Default for ArenaBounds {
+	fn default() -> Self {
+		Self {
+			half_extents: ARENA_HALF_EXTENTS,
+		}
+	}
+}
+
+impl ArenaBounds {
+	pub fn contains(&self, pos: Vec2) -> bool {
+		pos.x.abs() <= self.half_extents.x && pos.y.abs() <= self.half_extents.y
+	}
+}
+
+/// Wall that moves along `inward` when the arena shrinks. `origin` is its position in the full-size arena
+#[derive(Component)]
+struct ShrinkingWall {
+	origin: Vec2,
+	inward: Vec2,
+}
+
 #[derive(Component)]
 pub struct MainCamera;
 
-fn spawn_camera(mut commands: Commands) {
-	info!("SPAWN_CAMERA");
+pub struct CameraParams {
+	/// Half of the world area that is visible, whatever the window's aspect ratio is.
+	/// Windows with a different aspect ratio get black bars on one axis instead of stretching it
+	pub view_half_extents: Vec2,
+	/// How quickly the camera catches up with the player, fraction of the distance per 1/60 sec
+	pub follow_speed: f32,
+	/// Pixels the player can move away from the camera center before it starts following
+	pub deadzone: f32,
+}
+
+impl Default for CameraParams {
+	fn default() -> Self {
+		Self {
+			view_half_extents: ARENA_HALF_EXTENTS + Vec2::splat(15.0),
+			follow_speed: 0.1,
+			deadzone: 30.0,
+		}
+	}
+}
+
+/// Marks the static arena walls, e.g. so bullets know what they can bounce off
+#[derive(Component)]
+pub struct Wall;
+
+/// Black bar covering the part of the view beyond `CameraParams::view_half_extents` on the side it
+/// points to, so every window shows the same play area
+#[derive(Component)]
+struct LetterboxBar(Vec2);
+
+fn spawn_camera(mut commands: Commands, params: Res<CameraParams>) {
+	debug!("SPAWN_CAMERA");
+	let mut camera = OrthographicCameraBundle::new_2d();
+	// the projection spans -scale..scale vertically, horizontally it depends on the aspect ratio
+	camera.orthographic_projection.scaling_mode = ScalingMode::FixedVertical;
+	camera.orthographic_projection.scale = params.view_half_extents.y;
 	commands
-		.spawn_bundle(OrthographicCameraBundle::new_2d())
-		.insert(MainCamera);
+		.spawn_bundle(camera)
+		.insert(MainCamera)
+		.with_children(|parent| {
+			for side in [-Vec2::X, Vec2::X, -Vec2::Y, Vec2::Y] {
+				parent
+					.spawn_bundle(SpriteBundle {
+						sprite: Sprite {
+							color: Color::BLACK,
+							custom_size: Some(Vec2::ZERO),
+							..Default::default()
+						},
+						// just in front of the camera, so the bars cover everything in the world
+						transform: Transform::from_xyz(0.0, 0.0, -1.0),
+						..Default::default()
+					})
+					.insert(LetterboxBar(side));
+			}
+		});
+}
+
+/// Center and size of the bar on `side` when the view shows `visible_half_extents` of the world
+fn letterbox_bar(side: Vec2, visible_half_extents: Vec2, view_half_extents: Vec2) -> (Vec2, Vec2) {
+	let thickness = (visible_half_extents - view_half_extents).max(Vec2::ZERO);
+	let center = side * (view_half_extents + thickness / 2.0);
+	// along the side the bar spans the whole view
+	let size = if side.x != 0.0 {
+		Vec2::new(thickness.x, visible_half_extents.y * 2.0)
+	} else {
+		Vec2::new(visible_half_extents.x * 2.0, thickness.y)
+	};
+	(center, size)
+}
+
+/// Zooms the camera out on narrow windows, so the whole arena stays visible, and covers what is
+/// visible beyond the arena with the letterbox bars
+fn fit_camera_to_window(
+	mut resized_events: EventReader<WindowResized>,
+	mut created_events: EventReader<WindowCreated>,
+	windows: Res<Windows>,
+	params: Res<CameraParams>,
+	mut q_projection: Query<&mut OrthographicProjection, With<MainCamera>>,
+	mut q_bars: Query<(&LetterboxBar, &mut Transform, &mut Sprite)>,
+) {
+	let changed = resized_events.iter().count() + created_events.iter().count() > 0;
+	if !changed {
+		return;
+	}
+
+	if let Some(window) = windows.get_primary() {
+		let aspect_ratio = window.width() / window.height();
+		let scale = params
+			.view_half_extents
+			.y
+			.max(params.view_half_extents.x / aspect_ratio);
+		for mut projection in q_projection.iter_mut() {
+			projection.scale = scale;
+		}
+
+		let visible_half_extents = Vec2::new(scale * aspect_ratio, scale);
+		for (LetterboxBar(side), mut transform, mut sprite) in q_bars.iter_mut() {
+			let (center, size) =
+				letterbox_bar(*side, visible_half_extents, params.view_half_extents);
+			transform.translation = center.extend(transform.translation.z);
+			sprite.custom_size = Some(size);
+		}
+	}
+}
+
+fn reset_camera(mut q_camera: Query<&mut Transform, With<MainCamera>>) {
+	for mut transform in q_camera.iter_mut() {
+		transform.translation.x = 0.0;
+		transform.translation.y = 0.0;
+	}
+}
+
+/// Moves the camera toward the player, but never so far that the view leaves the arena walls.
+/// While the whole arena fits into the view the camera stays centered
+fn camera_follow(
+	params: Res<CameraParams>,
+	time: Res<Time>,
+	q_player: Query<&Transform, (With<Player>, Without<MainCamera>)>,
+	mut q_camera: Query<(&mut Transform, &OrthographicProjection), With<MainCamera>>,
+) {
+	let player_pos = match q_player.get_single() {
+		Ok(transform) => transform.translation.xy(),
+		Err(_) => return,
+	};
+
+	for (mut transform, projection) in q_camera.iter_mut() {
+		let camera_pos = transform.translation.xy();
+		let offset = player_pos - camera_pos;
+		let distance = offset.length();
+		let mut target = camera_pos;
+		if distance > params.deadzone {
+			target += offset * (distance - params.deadzone) / distance;
+		}
+
+		// frame rate independent lerp
+		let t = 1.0 - (1.0 - params.follow_speed.clamp(0.0, 1.0)).powf(time.delta_seconds() * 60.0);
+		let mut pos = camera_pos.lerp(target, t);
+
+		let visible_half_extents = Vec2::new(projection.right, projection.top) * projection.scale;
+		let max_offset = (params.view_half_extents - visible_half_extents).max(Vec2::ZERO);
+		pos = pos.clamp(-max_offset, max_offset);
+		transform.translation = pos.extend(transform.translation.z);
+	}
 }
 
 /// Startup system. Spawns all the things that are necessary to render the scene
-fn spawn_scene(mut commands: Commands, rapier_parameters: Res<RapierConfiguration>) {
-	info!("SPAWN_SCENE");
+fn spawn_scene(
+	mut commands: Commands,
+	rapier_parameters: Res<RapierConfiguration>,
+	materials: Res<PhysicsMaterialParams>,
+	map: Res<ArenaMap>,
+	layouts: Res<Assets<MapLayout>>,
+) {
+	debug!("SPAWN_SCENE");
+
+	// a map brings its own obstacles instead of the test dummy
+	if map.layout(&layouts).is_none() {
+		spawn_dummy(&mut commands, &rapier_parameters, &materials);
+	}
+
+	spawn_static_collider(
+		&mut commands,
+		&rapier_parameters,
+		&materials,
+		Vec2::new(0.0, 200.0),
+		Vec2::new(700.0, 10.0),
+	)
+	.insert(Wall)
+	.insert(ShrinkingWall {
+		origin: Vec2::new(0.0, 200.0),
+		inward: -Vec2::Y,
+	});
 
-	// test dummy rigidbody
+	spawn_static_collider(
+		&mut commands,
+		&rapier_parameters,
+		&materials,
+		Vec2::new(-345.0, 0.0),
+		Vec2::new(10.0, 400.0),
+	)
+	.insert(Wall)
+	.insert(ShrinkingWall {
+		origin: Vec2::new(-345.0, 0.0),
+		inward: Vec2::X,
+	});
+
+	spawn_static_collider(
+		&mut commands,
+		&rapier_parameters,
+		&materials,
+		Vec2::new(-175.0, -200.0),
+		Vec2::new(500.0, 10.0),
+	)
+	.insert(Wall)
+	.insert(ShrinkingWall {
+		origin: Vec2::new(-175.0, -200.0),
+		inward: Vec2::Y,
+	});
+
+	spawn_static_collider(
+		&mut commands,
+		&rapier_parameters,
+		&materials,
+		Vec2::new(345.0, 0.0),
+		Vec2::new(10.0, 400.0),
+	)
+	.insert(Wall)
+	.insert(ShrinkingWall {
+		origin: Vec2::new(345.0, 0.0),
+		inward: -Vec2::X,
+	});
+}
+
+/// Spinning test dummy rigidbody
+fn spawn_dummy(
+	commands: &mut Commands,
+	rapier_parameters: &RapierConfiguration,
+	materials: &PhysicsMaterialParams,
+) {
 	commands
 		.spawn_bundle(SpriteBundle {
 			sprite: Sprite {
@@ -59,6 +346,7 @@ fn spawn_scene(mut commands: Commands, rapier_parameters: Res<RapierConfiguratio
 		})
 		.insert_bundle(ColliderBundle {
 			position: Vec2::ZERO.into(),
+			material: materials.wall(),
 			shape: ColliderShapeComponent(ColliderShape::cuboid(
 				25.0 / rapier_parameters.scale,
 				5.0 / rapier_parameters.scale,
@@ -66,129 +354,121 @@ fn spawn_scene(mut commands: Commands, rapier_parameters: Res<RapierConfiguratio
 			..Default::default()
 		})
 		.insert(ColliderPositionSync::Discrete);
+}
 
-	// wall top
-	commands
-		.spawn_bundle(SpriteBundle {
-			sprite: Sprite {
-				color: Color::rgb(0.0, 0.0, 0.0),
-				custom_size: Some(Vec2::new(700.0, 10.0)),
-				..Default::default()
-			},
+/// Spawns a black static box at `pos` with the size `size` in pixels. Static bodies don't move on their own,
+/// so the transform is set once here instead of syncing it from the collider every frame.
+/// Code that moves them has to update the transform itself
+pub fn spawn_static_collider<'w, 's, 'a>(
+	commands: &'a mut Commands<'w, 's>,
+	rapier_parameters: &RapierConfiguration,
+	materials: &PhysicsMaterialParams,
+	pos: Vec2,
+	size: Vec2,
+) -> EntityCommands<'w, 's, 'a> {
+	let mut entity = commands.spawn_bundle(SpriteBundle {
+		sprite: Sprite {
+			color: Color::rgb(0.0, 0.0, 0.0),
+			custom_size: Some(size),
 			..Default::default()
-		})
+		},
+		transform: Transform::from_xyz(pos.x, pos.y, 0.0),
+		..Default::default()
+	});
+	entity
 		.insert_bundle(RigidBodyBundle {
 			body_type: RigidBodyType::Static.into(),
-			position: RigidBodyPosition {
-				position: Isometry::translation(0.0, 200.0 / rapier_parameters.scale),
-				..Default::default()
-			}
-			.into(),
+			position: (pos / rapier_parameters.scale).into(),
 			..Default::default()
 		})
 		.insert_bundle(ColliderBundle {
 			position: Vec2::ZERO.into(),
+			material: materials.wall(),
 			shape: ColliderShapeComponent(ColliderShape::cuboid(
-				350.0 / rapier_parameters.scale,
-				5.0 / rapier_parameters.scale,
+				size.x * 0.5 / rapier_parameters.scale,
+				size.y * 0.5 / rapier_parameters.scale,
 			)),
 			..Default::default()
-		})
-		.insert(ColliderPositionSync::Discrete);
+		});
+	entity
+}
 
-	// wall left
-	let size_x = 10.0;
-	let size_y = 400.0;
-	commands
-		.spawn_bundle(SpriteBundle {
-			sprite: Sprite {
-				color: Color::rgb(0.0, 0.0, 0.0),
-				custom_size: Some(Vec2::new(size_x, size_y)),
-				..Default::default()
-			},
-			..Default::default()
-		})
-		.insert_bundle(RigidBodyBundle {
-			body_type: RigidBodyType::Static.into(),
-			position: RigidBodyPosition {
-				position: Isometry::translation(-345.0 / rapier_parameters.scale, 0.0),
-				..Default::default()
-			}
-			.into(),
-			..Default::default()
-		})
-		.insert_bundle(ColliderBundle {
-			position: Vec2::ZERO.into(),
-			shape: ColliderShapeComponent(ColliderShape::cuboid(
-				size_x * 0.5 / rapier_parameters.scale,
-				size_y * 0.5 / rapier_parameters.scale,
-			)),
-			..Default::default()
-		})
-		.insert(ColliderPositionSync::Discrete);
+fn reset_arena_bounds(mut bounds: ResMut<ArenaBounds>) {
+	*bounds = ArenaBounds::default();
+}
 
-	// wall bottom
-	let size_x = 500.0;
-	let size_y = 10.0;
-	commands
-		.spawn_bundle(SpriteBundle {
-			sprite: Sprite {
-				color: Color::rgb(0.0, 0.0, 0.0),
-				custom_size: Some(Vec2::new(size_x, size_y)),
-				..Default::default()
-			},
-			..Default::default()
-		})
-		.insert_bundle(RigidBodyBundle {
-			body_type: RigidBodyType::Static.into(),
-			position: RigidBodyPosition {
-				position: Isometry::translation(
-					-350.0 * 0.5 / rapier_parameters.scale,
-					-200.0 / rapier_parameters.scale,
-				),
-				..Default::default()
-			}
-			.into(),
-			..Default::default()
-		})
-		.insert_bundle(ColliderBundle {
-			position: Vec2::ZERO.into(),
-			shape: ColliderShapeComponent(ColliderShape::cuboid(
-				size_x * 0.5 / rapier_parameters.scale,
-				size_y * 0.5 / rapier_parameters.scale,
-			)),
-			..Default::default()
-		})
-		.insert(ColliderPositionSync::Discrete);
+/// Moves the walls inwards over time when sudden death is enabled and damages the player outside the safe zone
+fn shrink_arena(
+	params: Res<SuddenDeathParams>,
+	chaos: Res<ChaosEffects>,
+	mut bounds: ResMut<ArenaBounds>,
+	clock: Res<GameClock>,
+	time: Res<Time>,
+	rapier_parameters: Res<RapierConfiguration>,
+	mut q_walls: Query<
+		(
+			&ShrinkingWall,
+			&mut RigidBodyPositionComponent,
+			&mut Transform,
+		),
+		Without<Player>,
+	>,
+	q_player: Query<(Entity, &Transform), With<Player>>,
+	mut damage_ew: EventWriter<DamageEvent>,
+) {
+	if !params.enabled && bounds.half_extents == ARENA_HALF_EXTENTS && chaos.arena_shrink == 0.0 {
+		return;
+	}
 
-	// wall right
-	let size_x = 10.0;
-	let size_y = 400.0;
-	commands
-		.spawn_bundle(SpriteBundle {
-			sprite: Sprite {
-				color: Color::rgb(0.0, 0.0, 0.0),
-				custom_size: Some(Vec2::new(size_x, size_y)),
-				..Default::default()
-			},
-			..Default::default()
-		})
-		.insert_bundle(RigidBodyBundle {
-			body_type: RigidBodyType::Static.into(),
-			position: RigidBodyPosition {
-				position: Isometry::translation(345.0 / rapier_parameters.scale, 0.0),
-				..Default::default()
-			}
-			.into(),
-			..Default::default()
-		})
-		.insert_bundle(ColliderBundle {
-			position: Vec2::ZERO.into(),
-			shape: ColliderShapeComponent(ColliderShape::cuboid(
-				size_x * 0.5 / rapier_parameters.scale,
-				size_y * 0.5 / rapier_parameters.scale,
-			)),
-			..Default::default()
-		})
-		.insert(ColliderPositionSync::Discrete);
+	let shrunk = if params.enabled {
+		let elapsed = clock.elapsed_secs();
+		(elapsed - params.start_delay).max(0.0) * params.shrink_rate
+	} else {
+		0.0
+	};
+	let half_extents = (ARENA_HALF_EXTENTS - Vec2::splat(shrunk + chaos.arena_shrink))
+		.max(params.min_half_extents);
+
+	// only touch the resource when it actually changes, other systems react to the change
+	if half_extents != bounds.half_extents {
+		bounds.half_extents = half_extents;
+		for (wall, mut rb_pos, mut transform) in q_walls.iter_mut() {
+			let pos = wall.origin + wall.inward * (ARENA_HALF_EXTENTS - half_extents);
+			rb_pos.0.position.translation.vector = (pos / rapier_parameters.scale).into();
+			rb_pos.0.next_position = rb_pos.0.position;
+			// walls don't sync their transform from the physics world
+			transform.translation = pos.extend(transform.translation.z);
+		}
+	}
+
+	for (player, transform) in q_player.iter() {
+		if !bounds.contains(transform.translation.xy()) {
+			damage_ew.send(DamageEvent {
+				target: player,
+				amount: params.outside_damage * time.delta_seconds(),
+				source: DamageSource::Environment,
+			});
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn letterbox_covers_the_sides_of_wide_windows() {
+		let view = Vec2::new(400.0, 300.0);
+		// twice as wide as the view, the height fits exactly
+		let visible = Vec2::new(600.0, 300.0);
+
+		let (center, size) = letterbox_bar(Vec2::X, visible, view);
+		assert_eq!(center, Vec2::new(500.0, 0.0));
+		assert_eq!(size, Vec2::new(200.0, 600.0));
+		let (center, _) = letterbox_bar(-Vec2::X, visible, view);
+		assert_eq!(center, Vec2::new(-500.0, 0.0));
+
+		let (_, size) = letterbox_bar(Vec2::Y, visible, view);
+		assert_eq!(size.y, 0.0);
+	}
 }
@@ -0,0 +1,178 @@
+use bevy::prelude::*;
+
+use crate::{
+	game::{DamageDealtEvent, DamageSource, GameGlobals, GameState},
+	player::Player,
+	shooting::{ShootEvent, Team, WeaponParams},
+};
+
+/// Points for kills, broken boss parts and boss phases arrive as `ScoreEvent`s. Every one of them raises a
+/// combo multiplier that decays again while the player doesn't score. The run's breakdown, including the
+/// player's accuracy, is shown on the game over screen
+pub struct ScorePlugin;
+
+impl Plugin for ScorePlugin {
+	fn build(&self, app: &mut App) {
+		app.add_event::<ScoreEvent>()
+			.insert_resource(ScoreParams::default())
+			.insert_resource(ScoreBreakdown::default())
+			.insert_resource(Combo::default())
+			.add_system_set(
+				SystemSet::on_enter(GameState::Playing)
+					.with_system(reset_score.before("reset_game_globals")),
+			)
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(apply_score_events.after("detect_deaths"))
+					.with_system(decay_combo)
+					.with_system(track_accuracy.after("apply_damage")),
+			);
+	}
+}
+
+pub struct ScoreParams {
+	/// Multiplier gained per scoring event
+	pub combo_step: f32,
+	pub combo_max: f32,
+	/// Seconds without scoring before the multiplier starts decaying
+	pub combo_window: f32,
+	/// Multiplier lost per second once decaying
+	pub combo_decay: f32,
+	/// Points for every boss phase reached
+	pub phase_score: u32,
+}
+
+impl Default for ScoreParams {
+	fn default() -> Self {
+		Self {
+			combo_step: 0.1,
+			combo_max: 3.0,
+			combo_window: 3.0,
+			combo_decay: 0.5,
+			phase_score: 50,
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScoreKind {
+	MinionKill,
+	/// Killing a boss that splits up
+	BossKill,
+	PartBreak,
+	PhaseReached,
+}
+
+/// Base points before the combo multiplier
+pub struct ScoreEvent {
+	pub kind: ScoreKind,
+	pub points: u32,
+}
+
+/// Points of the current run by where they came from. Survival time and victory bonuses are whatever
+/// the total has on top of these
+#[derive(Default)]
+pub struct ScoreBreakdown {
+	pub kills: u32,
+	pub parts: u32,
+	pub phases: u32,
+	/// Extra points from the combo multiplier
+	pub combo_bonus: u32,
+	pub best_combo: f32,
+	/// Player bullets fired and player bullets that hurt something
+	pub shots_fired: u32,
+	pub shots_hit: u32,
+}
+
+impl ScoreBreakdown {
+	/// Percent of fired bullets that hit, None before the first shot
+	pub fn accuracy(&self) -> Option<f32> {
+		if self.shots_fired == 0 {
+			return None;
+		}
+		Some((self.shots_hit as f32 / self.shots_fired as f32 * 100.0).min(100.0))
+	}
+}
+
+/// Current multiplier and the time since the last scoring event
+pub struct Combo {
+	pub multiplier: f32,
+	since_score: f32,
+}
+
+impl Default for Combo {
+	fn default() -> Self {
+		Self {
+			multiplier: 1.0,
+			since_score: 0.0,
+		}
+	}
+}
+
+/// Runs before the globals are reset, a continued run keeps its breakdown
+fn reset_score(
+	globals: Res<GameGlobals>,
+	mut breakdown: ResMut<ScoreBreakdown>,
+	mut combo: ResMut<Combo>,
+) {
+	*combo = Combo::default();
+	if !globals.continue_run {
+		*breakdown = ScoreBreakdown::default();
+	}
+}
+
+fn apply_score_events(
+	mut score_events: EventReader<ScoreEvent>,
+	mut globals: ResMut<GameGlobals>,
+	mut breakdown: ResMut<ScoreBreakdown>,
+	mut combo: ResMut<Combo>,
+	params: Res<ScoreParams>,
+) {
+	for ScoreEvent { kind, points } in score_events.iter() {
+		let total = (*points as f32 * combo.multiplier).round() as u32;
+		debug!("SCORE {:?} {} x{:.1}", kind, points, combo.multiplier);
+		match kind {
+			ScoreKind::MinionKill | ScoreKind::BossKill => breakdown.kills += points,
+			ScoreKind::PartBreak => breakdown.parts += points,
+			ScoreKind::PhaseReached => breakdown.phases += points,
+		}
+		breakdown.combo_bonus += total.saturating_sub(*points);
+		globals.kill_score += total;
+
+		combo.multiplier = (combo.multiplier + params.combo_step).min(params.combo_max);
+		combo.since_score = 0.0;
+		breakdown.best_combo = breakdown.best_combo.max(combo.multiplier);
+	}
+}
+
+fn decay_combo(mut combo: ResMut<Combo>, params: Res<ScoreParams>, time: Res<Time>) {
+	if combo.multiplier <= 1.0 {
+		return;
+	}
+	combo.since_score += time.delta_seconds();
+	if combo.since_score > params.combo_window {
+		combo.multiplier = (combo.multiplier - params.combo_decay * time.delta_seconds()).max(1.0);
+	}
+}
+
+/// Every projectile of a shot counts as fired. Hits are counted per damaged target, so one
+/// explosion can hit several times
+fn track_accuracy(
+	mut shoot_events: EventReader<ShootEvent>,
+	mut dealt_events: EventReader<DamageDealtEvent>,
+	mut breakdown: ResMut<ScoreBreakdown>,
+	weapons: Res<WeaponParams>,
+	q_players: Query<(), With<Player>>,
+) {
+	for ShootEvent(team, _, _, _, _, kind) in shoot_events.iter() {
+		if *team == Team::Player {
+			breakdown.shots_fired += weapons.get(*kind).projectiles.max(1);
+		}
+	}
+	for DamageDealtEvent { target, source, .. } in dealt_events.iter() {
+		let bullet = matches!(source, DamageSource::Bullet | DamageSource::Ricochet);
+		if bullet && q_players.get(*target).is_err() {
+			breakdown.shots_hit += 1;
+		}
+	}
+}
@@ -0,0 +1,152 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::shooting::WeaponKind;
+
+/// Keeps the player's `Settings` stored across runs: in the browser's local storage on WASM and in
+/// `settings.toml` next to the game on native. They are loaded in `main` before any plugin reads them,
+/// and saved again whenever they change
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_system_to_stage(CoreStage::Last, save_changed_settings);
+	}
+}
+
+/// Player settings. Volumes go from 0 to 1, music and effects are scaled by `master_volume`
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Settings {
+	pub master_volume: f32,
+	pub music_volume: f32,
+	pub sfx_volume: f32,
+	/// Pans and attenuates effects depending on where they happen relative to the player
+	pub spatial_audio: bool,
+	pub screen_shake: bool,
+	/// Pauses the run while the window or browser tab isn't focused
+	pub pause_on_focus_loss: bool,
+	/// Gamepad rumble strength from 0 to 1
+	pub rumble: f32,
+	/// Collider outlines and enemy paths, only available with the `debug` feature
+	pub debug_overlays: bool,
+	pub key_bindings: KeyBindings,
+	/// Weapon the player starts every run with, one of `WeaponKind::LOADOUTS`
+	pub loadout: WeaponKind,
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		Self {
+			master_volume: 1.0,
+			music_volume: 0.6,
+			sfx_volume: 0.8,
+			spatial_audio: true,
+			screen_shake: true,
+			pause_on_focus_loss: true,
+			rumble: 1.0,
+			debug_overlays: false,
+			key_bindings: KeyBindings::default(),
+			loadout: WeaponKind::Pistol,
+		}
+	}
+}
+
+/// Keyboard keys for the actions. The arrow keys always move as well, so a bad binding can't lock the player in place
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct KeyBindings {
+	pub up: KeyCode,
+	pub down: KeyCode,
+	pub left: KeyCode,
+	pub right: KeyCode,
+	pub dash: KeyCode,
+	/// Drops the active weapon, see `PlayerInventory`
+	pub drop: KeyCode,
+	pub pause: KeyCode,
+}
+
+impl Default for KeyBindings {
+	fn default() -> Self {
+		Self {
+			up: KeyCode::W,
+			down: KeyCode::S,
+			left: KeyCode::A,
+			right: KeyCode::D,
+			dash: KeyCode::Space,
+			drop: KeyCode::Q,
+			pause: KeyCode::Escape,
+		}
+	}
+}
+
+/// Sets settings by name, used by the dev console
+#[cfg(feature = "debug")]
+impl Settings {
+	pub fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+		match name {
+			"master_volume" => self.master_volume = value.clamp(0.0, 1.0),
+			"music_volume" => self.music_volume = value.clamp(0.0, 1.0),
+			"sfx_volume" => self.sfx_volume = value.clamp(0.0, 1.0),
+			"spatial_audio" => self.spatial_audio = value != 0.0,
+			"screen_shake" => self.screen_shake = value != 0.0,
+			"pause_on_focus_loss" => self.pause_on_focus_loss = value != 0.0,
+			"rumble" => self.rumble = value.clamp(0.0, 1.0),
+			"debug_overlays" => self.debug_overlays = value != 0.0,
+			_ => return Err(format!("unknown field settings.{}", name)),
+		}
+		Ok(())
+	}
+}
+
+const STORAGE_KEY: &str = "settings";
+
+/// Missing or unreadable settings fall back to the defaults
+pub fn load_settings() -> Settings {
+	match read_settings() {
+		Some(data) => toml::from_str(&data).unwrap_or_else(|err| {
+			warn!("Discarding corrupt settings: {}", err);
+			Settings::default()
+		}),
+		None => Settings::default(),
+	}
+}
+
+fn save_changed_settings(settings: Res<Settings>) {
+	// inserting the loaded settings counts as a change too, there's nothing new to save then
+	if !settings.is_changed() || settings.is_added() {
+		return;
+	}
+	match toml::to_string(&*settings) {
+		Ok(data) => write_settings(&data),
+		Err(err) => warn!("Couldn't serialize settings: {}", err),
+	}
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_settings() -> Option<String> {
+	let storage = web_sys::window()?.local_storage().ok()??;
+	storage.get_item(STORAGE_KEY).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_settings(data: &str) {
+	let storage = web_sys::window().and_then(|window| window.local_storage().ok().flatten());
+	if let Some(storage) = storage {
+		if storage.set_item(STORAGE_KEY, data).is_err() {
+			warn!("Couldn't save settings");
+		}
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_settings() -> Option<String> {
+	std::fs::read_to_string(format!("{}.toml", STORAGE_KEY)).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_settings(data: &str) {
+	if let Err(err) = std::fs::write(format!("{}.toml", STORAGE_KEY), data) {
+		warn!("Couldn't save settings: {}", err);
+	}
+}
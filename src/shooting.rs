@@ -1,13 +1,23 @@
-use std::time::Duration;
+use std::{
+	collections::{HashMap, HashSet, VecDeque},
+	time::Duration,
+};
 
 use bevy::{math::Vec3Swizzles, prelude::*};
-use bevy_rapier2d::prelude::*;
+use bevy_rapier2d::{na::UnitComplex, prelude::*};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-	game::{GameState, Health},
-	input::MousePosition,
-	physics::PhysicsGlobals,
+	drops::DamageBoost,
+	enemy::{BossPart, Enemy, Minion},
+	feel::{CameraShakeEvent, FeelParams, HitStopEvent},
+	game::{BulletTime, DamageEvent, DamageSource, GameGlobals, GameState, Health, Invulnerable},
+	input::{Action, InputAction, InputBuffer, MousePosition},
+	inventory::PlayerInventory,
+	physics::{PhysicsGlobals, PhysicsMaterialParams},
 	player::Player,
+	scene::Wall,
+	status_effects::{StatusEffectEvent, StatusEffectKind},
 };
 use bevy_inspector_egui::Inspectable;
 
@@ -16,29 +26,84 @@ pub struct ShootingPlugin;
 impl Plugin for ShootingPlugin {
 	fn build(&self, app: &mut App) {
 		app.add_event::<ShootEvent>() // TODO: handle on bullet hit event
+			.add_event::<DeflectEvent>()
 			.add_system_set_to_stage(
 				CoreStage::Update,
 				SystemSet::on_update(GameState::Playing)
 					.after("input")
-					.with_system(check_for_shoot_event) // TODO: check for shoot event long press
-					.label("check_for_shoot_event")
-					.with_system(shoot)
-					.label("shoot")
-					.with_system(check_bullet_hit),
+					// TODO: check for shoot event long press
+					.with_system(check_for_shoot_event.label("check_for_shoot_event"))
+					.with_system(shoot.label("shoot"))
+					// bullets released by a hit go back to the pool before `shoot` takes from it
+					.with_system(check_bullet_hit.label("bullet_hit").before("shoot"))
+					.with_system(steer_homing_bullets.label("steer_homing_bullets"))
+					.with_system(apply_bullet_time.label("apply_bullet_time"))
+					.with_system(
+						track_ricochet_speed
+							.after("bullet_hit")
+							.after("steer_homing_bullets")
+							.after("apply_bullet_time")
+							.after("deflect_bullets"),
+					)
+					.with_system(recover_spread)
+					.with_system(kick_player),
+			)
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(switch_weapon.label("input"))
+					.with_system(reload_weapon.after("input"))
+					.with_system(deflect_bullets.label("deflect_bullets")),
+			)
+			.add_system_set(
+				SystemSet::on_enter(GameState::Playing)
+					.with_system(reset_live_bullets)
+					.with_system(fill_bullet_pool),
 			)
 			.add_system_to_stage(CoreStage::Last, check_despawns)
-			.insert_resource(BulletParams::default());
+			.insert_resource(BulletParams::default())
+			.insert_resource(WeaponParams::default())
+			.insert_resource(LiveBullets::default())
+			.insert_resource(BulletPool::default())
+			.insert_resource(BulletTextures::default());
 		//.add_plugin(InspectorPlugin::<BulletParams>::new());
 	}
 }
 
 /// Values we might want to tweak and that are used to define specific properties of the entities.
-#[derive(Inspectable)]
-struct BulletParams {
+#[derive(Inspectable, Deserialize)]
+#[serde(default)]
+pub struct BulletParams {
 	bullet_force_scale: f32,
+	/// Offset along the direction when the shooter is unknown
 	bullet_offset: f32,
-	damage: f32,
+	/// Gap in pixels between the shooter's colliders and a freshly spawned bullet
+	muzzle_margin: f32,
 	bullet_lifetime_ms: u32,
+	/// How many times a player bullet bounces off walls before despawning. 0 disables ricochet
+	ricochet_bounces: u32,
+	/// Whether player bullets damage other players. A player's own bullets never damage them
+	friendly_fire: bool,
+	/// Maximum number of live bullets per side. When exceeded, the oldest bullet is despawned
+	max_player_bullets: usize,
+	max_enemy_bullets: usize,
+	player_bullet: BulletVisual,
+	enemy_bullet: BulletVisual,
+	/// Pixels / sec a single hit pushes anything with at most, no matter how light it is
+	max_impact_push: f32,
+	/// Extra damage and speed of minion bullets per boss level, so minions stay dangerous later on
+	minion_damage_per_level: f32,
+	minion_speed_per_level: f32,
+	/// Applied to players hit by minion bullets, on top of the weapon's own effect
+	#[inspectable(ignore)]
+	minion_bullet_status: Option<StatusEffectKind>,
+	/// From this many bullets fired in one frame on, they are spawned as one batch. 0 never batches
+	batch_threshold: usize,
+	/// Bullets kept around to be fired again instead of despawning them, and spawned up front every run
+	pool_size: usize,
+	/// Radians / sec the player's spread shrinks back towards the weapon's `base_spread`
+	spread_recovery: f32,
+	/// Spread multiplier while focusing (holding shift)
+	focus_spread_scale: f32,
 }
 
 impl Default for BulletParams {
@@ -46,26 +111,697 @@ impl Default for BulletParams {
 		Self {
 			bullet_force_scale: 100.0,
 			bullet_offset: 0.5,
-			damage: 5.0,
+			muzzle_margin: 2.0,
 			bullet_lifetime_ms: 1000,
+			ricochet_bounces: 0,
+			friendly_fire: false,
+			max_player_bullets: 100,
+			max_enemy_bullets: 300,
+			player_bullet: BulletVisual::default(),
+			enemy_bullet: BulletVisual::default(),
+			max_impact_push: 300.0,
+			minion_damage_per_level: 0.15,
+			minion_speed_per_level: 0.05,
+			minion_bullet_status: Some(StatusEffectKind::Poison),
+			batch_threshold: 16,
+			pool_size: 300,
+			spread_recovery: 0.15,
+			focus_spread_scale: 0.5,
+		}
+	}
+}
+
+/// Look of a bullet type. The collider is derived from the sprite size so both always match
+#[derive(Inspectable, Deserialize)]
+#[serde(default)]
+pub struct BulletVisual {
+	texture: String,
+	/// Sprite size in pixels
+	size: Vec2,
+	/// Collider size relative to the sprite size, the texture doesn't fill the whole sprite
+	collider_ratio: Vec2,
+}
+
+impl Default for BulletVisual {
+	fn default() -> Self {
+		Self {
+			texture: "physics_example/bullet.png".to_string(),
+			size: Vec2::new(10.0, 10.0),
+			collider_ratio: Vec2::new(1.0, 0.2),
+		}
+	}
+}
+
+impl BulletParams {
+	pub fn friendly_fire(&self) -> bool {
+		self.friendly_fire
+	}
+
+	/// Damage and speed multipliers of minion bullets at `level`, they get stronger with every level after
+	/// the first
+	pub fn minion_scaling(&self, level: u32) -> (f32, f32) {
+		let levels = level.saturating_sub(1) as f32;
+		(
+			1.0 + self.minion_damage_per_level * levels,
+			1.0 + self.minion_speed_per_level * levels,
+		)
+	}
+
+	/// Multiplier of the speed a `ShootEvent` asks for, giving the speed `shoot` fires the bullet with.
+	/// Enemy bullets are slowed down by bullet time, the player's aren't. `minion_level` is the level
+	/// of bullets fired by minions
+	pub fn speed_scale(
+		&self,
+		team: Team,
+		weapon: &WeaponSpec,
+		minion_level: Option<u32>,
+		bullet_time: f32,
+	) -> f32 {
+		let bullet_time = if team == Team::Player {
+			1.0
+		} else {
+			bullet_time
+		};
+		let minion = minion_level.map_or(1.0, |level| self.minion_scaling(level).1);
+		weapon.speed * bullet_time * minion
+	}
+
+	/// Damage of a bullet of `weapon`. `minion_level` is the level of bullets fired by minions, the
+	/// others are multiplied by the shooter's `DamageBoost`
+	pub fn bullet_damage(&self, weapon: &WeaponSpec, minion_level: Option<u32>, boost: f32) -> f32 {
+		match minion_level {
+			Some(level) => weapon.damage * self.minion_scaling(level).0,
+			None => weapon.damage * boost,
+		}
+	}
+
+	/// Distance in physics units from the shooter's center a bullet spawns at, so its back clears the
+	/// shooter's colliders by `muzzle_margin`. See `shooter_radius`
+	fn muzzle_offset(&self, shooter_radius: f32, half_extents: Vec2, rapier_scale: f32) -> f32 {
+		shooter_radius + (half_extents.x + self.muzzle_margin) / rapier_scale
+	}
+
+	/// Paths of all bullet textures, so they can be preloaded
+	pub fn textures(&self) -> [&str; 2] {
+		[
+			self.player_bullet.texture.as_str(),
+			self.enemy_bullet.texture.as_str(),
+		]
+	}
+}
+
+impl BulletVisual {
+	/// Half extents of the collider in pixels
+	fn collider_half_extents(&self) -> Vec2 {
+		self.size * self.collider_ratio * 0.5
+	}
+}
+
+/// Texture handles by path, so shooting doesn't go through the asset server for every bullet
+#[derive(Default)]
+struct BulletTextures(HashMap<String, Handle<Image>>);
+
+/// Sets numeric fields by name, used by the dev console
+#[cfg(feature = "debug")]
+impl BulletParams {
+	pub fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+		match name {
+			"bullet_force_scale" => self.bullet_force_scale = value,
+			"bullet_offset" => self.bullet_offset = value,
+			"muzzle_margin" => self.muzzle_margin = value,
+			"bullet_lifetime_ms" => self.bullet_lifetime_ms = value as u32,
+			"ricochet_bounces" => self.ricochet_bounces = value as u32,
+			"friendly_fire" => self.friendly_fire = value != 0.0,
+			"max_player_bullets" => self.max_player_bullets = value as usize,
+			"max_enemy_bullets" => self.max_enemy_bullets = value as usize,
+			"max_impact_push" => self.max_impact_push = value,
+			"minion_damage_per_level" => self.minion_damage_per_level = value,
+			"minion_speed_per_level" => self.minion_speed_per_level = value,
+			"batch_threshold" => self.batch_threshold = value as usize,
+			"pool_size" => self.pool_size = value as usize,
+			"spread_recovery" => self.spread_recovery = value,
+			"focus_spread_scale" => self.focus_spread_scale = value,
+			_ => return Err(format!("unknown field bullet.{}", name)),
+		}
+		Ok(())
+	}
+}
+
+/// Kinds of weapons. The player switches between them with the number keys, enemies shoot with a
+/// pistol unless they have a `Weapon`
+#[derive(Inspectable, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum WeaponKind {
+	Pistol,
+	Shotgun,
+	Laser,
+	Rocket,
+	/// Homing missiles of the boss, players can't switch to it
+	Missile,
+	/// Continuous beam while fire is held, see `BeamPlugin`
+	Beam,
+}
+
+impl WeaponKind {
+	/// Weapons the player can start a run with, in the order of the number keys
+	pub const LOADOUTS: [WeaponKind; 5] = [
+		WeaponKind::Pistol,
+		WeaponKind::Shotgun,
+		WeaponKind::Laser,
+		WeaponKind::Rocket,
+		WeaponKind::Beam,
+	];
+
+	/// One line for the loadout choice in the settings menu
+	pub fn description(&self) -> &'static str {
+		match self {
+			WeaponKind::Pistol => "Accurate, never runs dry",
+			WeaponKind::Shotgun => "Six pellets, heavy kick",
+			WeaponKind::Laser => "Rapid fire, slows targets",
+			WeaponKind::Rocket => "Homing, explodes and burns",
+			WeaponKind::Missile => "The boss' homing missiles",
+			WeaponKind::Beam => "Hold to burn through",
+		}
+	}
+}
+
+#[derive(Component)]
+pub struct Weapon(pub WeaponKind);
+
+/// Rounds of one weapon
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Magazine {
+	pub loaded: u32,
+	pub reserve: u32,
+}
+
+/// Ammo of the player's weapons, see `WeaponSpec::magazine`. The boss' missiles and the beam never run out
+#[derive(Component)]
+pub struct Ammo {
+	pub pistol: Magazine,
+	pub shotgun: Magazine,
+	pub laser: Magazine,
+	pub rocket: Magazine,
+}
+
+impl Ammo {
+	/// Full magazines and reserves
+	pub fn new(weapons: &WeaponParams) -> Self {
+		let full = |spec: &WeaponSpec| Magazine {
+			loaded: spec.magazine,
+			reserve: spec.max_reserve,
+		};
+		Self {
+			pistol: full(&weapons.pistol),
+			shotgun: full(&weapons.shotgun),
+			laser: full(&weapons.laser),
+			rocket: full(&weapons.rocket),
+		}
+	}
+
+	pub fn get(&self, kind: WeaponKind) -> Option<&Magazine> {
+		match kind {
+			WeaponKind::Pistol => Some(&self.pistol),
+			WeaponKind::Shotgun => Some(&self.shotgun),
+			WeaponKind::Laser => Some(&self.laser),
+			WeaponKind::Rocket => Some(&self.rocket),
+			WeaponKind::Missile | WeaponKind::Beam => None,
+		}
+	}
+
+	pub fn get_mut(&mut self, kind: WeaponKind) -> Option<&mut Magazine> {
+		match kind {
+			WeaponKind::Pistol => Some(&mut self.pistol),
+			WeaponKind::Shotgun => Some(&mut self.shotgun),
+			WeaponKind::Laser => Some(&mut self.laser),
+			WeaponKind::Rocket => Some(&mut self.rocket),
+			WeaponKind::Missile | WeaponKind::Beam => None,
+		}
+	}
+
+	/// Adds `magazines` full magazines to the reserve of every weapon, up to its `max_reserve`
+	pub fn refill(&mut self, weapons: &WeaponParams, magazines: u32) {
+		for kind in [
+			WeaponKind::Pistol,
+			WeaponKind::Shotgun,
+			WeaponKind::Laser,
+			WeaponKind::Rocket,
+		] {
+			let spec = weapons.get(kind);
+			if let Some(magazine) = self.get_mut(kind) {
+				magazine.reserve =
+					(magazine.reserve + spec.magazine * magazines).min(spec.max_reserve);
+			}
+		}
+	}
+}
+
+/// Attached to the player while reloading, switching weapons cancels it
+#[derive(Component)]
+pub struct ReloadTimer {
+	pub weapon: WeaponKind,
+	pub timer: Timer,
+}
+
+#[derive(Inspectable)]
+pub struct WeaponSpec {
+	/// Minimum seconds between two player shots. Presses during the cooldown are buffered
+	pub fire_cooldown: f32,
+	/// Shots per magazine, 0 for weapons that never run out
+	pub magazine: u32,
+	/// Shots carried on top of the magazine, 0 for an endless reserve
+	pub max_reserve: u32,
+	pub reload_secs: f32,
+	/// Bullets per shot, fanned out `projectile_spacing` radians apart
+	pub projectiles: u32,
+	pub projectile_spacing: f32,
+	pub damage: f32,
+	/// Multiplies the bullet speed
+	pub speed: f32,
+	/// Pixels around the hit in which everything else takes the damage too, 0 for no explosion
+	pub blast_radius: f32,
+	/// Impulse in physics units a hit pushes the target away with, see `Impact`
+	pub impact: f32,
+	/// Applied to everything the bullets damage
+	#[inspectable(ignore)]
+	pub status: Option<StatusEffectKind>,
+	/// Radians / sec the bullets turn towards the nearest target, 0 for bullets flying straight
+	pub homing_turn_rate: f32,
+	/// Half angle in radians around the flight direction in which targets are picked up
+	pub homing_cone: f32,
+	/// Half angle in radians of the player's spread cone when firing from rest
+	pub base_spread: f32,
+	/// Radians added to the spread by every shot, up to `max_spread`
+	pub spread_bloom: f32,
+	pub max_spread: f32,
+	/// Pixels / sec every shot pushes the shooter back with, also shakes the camera. 0 for light weapons
+	pub kick: f32,
+}
+
+/// Stats of every weapon kind
+#[derive(Inspectable)]
+pub struct WeaponParams {
+	pub pistol: WeaponSpec,
+	pub shotgun: WeaponSpec,
+	pub laser: WeaponSpec,
+	pub rocket: WeaponSpec,
+	pub missile: WeaponSpec,
+	pub beam: WeaponSpec,
+}
+
+impl Default for WeaponParams {
+	fn default() -> Self {
+		Self {
+			pistol: WeaponSpec {
+				fire_cooldown: 0.15,
+				magazine: 12,
+				max_reserve: 0,
+				reload_secs: 1e+00,
+				projectiles: 1,
+				projectile_spacing: 0.0,
+				damage: 5.0,
+				speed: 1.0,
+				blast_radius: 0.0,
+				impact: 20.0,
+				status: None,
+				homing_turn_rate: 0.0,
+				homing_cone: 0.0,
+				base_spread: 0.02,
+				spread_bloom: 0.04,
+				max_spread: 0.25,
+				kick: 0.0,
+			},
+			shotgun: WeaponSpec {
+				fire_cooldown: 0.6,
+				magazine: 6,
+				max_reserve: 36,
+				reload_secs: 2e+00,
+				projectiles: 6,
+				projectile_spacing: 0.08,
+				damage: 3.0,
+				speed: 0.9,
+				blast_radius: 0.0,
+				impact: 60.0,
+				status: None,
+				homing_turn_rate: 0.0,
+				homing_cone: 0.0,
+				base_spread: 0.05,
+				spread_bloom: 0.1,
+				max_spread: 0.3,
+				kick: 250.0,
+			},
+			laser: WeaponSpec {
+				fire_cooldown: 0.05,
+				magazine: 40,
+				max_reserve: 200,
+				reload_secs: 1e+00,
+				projectiles: 1,
+				projectile_spacing: 0.0,
+				damage: 1.5,
+				speed: 2.5,
+				blast_radius: 0.0,
+				impact: 5.0,
+				status: Some(StatusEffectKind::Slow),
+				homing_turn_rate: 0.0,
+				homing_cone: 0.0,
+				base_spread: 0.0,
+				spread_bloom: 0.01,
+				max_spread: 0.08,
+				kick: 0.0,
+			},
+			rocket: WeaponSpec {
+				fire_cooldown: 0.9,
+				magazine: 2,
+				max_reserve: 8,
+				reload_secs: 2e+00,
+				projectiles: 1,
+				projectile_spacing: 0.0,
+				damage: 20.0,
+				speed: 0.6,
+				blast_radius: 60.0,
+				impact: 400.0,
+				status: Some(StatusEffectKind::Burn),
+				homing_turn_rate: 2.5,
+				homing_cone: 0.6,
+				base_spread: 0.0,
+				spread_bloom: 0.05,
+				max_spread: 0.1,
+				kick: 350.0,
+			},
+			// slow and turning wide, so they can be outrun and dodged
+			missile: WeaponSpec {
+				fire_cooldown: 0.0,
+				magazine: 0,
+				max_reserve: 0,
+				reload_secs: 0e+00,
+				projectiles: 1,
+				projectile_spacing: 0.0,
+				damage: 8.0,
+				speed: 0.5,
+				blast_radius: 0.0,
+				impact: 150.0,
+				status: None,
+				homing_turn_rate: 1.5,
+				homing_cone: 1.2,
+				base_spread: 0.0,
+				spread_bloom: 0.0,
+				max_spread: 0.0,
+				kick: 0.0,
+			},
+			// fires no bullets, only the damage counts, per second
+			beam: WeaponSpec {
+				fire_cooldown: 0.0,
+				magazine: 0,
+				max_reserve: 0,
+				reload_secs: 0e+00,
+				projectiles: 0,
+				projectile_spacing: 0.0,
+				damage: 20.0,
+				speed: 0.0,
+				blast_radius: 0.0,
+				impact: 0.0,
+				status: None,
+				homing_turn_rate: 0.0,
+				homing_cone: 0.0,
+				base_spread: 0.0,
+				spread_bloom: 0.0,
+				max_spread: 0.0,
+				kick: 0.0,
+			},
+		}
+	}
+}
+
+impl WeaponSpec {
+	/// Pixels / sec a shot fired in `dir` pushes the shooter with
+	fn kick_velocity(&self, dir: Vec2) -> Vec2 {
+		-dir.normalize_or_zero() * self.kick
+	}
+}
+
+impl WeaponParams {
+	pub fn get(&self, kind: WeaponKind) -> &WeaponSpec {
+		match kind {
+			WeaponKind::Pistol => &self.pistol,
+			WeaponKind::Shotgun => &self.shotgun,
+			WeaponKind::Laser => &self.laser,
+			WeaponKind::Rocket => &self.rocket,
+			WeaponKind::Missile => &self.missile,
+			WeaponKind::Beam => &self.beam,
 		}
 	}
 }
 
+/// Sets numeric fields by name as `<weapon>.<field>`, used by the dev console
+#[cfg(feature = "debug")]
+impl WeaponParams {
+	pub fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+		let (weapon, field) = name
+			.split_once('.')
+			.ok_or_else(|| format!("expected <weapon>.<field>, got {}", name))?;
+		let spec = match weapon {
+			"pistol" => &mut self.pistol,
+			"shotgun" => &mut self.shotgun,
+			"laser" => &mut self.laser,
+			"rocket" => &mut self.rocket,
+			"missile" => &mut self.missile,
+			"beam" => &mut self.beam,
+			_ => return Err(format!("unknown weapon {}", weapon)),
+		};
+		match field {
+			"fire_cooldown" => spec.fire_cooldown = value,
+			"magazine" => spec.magazine = value as u32,
+			"max_reserve" => spec.max_reserve = value as u32,
+			"reload_secs" => spec.reload_secs = value,
+			"projectiles" => spec.projectiles = value as u32,
+			"projectile_spacing" => spec.projectile_spacing = value,
+			"damage" => spec.damage = value,
+			"speed" => spec.speed = value,
+			"blast_radius" => spec.blast_radius = value,
+			"impact" => spec.impact = value,
+			"homing_turn_rate" => spec.homing_turn_rate = value,
+			"homing_cone" => spec.homing_cone = value,
+			"base_spread" => spec.base_spread = value,
+			"spread_bloom" => spec.spread_bloom = value,
+			"max_spread" => spec.max_spread = value,
+			"kick" => spec.kick = value,
+			_ => return Err(format!("unknown field weapon.{}.{}", weapon, field)),
+		}
+		Ok(())
+	}
+}
+
+/// Extra half angle in radians of a player's spread cone on top of `WeaponSpec::base_spread`.
+/// Grows with every shot and recovers when not shooting
+#[derive(Component, Default)]
+pub struct Spread(pub f32);
+
+impl Spread {
+	/// Half angle in radians of the whole cone of `weapon`
+	fn cone(&self, weapon: &WeaponSpec) -> f32 {
+		weapon.base_spread + self.0
+	}
+
+	/// Widens the cone after a shot of `weapon`, up to its `max_spread`
+	fn bloom(&mut self, weapon: &WeaponSpec) {
+		let max_bloom = (weapon.max_spread - weapon.base_spread).max(0.0);
+		self.0 = (self.0 + weapon.spread_bloom).min(max_bloom);
+	}
+
+	/// Narrows the cone by `recovery` radians / sec for `dt` seconds
+	fn recover(&mut self, recovery: f32, dt: f32) {
+		self.0 = (self.0 - recovery * dt).max(0.0);
+	}
+}
+
 #[derive(Component)]
 struct DespawnTimer(Duration, Duration);
 
+/// Live bullets in the order they were spawned, kept separately for player and enemies so enemy fire
+/// can't push out the player's bullets
+#[derive(Default)]
+struct LiveBullets {
+	player: VecDeque<Entity>,
+	enemy: VecDeque<Entity>,
+}
+
+/// Bullets of the previous run were despawned by the teardown, so their entities can be forgotten
+fn reset_live_bullets(mut live_bullets: ResMut<LiveBullets>) {
+	live_bullets.player.clear();
+	live_bullets.enemy.clear();
+}
+
+/// Inactive bullet entities that are fired again instead of spawning new ones, since spawning and
+/// despawning a body and collider for every shot is too slow when spamming bullets.
+/// Pooled bullets keep their body and collider, but collide with nothing, don't move and are hidden.
+/// They don't have a `Bullet` component, so nothing treats them as bullets until they are fired again
+#[derive(Default)]
+struct BulletPool {
+	free: Vec<Entity>,
+}
+
+/// Pixel position of the first parking slot of pooled bullets, far outside the arena
+const POOL_PARKING_ORIGIN: Vec2 = Vec2::new(-10000.0, -10000.0);
+/// Pixels between two parking slots, so pooled bullets never overlap in the broad phase
+const POOL_PARKING_SPACING: f32 = 50.0;
+const POOL_PARKING_COLUMNS: usize = 20;
+
+impl BulletPool {
+	/// A pooled bullet, or a new entity when the pool ran dry
+	fn take(&mut self, commands: &mut Commands) -> Entity {
+		self.free.pop().unwrap_or_else(|| commands.spawn().id())
+	}
+
+	/// Deactivates the bullet and keeps it for later, bullets beyond `max` are despawned.
+	/// Releasing a bullet that is already in the pool does nothing
+	fn release(&mut self, commands: &mut Commands, bullet: Entity, max: usize, rapier_scale: f32) {
+		if self.free.contains(&bullet) {
+			return;
+		}
+		if self.free.len() >= max {
+			commands.entity(bullet).despawn_recursive();
+			return;
+		}
+		commands
+			.entity(bullet)
+			.remove::<Bullet>()
+			.remove::<Impact>()
+			.remove::<Ricochet>()
+			.remove::<Homing>()
+			.remove::<DespawnTimer>()
+			.insert_bundle(PooledBulletBundle::parked(self.free.len(), rapier_scale));
+		self.free.push(bullet);
+	}
+}
+
+/// Overrides the components of a fired bullet that would make it move, collide or show up.
+/// The body is parked asleep in its own slot far outside the arena, so the physics step skips it
+#[derive(Bundle)]
+struct PooledBulletBundle {
+	visibility: Visibility,
+	position: RigidBodyPositionComponent,
+	velocity: RigidBodyVelocityComponent,
+	forces: RigidBodyForcesComponent,
+	activation: RigidBodyActivationComponent,
+	flags: ColliderFlagsComponent,
+}
+
+impl PooledBulletBundle {
+	/// Parked in the given slot, the free bullets must all use different ones
+	fn parked(slot: usize, rapier_scale: f32) -> Self {
+		let park_pos = POOL_PARKING_ORIGIN
+			+ Vec2::new(
+				(slot % POOL_PARKING_COLUMNS) as f32,
+				(slot / POOL_PARKING_COLUMNS) as f32,
+			) * POOL_PARKING_SPACING;
+		Self {
+			visibility: Visibility { is_visible: false },
+			position: (park_pos / rapier_scale).into(),
+			velocity: RigidBodyVelocity::zero().into(),
+			forces: RigidBodyForces::default().into(),
+			activation: RigidBodyActivation::inactive().into(),
+			flags: ColliderFlags {
+				collision_groups: InteractionGroups::none(),
+				..Default::default()
+			}
+			.into(),
+		}
+	}
+}
+
+/// The teardown despawned the pool of the previous run along with everything else
+fn fill_bullet_pool(
+	mut commands: Commands,
+	mut pool: ResMut<BulletPool>,
+	params: Res<BulletParams>,
+	rapier_config: Res<RapierConfiguration>,
+) {
+	pool.free.clear();
+	let half_extents = params.enemy_bullet.collider_half_extents();
+	for slot in 0..params.pool_size {
+		let bullet = commands
+			.spawn_bundle(SpriteBundle::default())
+			.insert_bundle(RigidBodyBundle::default())
+			.insert_bundle(ColliderBundle {
+				shape: ColliderShape::cuboid(
+					half_extents.x / rapier_config.scale,
+					half_extents.y / rapier_config.scale,
+				)
+				.into(),
+				..Default::default()
+			})
+			.insert_bundle(PooledBulletBundle::parked(slot, rapier_config.scale))
+			.insert(ColliderPositionSync::Discrete)
+			.id();
+		pool.free.push(bullet);
+	}
+}
+
 /// used to check and trigger the shooting mechanic
-/// inner value represents boolean if bullet sent from player
+/// inner value is the team that fired the bullet
 /// second inner value is position from bullet fire
 /// third inner value is direction
-pub struct ShootEvent(pub bool, pub Vec2, pub Vec2);
+/// fourth inner value is the shooter, used to spawn the bullet just outside of its colliders
+/// fifth inner value multiplies the bullet speed
+/// sixth inner value is the weapon, which decides damage, speed and how many bullets are fired
+pub struct ShootEvent(
+	pub Team,
+	pub Vec2,
+	pub Vec2,
+	pub Option<Entity>,
+	pub f32,
+	pub WeaponKind,
+);
+
+/// Side a bullet was fired by. It decides what the bullet can hit
+#[derive(Inspectable, Clone, Copy, PartialEq, Debug)]
+pub enum Team {
+	Player,
+	Enemy,
+	/// Hits everyone, e.g. for arena turrets
+	Neutral,
+}
+
+impl Team {
+	/// Collision groups bullets of this team pass through. With friendly fire, player bullets have to
+	/// touch players and ownership decides who takes damage
+	pub fn ignored_mask(&self, friendly_fire: bool, physics_globals: &PhysicsGlobals) -> u32 {
+		match (self, friendly_fire) {
+			(Team::Player, true) | (Team::Neutral, _) => 0,
+			(Team::Player, false) => physics_globals.player_mask,
+			(Team::Enemy, _) => physics_globals.enemy_mask,
+		}
+	}
+}
+
+/// Turns an enemy bullet into one of `owner`'s flying along `dir`, e.g. when a melee swing hits it
+pub struct DeflectEvent {
+	pub bullet: Entity,
+	pub owner: Entity,
+	pub dir: Vec2,
+	/// Multiplies the bullet's speed
+	pub speed_scale: f32,
+}
 
 // COMPONENTS
 
-/// Bullet with inner value as damage
+/// Bullet with the damage it deals and who fired it
 #[derive(Inspectable, Component)]
-struct Bullet(pub f32);
+pub struct Bullet {
+	damage: f32,
+	/// The shooter never takes damage from its own bullets
+	owner: Option<Entity>,
+	team: Team,
+	/// Pixels, see `WeaponSpec::blast_radius`
+	blast_radius: f32,
+	#[inspectable(ignore)]
+	status: Vec<StatusEffectKind>,
+}
+
+impl Bullet {
+	pub fn team(&self) -> Team {
+		self.team
+	}
+}
 
 // Components used to hold informations and data realtive to the entity they are attached to
 
@@ -79,6 +815,30 @@ struct Direction {
 	value: Vec2,
 }
 
+/// Bullets with this component are reflected off walls instead of despawning, until no bounces are left
+#[derive(Inspectable, Component)]
+struct Ricochet {
+	bounces_left: u32,
+	/// Physics units / sec the bullet flew with before the last physics step. Contacts are only reported
+	/// after the solver changed the velocity, so the bounce can't use the current one
+	speed: f32,
+}
+
+/// Impulse a bullet hit pushes the target with, along the bullet's direction. Heavier bodies are pushed
+/// less, so heavy weapons shove minions around while the boss only staggers
+#[derive(Inspectable, Component)]
+struct Impact {
+	strength: f32,
+}
+
+/// Bullets with this component steer towards the nearest target in front of them, see
+/// `WeaponSpec::homing_turn_rate`
+#[derive(Inspectable, Component)]
+struct Homing {
+	turn_rate: f32,
+	cone: f32,
+}
+
 // CUSTOM BUNDLES
 
 /// Just custom bundles, to spawn a specific entity without the need to insert every time the specific
@@ -93,6 +853,9 @@ struct BulletBundle {
 	rigidbody: RigidBodyBundle,
 	#[bundle]
 	collider: ColliderBundle,
+	sync: ColliderPositionSync,
+	bullet: Bullet,
+	impact: Impact,
 }
 
 // SYSTEMS
@@ -100,147 +863,1043 @@ struct BulletBundle {
 // The names of the systems are as expressive as possible in order to allow an easy understanding of
 // what they are doing
 
-/// System that checks if the mouse button has been pressed. If so, queues a new event to shoot a bullet
+/// System that checks if a shot is buffered and the cooldown of the player's weapon is over. If so, queues
+/// a new event to shoot in a random direction within the player's spread cone and widens the cone.
+/// Every shot takes a round from the magazine, an empty magazine is reloaded
 fn check_for_shoot_event(
+	mut commands: Commands,
 	mut ev_shoot_writer: EventWriter<ShootEvent>,
 	mouse_pos: Res<MousePosition>,
-	mouse_input: Res<Input<MouseButton>>,
-	q_player_t: Query<&Transform, With<Player>>,
+	input: Res<InputAction>,
+	mut input_buffer: ResMut<InputBuffer>,
+	keyboard_input: Res<Input<KeyCode>>,
+	params: Res<BulletParams>,
+	weapons: Res<WeaponParams>,
+	mut q_player_t: Query<
+		(
+			Entity,
+			&Transform,
+			&mut Spread,
+			&Weapon,
+			&mut Ammo,
+			Option<&ReloadTimer>,
+		),
+		With<Player>,
+	>,
+	mut last_shot: Local<Option<f64>>,
+	time: Res<Time>,
 ) {
-	if mouse_input.just_pressed(MouseButton::Left) {
-		if let Ok(player_t) = q_player_t.get_single() {
-			let player_pos = player_t.translation.xy();
-			let dir = mouse_pos.0 - player_pos;
-			ev_shoot_writer.send(ShootEvent(true, player_pos, dir));
+	let (player, player_t, mut spread, Weapon(kind), mut ammo, reload) =
+		match q_player_t.get_single_mut() {
+			Ok(player) => player,
+			Err(_) => return,
+		};
+	// the beam fires while held, see `BeamPlugin`
+	if *kind == WeaponKind::Beam {
+		return;
+	}
+	let weapon = weapons.get(*kind);
+	let now = time.seconds_since_startup();
+	let ready = last_shot.map_or(true, |last_shot| {
+		now - last_shot >= weapon.fire_cooldown as f64
+	});
+	let reloading = reload.map_or(false, |reload| reload.weapon == *kind);
+	// the press stays buffered until the cooldown or the reload is over
+	if !ready || reloading || !input_buffer.consume(Action::Shoot, now) {
+		return;
+	}
+	if let Some(magazine) = ammo.get_mut(*kind).filter(|_| weapon.magazine > 0) {
+		if magazine.loaded == 0 {
+			start_reload(&mut commands, player, *kind, weapon, magazine);
+			return;
+		}
+		magazine.loaded -= 1;
+		if magazine.loaded == 0 {
+			start_reload(&mut commands, player, *kind, weapon, magazine);
+		}
+	}
+	*last_shot = Some(now);
+	let player_pos = player_t.translation.xy();
+	let mut cone = spread.cone(weapon);
+	if keyboard_input.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+		cone *= params.focus_spread_scale;
+	}
+	let angle = cone * (rand::random::<f32>() * 2.0 - 1.0);
+	let aim = input.aim.unwrap_or(mouse_pos.0 - player_pos);
+	let dir = Mat2::from_angle(angle) * aim;
+	ev_shoot_writer.send(ShootEvent(
+		Team::Player,
+		player_pos,
+		dir,
+		Some(player),
+		1.0,
+		*kind,
+	));
+
+	spread.bloom(weapon);
+}
+
+/// Pushes the player back and shakes the camera for shots of heavy weapons
+fn kick_player(
+	mut shoot_events: EventReader<ShootEvent>,
+	weapons: Res<WeaponParams>,
+	feel_params: Res<FeelParams>,
+	rapier_config: Res<RapierConfiguration>,
+	mut shake_ew: EventWriter<CameraShakeEvent>,
+	mut q_players: Query<&mut RigidBodyVelocityComponent, With<Player>>,
+) {
+	for ShootEvent(_, _, dir, shooter, _, kind) in shoot_events.iter() {
+		let weapon = weapons.get(*kind);
+		let mut rb_vel = match shooter.and_then(|shooter| q_players.get_mut(shooter).ok()) {
+			Some(rb_vel) if weapon.kick > 0.0 => rb_vel,
+			_ => continue,
+		};
+		// the movement decelerates the push away again
+		let linvel = Vec2::from(rb_vel.linvel) + weapon.kick_velocity(*dir) / rapier_config.scale;
+		rb_vel.linvel = linvel.into();
+		shake_ew.send(CameraShakeEvent {
+			magnitude: weapon.kick * feel_params.kick_shake.0,
+			duration: feel_params.kick_shake.1,
+		});
+	}
+}
+
+/// Switches the player's weapon to the inventory slot of the number key, see `PlayerInventory`
+fn switch_weapon(
+	keyboard_input: Res<Input<KeyCode>>,
+	mut q_weapon: Query<(&mut Weapon, &mut PlayerInventory), With<Player>>,
+) {
+	let keys = [
+		KeyCode::Key1,
+		KeyCode::Key2,
+		KeyCode::Key3,
+		KeyCode::Key4,
+		KeyCode::Key5,
+	];
+	let slot = match keys
+		.iter()
+		.position(|key| keyboard_input.just_pressed(*key))
+	{
+		Some(slot) => slot,
+		None => return,
+	};
+	for (mut weapon, mut inventory) in q_weapon.iter_mut() {
+		match inventory.select(slot) {
+			Some(kind) if weapon.0 != kind => {
+				debug!("SWITCH_WEAPON {:?}", kind);
+				weapon.0 = kind;
+			}
+			_ => {}
+		}
+	}
+}
+
+/// Starts reloading unless the magazine is full or there is nothing left to load
+fn start_reload(
+	commands: &mut Commands,
+	player: Entity,
+	kind: WeaponKind,
+	weapon: &WeaponSpec,
+	magazine: &Magazine,
+) {
+	let endless = weapon.max_reserve == 0;
+	if magazine.loaded >= weapon.magazine || (!endless && magazine.reserve == 0) {
+		return;
+	}
+	debug!("RELOAD {:?}", kind);
+	commands.entity(player).insert(ReloadTimer {
+		weapon: kind,
+		timer: Timer::from_seconds(weapon.reload_secs, false),
+	});
+}
+
+/// Reloads the player's weapon on R and fills the magazine up from the reserve once the reload is done.
+/// Switching weapons cancels the reload
+fn reload_weapon(
+	mut commands: Commands,
+	keyboard_input: Res<Input<KeyCode>>,
+	weapons: Res<WeaponParams>,
+	mut q_player: Query<(Entity, &Weapon, &mut Ammo, Option<&mut ReloadTimer>), With<Player>>,
+	time: Res<Time>,
+) {
+	for (player, Weapon(kind), mut ammo, reload) in q_player.iter_mut() {
+		let weapon = weapons.get(*kind);
+		match reload {
+			Some(reload) if reload.weapon != *kind => {
+				commands.entity(player).remove::<ReloadTimer>();
+			}
+			Some(mut reload) => {
+				reload.timer.tick(time.delta());
+				if !reload.timer.finished() {
+					continue;
+				}
+				commands.entity(player).remove::<ReloadTimer>();
+				if let Some(magazine) = ammo.get_mut(*kind) {
+					let missing = weapon.magazine.saturating_sub(magazine.loaded);
+					if weapon.max_reserve == 0 {
+						magazine.loaded += missing;
+					} else {
+						let loaded = missing.min(magazine.reserve);
+						magazine.loaded += loaded;
+						magazine.reserve -= loaded;
+					}
+				}
+			}
+			None if keyboard_input.just_pressed(KeyCode::R) && weapon.magazine > 0 => {
+				if let Some(magazine) = ammo.get(*kind) {
+					start_reload(&mut commands, player, *kind, weapon, magazine);
+				}
+			}
+			None => {}
+		}
+	}
+}
+
+/// Shrinks the players' spread back towards the base spread over time
+fn recover_spread(mut q_spread: Query<&mut Spread>, params: Res<BulletParams>, time: Res<Time>) {
+	for mut spread in q_spread.iter_mut() {
+		if spread.0 > 0.0 {
+			spread.recover(params.spread_recovery, time.delta_seconds());
 		}
 	}
 }
 
 /// System that spawns a bullet if a ShootEvent was triggered. It just spawns a bullet in the current player position and calculates the direction
-/// the bullet must follow
+/// the bullet must follow.
+/// Bullets are taken from the `BulletPool` when possible, their components are simply overwritten.
+/// Entities are reserved up front, because the live bullet queues need their ids. Dense patterns then
+/// insert all bundles with a single `insert_or_spawn_batch` command instead of one command per bullet.
+/// `spawn_batch` can't be used since it doesn't hand out the ids, and every component has to be part of
+/// `BulletBundle`, as batches can't insert extra components. Only the rare `Ricochet` and `Homing` are
+/// inserted afterwards
 fn shoot(
 	mut commands: Commands,
 	mut ev_shoot_reader: EventReader<ShootEvent>,
 	asset_server: Res<AssetServer>,
 	rapier_config: Res<RapierConfiguration>,
 	physics_globals: Res<PhysicsGlobals>,
+	materials: Res<PhysicsMaterialParams>,
 	params: Res<BulletParams>,
+	(mut live_bullets, mut pool): (ResMut<LiveBullets>, ResMut<BulletPool>),
+	mut textures: ResMut<BulletTextures>,
+	bullet_time: Res<BulletTime>,
+	q_bullets: Query<&Bullet>,
+	q_colliders: Query<(&ColliderShapeComponent, Option<&ColliderParentComponent>)>,
+	q_children: Query<&Children>,
+	q_minions: Query<&Minion>,
+	(game_globals, weapons): (Res<GameGlobals>, Res<WeaponParams>),
+	q_boosts: Query<&DamageBoost>,
 ) {
-	for ShootEvent(from_player, from_pos, dir) in ev_shoot_reader.iter() {
-		let direction = Direction {
-			value: dir.normalize(),
+	// forget bullets that were despawned in the meantime, or that a hit released back into the pool
+	let free: HashSet<Entity> = pool.free.iter().copied().collect();
+	let live = |e: &Entity| q_bullets.get(*e).is_ok() && !free.contains(e);
+	live_bullets.player.retain(live);
+	live_bullets.enemy.retain(live);
+
+	let mut batch: Vec<(Entity, BulletBundle)> = vec![];
+	let mut homing: Vec<(Entity, Homing)> = vec![];
+	for ShootEvent(team, from_pos, dir, shooter, speed, weapon) in ev_shoot_reader.iter() {
+		let from_player = *team == Team::Player;
+		let weapon = weapons.get(*weapon);
+		let visual = if from_player {
+			&params.player_bullet
+		} else {
+			&params.enemy_bullet
 		};
-		let ignore_mask = if *from_player {
-			physics_globals.player_mask
+		let texture = textures
+			.0
+			.entry(visual.texture.clone())
+			.or_insert_with(|| asset_server.load(visual.texture.as_str()))
+			.clone();
+		let half_extents = visual.collider_half_extents();
+		// minion bullets get stronger with every level
+		let from_minion = shooter.map_or(false, |shooter| q_minions.get(shooter).is_ok());
+		let minion_level = Some(game_globals.level).filter(|_| from_minion);
+		let speed = *speed * params.speed_scale(*team, weapon, minion_level, bullet_time.scale);
+		let status: Vec<StatusEffectKind> = weapon
+			.status
+			.iter()
+			.chain(params.minion_bullet_status.iter().filter(|_| from_minion))
+			.copied()
+			.collect();
+		let boost = shooter
+			.and_then(|shooter| q_boosts.get(shooter).ok())
+			.map_or(1.0, |boost| boost.multiplier);
+		let damage = params.bullet_damage(weapon, minion_level, boost);
+
+		// spawn the bullet just outside of the shooter so it never clips into it
+		let offset = if let Some(shooter) = shooter {
+			let radius = shooter_radius(*shooter, &q_colliders, &q_children);
+			params.muzzle_offset(radius, half_extents, rapier_config.scale)
 		} else {
-			physics_globals.enemy_mask
+			params.bullet_offset
 		};
-		commands
-			.spawn_bundle(BulletBundle {
-				speed: Speed {
-					value: params.bullet_force_scale,
-				},
-				direction: direction.clone(),
-				sprite: SpriteBundle {
-					texture: asset_server.load("physics_example/bullet.png"),
-					sprite: Sprite {
-						custom_size: Some(Vec2::new(10.0, 10.0)),
-						..Default::default()
+		let ignore_mask = team.ignored_mask(params.friendly_fire, &physics_globals);
+		// projectiles of one shot fan out evenly around the aimed direction
+		for i in 0..weapon.projectiles.max(1) {
+			let angle = (i as f32 - (weapon.projectiles.max(1) - 1) as f32 * 0.5)
+				* weapon.projectile_spacing;
+			let direction = Direction {
+				value: Mat2::from_angle(angle) * dir.normalize(),
+			};
+			let (queue, max_bullets) = if from_player {
+				(&mut live_bullets.player, params.max_player_bullets)
+			} else {
+				(&mut live_bullets.enemy, params.max_enemy_bullets)
+			};
+			while !queue.is_empty() && queue.len() >= max_bullets {
+				if let Some(oldest) = queue.pop_front() {
+					// the oldest bullet might only be reserved in this frame, it must not be fired anymore
+					batch.retain(|(e, _)| *e != oldest);
+					homing.retain(|(e, _)| *e != oldest);
+					// `check_despawns` must not release it a second time
+					commands.entity(oldest).remove::<DespawnTimer>();
+					pool.release(&mut commands, oldest, params.pool_size, rapier_config.scale);
+				}
+			}
+
+			let bullet = pool.take(&mut commands);
+			batch.push((
+				bullet,
+				BulletBundle {
+					speed: Speed {
+						value: params.bullet_force_scale * speed,
 					},
-					..Default::default()
-				},
-				rigidbody: RigidBodyBundle {
-					position: RigidBodyPosition {
-						position: Isometry::translation(
-							from_pos.x / rapier_config.scale,
-							from_pos.y / rapier_config.scale,
-						) * Isometry::from(direction.value * params.bullet_offset)
-							* Isometry::rotation((direction.value.y / direction.value.x).atan()),
+					direction: direction.clone(),
+					sprite: SpriteBundle {
+						texture: texture.clone(),
+						sprite: Sprite {
+							custom_size: Some(visual.size),
+							..Default::default()
+						},
 						..Default::default()
-					}
-					.into(),
-					forces: RigidBodyForces {
-						force: (direction.value * params.bullet_force_scale).into(),
+					},
+					rigidbody: RigidBodyBundle {
+						position: RigidBodyPosition {
+							position: Isometry::translation(
+								from_pos.x / rapier_config.scale,
+								from_pos.y / rapier_config.scale,
+							) * Isometry::from(direction.value * offset)
+								* Isometry::rotation(
+									(direction.value.y / direction.value.x).atan(),
+								),
+							..Default::default()
+						}
+						.into(),
+						forces: RigidBodyForces {
+							force: (direction.value * params.bullet_force_scale * speed).into(),
+							..Default::default()
+						}
+						.into(),
 						..Default::default()
-					}
-					.into(),
-					..Default::default()
-				},
-				collider: ColliderBundle {
-					flags: ColliderFlags {
-						// accept all bullets for now
-						collision_groups: InteractionGroups::new(
-							physics_globals.bullet_mask,
-							u32::MAX - ignore_mask,
-						),
-						active_events: ActiveEvents::CONTACT_EVENTS,
+					},
+					collider: ColliderBundle {
+						flags: ColliderFlags {
+							// accept all bullets for now
+							collision_groups: InteractionGroups::new(
+								physics_globals.bullet_mask,
+								u32::MAX - ignore_mask,
+							),
+							active_events: ActiveEvents::CONTACT_EVENTS,
+							..Default::default()
+						}
+						.into(),
+						material: materials.bullet(),
+						shape: ColliderShape::cuboid(
+							half_extents.x / rapier_config.scale,
+							half_extents.y / rapier_config.scale,
+						)
+						.into(),
 						..Default::default()
-					}
-					.into(),
-					shape: ColliderShape::cuboid(
-						5.0 / rapier_config.scale,
-						1.0 / rapier_config.scale,
-					)
-					.into(),
-					..Default::default()
+					},
+					sync: ColliderPositionSync::Discrete,
+					bullet: Bullet {
+						damage,
+						owner: *shooter,
+						team: *team,
+						blast_radius: weapon.blast_radius,
+						status: status.clone(),
+					},
+					impact: Impact {
+						strength: weapon.impact,
+					},
 				},
+			));
+			queue.push_back(bullet);
+			if weapon.homing_turn_rate > 0.0 {
+				homing.push((
+					bullet,
+					Homing {
+						turn_rate: weapon.homing_turn_rate,
+						cone: weapon.homing_cone,
+					},
+				));
+			}
+		}
+	}
+
+	let ricochets: Vec<Entity> = if params.ricochet_bounces > 0 {
+		batch
+			.iter()
+			.filter(|(_, bundle)| bundle.bullet.team == Team::Player)
+			.map(|(e, _)| *e)
+			.collect()
+	} else {
+		vec![]
+	};
+
+	if params.batch_threshold > 0 && batch.len() >= params.batch_threshold {
+		commands.insert_or_spawn_batch(batch);
+	} else {
+		for (bullet, bundle) in batch {
+			commands.entity(bullet).insert_bundle(bundle);
+		}
+	}
+
+	for bullet in ricochets {
+		commands
+			.entity(bullet)
+			.insert(Ricochet {
+				bounces_left: params.ricochet_bounces,
+				speed: 0.0,
 			})
-			.insert(ColliderPositionSync::Discrete)
-			.insert(Bullet(params.damage));
+			.insert(materials.ricochet());
+	}
+	for (bullet, homing) in homing {
+		commands.entity(bullet).insert(homing);
 	}
 }
 
+/// Radius of the circle around the shooter's center that encloses all of its colliders, in physics units
+fn shooter_radius(
+	shooter: Entity,
+	q_colliders: &Query<(&ColliderShapeComponent, Option<&ColliderParentComponent>)>,
+	q_children: &Query<&Children>,
+) -> f32 {
+	let collider_radius = |entity: Entity| {
+		q_colliders
+			.get(entity)
+			.map(|(shape, parent)| {
+				let sphere = shape.compute_local_bounding_sphere();
+				let offset = parent.map_or(0.0, |parent| {
+					parent.pos_wrt_parent.translation.vector.norm()
+				});
+				offset + sphere.center.coords.norm() + sphere.radius
+			})
+			.unwrap_or(0.0)
+	};
+
+	let mut radius = collider_radius(shooter);
+	if let Ok(children) = q_children.get(shooter) {
+		for &child in children.iter() {
+			radius = radius.max(collider_radius(child));
+		}
+	}
+	radius
+}
+
 /// A system that listens to contact events triggered only by bullets
 fn check_bullet_hit(
 	mut commands: Commands,
 	mut contact_events: EventReader<ContactEvent>,
-	q_bullet: Query<(Entity, &Bullet)>,
-	mut q_health: Query<&mut Health>,
+	q_bullet: Query<(Entity, &Bullet, &Impact, &Transform)>,
+	mut q_motion: Query<
+		(
+			Option<&mut Ricochet>,
+			&mut Direction,
+			&mut RigidBodyVelocityComponent,
+		),
+		With<Bullet>,
+	>,
+	q_walls: Query<&Wall>,
+	narrow_phase: Res<NarrowPhase>,
+	q_health: Query<&Health>,
+	q_blast: Query<(Entity, &GlobalTransform), (With<Health>, Without<Invulnerable>)>,
+	q_invulnerable: Query<&Invulnerable>,
 	q_parent: Query<&Parent>,
-	params: Res<BulletParams>,
+	q_players: Query<&Player>,
+	q_enemies: Query<&Enemy>,
+	mut damage_ew: EventWriter<DamageEvent>,
+	(params, rapier_config): (Res<BulletParams>, Res<RapierConfiguration>),
 	time: Res<Time>,
+	(q_parts, feel_params, mut shake_ew, mut hit_stop_ew, mut status_ew, mut q_knockback): (
+		Query<&BossPart>,
+		Res<FeelParams>,
+		EventWriter<CameraShakeEvent>,
+		EventWriter<HitStopEvent>,
+		EventWriter<StatusEffectEvent>,
+		Query<
+			(
+				&mut RigidBodyVelocityComponent,
+				&RigidBodyMassPropsComponent,
+			),
+			Without<Bullet>,
+		>,
+	),
 ) {
+	// effects on a boss part affect the whole boss
+	let status_target = |target: Entity| {
+		q_parts
+			.get(target)
+			.ok()
+			.and(q_parent.get(target).ok())
+			.map_or(target, |Parent(boss)| *boss)
+	};
 	for contact_event in contact_events.iter() {
 		if let ContactEvent::Started(h1, h2) = contact_event {
-			if let Ok((e, Bullet(dmg))) = q_bullet.get(h2.entity()).or(q_bullet.get(h1.entity())) {
-				if let Ok(mut health) = q_health.get_mut(h1.entity()) {
-					health.0 -= dmg;
-					info!("DAMAGE -> HEALTH {}", health.0);
-				} else if let Ok(mut health) = q_health.get_mut(h2.entity()) {
-					health.0 -= dmg;
-					info!("DAMAGE -> HEALTH {}", health.0);
-				} else if let Ok(Parent(parent_e)) = q_parent.get(h1.entity()) {
-					// modify health on parent
-					if let Ok(mut health) = q_health.get_mut(*parent_e) {
-						health.0 -= dmg;
-					} else if let Ok(Parent(parent_e)) = q_parent.get(h2.entity()) {
-						if let Ok(mut health) = q_health.get_mut(*parent_e) {
-							health.0 -= dmg;
+			if let Ok((e, bullet, impact, bullet_t)) =
+				q_bullet.get(h2.entity()).or(q_bullet.get(h1.entity()))
+			{
+				let other = if e == h1.entity() {
+					h2.entity()
+				} else {
+					h1.entity()
+				};
+
+				// bounce off walls instead of despawning while the bullet has bounces left
+				if q_walls.get(other).is_ok() {
+					if let Ok((Some(mut ricochet), mut direction, mut rb_vel)) = q_motion.get_mut(e)
+					{
+						if ricochet.bounces_left > 0 {
+							if let Some(normal) = contact_normal(&narrow_phase, *h1, *h2) {
+								// the direction is still the one from before the contact
+								let speed = if ricochet.speed > 0.0 {
+									ricochet.speed
+								} else {
+									Vec2::from(rb_vel.linvel).length()
+								};
+								direction.value = reflect(direction.value, normal);
+								rb_vel.linvel = (direction.value * speed).into();
+							}
+							ricochet.bounces_left -= 1;
+							continue;
 						}
 					}
-				} else if let Ok(Parent(parent_e)) = q_parent.get(h2.entity()) {
-					if let Ok(mut health) = q_health.get_mut(*parent_e) {
-						health.0 -= dmg;
+				}
+
+				// the hit collider either has health itself or is a part of a parent with health
+				let target = if q_health.get(other).is_ok() {
+					Some(other)
+				} else {
+					q_parent
+						.get(other)
+						.ok()
+						.map(|Parent(parent_e)| *parent_e)
+						.filter(|parent_e| q_health.get(*parent_e).is_ok())
+				};
+
+				if let Some(target) = target {
+					// invulnerable targets, e.g. a dashing player, aren't hit at all
+					if q_invulnerable.get(target).is_err()
+						&& bullet_hits(bullet, target, &q_players, &q_enemies, params.friendly_fire)
+					{
+						let bounced = q_motion
+							.get(e)
+							.ok()
+							.and_then(|(ricochet, _, _)| ricochet)
+							.map_or(false, |ricochet| {
+								ricochet.bounces_left < params.ricochet_bounces
+							});
+						damage_ew.send(DamageEvent {
+							target,
+							amount: bullet.damage,
+							source: if bounced {
+								DamageSource::Ricochet
+							} else {
+								DamageSource::Bullet
+							},
+						});
+						for kind in bullet.status.iter() {
+							status_ew.send(StatusEffectEvent {
+								target: status_target(target),
+								kind: *kind,
+							});
+						}
+						let dir = q_motion
+							.get(e)
+							.map_or(Vec2::ZERO, |(_, direction, _)| direction.value);
+						push(
+							&mut q_knockback,
+							status_target(target),
+							dir * impact.strength,
+							params.max_impact_push / rapier_config.scale,
+						);
+
+						// the player getting hit and a boss part breaking off should be felt
+						let shake = if q_players.get(target).is_ok() {
+							Some(feel_params.player_hit_shake)
+						} else if q_parts.get(target).is_ok()
+							&& q_health.get(target).map_or(false, |Health(health)| {
+								*health > 0.0 && *health <= bullet.damage
+							}) {
+							Some(feel_params.part_break_shake)
+						} else {
+							None
+						};
+						if let Some((magnitude, duration)) = shake {
+							shake_ew.send(CameraShakeEvent {
+								magnitude,
+								duration,
+							});
+							hit_stop_ew.send(HitStopEvent(feel_params.hit_stop_secs));
+						}
 					}
 				}
 
-				commands.entity(e).insert(DespawnTimer(
-					Duration::new(0, params.bullet_lifetime_ms * 1000000),
-					time.time_since_startup(),
-				));
+				// explosions hurt everything around the hit as well, and only go off once
+				let lifetime = if bullet.blast_radius > 0.0 {
+					let pos = bullet_t.translation.xy();
+					for (entity, transform) in q_blast.iter() {
+						if Some(entity) != target
+							&& transform.translation.xy().distance(pos) <= bullet.blast_radius
+							&& bullet_hits(
+								bullet,
+								entity,
+								&q_players,
+								&q_enemies,
+								params.friendly_fire,
+							) {
+							damage_ew.send(DamageEvent {
+								target: entity,
+								amount: bullet.damage,
+								source: DamageSource::Bullet,
+							});
+							for kind in bullet.status.iter() {
+								status_ew.send(StatusEffectEvent {
+									target: status_target(entity),
+									kind: *kind,
+								});
+							}
+							// the blast pushes away from its center
+							let away = (transform.translation.xy() - pos).normalize_or_zero();
+							push(
+								&mut q_knockback,
+								status_target(entity),
+								away * impact.strength,
+								params.max_impact_push / rapier_config.scale,
+							);
+						}
+					}
+					Duration::ZERO
+				} else {
+					Duration::new(0, params.bullet_lifetime_ms * 1000000)
+				};
+				commands
+					.entity(e)
+					.insert(DespawnTimer(lifetime, time.time_since_startup()));
 			}
 		}
 	}
 }
 
+/// Changes the body's velocity by `impulse` over its mass, by at most `max_push` in physics units.
+/// Bodies without a finite mass, e.g. static ones, don't move
+fn push(
+	q_knockback: &mut Query<
+		(
+			&mut RigidBodyVelocityComponent,
+			&RigidBodyMassPropsComponent,
+		),
+		Without<Bullet>,
+	>,
+	body: Entity,
+	impulse: Vec2,
+	max_push: f32,
+) {
+	if impulse == Vec2::ZERO {
+		return;
+	}
+	if let Ok((mut rb_vel, mass_props)) = q_knockback.get_mut(body) {
+		let delta = (impulse * mass_props.effective_inv_mass).clamp_length_max(max_push);
+		rb_vel.linvel = (Vec2::from(rb_vel.linvel) + delta).into();
+	}
+}
+
+/// Whether `bullet` damages `target`. Nobody is hurt by their own bullets, players hurt each other only
+/// with friendly fire and enemies never hurt each other, like `Team::ignored_mask`
+fn bullet_hits(
+	bullet: &Bullet,
+	target: Entity,
+	q_players: &Query<&Player>,
+	q_enemies: &Query<&Enemy>,
+	friendly_fire: bool,
+) -> bool {
+	let target_team = if q_players.get(target).is_ok() {
+		Some(Team::Player)
+	} else if q_enemies.get(target).is_ok() {
+		Some(Team::Enemy)
+	} else {
+		None
+	};
+	let friendly = bullet.team != Team::Neutral && target_team == Some(bullet.team);
+	Some(target) != bullet.owner && (!friendly || (friendly_fire && bullet.team == Team::Player))
+}
+
+/// Switches the bullet's team and collision groups, so it hits enemies instead of players now
+fn deflect_bullets(
+	mut deflect_events: EventReader<DeflectEvent>,
+	mut q_bullets: Query<(
+		&mut Bullet,
+		&mut Direction,
+		&mut RigidBodyVelocityComponent,
+		&mut ColliderFlagsComponent,
+	)>,
+	mut live_bullets: ResMut<LiveBullets>,
+	params: Res<BulletParams>,
+	physics_globals: Res<PhysicsGlobals>,
+	bullet_time: Res<BulletTime>,
+) {
+	for DeflectEvent {
+		bullet: e,
+		owner,
+		dir,
+		speed_scale,
+	} in deflect_events.iter()
+	{
+		let (mut bullet, mut direction, mut rb_vel, mut flags) = match q_bullets.get_mut(*e) {
+			Ok(bullet) if bullet.0.team == Team::Enemy => bullet,
+			_ => continue,
+		};
+		debug!("DEFLECT_BULLET");
+		bullet.team = Team::Player;
+		bullet.owner = Some(*owner);
+		flags.collision_groups = InteractionGroups::new(
+			physics_globals.bullet_mask,
+			u32::MAX - Team::Player.ignored_mask(params.friendly_fire, &physics_globals),
+		);
+
+		// player bullets aren't slowed down by bullet time
+		let speed = Vec2::from(rb_vel.linvel).length() / bullet_time.scale.max(0.01) * speed_scale;
+		direction.value = dir.normalize_or_zero();
+		rb_vel.linvel = (direction.value * speed).into();
+
+		live_bullets.enemy.retain(|live| live != e);
+		live_bullets.player.push_back(*e);
+	}
+}
+
+/// Turns homing bullets towards the closest target within their cone. Targets are whatever the bullet
+/// can hit, apart from its own team even with friendly fire
+fn steer_homing_bullets(
+	mut q_bullets: Query<(
+		&Bullet,
+		&Homing,
+		&mut Direction,
+		&mut RigidBodyVelocityComponent,
+		&mut RigidBodyPositionComponent,
+	)>,
+	q_targets: Query<(Entity, &GlobalTransform), Or<(With<Player>, With<Enemy>)>>,
+	q_players: Query<&Player>,
+	q_enemies: Query<&Enemy>,
+	rapier_config: Res<RapierConfiguration>,
+	bullet_time: Res<BulletTime>,
+	time: Res<Time>,
+) {
+	for (bullet, homing, mut direction, mut rb_vel, mut rb_pos) in q_bullets.iter_mut() {
+		let velocity = Vec2::from(rb_vel.linvel);
+		if velocity == Vec2::ZERO {
+			continue;
+		}
+		let heading = velocity.normalize();
+		let pos = Vec2::from(rb_pos.0.position.translation.vector) * rapier_config.scale;
+		let to_target = q_targets
+			.iter()
+			.filter(|(target, _)| bullet_hits(bullet, *target, &q_players, &q_enemies, false))
+			.map(|(_, target_t)| target_t.translation.xy() - pos)
+			.filter(|to_target| heading.angle_between(*to_target).abs() <= homing.cone)
+			.min_by(|a, b| a.length_squared().total_cmp(&b.length_squared()));
+		let to_target = match to_target {
+			Some(to_target) => to_target,
+			None => continue,
+		};
+
+		// like their speed, enemy bullets turn slower in bullet time
+		let scale = if bullet.team == Team::Player {
+			1.0
+		} else {
+			bullet_time.scale
+		};
+		let max_turn = homing.turn_rate * time.delta_seconds() * scale;
+		let turn = heading.angle_between(to_target).clamp(-max_turn, max_turn);
+		direction.value = Mat2::from_angle(turn) * heading;
+		rb_vel.linvel = (direction.value * velocity.length()).into();
+		rb_pos.0.position.rotation = UnitComplex::new(direction.value.y.atan2(direction.value.x));
+	}
+}
+
+/// Slows live enemy bullets down when bullet time starts and speeds them back up when it ends.
+/// Bullets spawned meanwhile already start with the scaled speed
+fn apply_bullet_time(
+	bullet_time: Res<BulletTime>,
+	mut last_scale: Local<Option<f32>>,
+	mut q_bullets: Query<(&Bullet, &mut RigidBodyVelocityComponent)>,
+) {
+	let last = last_scale.unwrap_or(1.0);
+	if bullet_time.scale == last {
+		return;
+	}
+
+	let ratio = bullet_time.scale / last;
+	for (bullet, mut rb_vel) in q_bullets.iter_mut() {
+		if bullet.team != Team::Player {
+			rb_vel.linvel *= ratio;
+		}
+	}
+	*last_scale = Some(bullet_time.scale);
+}
+
+/// Remembers how fast ricochet bullets fly before the physics step, see `Ricochet::speed`
+fn track_ricochet_speed(mut q_ricochets: Query<(&mut Ricochet, &RigidBodyVelocityComponent)>) {
+	for (mut ricochet, rb_vel) in q_ricochets.iter_mut() {
+		let speed = Vec2::from(rb_vel.linvel).length();
+		if speed > 0.0 {
+			ricochet.speed = speed;
+		}
+	}
+}
+
+/// Returns the world-space normal of the first contact manifold between two colliders
+fn contact_normal(
+	narrow_phase: &NarrowPhase,
+	h1: ColliderHandle,
+	h2: ColliderHandle,
+) -> Option<Vec2> {
+	let pair = narrow_phase.contact_pair(h1, h2)?;
+	let manifold = pair.manifolds.first()?;
+	Some(manifold.data.normal.into())
+}
+
+/// Reflects a vector off a surface with the given normal
+fn reflect(v: Vec2, normal: Vec2) -> Vec2 {
+	let n = normal.normalize();
+	v - 2.0 * v.dot(n) * n
+}
+
+/// Bullets that are done go back to the pool
 fn check_despawns(
 	mut commands: Commands,
 	q_despawns: Query<(Entity, &DespawnTimer)>,
+	mut pool: ResMut<BulletPool>,
+	params: Res<BulletParams>,
+	rapier_config: Res<RapierConfiguration>,
 	time: Res<Time>,
 ) {
 	for (e, DespawnTimer(lifetime, start_time)) in q_despawns.iter() {
 		if time.time_since_startup() - *start_time > *lifetime {
-			commands.entity(e).despawn_recursive();
+			pool.release(&mut commands, e, params.pool_size, rapier_config.scale);
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use bevy::ecs::system::{CommandQueue, SystemState};
+
+	use super::*;
+	use crate::enemy::EnemyState;
+
+	fn bullet(team: Team, owner: Option<Entity>) -> Bullet {
+		Bullet {
+			damage: 1.0,
+			owner,
+			team,
+			blast_radius: 0.0,
+			status: vec![],
+		}
+	}
+
+	/// Whether `bullet` hits `target` in `world`, with the queries `check_bullet_hit` uses
+	fn hits(world: &mut World, bullet: &Bullet, target: Entity, friendly_fire: bool) -> bool {
+		let mut state: SystemState<(Query<&Player>, Query<&Enemy>)> = SystemState::new(world);
+		let (q_players, q_enemies) = state.get(world);
+		bullet_hits(bullet, target, &q_players, &q_enemies, friendly_fire)
+	}
+
+	#[test]
+	fn players_only_hit_each_other_with_friendly_fire() {
+		let mut world = World::new();
+		let player_1 = world.spawn().insert(Player(0.0)).id();
+		let player_2 = world.spawn().insert(Player(0.0)).id();
+		let shot = bullet(Team::Player, Some(player_1));
+
+		assert!(hits(&mut world, &shot, player_2, true));
+		assert!(!hits(&mut world, &shot, player_2, false));
+		// nobody is hurt by their own bullets
+		assert!(!hits(&mut world, &shot, player_1, true));
+		assert!(!hits(&mut world, &shot, player_1, false));
+	}
+
+	#[test]
+	fn bullets_hit_the_other_side() {
+		let mut world = World::new();
+		let player = world.spawn().insert(Player(0.0)).id();
+		let enemy = world.spawn().insert(Enemy(EnemyState::IDLE)).id();
+		let other_enemy = world.spawn().insert(Enemy(EnemyState::IDLE)).id();
+		let other_player = world.spawn().insert(Player(0.0)).id();
+
+		for friendly_fire in [false, true] {
+			let enemy_shot = bullet(Team::Enemy, Some(enemy));
+			assert!(hits(&mut world, &enemy_shot, player, friendly_fire));
+			assert!(!hits(&mut world, &enemy_shot, other_enemy, friendly_fire));
+
+			let player_shot = bullet(Team::Player, Some(player));
+			assert!(hits(&mut world, &player_shot, enemy, friendly_fire));
+
+			// e.g. arena turrets
+			let neutral_shot = bullet(Team::Neutral, None);
+			assert!(hits(&mut world, &neutral_shot, player, friendly_fire));
+			assert!(hits(&mut world, &neutral_shot, enemy, friendly_fire));
+		}
+		let player_shot = bullet(Team::Player, Some(player));
+		assert!(!hits(&mut world, &player_shot, other_player, false));
+	}
+
+	#[test]
+	fn only_heavy_weapons_kick() {
+		let weapons = WeaponParams::default();
+		let dir = Vec2::new(3.0, 4.0);
+		assert_eq!(weapons.pistol.kick_velocity(dir), Vec2::ZERO);
+		assert_eq!(weapons.laser.kick_velocity(dir), Vec2::ZERO);
+
+		let kick = weapons.shotgun.kick_velocity(dir);
+		assert!(kick.abs_diff_eq(Vec2::new(-0.6, -0.8) * weapons.shotgun.kick, 1e-3));
+		assert!(weapons.rocket.kick > weapons.shotgun.kick);
+	}
+
+	#[test]
+	fn reflects_off_wall_at_45_degrees() {
+		let dir = Vec2::new(1.0, -1.0).normalize();
+		let reflected = reflect(dir, Vec2::Y);
+		assert!(reflected.abs_diff_eq(Vec2::new(1.0, 1.0).normalize(), 1e-6));
+		// the side of the wall the normal points to doesn't matter
+		assert!(reflect(dir, -Vec2::Y).abs_diff_eq(reflected, 1e-6));
+	}
+
+	#[test]
+	fn bounces_straight_back_off_wall() {
+		// flying right into a wall on the right, whose normal points left
+		let reflected = reflect(Vec2::X, -Vec2::X);
+		assert!(reflected.abs_diff_eq(-Vec2::X, 1e-6));
+		assert_eq!(reflected.length(), 1.0);
+	}
+
+	#[test]
+	fn minion_bullets_scale_with_level() {
+		let params = BulletParams {
+			minion_damage_per_level: 0.5,
+			minion_speed_per_level: 0.25,
+			..Default::default()
+		};
+		assert_eq!(params.minion_scaling(0), (1.0, 1.0));
+		assert_eq!(params.minion_scaling(1), (1.0, 1.0));
+
+		// a pistol bullet of a minion on level 3 deals twice the damage and flies 1.5 times as fast
+		let pistol = WeaponParams::default().pistol;
+		assert_eq!(params.bullet_damage(&pistol, Some(1), 1.0), pistol.damage);
+		assert_eq!(
+			params.bullet_damage(&pistol, Some(3), 1.0),
+			pistol.damage * 2.0
+		);
+		assert_eq!(
+			params.speed_scale(Team::Enemy, &pistol, Some(3), 1.0),
+			pistol.speed * 1.5
+		);
+		// damage boosts are the player's, minions don't get them
+		assert_eq!(
+			params.bullet_damage(&pistol, Some(3), 3.0),
+			pistol.damage * 2.0
+		);
+		assert_eq!(
+			params.bullet_damage(&pistol, None, 3.0),
+			pistol.damage * 3.0
+		);
+	}
+
+	#[test]
+	fn teams_ignore_their_own_side() {
+		let globals = PhysicsGlobals {
+			player_mask: 0b0001,
+			enemy_mask: 0b0010,
+			scene_mask: 0b0100,
+			bullet_mask: 0b1000,
+		};
+		assert_eq!(
+			Team::Player.ignored_mask(false, &globals),
+			globals.player_mask
+		);
+		assert_eq!(
+			Team::Enemy.ignored_mask(false, &globals),
+			globals.enemy_mask
+		);
+		assert_eq!(Team::Neutral.ignored_mask(false, &globals), 0);
+
+		// with friendly fire player bullets touch players, enemies never shoot each other
+		assert_eq!(Team::Player.ignored_mask(true, &globals), 0);
+		assert_eq!(Team::Enemy.ignored_mask(true, &globals), globals.enemy_mask);
+		assert_eq!(Team::Neutral.ignored_mask(true, &globals), 0);
+	}
+
+	#[test]
+	fn rapid_fire_widens_spread() {
+		let pistol = WeaponParams::default().pistol;
+		let recovery = BulletParams::default().spread_recovery;
+		let mut spread = Spread::default();
+		assert_eq!(spread.cone(&pistol), pistol.base_spread);
+
+		// shooting as fast as the cooldown allows grows the cone faster than it recovers
+		let mut last_cone = spread.cone(&pistol);
+		for _ in 0..3 {
+			spread.bloom(&pistol);
+			spread.recover(recovery, pistol.fire_cooldown);
+			assert!(spread.cone(&pistol) > last_cone);
+			last_cone = spread.cone(&pistol);
+		}
+		for _ in 0..100 {
+			spread.bloom(&pistol);
+		}
+		assert!((spread.cone(&pistol) - pistol.max_spread).abs() < 1e-6);
+
+		// and it shrinks back to the base spread when not shooting
+		spread.recover(recovery, 10.0);
+		assert_eq!(spread.cone(&pistol), pistol.base_spread);
+	}
+
+	#[test]
+	fn releasing_twice_keeps_one_slot() {
+		let mut world = World::new();
+		let bullet = world.spawn().insert(bullet(Team::Player, None)).id();
+		let mut pool = BulletPool::default();
+		let mut queue = CommandQueue::default();
+		let mut commands = Commands::new(&mut queue, &world);
+		pool.release(&mut commands, bullet, 10, 20.0);
+		pool.release(&mut commands, bullet, 10, 20.0);
+		queue.apply(&mut world);
+
+		assert_eq!(pool.free, vec![bullet]);
+		assert!(world.get::<Bullet>(bullet).is_none());
+	}
+
+	#[test]
+	fn bullets_spawn_outside_the_shooter() {
+		let scale = 20.0;
+		let params = BulletParams::default();
+		let mut world = World::new();
+		let shooter = world
+			.spawn()
+			.insert(ColliderShapeComponent(ColliderShape::ball(10.0 / scale)))
+			.id();
+		let mut state: SystemState<(
+			Query<(&ColliderShapeComponent, Option<&ColliderParentComponent>)>,
+			Query<&Children>,
+		)> = SystemState::new(&mut world);
+		let (q_colliders, q_children) = state.get(&world);
+		let radius = shooter_radius(shooter, &q_colliders, &q_children);
+		assert!((radius * scale - 10.0).abs() < 1e-4);
+
+		let half_extents = params.player_bullet.collider_half_extents();
+		let offset = params.muzzle_offset(radius, half_extents, scale) * scale;
+		assert!(offset > 10.0 + params.muzzle_margin);
+		// not just the center, the back of the bullet clears the shooter by the margin too
+		assert!(offset - half_extents.x >= 10.0 + params.muzzle_margin - 1e-4);
+	}
+}
@@ -0,0 +1,265 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	enemy::{Boss, Enemy},
+	game::{DeathEvent, GameClock, GameGlobals, GameState},
+};
+
+/// Stats accumulated over all runs and the local high score table. They are saved to local storage
+/// in the browser and to `career_stats.ron` and `highscores.ron` natively, every time a run ends
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(load_career_stats())
+			.insert_resource(load_highscores())
+			.insert_resource(RunKills::default())
+			.insert_resource(RunTime::default())
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(count_kills.after("detect_deaths")),
+			)
+			.add_system_set(SystemSet::on_enter(GameState::Victory).with_system(add_level_time))
+			.add_system_set(
+				SystemSet::on_enter(GameState::GameOver)
+					.with_system(update_career_stats.label("update_career_stats"))
+					.with_system(
+						record_highscore
+							.label("record_highscore")
+							.after("update_career_stats"),
+					),
+			);
+	}
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct CareerStats {
+	pub total_runs: u32,
+	pub total_kills: u32,
+	pub best_score: u32,
+	/// Seconds
+	pub longest_survival: f32,
+	pub bosses_defeated: u32,
+}
+
+/// Kills of the current run, added to the career stats once it ends
+#[derive(Default)]
+struct RunKills {
+	enemies: u32,
+	bosses: u32,
+}
+
+/// A finished run in the local high score table
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HighscoreEntry {
+	pub score: u32,
+	pub level: u32,
+	/// Seconds over all levels of the run
+	pub duration: f32,
+	/// Seconds since the unix epoch when the run ended
+	pub timestamp: u64,
+}
+
+impl HighscoreEntry {
+	/// `YYYY-MM-DD` in UTC
+	pub fn date(&self) -> String {
+		// days since the epoch to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+		let days = (self.timestamp / 86400) as i64 + 719468;
+		let era = days.div_euclid(146097);
+		let day_of_era = days - era * 146097;
+		let year_of_era =
+			(day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+		let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+		let mp = (5 * day_of_year + 2) / 153;
+		let day = day_of_year - (153 * mp + 2) / 5 + 1;
+		let month = if mp < 10 { mp + 3 } else { mp - 9 };
+		let year = year_of_era + era * 400 + (month <= 2) as i64;
+		format!("{:04}-{:02}-{:02}", year, month, day)
+	}
+}
+
+/// The best local runs, highest score first. Independent of the online leaderboard
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct HighscoreStore {
+	pub entries: Vec<HighscoreEntry>,
+	/// Whether the run that just ended beat the best score, not stored
+	#[serde(skip)]
+	pub new_best: bool,
+}
+
+const MAX_HIGHSCORES: usize = 10;
+
+impl HighscoreStore {
+	/// Inserts the run if it makes the table. Returns whether it beat the best score
+	fn record(&mut self, entry: HighscoreEntry) -> bool {
+		let new_best = entry.score > 0
+			&& self
+				.entries
+				.first()
+				.map_or(true, |best| entry.score > best.score);
+		let rank = self
+			.entries
+			.iter()
+			.position(|other| entry.score > other.score)
+			.unwrap_or(self.entries.len());
+		if rank < MAX_HIGHSCORES {
+			self.entries.insert(rank, entry);
+			self.entries.truncate(MAX_HIGHSCORES);
+		}
+		new_best
+	}
+}
+
+/// Seconds of the levels already won in the current run
+#[derive(Default)]
+struct RunTime(f32);
+
+impl RunTime {
+	/// Seconds of the whole run, the won levels and the current one
+	fn total(&self, clock: &GameClock) -> f32 {
+		self.0 + clock.elapsed().as_secs_f32()
+	}
+}
+
+const STORAGE_KEY: &str = "career_stats";
+const HIGHSCORES_KEY: &str = "highscores";
+
+/// Missing or unreadable stats start over from zero
+fn load_career_stats() -> CareerStats {
+	match read_stats(STORAGE_KEY) {
+		Some(data) => ron::de::from_str(&data).unwrap_or_else(|err| {
+			warn!("Discarding corrupt career stats: {}", err);
+			CareerStats::default()
+		}),
+		None => CareerStats::default(),
+	}
+}
+
+fn save_career_stats(stats: &CareerStats) {
+	match ron::ser::to_string(stats) {
+		Ok(data) => write_stats(STORAGE_KEY, &data),
+		Err(err) => warn!("Couldn't serialize career stats: {}", err),
+	}
+}
+
+/// Missing or unreadable high scores start with an empty table
+fn load_highscores() -> HighscoreStore {
+	match read_stats(HIGHSCORES_KEY) {
+		Some(data) => ron::de::from_str(&data).unwrap_or_else(|err| {
+			warn!("Discarding corrupt high scores: {}", err);
+			HighscoreStore::default()
+		}),
+		None => HighscoreStore::default(),
+	}
+}
+
+fn save_highscores(highscores: &HighscoreStore) {
+	match ron::ser::to_string(highscores) {
+		Ok(data) => write_stats(HIGHSCORES_KEY, &data),
+		Err(err) => warn!("Couldn't serialize high scores: {}", err),
+	}
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_stats(key: &str) -> Option<String> {
+	let storage = web_sys::window()?.local_storage().ok()??;
+	storage.get_item(key).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_stats(key: &str, data: &str) {
+	let storage = web_sys::window().and_then(|window| window.local_storage().ok().flatten());
+	if let Some(storage) = storage {
+		if storage.set_item(key, data).is_err() {
+			warn!("Couldn't save {}", key);
+		}
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_stats(key: &str) -> Option<String> {
+	std::fs::read_to_string(format!("{}.ron", key)).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_stats(key: &str, data: &str) {
+	if let Err(err) = std::fs::write(format!("{}.ron", key), data) {
+		warn!("Couldn't save {}: {}", key, err);
+	}
+}
+
+/// `SystemTime` isn't available in the browser
+#[cfg(target_arch = "wasm32")]
+fn unix_timestamp() -> u64 {
+	(js_sys::Date::now() / 1000.0) as u64
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn unix_timestamp() -> u64 {
+	std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.map_or(0, |since| since.as_secs())
+}
+
+/// A dying enemy can be reported more than once in a frame, so every entity is only counted once
+fn count_kills(
+	mut death_events: EventReader<DeathEvent>,
+	q_enemies: Query<Option<&Boss>, With<Enemy>>,
+	mut kills: ResMut<RunKills>,
+) {
+	let mut counted: Vec<Entity> = vec![];
+	for DeathEvent(entity) in death_events.iter() {
+		if counted.contains(entity) {
+			continue;
+		}
+		counted.push(*entity);
+		match q_enemies.get(*entity) {
+			Ok(Some(_)) => kills.bosses += 1,
+			Ok(None) => kills.enemies += 1,
+			Err(_) => {}
+		}
+	}
+}
+
+fn update_career_stats(
+	mut stats: ResMut<CareerStats>,
+	mut kills: ResMut<RunKills>,
+	run_time: Res<RunTime>,
+	globals: Res<GameGlobals>,
+	clock: Res<GameClock>,
+) {
+	stats.total_runs += 1;
+	stats.total_kills += kills.enemies + kills.bosses;
+	stats.bosses_defeated += kills.bosses;
+	stats.best_score = stats.best_score.max(globals.score);
+	stats.longest_survival = stats.longest_survival.max(run_time.total(&clock));
+	save_career_stats(&stats);
+	// a run spans all levels won on the way, so the kills are only reset once it is over
+	*kills = RunKills::default();
+}
+
+/// The clock restarts with every level, so the time of won levels is added up for the run's duration
+fn add_level_time(mut run_time: ResMut<RunTime>, clock: Res<GameClock>) {
+	run_time.0 += clock.elapsed().as_secs_f32();
+}
+
+/// Resets the run time, so it runs after `update_career_stats`
+fn record_highscore(
+	mut highscores: ResMut<HighscoreStore>,
+	mut run_time: ResMut<RunTime>,
+	globals: Res<GameGlobals>,
+	clock: Res<GameClock>,
+) {
+	let entry = HighscoreEntry {
+		score: globals.score,
+		level: globals.level,
+		duration: run_time.total(&clock),
+		timestamp: unix_timestamp(),
+	};
+	highscores.new_best = highscores.record(entry);
+	save_highscores(&highscores);
+	*run_time = RunTime::default();
+}
@@ -0,0 +1,252 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::game::{DamageEvent, DamageSource, GameState, Health};
+
+/// Burning and poison hurt over time, slow makes its target move slower. Effects are applied with
+/// `StatusEffectEvent`s, e.g. by bullets of weapons that carry one. Every application adds a stack with
+/// its own duration, up to the kind's `max_stacks`
+pub struct StatusEffectsPlugin;
+
+impl Plugin for StatusEffectsPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_event::<StatusEffectEvent>()
+			.insert_resource(StatusEffectParams::default())
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(apply_status_effects.label("apply_status_effects"))
+					.with_system(
+						tick_status_effects
+							.after("apply_status_effects")
+							.before("apply_damage"),
+					),
+			);
+	}
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum StatusEffectKind {
+	Burn,
+	Slow,
+	Poison,
+}
+
+pub struct StatusEffectSpec {
+	/// Seconds a stack lasts
+	pub duration: f32,
+	pub max_stacks: u32,
+	/// Damage per second of every stack
+	pub damage_per_sec: f32,
+	/// Multiplies the movement speed once per stack
+	pub speed_multiplier: f32,
+}
+
+pub struct StatusEffectParams {
+	pub burn: StatusEffectSpec,
+	pub slow: StatusEffectSpec,
+	pub poison: StatusEffectSpec,
+	/// Seconds between two damage ticks
+	pub tick_secs: f32,
+	/// The slowest anything moves no matter how many slows stack up
+	pub min_speed_multiplier: f32,
+}
+
+impl Default for StatusEffectParams {
+	fn default() -> Self {
+		Self {
+			// short and painful
+			burn: StatusEffectSpec {
+				duration: 2.0,
+				max_stacks: 3,
+				damage_per_sec: 3.0,
+				speed_multiplier: 1.0,
+			},
+			slow: StatusEffectSpec {
+				duration: 1.5,
+				max_stacks: 3,
+				damage_per_sec: 0.0,
+				speed_multiplier: 0.8,
+			},
+			// long and stacking up
+			poison: StatusEffectSpec {
+				duration: 5.0,
+				max_stacks: 5,
+				damage_per_sec: 1.0,
+				speed_multiplier: 1.0,
+			},
+			tick_secs: 0.5,
+			min_speed_multiplier: 0.3,
+		}
+	}
+}
+
+impl StatusEffectParams {
+	pub fn get(&self, kind: StatusEffectKind) -> &StatusEffectSpec {
+		match kind {
+			StatusEffectKind::Burn => &self.burn,
+			StatusEffectKind::Slow => &self.slow,
+			StatusEffectKind::Poison => &self.poison,
+		}
+	}
+}
+
+/// Sets numeric fields by name as `<effect>.<field>`, used by the dev console
+#[cfg(feature = "debug")]
+impl StatusEffectParams {
+	pub fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+		match name {
+			"tick_secs" => self.tick_secs = value,
+			"min_speed_multiplier" => self.min_speed_multiplier = value,
+			_ => {
+				let (effect, field) = name
+					.split_once('.')
+					.ok_or_else(|| format!("unknown field status.{}", name))?;
+				let spec = match effect {
+					"burn" => &mut self.burn,
+					"slow" => &mut self.slow,
+					"poison" => &mut self.poison,
+					_ => return Err(format!("unknown status effect {}", effect)),
+				};
+				match field {
+					"duration" => spec.duration = value,
+					"max_stacks" => spec.max_stacks = value as u32,
+					"damage_per_sec" => spec.damage_per_sec = value,
+					"speed_multiplier" => spec.speed_multiplier = value,
+					_ => return Err(format!("unknown field status.{}", name)),
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Adds a stack of the effect to the target
+pub struct StatusEffectEvent {
+	pub target: Entity,
+	pub kind: StatusEffectKind,
+}
+
+/// Stacks of effects on an entity, added on the first effect
+#[derive(Component, Default)]
+pub struct StatusEffects {
+	stacks: Vec<StatusStack>,
+	/// Seconds since the last damage tick
+	since_tick: f32,
+}
+
+struct StatusStack {
+	kind: StatusEffectKind,
+	/// Seconds left
+	left: f32,
+	damage_per_sec: f32,
+	speed_multiplier: f32,
+}
+
+impl StatusEffects {
+	/// Multiplier of the movement speed, 1 when nothing slows the entity down
+	pub fn speed_multiplier(&self, params: &StatusEffectParams) -> f32 {
+		self.stacks
+			.iter()
+			.map(|stack| stack.speed_multiplier)
+			.product::<f32>()
+			.max(params.min_speed_multiplier)
+	}
+
+	pub fn stacks(&self, kind: StatusEffectKind) -> usize {
+		self.stacks
+			.iter()
+			.filter(|stack| stack.kind == kind)
+			.count()
+	}
+
+	/// Removes the given effects, e.g. when the entity is cured
+	pub fn clear(&mut self, kinds: &[StatusEffectKind]) {
+		self.stacks.retain(|stack| !kinds.contains(&stack.kind));
+	}
+
+	/// At `max_stacks`, the stack that would run out first is renewed instead
+	fn add(&mut self, kind: StatusEffectKind, spec: &StatusEffectSpec) {
+		let stack = StatusStack {
+			kind,
+			left: spec.duration,
+			damage_per_sec: spec.damage_per_sec,
+			speed_multiplier: spec.speed_multiplier,
+		};
+		if self.stacks(kind) < spec.max_stacks.max(1) as usize {
+			self.stacks.push(stack);
+		} else if let Some(oldest) = self
+			.stacks
+			.iter_mut()
+			.filter(|stack| stack.kind == kind)
+			.min_by(|a, b| a.left.total_cmp(&b.left))
+		{
+			*oldest = stack;
+		}
+	}
+}
+
+/// Only entities with health are affected. Effects on ones without `StatusEffects` are gathered first,
+/// several can hit them in one frame
+fn apply_status_effects(
+	mut commands: Commands,
+	mut status_er: EventReader<StatusEffectEvent>,
+	mut q_effects: Query<&mut StatusEffects>,
+	q_health: Query<(), With<Health>>,
+	params: Res<StatusEffectParams>,
+) {
+	let mut added: Vec<(Entity, StatusEffects)> = vec![];
+	for StatusEffectEvent { target, kind } in status_er.iter() {
+		debug!("STATUS_EFFECT {:?} {:?}", kind, target);
+		let spec = params.get(*kind);
+		if let Ok(mut effects) = q_effects.get_mut(*target) {
+			effects.add(*kind, spec);
+		} else if let Some((_, effects)) = added.iter_mut().find(|(e, _)| e == target) {
+			effects.add(*kind, spec);
+		} else if q_health.get(*target).is_ok() {
+			let mut effects = StatusEffects::default();
+			effects.add(*kind, spec);
+			added.push((*target, effects));
+		}
+	}
+	for (target, effects) in added {
+		commands.entity(target).insert(effects);
+	}
+}
+
+/// Runs the stacks down and deals their damage every `tick_secs`
+fn tick_status_effects(
+	mut q_effects: Query<(Entity, &mut StatusEffects)>,
+	mut damage_ew: EventWriter<DamageEvent>,
+	params: Res<StatusEffectParams>,
+	time: Res<Time>,
+) {
+	let delta = time.delta_seconds();
+	for (entity, mut effects) in q_effects.iter_mut() {
+		if effects.stacks.is_empty() {
+			continue;
+		}
+		effects.since_tick += delta;
+		if effects.since_tick >= params.tick_secs {
+			let damage: f32 = effects
+				.stacks
+				.iter()
+				.map(|stack| stack.damage_per_sec * effects.since_tick)
+				.sum();
+			effects.since_tick = 0.0;
+			if damage > 0.0 {
+				damage_ew.send(DamageEvent {
+					target: entity,
+					amount: damage,
+					source: DamageSource::Status,
+				});
+			}
+		}
+		for stack in effects.stacks.iter_mut() {
+			stack.left -= delta;
+		}
+		effects.stacks.retain(|stack| stack.left > 0.0);
+		if effects.stacks.is_empty() {
+			effects.since_tick = 0.0;
+		}
+	}
+}
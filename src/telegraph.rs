@@ -0,0 +1,211 @@
+use std::f32::consts::PI;
+
+use bevy::{
+	math::Vec3Swizzles,
+	prelude::*,
+	render::{mesh::Indices, render_resource::PrimitiveTopology},
+	sprite::MaterialMesh2dBundle,
+};
+
+use crate::game::{BulletTime, GameState};
+
+/// Warns the player of enemy attacks during their wind-up. A `TelegraphEvent` spawns a translucent
+/// shape covering the area the attack will hit, filled up from the attacker while the wind-up runs
+pub struct TelegraphPlugin;
+
+impl Plugin for TelegraphPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_event::<TelegraphEvent>()
+			.add_event::<CancelTelegraphsEvent>()
+			.insert_resource(TelegraphParams::default())
+			.add_system_set(
+				SystemSet::on_update(GameState::Playing)
+					.with_system(spawn_telegraphs.after("enemy_ai"))
+					.with_system(update_telegraphs.after("enemy_ai")),
+			);
+	}
+}
+
+pub struct TelegraphParams {
+	/// Alpha of the whole area, the fill is drawn with the event's color
+	pub area_alpha: f32,
+	/// Triangles of a full circle, cones get their share of them
+	pub circle_segments: u32,
+}
+
+impl Default for TelegraphParams {
+	fn default() -> Self {
+		Self {
+			area_alpha: 0.15,
+			circle_segments: 32,
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TelegraphShape {
+	/// Pixels and radians, centered on the direction
+	Cone {
+		range: f32,
+		angle: f32,
+	},
+	/// Starts at the position and runs `length` pixels in the direction
+	Line {
+		length: f32,
+		width: f32,
+	},
+	Circle {
+		radius: f32,
+	},
+}
+
+pub const TELEGRAPH_COLOR: Color = Color::rgba(1.0, 0.2, 0.2, 0.35);
+
+pub struct TelegraphEvent {
+	/// Enemy winding up, the telegraph goes away with it
+	pub owner: Entity,
+	pub shape: TelegraphShape,
+	pub pos: Vec2,
+	/// Direction cones and lines point in
+	pub dir: Vec2,
+	/// Seconds until the attack goes off
+	pub secs: f32,
+	/// Moves the telegraph along with the owner
+	pub follow: bool,
+	pub color: Color,
+}
+
+/// Removes the telegraphs of an enemy whose attack was called off
+pub struct CancelTelegraphsEvent(pub Entity);
+
+#[derive(Component)]
+pub struct Telegraph {
+	owner: Entity,
+	follow: bool,
+	shape: TelegraphShape,
+	timer: Timer,
+	/// Child growing over the area as the wind-up runs out
+	fill: Entity,
+}
+
+/// Triangle fan around the origin, pointing along the x axis
+fn fan_mesh(radius: f32, angle: f32, segments: u32) -> Mesh {
+	let mut positions = vec![[0.0, 0.0, 0.0]];
+	for i in 0..=segments {
+		let a = (i as f32 / segments as f32 - 0.5) * angle;
+		positions.push([a.cos() * radius, a.sin() * radius, 0.0]);
+	}
+	let indices = (1..=segments).flat_map(|i| [0, i, i + 1]).collect();
+	mesh_from(positions, indices)
+}
+
+/// Rectangle from the origin along the x axis
+fn line_mesh(length: f32, width: f32) -> Mesh {
+	let half = width * 0.5;
+	let positions = vec![
+		[0.0, -half, 0.0],
+		[length, -half, 0.0],
+		[length, half, 0.0],
+		[0.0, half, 0.0],
+	];
+	mesh_from(positions, vec![0, 1, 2, 0, 2, 3])
+}
+
+fn mesh_from(positions: Vec<[f32; 3]>, indices: Vec<u32>) -> Mesh {
+	let count = positions.len();
+	let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+	mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+	mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 0.0, 1.0]; count]);
+	mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]; count]);
+	mesh.set_indices(Some(Indices::U32(indices)));
+	mesh
+}
+
+fn spawn_telegraphs(
+	mut commands: Commands,
+	mut telegraph_er: EventReader<TelegraphEvent>,
+	params: Res<TelegraphParams>,
+	mut meshes: ResMut<Assets<Mesh>>,
+	mut color_materials: ResMut<Assets<ColorMaterial>>,
+) {
+	for event in telegraph_er.iter() {
+		let segments = params.circle_segments.max(3);
+		let mesh = match event.shape {
+			TelegraphShape::Cone { range, angle } => {
+				let angle = angle.clamp(0.0, 2.0 * PI);
+				let share = (segments as f32 * angle / (2.0 * PI)).ceil() as u32;
+				fan_mesh(range, angle, share.max(1))
+			}
+			TelegraphShape::Line { length, width } => line_mesh(length, width),
+			TelegraphShape::Circle { radius } => fan_mesh(radius, 2.0 * PI, segments),
+		};
+		let mesh = meshes.add(mesh);
+		let mut area_color = event.color;
+		area_color.set_a(params.area_alpha);
+
+		let fill = commands
+			.spawn_bundle(MaterialMesh2dBundle {
+				mesh: mesh.clone().into(),
+				material: color_materials.add(event.color.into()),
+				transform: Transform::from_xyz(0.0, 0.0, 0.1).with_scale(Vec3::ZERO),
+				..Default::default()
+			})
+			.id();
+		commands
+			.spawn_bundle(MaterialMesh2dBundle {
+				mesh: mesh.into(),
+				material: color_materials.add(area_color.into()),
+				transform: Transform::from_translation(event.pos.extend(-0.5))
+					.with_rotation(Quat::from_rotation_z(event.dir.y.atan2(event.dir.x))),
+				..Default::default()
+			})
+			.push_children(&[fill])
+			.insert(Telegraph {
+				owner: event.owner,
+				follow: event.follow,
+				shape: event.shape,
+				timer: Timer::from_seconds(event.secs, false),
+				fill,
+			});
+	}
+}
+
+/// Fills the telegraphs up and removes them once the attack goes off, it was called off or the owner is gone
+fn update_telegraphs(
+	mut commands: Commands,
+	mut cancel_er: EventReader<CancelTelegraphsEvent>,
+	mut q_telegraphs: Query<(Entity, &mut Telegraph, &mut Transform)>,
+	mut q_fill: Query<&mut Transform, Without<Telegraph>>,
+	q_owner_t: Query<&GlobalTransform, Without<Telegraph>>,
+	(time, bullet_time): (Res<Time>, Res<BulletTime>),
+) {
+	let cancelled: Vec<Entity> = cancel_er
+		.iter()
+		.map(|CancelTelegraphsEvent(owner)| *owner)
+		.collect();
+	for (entity, mut telegraph, mut transform) in q_telegraphs.iter_mut() {
+		telegraph
+			.timer
+			.tick(time.delta().mul_f32(bullet_time.scale));
+		let owner_t = q_owner_t.get(telegraph.owner);
+		if telegraph.timer.finished() || owner_t.is_err() || cancelled.contains(&telegraph.owner) {
+			commands.entity(entity).despawn_recursive();
+			continue;
+		}
+
+		if let (true, Ok(owner_t)) = (telegraph.follow, owner_t) {
+			let pos = owner_t.translation.xy();
+			transform.translation = pos.extend(transform.translation.z);
+		}
+		if let Ok(mut fill_t) = q_fill.get_mut(telegraph.fill) {
+			let progress = telegraph.timer.percent();
+			// lines fill up along their length, cones and circles outwards
+			fill_t.scale = match telegraph.shape {
+				TelegraphShape::Line { .. } => Vec3::new(progress, 1.0, 1.0),
+				TelegraphShape::Cone { .. } | TelegraphShape::Circle { .. } => {
+					Vec3::new(progress, progress, 1.0)
+				}
+			};
+		}
+	}
+}
@@ -3,10 +3,19 @@ use std::{cell::RefCell, rc::Rc};
 use bevy::prelude::*;
 
 use crate::{
-	enemy::{Boss, BossSpawnEvent},
-	game::{run_when_enter_playing_state, GameGlobals, GameState, Health, LeaderboardEvent},
-	player::{Player, PlayerSpawnEvent},
+	drops::DamageBoost,
+	enemy::{Boss, BossSpawnEvent, Minion, WaveStartedEvent},
+	game::{
+		DamageImmunity, GameClock, GameGlobals, GameState, Health, LeaderboardEvent,
+		LeaderboardState, LeaderboardStatus, Lives,
+	},
+	inventory::{ItemSlot, PlayerInventory, SLOTS},
+	player::{Dash, Player, PlayerSpawnEvent},
 	scene::MainCamera,
+	score::{Combo, ScoreBreakdown},
+	settings::Settings,
+	shooting::{Ammo, ReloadTimer, Weapon, WeaponKind, WeaponParams},
+	stats::{CareerStats, HighscoreStore},
 };
 
 pub struct UIPlugin;
@@ -16,18 +25,78 @@ impl Plugin for UIPlugin {
 		app.insert_resource(UIParams {
 			health_pos: Vec2::new(0.1, 0.1),
 			enemy_health_pos: Vec2::new(0.9, 0.1),
+			chip_drain_rate: 40.0,
+			damage_numbers: true,
 		})
 		.insert_resource(UIGlobals::default())
+		.insert_resource(SettingsMenu::default())
 		.add_startup_system(spawn_ui_camera)
 		.add_system(spawn_health_bars)
-		.add_system_set(SystemSet::on_update(GameState::Playing).with_system(update_health_bars))
-		.add_system_set(SystemSet::on_exit(GameState::Playing).with_system(reset_state));
+		.add_system_set(
+			SystemSet::on_enter(GameState::Playing)
+				.with_system(spawn_lives_text)
+				.with_system(spawn_dash_text)
+				.with_system(spawn_ammo_text)
+				.with_system(spawn_hotbar)
+				.with_system(spawn_enemy_count_text)
+				.with_system(spawn_combo_text)
+				.with_system(spawn_immunity_text)
+				.with_system(spawn_wave_text),
+		)
+		.add_system_set(
+			SystemSet::on_update(GameState::Playing)
+				.with_system(update_health_bars)
+				.with_system(drain_health_chips)
+				.with_system(update_lives_text)
+				.with_system(update_dash_text)
+				.with_system(update_ammo_text)
+				.with_system(update_hotbar)
+				.with_system(update_enemy_count_text)
+				.with_system(update_combo_text)
+				.with_system(update_immunity_text)
+				.with_system(update_wave_text),
+		)
+		.add_system_set(SystemSet::on_pause(GameState::Playing).with_system(hide_enemy_count_text))
+		.add_system_set(SystemSet::on_resume(GameState::Playing).with_system(show_enemy_count_text))
+		.add_system_set(SystemSet::on_exit(GameState::Playing).with_system(reset_state))
+		.add_system_set(
+			SystemSet::on_enter(GameState::GameOver).with_system(
+				spawn_leaderboard
+					.after("update_career_stats")
+					.after("record_highscore"),
+			),
+		)
+		.add_system_set(
+			SystemSet::on_update(GameState::GameOver)
+				.with_system(update_leaderboard)
+				.with_system(retry_leaderboard_button)
+				.with_system(restart_button),
+		)
+		.add_system_set(SystemSet::on_enter(GameState::Victory).with_system(spawn_victory_screen))
+		.add_system_set(SystemSet::on_enter(GameState::Paused).with_system(spawn_settings_screen))
+		.add_system_set(
+			SystemSet::on_update(GameState::Paused)
+				.with_system(navigate_settings.before("toggle_pause"))
+				.with_system(update_settings_text),
+		)
+		.add_system_set(SystemSet::on_exit(GameState::Paused).with_system(despawn_settings_screen));
 	}
 }
 
-struct UIParams {
+pub struct UIParams {
 	health_pos: Vec2,
 	enemy_health_pos: Vec2,
+	/// Health per second the lighter bar behind a health bar catches up with lost health
+	chip_drain_rate: f32,
+	/// Show the damage of bullet hits as floating numbers, see `DamageNumberPlugin`
+	pub damage_numbers: bool,
+}
+
+/// Lighter bar behind a health bar that trails lost health, so players see how much a hit took
+#[derive(Component)]
+struct HealthChip {
+	health_entity: Entity,
+	value: f32,
 }
 
 #[derive(Default)]
@@ -42,7 +111,7 @@ fn reset_state(mut globals: ResMut<UIGlobals>) {
 }
 
 fn spawn_ui_camera(mut commands: Commands) {
-	info!("SPAWN_UI_CAMERA");
+	debug!("SPAWN_UI_CAMERA");
 	commands.spawn_bundle(UiCameraBundle::default());
 }
 
@@ -114,6 +183,27 @@ fn spawn_health_bars(
 				..Default::default()
 			})
 			.with_children(|parent| {
+				// spawned first, so the actual health bar is drawn on top of it
+				parent
+					.spawn_bundle(NodeBundle {
+						style: Style {
+							size: Size::new(Val::Percent(*health), Val::Px(80.0)),
+							position_type: PositionType::Absolute,
+							position: Rect {
+								top: Val::Px(0.0),
+								left: Val::Px(0.0),
+								..Default::default()
+							},
+							..Default::default()
+						},
+						color: Color::rgb(1.0, 0.75, 0.75).into(),
+						..Default::default()
+					})
+					.insert(HealthChip {
+						health_entity: *health_entity,
+						value: *health,
+					});
+
 				let health_bar_entity = parent
 					.spawn_bundle(NodeBundle {
 						style: Style {
@@ -171,11 +261,1079 @@ fn update_health_bars(
 	}
 }
 
+/// Drains the chips down to the current health. Health going up, e.g. on respawn, fills them up instantly
+fn drain_health_chips(
+	params: Res<UIParams>,
+	q_health: Query<&Health>,
+	mut q_chips: Query<(&mut HealthChip, &mut Style)>,
+	time: Res<Time>,
+) {
+	for (mut chip, mut style) in q_chips.iter_mut() {
+		let health = match q_health.get(chip.health_entity) {
+			Ok(Health(health)) => health.max(0.0),
+			Err(_) => continue,
+		};
+		if chip.value == health {
+			continue;
+		}
+		chip.value = if health > chip.value {
+			health
+		} else {
+			(chip.value - params.chip_drain_rate * time.delta_seconds()).max(health)
+		};
+		style.size.width = Val::Percent(chip.value);
+	}
+}
+
+#[derive(Component)]
+struct LivesText;
+
+fn spawn_lives_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+	commands
+		.spawn_bundle(TextBundle {
+			style: Style {
+				position_type: PositionType::Absolute,
+				position: Rect {
+					top: Val::Px(10.0),
+					left: Val::Px(10.0),
+					..Default::default()
+				},
+				..Default::default()
+			},
+			text: Text::with_section(
+				"",
+				TextStyle {
+					font: asset_server.load("fonts/PressStart2P-Regular.ttf"),
+					font_size: 24.0,
+					color: Color::rgb(0.9, 0.9, 0.9),
+				},
+				Default::default(),
+			),
+			..Default::default()
+		})
+		.insert(LivesText);
+}
+
+/// The text is spawned with the run, so it also has to be filled in when it was just added
+fn update_lives_text(lives: Res<Lives>, mut q_text: Query<(&mut Text, ChangeTrackers<LivesText>)>) {
+	for (mut text, text_tracker) in q_text.iter_mut() {
+		if lives.is_changed() || text_tracker.is_added() {
+			text.sections[0].value = format!("Lives: {}", lives.0);
+		}
+	}
+}
+
+#[derive(Component)]
+struct DashText;
+
+/// Below the lives
+fn spawn_dash_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+	commands
+		.spawn_bundle(TextBundle {
+			style: Style {
+				position_type: PositionType::Absolute,
+				position: Rect {
+					top: Val::Px(44.0),
+					left: Val::Px(10.0),
+					..Default::default()
+				},
+				..Default::default()
+			},
+			text: Text::with_section(
+				"",
+				TextStyle {
+					font: asset_server.load("fonts/PressStart2P-Regular.ttf"),
+					font_size: 12.0,
+					color: Color::rgb(0.9, 0.9, 0.9),
+				},
+				Default::default(),
+			),
+			..Default::default()
+		})
+		.insert(DashText);
+}
+
+fn update_dash_text(
+	q_dash: Query<&Dash, With<Player>>,
+	mut q_text: Query<&mut Text, With<DashText>>,
+) {
+	let (value, color) = match q_dash.iter().next() {
+		Some(dash) if !dash.cooldown.finished() => {
+			let left = dash.cooldown.duration().as_secs_f32() - dash.cooldown.elapsed_secs();
+			(format!("Dash: {:.1}", left), Color::rgb(0.5, 0.5, 0.5))
+		}
+		Some(_) => ("Dash: ready".to_string(), Color::rgb(0.3, 1.0, 0.3)),
+		None => (String::new(), Color::NONE),
+	};
+	for mut text in q_text.iter_mut() {
+		if text.sections[0].value != value {
+			text.sections[0].value = value.clone();
+			text.sections[0].style.color = color;
+		}
+	}
+}
+
+#[derive(Component)]
+struct AmmoText;
+
+/// Below the dash cooldown
+fn spawn_ammo_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+	commands
+		.spawn_bundle(TextBundle {
+			style: Style {
+				position_type: PositionType::Absolute,
+				position: Rect {
+					top: Val::Px(62.0),
+					left: Val::Px(10.0),
+					..Default::default()
+				},
+				..Default::default()
+			},
+			text: Text::with_section(
+				"",
+				TextStyle {
+					font: asset_server.load("fonts/PressStart2P-Regular.ttf"),
+					font_size: 12.0,
+					color: Color::rgb(0.9, 0.9, 0.9),
+				},
+				Default::default(),
+			),
+			..Default::default()
+		})
+		.insert(AmmoText);
+}
+
+/// Loaded and reserve rounds of the current weapon, only the loaded ones when the reserve is endless
+fn update_ammo_text(
+	q_player: Query<(&Weapon, &Ammo, Option<&ReloadTimer>, Option<&DamageBoost>), With<Player>>,
+	weapons: Res<WeaponParams>,
+	mut q_text: Query<&mut Text, With<AmmoText>>,
+) {
+	let value = match q_player.iter().next() {
+		Some((Weapon(kind), ammo, reload, boost)) => {
+			let spec = weapons.get(*kind);
+			let rounds = match (ammo.get(*kind).filter(|_| spec.magazine > 0), reload) {
+				(Some(_), Some(reload)) if reload.weapon == *kind => " reloading".to_string(),
+				(Some(magazine), _) if spec.max_reserve == 0 => format!(" {}", magazine.loaded),
+				(Some(magazine), _) => format!(" {}/{}", magazine.loaded, magazine.reserve),
+				(None, _) => String::new(),
+			};
+			let boost = boost.map_or(String::new(), |boost| {
+				format!("  x{:.1} damage", boost.multiplier)
+			});
+			format!("{:?}:{}{}", kind, rounds, boost)
+		}
+		None => String::new(),
+	};
+	for mut text in q_text.iter_mut() {
+		if text.sections[0].value != value {
+			text.sections[0].value = value.clone();
+		}
+	}
+}
+
+#[derive(Component)]
+struct Hotbar;
+
+/// Bottom center, one section per inventory slot with its number key
+fn spawn_hotbar(mut commands: Commands, asset_server: Res<AssetServer>) {
+	let style = TextStyle {
+		font: asset_server.load("fonts/PressStart2P-Regular.ttf"),
+		font_size: 12.0,
+		color: Color::rgb(0.6, 0.6, 0.6),
+	};
+	commands
+		.spawn_bundle(NodeBundle {
+			style: Style {
+				size: Size::new(Val::Percent(100.0), Val::Auto),
+				position_type: PositionType::Absolute,
+				position: Rect {
+					bottom: Val::Px(10.0),
+					..Default::default()
+				},
+				justify_content: JustifyContent::Center,
+				..Default::default()
+			},
+			color: Color::NONE.into(),
+			..Default::default()
+		})
+		.with_children(|parent| {
+			parent
+				.spawn_bundle(TextBundle {
+					text: Text {
+						sections: (0..SLOTS)
+							.map(|_| TextSection {
+								value: String::new(),
+								style: style.clone(),
+							})
+							.collect(),
+						alignment: Default::default(),
+					},
+					..Default::default()
+				})
+				.insert(Hotbar);
+		});
+}
+
+/// The active slot is highlighted
+fn update_hotbar(
+	q_inventory: Query<&PlayerInventory, With<Player>>,
+	mut q_text: Query<&mut Text, With<Hotbar>>,
+) {
+	let inventory = match q_inventory.iter().next() {
+		Some(inventory) => inventory,
+		None => return,
+	};
+	for mut text in q_text.iter_mut() {
+		for (i, (section, slot)) in text.sections.iter_mut().zip(inventory.slots()).enumerate() {
+			let value = match slot {
+				ItemSlot::Weapon(kind) => format!(" {} {:?} ", i + 1, kind),
+				ItemSlot::Empty => format!(" {} - ", i + 1),
+			};
+			let color = if i == inventory.active() {
+				Color::rgb(1.0, 0.9, 0.3)
+			} else {
+				Color::rgb(0.6, 0.6, 0.6)
+			};
+			if section.value != value || section.style.color != color {
+				section.value = value;
+				section.style.color = color;
+			}
+		}
+	}
+}
+
+#[derive(Component)]
+struct EnemyCountText;
+
+/// Top right, opposite of the lives
+fn spawn_enemy_count_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+	commands
+		.spawn_bundle(TextBundle {
+			style: Style {
+				position_type: PositionType::Absolute,
+				position: Rect {
+					top: Val::Px(10.0),
+					right: Val::Px(10.0),
+					..Default::default()
+				},
+				..Default::default()
+			},
+			text: Text::with_section(
+				"",
+				TextStyle {
+					font: asset_server.load("fonts/PressStart2P-Regular.ttf"),
+					font_size: 24.0,
+					color: Color::rgb(0.9, 0.9, 0.9),
+				},
+				Default::default(),
+			),
+			..Default::default()
+		})
+		.insert(EnemyCountText);
+}
+
+fn update_enemy_count_text(
+	q_minions: Query<&Minion>,
+	mut q_text: Query<&mut Text, With<EnemyCountText>>,
+) {
+	let value = format!("Minions: {}", q_minions.iter().count());
+	for mut text in q_text.iter_mut() {
+		// only touch the text when it changed, so it isn't laid out again every frame
+		if text.sections[0].value != value {
+			text.sections[0].value = value.clone();
+		}
+	}
+}
+
+fn hide_enemy_count_text(mut q_visibility: Query<&mut Visibility, With<EnemyCountText>>) {
+	for mut visibility in q_visibility.iter_mut() {
+		visibility.is_visible = false;
+	}
+}
+
+fn show_enemy_count_text(mut q_visibility: Query<&mut Visibility, With<EnemyCountText>>) {
+	for mut visibility in q_visibility.iter_mut() {
+		visibility.is_visible = true;
+	}
+}
+
+#[derive(Component)]
+struct ComboText;
+
+/// Below the minion count, only while the multiplier is above 1
+fn spawn_combo_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+	commands
+		.spawn_bundle(TextBundle {
+			style: Style {
+				position_type: PositionType::Absolute,
+				position: Rect {
+					top: Val::Px(44.0),
+					right: Val::Px(10.0),
+					..Default::default()
+				},
+				..Default::default()
+			},
+			text: Text::with_section(
+				"",
+				TextStyle {
+					font: asset_server.load("fonts/PressStart2P-Regular.ttf"),
+					font_size: 12.0,
+					color: Color::rgb(1.0, 0.85, 0.2),
+				},
+				Default::default(),
+			),
+			..Default::default()
+		})
+		.insert(ComboText);
+}
+
+fn update_combo_text(combo: Res<Combo>, mut q_text: Query<&mut Text, With<ComboText>>) {
+	let value = if combo.multiplier > 1.0 {
+		format!("Combo x{:.1}", combo.multiplier)
+	} else {
+		String::new()
+	};
+	for mut text in q_text.iter_mut() {
+		if text.sections[0].value != value {
+			text.sections[0].value = value.clone();
+		}
+	}
+}
+
+#[derive(Component)]
+struct ImmunityText;
+
+/// Top center warning while the boss only takes damage from one source
+fn spawn_immunity_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+	commands
+		.spawn_bundle(NodeBundle {
+			style: Style {
+				size: Size::new(Val::Percent(100.0), Val::Auto),
+				position_type: PositionType::Absolute,
+				position: Rect {
+					top: Val::Px(40.0),
+					..Default::default()
+				},
+				justify_content: JustifyContent::Center,
+				..Default::default()
+			},
+			color: Color::NONE.into(),
+			..Default::default()
+		})
+		.with_children(|parent| {
+			parent
+				.spawn_bundle(TextBundle {
+					text: Text::with_section(
+						"",
+						TextStyle {
+							font: asset_server.load("fonts/PressStart2P-Regular.ttf"),
+							font_size: 16.0,
+							color: Color::rgb(1.0, 0.3, 0.3),
+						},
+						Default::default(),
+					),
+					visibility: Visibility { is_visible: false },
+					..Default::default()
+				})
+				.insert(ImmunityText);
+		});
+}
+
+fn update_immunity_text(
+	q_immunity: Query<&DamageImmunity, With<Boss>>,
+	mut q_text: Query<(&mut Text, &mut Visibility), With<ImmunityText>>,
+) {
+	let immunity = q_immunity.iter().next();
+	for (mut text, mut visibility) in q_text.iter_mut() {
+		if visibility.is_visible != immunity.is_some() {
+			visibility.is_visible = immunity.is_some();
+		}
+		if let Some(immunity) = immunity {
+			let value = format!("BOSS IMMUNE - only {} hurt it", immunity.except.name());
+			if text.sections[0].value != value {
+				text.sections[0].value = value;
+			}
+		}
+	}
+}
+
+/// Hides the text again once the timer finishes
+#[derive(Component)]
+struct WaveText(Timer);
+
+/// Center announcement whenever a new wave of minions starts
+fn spawn_wave_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+	commands
+		.spawn_bundle(NodeBundle {
+			style: Style {
+				size: Size::new(Val::Percent(100.0), Val::Auto),
+				position_type: PositionType::Absolute,
+				position: Rect {
+					top: Val::Px(70.0),
+					..Default::default()
+				},
+				justify_content: JustifyContent::Center,
+				..Default::default()
+			},
+			color: Color::NONE.into(),
+			..Default::default()
+		})
+		.with_children(|parent| {
+			parent
+				.spawn_bundle(TextBundle {
+					text: Text::with_section(
+						"",
+						TextStyle {
+							font: asset_server.load("fonts/PressStart2P-Regular.ttf"),
+							font_size: 20.0,
+							color: Color::rgb(1.0, 0.6, 0.2),
+						},
+						Default::default(),
+					),
+					visibility: Visibility { is_visible: false },
+					..Default::default()
+				})
+				.insert(WaveText(Timer::from_seconds(2.0, false)));
+		});
+}
+
+fn update_wave_text(
+	mut wave_events: EventReader<WaveStartedEvent>,
+	mut q_text: Query<(&mut Text, &mut Visibility, &mut WaveText)>,
+	time: Res<Time>,
+) {
+	let started = wave_events.iter().last();
+	for (mut text, mut visibility, mut wave_text) in q_text.iter_mut() {
+		if let Some(WaveStartedEvent { wave, size }) = started {
+			text.sections[0].value = format!("WAVE {} - {} minions", wave, size);
+			wave_text.0.reset();
+			visibility.is_visible = true;
+		}
+		wave_text.0.tick(time.delta());
+		if wave_text.0.just_finished() {
+			visibility.is_visible = false;
+		}
+	}
+}
+
+/// Shown between levels after killing the boss, the next level starts on its own
+fn spawn_victory_screen(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	game_globals: Res<GameGlobals>,
+) {
+	let text_style = |font_size: f32, color: Color| TextStyle {
+		font: asset_server.load("fonts/PressStart2P-Regular.ttf"),
+		font_size,
+		color,
+	};
+
+	commands
+		.spawn_bundle(NodeBundle {
+			style: Style {
+				size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+				flex_direction: FlexDirection::ColumnReverse,
+				justify_content: JustifyContent::Center,
+				align_items: AlignItems::Center,
+				..Default::default()
+			},
+			color: Color::NONE.into(),
+			..Default::default()
+		})
+		.with_children(|parent| {
+			parent.spawn_bundle(TextBundle {
+				text: Text::with_section(
+					"VICTORY!",
+					text_style(32.0, Color::rgb(1.0, 0.85, 0.2)),
+					Default::default(),
+				),
+				..Default::default()
+			});
+			parent.spawn_bundle(TextBundle {
+				text: Text::with_section(
+					format!(
+						"Boss defeated at level {}\nBonus: {}\nScore: {}\n\nNext up: level {}",
+						game_globals.level,
+						game_globals.victory_bonus * game_globals.level,
+						game_globals.score,
+						game_globals.level + 1,
+					),
+					text_style(16.0, Color::rgb(0.9, 0.9, 0.9)),
+					TextAlignment {
+						horizontal: HorizontalAlign::Center,
+						..Default::default()
+					},
+				),
+				style: Style {
+					margin: Rect::all(Val::Px(20.0)),
+					..Default::default()
+				},
+				..Default::default()
+			});
+		});
+}
+
+#[derive(Component)]
+struct LeaderboardText;
+
+/// Holds the leaderboard text, clickable while loading failed
+#[derive(Component)]
+struct LeaderboardRetryButton;
+
+#[derive(Component)]
+struct RestartText;
+
+#[derive(Component)]
+struct RestartButton;
+
+/// Game over screen with the score, the career stats, the leaderboard and how to restart
 fn spawn_leaderboard(
 	mut commands: Commands,
 	asset_server: Res<AssetServer>,
 	game_globals: Res<GameGlobals>,
-	q_ui_camera: Query<&OrthographicProjection, Without<MainCamera>>,
-	_ev_reader_player: EventReader<LeaderboardEvent>,
+	career_stats: Res<CareerStats>,
+	breakdown: Res<ScoreBreakdown>,
+	highscores: Res<HighscoreStore>,
+) {
+	let earned = breakdown.kills + breakdown.parts + breakdown.phases + breakdown.combo_bonus;
+	let accuracy = breakdown
+		.accuracy()
+		.map_or("-".to_string(), |accuracy| format!("{:.0}%", accuracy));
+
+	let text_style = |font_size: f32| TextStyle {
+		font: asset_server.load("fonts/PressStart2P-Regular.ttf"),
+		font_size,
+		color: Color::rgb(0.9, 0.9, 0.9),
+	};
+
+	commands
+		.spawn_bundle(NodeBundle {
+			style: Style {
+				size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+				flex_direction: FlexDirection::ColumnReverse,
+				justify_content: JustifyContent::Center,
+				align_items: AlignItems::Center,
+				..Default::default()
+			},
+			color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+			..Default::default()
+		})
+		.with_children(|parent| {
+			parent.spawn_bundle(TextBundle {
+				text: Text::with_section("GAME OVER", text_style(40.0), Default::default()),
+				..Default::default()
+			});
+			parent.spawn_bundle(TextBundle {
+				style: Style {
+					margin: Rect::all(Val::Px(20.0)),
+					..Default::default()
+				},
+				text: Text::with_section(
+					format!("Score: {}", game_globals.score),
+					text_style(24.0),
+					Default::default(),
+				),
+				..Default::default()
+			});
+			if highscores.new_best {
+				parent.spawn_bundle(TextBundle {
+					style: Style {
+						margin: Rect {
+							bottom: Val::Px(20.0),
+							..Default::default()
+						},
+						..Default::default()
+					},
+					text: Text::with_section(
+						"NEW BEST!",
+						TextStyle {
+							color: Color::rgb(1.0, 0.85, 0.2),
+							..text_style(20.0)
+						},
+						Default::default(),
+					),
+					..Default::default()
+				});
+			}
+			parent.spawn_bundle(TextBundle {
+				style: Style {
+					margin: Rect {
+						bottom: Val::Px(20.0),
+						..Default::default()
+					},
+					..Default::default()
+				},
+				text: Text::with_section(
+					format!(
+						"Survival & bonuses: {}\nKills: {}  Parts: {}  Phases: {}\nCombo bonus: {} (best x{:.1})\nAccuracy: {} ({}/{})",
+						game_globals.score.saturating_sub(earned),
+						breakdown.kills,
+						breakdown.parts,
+						breakdown.phases,
+						breakdown.combo_bonus,
+						breakdown.best_combo.max(1.0),
+						accuracy,
+						breakdown.shots_hit.min(breakdown.shots_fired),
+						breakdown.shots_fired,
+					),
+					text_style(12.0),
+					TextAlignment {
+						horizontal: HorizontalAlign::Center,
+						..Default::default()
+					},
+				),
+				..Default::default()
+			});
+			parent.spawn_bundle(TextBundle {
+				style: Style {
+					margin: Rect {
+						bottom: Val::Px(20.0),
+						..Default::default()
+					},
+					..Default::default()
+				},
+				text: Text::with_section(
+					format!(
+						"Runs: {}  Kills: {}  Bosses: {}\nBest: {}  Longest: {:.0}s",
+						career_stats.total_runs,
+						career_stats.total_kills,
+						career_stats.bosses_defeated,
+						career_stats.best_score,
+						career_stats.longest_survival,
+					),
+					text_style(12.0),
+					TextAlignment {
+						horizontal: HorizontalAlign::Center,
+						..Default::default()
+					},
+				),
+				..Default::default()
+			});
+			parent.spawn_bundle(TextBundle {
+				style: Style {
+					margin: Rect {
+						bottom: Val::Px(20.0),
+						..Default::default()
+					},
+					..Default::default()
+				},
+				text: Text::with_section(
+					highscores
+						.entries
+						.iter()
+						.enumerate()
+						.map(|(i, entry)| {
+							format!(
+								"{:>2}. {:>7}  L{:<2} {:>5.0}s  {}",
+								i + 1,
+								entry.score,
+								entry.level,
+								entry.duration,
+								entry.date()
+							)
+						})
+						.collect::<Vec<_>>()
+						.join("\n"),
+					text_style(10.0),
+					Default::default(),
+				),
+				..Default::default()
+			});
+			parent
+				.spawn_bundle(ButtonBundle {
+					style: Style {
+						padding: Rect::all(Val::Px(10.0)),
+						..Default::default()
+					},
+					color: Color::NONE.into(),
+					..Default::default()
+				})
+				.insert(LeaderboardRetryButton)
+				.with_children(|parent| {
+					parent
+						.spawn_bundle(TextBundle {
+							text: Text::with_section("", text_style(16.0), Default::default()),
+							..Default::default()
+						})
+						.insert(LeaderboardText);
+				});
+			parent
+				.spawn_bundle(ButtonBundle {
+					style: Style {
+						margin: Rect::all(Val::Px(20.0)),
+						padding: Rect::all(Val::Px(10.0)),
+						..Default::default()
+					},
+					color: Color::GRAY.into(),
+					..Default::default()
+				})
+				.insert(RestartButton)
+				.with_children(|parent| {
+					parent
+						.spawn_bundle(TextBundle {
+							text: Text::with_section("", text_style(16.0), Default::default()),
+							..Default::default()
+						})
+						.insert(RestartText);
+				});
+		});
+}
+
+fn update_leaderboard(
+	game_globals: Res<GameGlobals>,
+	clock: Res<GameClock>,
+	status: Res<LeaderboardStatus>,
+	mut q_leaderboard: Query<&mut Text, (With<LeaderboardText>, Without<RestartText>)>,
+	mut q_restart: Query<&mut Text, (With<RestartText>, Without<LeaderboardText>)>,
+	mut q_retry: Query<&mut UiColor, With<LeaderboardRetryButton>>,
 ) {
+	for mut text in q_leaderboard.iter_mut() {
+		text.sections[0].value = match &status.state {
+			LeaderboardState::Loading => "Loading leaderboard...".to_string(),
+			LeaderboardState::Failed(_) => format!(
+				"Couldn't load leaderboard - retry\n(retrying in {}s)",
+				status.retry_in().ceil()
+			),
+			LeaderboardState::Loaded if game_globals.scores.is_empty() => {
+				"No scores yet".to_string()
+			}
+			LeaderboardState::Loaded => game_globals
+				.scores
+				.iter()
+				.take(10)
+				.enumerate()
+				.map(|(i, score)| format!("{}. {} {}", i + 1, score.guest, score.score))
+				.collect::<Vec<_>>()
+				.join("\n"),
+		};
+	}
+	for mut color in q_retry.iter_mut() {
+		*color = if matches!(status.state, LeaderboardState::Failed(_)) {
+			Color::GRAY.into()
+		} else {
+			Color::NONE.into()
+		};
+	}
+
+	for mut text in q_restart.iter_mut() {
+		text.sections[0].value = if game_globals.auto_restart {
+			let remaining = game_globals
+				.time_until_restart
+				.saturating_sub(clock.game_over_elapsed());
+			format!("Restarting in {}s", remaining.as_secs() + 1)
+		} else {
+			"Press Enter to restart".to_string()
+		};
+	}
+}
+
+fn retry_leaderboard_button(
+	q_button: Query<&Interaction, (Changed<Interaction>, With<LeaderboardRetryButton>)>,
+	status: Res<LeaderboardStatus>,
+	mut retry_ew: EventWriter<LeaderboardEvent>,
+) {
+	let failed = matches!(status.state, LeaderboardState::Failed(_));
+	for interaction in q_button.iter() {
+		if failed && *interaction == Interaction::Clicked {
+			retry_ew.send(LeaderboardEvent);
+		}
+	}
+}
+
+fn restart_button(
+	q_button: Query<&Interaction, (Changed<Interaction>, With<RestartButton>)>,
+	mut state: ResMut<State<GameState>>,
+) {
+	for interaction in q_button.iter() {
+		if *interaction == Interaction::Clicked {
+			let _ = state.overwrite_set(GameState::Playing);
+		}
+	}
+}
+
+/// Rows of the settings screen, in display order
+#[derive(Clone, Copy, PartialEq)]
+enum SettingsRow {
+	Loadout,
+	MasterVolume,
+	MusicVolume,
+	SfxVolume,
+	SpatialAudio,
+	ScreenShake,
+	PauseOnFocusLoss,
+	Rumble,
+	DebugOverlays,
+	Up,
+	Down,
+	Left,
+	Right,
+	Dash,
+	Drop,
+	Pause,
+}
+
+impl SettingsRow {
+	/// The debug overlays only exist with the `debug` feature
+	fn all() -> Vec<SettingsRow> {
+		use SettingsRow::*;
+		[
+			Loadout,
+			MasterVolume,
+			MusicVolume,
+			SfxVolume,
+			SpatialAudio,
+			ScreenShake,
+			PauseOnFocusLoss,
+			Rumble,
+			DebugOverlays,
+			Up,
+			Down,
+			Left,
+			Right,
+			Dash,
+			Drop,
+			Pause,
+		]
+		.into_iter()
+		.filter(|row| cfg!(feature = "debug") || *row != DebugOverlays)
+		.collect()
+	}
+
+	fn is_key(&self) -> bool {
+		matches!(
+			self,
+			SettingsRow::Up
+				| SettingsRow::Down
+				| SettingsRow::Left
+				| SettingsRow::Right
+				| SettingsRow::Dash
+				| SettingsRow::Drop
+				| SettingsRow::Pause
+		)
+	}
+
+	fn key<'a>(&self, settings: &'a mut Settings) -> Option<&'a mut KeyCode> {
+		let keys = &mut settings.key_bindings;
+		match self {
+			SettingsRow::Up => Some(&mut keys.up),
+			SettingsRow::Down => Some(&mut keys.down),
+			SettingsRow::Left => Some(&mut keys.left),
+			SettingsRow::Right => Some(&mut keys.right),
+			SettingsRow::Dash => Some(&mut keys.dash),
+			SettingsRow::Drop => Some(&mut keys.drop),
+			SettingsRow::Pause => Some(&mut keys.pause),
+			_ => None,
+		}
+	}
+
+	fn label(&self, settings: &Settings) -> String {
+		let volume = |value: f32| format!("{:>3}%", (value * 100.0).round() as i32);
+		let toggle = |value: bool| if value { "ON" } else { "OFF" }.to_string();
+		let keys = &settings.key_bindings;
+		let (name, value) = match self {
+			SettingsRow::Loadout => ("Start weapon", format!("{:?}", settings.loadout)),
+			SettingsRow::MasterVolume => ("Master volume", volume(settings.master_volume)),
+			SettingsRow::MusicVolume => ("Music volume", volume(settings.music_volume)),
+			SettingsRow::SfxVolume => ("Effects volume", volume(settings.sfx_volume)),
+			SettingsRow::SpatialAudio => ("Spatial audio", toggle(settings.spatial_audio)),
+			SettingsRow::ScreenShake => ("Screen shake", toggle(settings.screen_shake)),
+			SettingsRow::PauseOnFocusLoss => {
+				("Pause unfocused", toggle(settings.pause_on_focus_loss))
+			}
+			SettingsRow::Rumble => ("Rumble", volume(settings.rumble)),
+			SettingsRow::DebugOverlays => ("Debug overlays", toggle(settings.debug_overlays)),
+			SettingsRow::Up => ("Move up", format!("{:?}", keys.up)),
+			SettingsRow::Down => ("Move down", format!("{:?}", keys.down)),
+			SettingsRow::Left => ("Move left", format!("{:?}", keys.left)),
+			SettingsRow::Right => ("Move right", format!("{:?}", keys.right)),
+			SettingsRow::Dash => ("Dash", format!("{:?}", keys.dash)),
+			SettingsRow::Drop => ("Drop weapon", format!("{:?}", keys.drop)),
+			SettingsRow::Pause => ("Pause", format!("{:?}", keys.pause)),
+		};
+		// the next run starts with the weapon, the one going on keeps its own
+		match self {
+			SettingsRow::Loadout => format!(
+				"{:<16}{:>10}\n  {:<24}",
+				name,
+				value,
+				settings.loadout.description()
+			),
+			_ => format!("{:<16}{:>10}", name, value),
+		}
+	}
+}
+
+/// Selected row of the settings screen. While `rebinding`, the next key pressed is bound to it
+#[derive(Default)]
+struct SettingsMenu {
+	selected: usize,
+	rebinding: bool,
+}
+
+#[derive(Component)]
+struct SettingsScreen;
+
+#[derive(Component)]
+struct SettingsText;
+
+/// The pause screen doubles as the settings menu
+fn spawn_settings_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+	let text_style = |font_size: f32| TextStyle {
+		font: asset_server.load("fonts/PressStart2P-Regular.ttf"),
+		font_size,
+		color: Color::rgb(0.9, 0.9, 0.9),
+	};
+
+	commands
+		.spawn_bundle(NodeBundle {
+			style: Style {
+				size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+				position_type: PositionType::Absolute,
+				flex_direction: FlexDirection::ColumnReverse,
+				justify_content: JustifyContent::Center,
+				align_items: AlignItems::Center,
+				..Default::default()
+			},
+			color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+			..Default::default()
+		})
+		.insert(SettingsScreen)
+		.with_children(|parent| {
+			parent.spawn_bundle(TextBundle {
+				text: Text::with_section("PAUSED", text_style(32.0), Default::default()),
+				..Default::default()
+			});
+			parent
+				.spawn_bundle(TextBundle {
+					text: Text {
+						sections: SettingsRow::all()
+							.iter()
+							.map(|_| TextSection {
+								value: String::new(),
+								style: text_style(14.0),
+							})
+							.collect(),
+						..Default::default()
+					},
+					style: Style {
+						margin: Rect::all(Val::Px(20.0)),
+						..Default::default()
+					},
+					..Default::default()
+				})
+				.insert(SettingsText);
+			parent.spawn_bundle(TextBundle {
+				text: Text::with_section(
+					"Up/Down: select  Left/Right: change  Enter: rebind",
+					text_style(10.0),
+					Default::default(),
+				),
+				..Default::default()
+			});
+		});
+}
+
+/// Runs before the pause toggle, so binding the pause key doesn't also resume the run
+fn navigate_settings(
+	mut keyboard_input: ResMut<Input<KeyCode>>,
+	mut menu: ResMut<SettingsMenu>,
+	mut settings: ResMut<Settings>,
+) {
+	let rows = SettingsRow::all();
+	let row = rows[menu.selected];
+
+	if menu.rebinding {
+		if let Some(key) = keyboard_input.get_just_pressed().next().copied() {
+			keyboard_input.reset(key);
+			menu.rebinding = false;
+			if let Some(binding) = row.key(&mut settings) {
+				*binding = key;
+			}
+		}
+		return;
+	}
+
+	if keyboard_input.just_pressed(KeyCode::Up) {
+		menu.selected = (menu.selected + rows.len() - 1) % rows.len();
+	}
+	if keyboard_input.just_pressed(KeyCode::Down) {
+		menu.selected = (menu.selected + 1) % rows.len();
+	}
+	if keyboard_input.just_pressed(KeyCode::Return) && row.is_key() {
+		menu.rebinding = true;
+	}
+
+	let step = if keyboard_input.just_pressed(KeyCode::Right) {
+		0.1
+	} else if keyboard_input.just_pressed(KeyCode::Left) {
+		-0.1
+	} else {
+		return;
+	};
+	let change_volume =
+		|volume: &mut f32| *volume = ((*volume + step) * 10.0).round().clamp(0.0, 10.0) / 10.0;
+	match row {
+		SettingsRow::Loadout => {
+			let loadouts = WeaponKind::LOADOUTS;
+			let current = loadouts
+				.iter()
+				.position(|kind| *kind == settings.loadout)
+				.unwrap_or(0);
+			let next = if step > 0.0 {
+				current + 1
+			} else {
+				current + loadouts.len() - 1
+			};
+			settings.loadout = loadouts[next % loadouts.len()];
+		}
+		SettingsRow::MasterVolume => change_volume(&mut settings.master_volume),
+		SettingsRow::MusicVolume => change_volume(&mut settings.music_volume),
+		SettingsRow::SfxVolume => change_volume(&mut settings.sfx_volume),
+		SettingsRow::SpatialAudio => settings.spatial_audio = !settings.spatial_audio,
+		SettingsRow::ScreenShake => settings.screen_shake = !settings.screen_shake,
+		SettingsRow::PauseOnFocusLoss => {
+			settings.pause_on_focus_loss = !settings.pause_on_focus_loss
+		}
+		SettingsRow::Rumble => change_volume(&mut settings.rumble),
+		SettingsRow::DebugOverlays => settings.debug_overlays = !settings.debug_overlays,
+		_ => {}
+	}
+}
+
+fn update_settings_text(
+	menu: Res<SettingsMenu>,
+	settings: Res<Settings>,
+	mut q_text: Query<&mut Text, With<SettingsText>>,
+) {
+	for mut text in q_text.iter_mut() {
+		// the sections don't change on their own, so don't lay them out again every frame
+		if !menu.is_changed() && !settings.is_changed() && !text.is_added() {
+			continue;
+		}
+		for (i, (section, row)) in text.sections.iter_mut().zip(SettingsRow::all()).enumerate() {
+			let selected = i == menu.selected;
+			section.value = match (selected, menu.rebinding) {
+				(true, true) => format!("> {:<16}{:>10}\n", "Press a key", "..."),
+				(true, false) => format!("> {}\n", row.label(&settings)),
+				(false, _) => format!("  {}\n", row.label(&settings)),
+			};
+			section.style.color = if selected {
+				Color::rgb(1.0, 0.85, 0.2)
+			} else {
+				Color::rgb(0.9, 0.9, 0.9)
+			};
+		}
+	}
+}
+
+fn despawn_settings_screen(
+	mut commands: Commands,
+	mut menu: ResMut<SettingsMenu>,
+	q_screen: Query<Entity, With<SettingsScreen>>,
+) {
+	for entity in q_screen.iter() {
+		commands.entity(entity).despawn_recursive();
+	}
+	*menu = SettingsMenu::default();
 }
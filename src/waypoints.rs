@@ -1,16 +1,28 @@
 use std::{
+	cmp::Ordering,
+	collections::BinaryHeap,
 	f32::INFINITY,
 	sync::{Arc, Mutex},
+	time::Duration,
 };
 
-use bevy::{math::Vec3Swizzles, prelude::*, utils::HashMap};
+use bevy::{
+	math::Vec3Swizzles,
+	prelude::*,
+	utils::{HashMap, HashSet},
+};
 use bevy_inspector_egui::Inspectable;
 use bevy_prototype_debug_lines::{DebugLines, DebugLinesPlugin};
 use bevy_rapier2d::prelude::*;
+use rand::seq::IteratorRandom;
 
+#[cfg(feature = "debug")]
+use crate::settings::Settings;
 use crate::{
-	game::{GameGlobals, GameState},
+	enemy::raycast_between,
+	game::{GameClock, GameState, LogParams},
 	physics::PhysicsGlobals,
+	scene::{ArenaBounds, Wall},
 };
 
 pub struct WaypointsPlugin;
@@ -19,20 +31,43 @@ impl Plugin for WaypointsPlugin {
 	fn build(&self, app: &mut App) {
 		app.insert_resource(WaypointGlobals {
 			weights_cell: Arc::new(Mutex::new(HashMap::default())),
+			refined: false,
+			arena_half_extents: Vec2::ZERO,
 		})
 		.add_event::<CreatePathEvent>()
 		.insert_resource(WaypointsParams::default())
+		.insert_resource(WaypointGraphDirty::default())
+		.insert_resource(WaypointGrid::default())
+		.insert_resource(PathCache::default())
 		//.register_inspectable::<Waypoint>()
 		//.add_plugin(InspectorPlugin::<WaypointsParams>::new())
-		.add_system_set(SystemSet::on_enter(GameState::Playing).with_system(spawn_waypoints))
-		.add_system_to_stage(CoreStage::PostUpdate, construct_edges)
+		.add_system_set(
+			SystemSet::on_enter(GameState::Playing).with_system(spawn_waypoints.after("arena")),
+		)
+		.add_system_to_stage(
+			CoreStage::PostUpdate,
+			construct_edges.label("construct_edges"),
+		)
+		.add_system_to_stage(CoreStage::PostUpdate, rebake_edges.after("construct_edges"))
+		// waypoints are despawned by commands in `PostUpdate`, their removal is only visible after it
+		.add_system_to_stage(CoreStage::Last, update_waypoint_grid)
 		.add_system_set(
 			SystemSet::on_update(GameState::Playing)
 				.with_system(create_path_event_listener.before("set_next_waypoint"))
-				.with_system(set_next_waypoint.label("set_next_waypoint")),
+				.with_system(set_next_waypoint.label("set_next_waypoint"))
+				.with_system(refine_waypoints)
+				.with_system(remove_waypoints_outside_arena)
+				.with_system(restore_waypoints_inside_arena)
+				.with_system(track_obstacle_changes),
 		);
 		//.add_plugin(DebugLinesPlugin::default())
 		//.add_system_to_stage(CoreStage::Last, debug_render);
+
+		// the debug lines plugin itself is added by the collider debug plugin
+		#[cfg(feature = "debug")]
+		app.insert_resource(PathDebug::default())
+			.add_system(toggle_path_debug)
+			.add_system_to_stage(CoreStage::Last, draw_paths);
 	}
 }
 
@@ -42,6 +77,21 @@ struct WaypointsParams {
 	debug_size: f32,
 	scale: Vec2,
 	offset: Vec2,
+	/// Every grid cell is split into this many steps per axis to find extra waypoints near obstacles.
+	/// 1 or less keeps the plain grid
+	refine_subdivisions: u32,
+	/// Extra waypoints are only added within this distance in pixels of an obstacle
+	refine_radius: f32,
+	/// Minimum distance in pixels of extra waypoints to obstacles, so enemies fit through
+	refine_clearance: f32,
+	/// Refinement stops once this many waypoints exist
+	max_waypoints: usize,
+	/// Edges passing within this distance in pixels of a changed obstacle are raycast again
+	rebake_margin: f32,
+	/// Size in pixels of the cells of the grid used to look up the nearest waypoint
+	grid_cell_size: f32,
+	/// The path cache is cleared once it holds this many paths
+	path_cache_size: usize,
 }
 
 impl Default for WaypointsParams {
@@ -51,12 +101,188 @@ impl Default for WaypointsParams {
 			scale: Vec2::new(1.0, 1.75),
 			offset: Vec2::new(0.0, 50.0),
 			debug_size: 20.0,
+			refine_subdivisions: 2,
+			refine_radius: 40.0,
+			refine_clearance: 15.0,
+			max_waypoints: 200,
+			rebake_margin: 20.0,
+			grid_cell_size: 100.0,
+			path_cache_size: 256,
 		}
 	}
 }
 
 struct WaypointGlobals {
 	weights_cell: Arc<Mutex<HashMap<Entity, f32>>>,
+	/// Whether the extra waypoints near obstacles were added for this run
+	refined: bool,
+	/// Size of the arena the waypoints were last fitted to, to notice when it grows again
+	arena_half_extents: Vec2,
+}
+
+/// Areas whose obstacles were spawned, despawned or moved since the last re-bake, as (min, max) corners.
+/// Only edges crossing them are raycast again
+#[derive(Default)]
+pub struct WaypointGraphDirty {
+	regions: Vec<(Vec2, Vec2)>,
+}
+
+impl WaypointGraphDirty {
+	pub fn mark(&mut self, center: Vec2, half_extents: Vec2) {
+		self.regions
+			.push((center - half_extents, center + half_extents));
+	}
+
+	pub fn is_dirty(&self) -> bool {
+		!self.regions.is_empty()
+	}
+
+	/// Whether the segment from `a` to `b` passes within `margin` of any dirty region
+	fn crosses(&self, a: Vec2, b: Vec2, margin: f32) -> bool {
+		self.regions.iter().any(|(min, max)| {
+			segment_hits_box(a, b, *min - Vec2::splat(margin), *max + Vec2::splat(margin))
+		})
+	}
+}
+
+/// Slab test of the segment from `a` to `b` against the box spanned by `min` and `max`
+fn segment_hits_box(a: Vec2, b: Vec2, min: Vec2, max: Vec2) -> bool {
+	let dir = b - a;
+	let (mut t_min, mut t_max) = (0.0f32, 1.0f32);
+	for axis in 0..2 {
+		if dir[axis].abs() < f32::EPSILON {
+			if a[axis] < min[axis] || a[axis] > max[axis] {
+				return false;
+			}
+			continue;
+		}
+		let t1 = (min[axis] - a[axis]) / dir[axis];
+		let t2 = (max[axis] - a[axis]) / dir[axis];
+		t_min = t_min.max(t1.min(t2));
+		t_max = t_max.min(t1.max(t2));
+		if t_min > t_max {
+			return false;
+		}
+	}
+	true
+}
+
+/// Uniform spatial hash of all waypoints, so the nearest one is found by looking at a few cells
+/// instead of every waypoint
+#[derive(Default)]
+pub struct WaypointGrid {
+	cell_size: f32,
+	cells: HashMap<(i32, i32), Vec<(Entity, Vec2)>>,
+	/// Cell of every waypoint, to remove them again
+	entities: HashMap<Entity, (i32, i32)>,
+}
+
+impl WaypointGrid {
+	fn new(cell_size: f32) -> Self {
+		Self {
+			cell_size: cell_size.max(1.0),
+			..Default::default()
+		}
+	}
+
+	fn cell(&self, pos: Vec2) -> (i32, i32) {
+		let cell = (pos / self.cell_size).floor();
+		(cell.x as i32, cell.y as i32)
+	}
+
+	fn insert(&mut self, entity: Entity, pos: Vec2) {
+		self.remove(entity);
+		let cell = self.cell(pos);
+		self.cells.entry(cell).or_default().push((entity, pos));
+		self.entities.insert(entity, cell);
+	}
+
+	fn remove(&mut self, entity: Entity) {
+		if let Some(cell) = self.entities.remove(&entity) {
+			if let Some(waypoints) = self.cells.get_mut(&cell) {
+				waypoints.retain(|(e, _)| *e != entity);
+			}
+		}
+	}
+
+	/// Nearest waypoint to `pos`. Searches rings of cells around it until no closer waypoint can exist
+	pub fn nearest(&self, pos: Vec2) -> Option<Entity> {
+		self.nearest_where(pos, |_| true)
+	}
+
+	/// Nearest waypoint to `pos` that `accept` returns true for
+	pub fn nearest_where(&self, pos: Vec2, accept: impl Fn(Entity) -> bool) -> Option<Entity> {
+		let (cx, cy) = self.cell(pos);
+		// rings beyond the furthest occupied cell can't contain anything
+		let max_ring = self
+			.cells
+			.iter()
+			.filter(|(_, waypoints)| !waypoints.is_empty())
+			.map(|((x, y), _)| (x - cx).abs().max((y - cy).abs()))
+			.max()?;
+		let mut nearest: Option<(Entity, f32)> = None;
+		for ring in 0..=max_ring {
+			// everything in this ring and beyond is at least this far away
+			let ring_dist = (ring - 1).max(0) as f32 * self.cell_size;
+			if nearest.map_or(false, |(_, dist)| dist <= ring_dist) {
+				break;
+			}
+			for y in cy - ring..=cy + ring {
+				for x in cx - ring..=cx + ring {
+					// only the border of the ring, the inside was searched before
+					if (x - cx).abs() != ring && (y - cy).abs() != ring {
+						continue;
+					}
+					for (entity, wp_pos) in self.cells.get(&(x, y)).into_iter().flatten() {
+						if !accept(*entity) {
+							continue;
+						}
+						let dist = pos.distance(*wp_pos);
+						if nearest.map_or(true, |(_, n_dist)| dist < n_dist) {
+							nearest = Some((*entity, dist));
+						}
+					}
+				}
+			}
+		}
+		nearest.map(|(entity, _)| entity)
+	}
+}
+
+/// Paths between pairs of waypoints, keyed by (start, destination). Cleared whenever the graph changes
+#[derive(Default)]
+struct PathCache {
+	paths: HashMap<(Entity, Entity), Vec<(Waypoint, Entity)>>,
+}
+
+/// Limits how often an enemy asks for a new path while chasing: once `interval` passed, or right away
+/// when the target moved far enough since the last request
+#[derive(Component, Default)]
+pub struct PathRequestThrottle {
+	since_request: Duration,
+	last_target: Option<Vec2>,
+}
+
+impl PathRequestThrottle {
+	/// Whether a path to `target` should be requested now. Resets the throttle if so
+	pub fn ready(
+		&mut self,
+		delta: Duration,
+		target: Vec2,
+		interval: f32,
+		retarget_dist: f32,
+	) -> bool {
+		self.since_request += delta;
+		let moved = self
+			.last_target
+			.map_or(true, |last| last.distance(target) > retarget_dist);
+		if !moved && self.since_request.as_secs_f32() < interval {
+			return false;
+		}
+		self.since_request = Duration::ZERO;
+		self.last_target = Some(target);
+		true
+	}
 }
 
 #[derive(Component, Debug, Clone, Inspectable)]
@@ -72,6 +298,11 @@ struct WaypointEdge(Option<Entity>, f32);
 pub struct WaypointPath(Vec<(Waypoint, Entity)>);
 
 impl Waypoint {
+	/// Unconnected waypoint, edges are added by `construct_edges`
+	pub fn new(pos: Vec2) -> Self {
+		Self(pos, vec![])
+	}
+
 	pub fn find_nearest<'a>(
 		waypoints: impl Iterator<Item = (&'a Waypoint, Entity)>,
 		pos: &Vec2,
@@ -93,26 +324,53 @@ impl Waypoint {
 			None
 		}
 	}
-	pub fn find_nearest_owned<'a>(
-		waypoints: impl Iterator<Item = &'a (Waypoint, Entity)>,
-		pos: &'a bevy::prelude::Vec2,
-	) -> Option<(&Self, Entity)> {
-		let mut nearest: Option<(Entity, &Waypoint, f32)> = None;
-		for (waypoint, entity) in waypoints {
-			let dist = pos.distance(waypoint.0);
-			if let Some((_, _, n_dist)) = nearest {
-				if dist < n_dist {
-					nearest = Some((*entity, waypoint, dist));
-				}
-			} else {
-				nearest = Some((*entity, waypoint, dist));
-			}
-		}
-		if let Some((entity, wp, _)) = nearest {
-			Some((wp, entity))
-		} else {
-			None
-		}
+	/// Picks a random connected waypoint whose distance to `pos` lies between `min_dist` and `max_dist`.
+	/// Connected waypoints are reachable, so they are valid positions to spawn or teleport to
+	pub fn find_in_range<'a>(
+		waypoints: impl Iterator<Item = (&'a Waypoint, Entity)>,
+		pos: &Vec2,
+		min_dist: f32,
+		max_dist: f32,
+	) -> Option<(&'a Self, Entity)> {
+		waypoints
+			.filter(|(waypoint, _)| {
+				let dist = pos.distance(waypoint.0);
+				!waypoint.1.is_empty() && dist >= min_dist && dist <= max_dist
+			})
+			.choose(&mut rand::thread_rng())
+	}
+	/// Connected waypoint furthest away from `pos`
+	pub fn find_furthest<'a>(
+		waypoints: impl Iterator<Item = (&'a Waypoint, Entity)>,
+		pos: &Vec2,
+	) -> Option<(&'a Self, Entity)> {
+		waypoints
+			.filter(|(waypoint, _)| !waypoint.1.is_empty())
+			.max_by(|(a, _), (b, _)| pos.distance(a.0).total_cmp(&pos.distance(b.0)))
+	}
+	/// Nearest connected waypoint to `pos` that can't be seen from `hidden_from`, i.e. something blocks the
+	/// line of sight between them
+	pub fn find_nearest_hidden<'a>(
+		waypoints: impl Iterator<Item = (&'a Waypoint, Entity)>,
+		pos: &Vec2,
+		hidden_from: Vec2,
+		query_pipeline: &Res<QueryPipeline>,
+		physics_globals: &Res<PhysicsGlobals>,
+		collider_set: &QueryPipelineColliderComponentsSet,
+	) -> Option<(&'a Self, Entity)> {
+		Waypoint::find_nearest(
+			waypoints.filter(|(waypoint, _)| {
+				!waypoint.1.is_empty()
+					&& raycast_between(
+						waypoint.0,
+						hidden_from,
+						query_pipeline,
+						physics_globals,
+						collider_set,
+					)
+			}),
+			pos,
+		)
 	}
 }
 
@@ -120,16 +378,99 @@ fn spawn_waypoints(
 	mut commands: Commands,
 	window: Res<WindowDescriptor>,
 	params: Res<WaypointsParams>,
+	mut globals: ResMut<WaypointGlobals>,
+	mut grid: ResMut<WaypointGrid>,
+	mut cache: ResMut<PathCache>,
 ) {
+	globals.refined = false;
+	globals.arena_half_extents = ArenaBounds::default().half_extents;
+	*grid = WaypointGrid::new(params.grid_cell_size);
+	cache.paths.clear();
+	for pos in grid_positions(&window, &params) {
+		commands.spawn().insert(Waypoint::new(pos));
+	}
+}
+
+/// Positions of the regular waypoint grid, without the extra waypoints near obstacles
+fn grid_positions(
+	window: &WindowDescriptor,
+	params: &WaypointsParams,
+) -> impl Iterator<Item = Vec2> {
 	let x_max = (window.width / params.gap.x / 2.0) as i32;
 	let y_max = (window.height / params.gap.y / 2.0) as i32;
+	let (gap, offset, scale) = (params.gap, params.offset, params.scale);
+	(-y_max..y_max).flat_map(move |y_i| {
+		(-x_max..x_max)
+			.map(move |x_i| (Vec2::new(x_i as f32 * gap.x, y_i as f32 * gap.y) + offset) * scale)
+	})
+}
+
+/// Adds waypoints between the grid points close to obstacles, so paths can thread gaps the coarse grid misses.
+/// Runs once per run after the same warm-up as `construct_edges`, when all colliders are in the query pipeline
+fn refine_waypoints(
+	mut commands: Commands,
+	q_waypoints: Query<&Waypoint>,
+	query_pipeline: Res<QueryPipeline>,
+	collider_query: QueryPipelineColliderComponentsQuery,
+	rapier_params: Res<RapierConfiguration>,
+	physics_globals: Res<PhysicsGlobals>,
+	params: Res<WaypointsParams>,
+	mut globals: ResMut<WaypointGlobals>,
+	window: Res<WindowDescriptor>,
+	bounds: Res<ArenaBounds>,
+	clock: Res<GameClock>,
+) {
+	if globals.refined || !clock.warmed_up() {
+		return;
+	}
+	globals.refined = true;
+	if params.refine_subdivisions <= 1 {
+		return;
+	}
+
+	let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+	let obstacles = InteractionGroups::new(
+		u32::MAX,
+		u32::MAX
+			- physics_globals.player_mask
+			- physics_globals.enemy_mask
+			- physics_globals.bullet_mask,
+	);
+	let near = ColliderShape::ball(params.refine_radius / rapier_params.scale);
+	let clearance = ColliderShape::ball(params.refine_clearance / rapier_params.scale);
+	let hits = |pos: Vec2, shape: &ColliderShape| {
+		let iso = Isometry::translation(pos.x / rapier_params.scale, pos.y / rapier_params.scale);
+		query_pipeline
+			.intersection_with_shape(&collider_set, &iso, &**shape, obstacles, None)
+			.is_some()
+	};
+
+	let mut count = q_waypoints.iter().count();
+	let steps = params.refine_subdivisions as i32;
+	// same grid as in `spawn_waypoints`, sampled `steps` times finer
+	let x_max = (window.width / params.gap.x / 2.0) as i32 * steps;
+	let y_max = (window.height / params.gap.y / 2.0) as i32 * steps;
 	for y_i in -y_max..y_max {
 		for x_i in -x_max..x_max {
-			let pos =
-				Vec2::new(x_i as f32 * params.gap.x, y_i as f32 * params.gap.y) + params.offset;
-			commands
-				.spawn()
-				.insert(Waypoint(pos * params.scale, vec![]));
+			if count >= params.max_waypoints {
+				debug!("Waypoint refinement stopped at {} waypoints", count);
+				return;
+			}
+			// grid points already have a waypoint
+			if x_i % steps == 0 && y_i % steps == 0 {
+				continue;
+			}
+
+			let pos = (Vec2::new(x_i as f32 * params.gap.x, y_i as f32 * params.gap.y)
+				/ steps as f32
+				+ params.offset)
+				* params.scale;
+			// waypoints that survived a shrinking arena are still there when refining again
+			let exists = q_waypoints.iter().any(|wp| wp.0.distance(pos) < 1.0);
+			if bounds.contains(pos) && !exists && hits(pos, &near) && !hits(pos, &clearance) {
+				commands.spawn().insert(Waypoint(pos, vec![]));
+				count += 1;
+			}
 		}
 	}
 }
@@ -141,15 +482,14 @@ fn construct_edges(
 	collider_query: QueryPipelineColliderComponentsQuery,
 	rapier_params: Res<RapierConfiguration>,
 	state: Res<State<GameState>>,
-	game_globals: Res<GameGlobals>,
+	clock: Res<GameClock>,
 	physics_globals: Res<PhysicsGlobals>,
-	time: Res<Time>,
 ) {
 	if *state.current() != GameState::Playing {
 		return;
 	}
 
-	if (time.time_since_startup() - game_globals.time_started).as_secs() < 3 {
+	if !clock.warmed_up() {
 		return;
 	}
 
@@ -159,39 +499,281 @@ fn construct_edges(
 		}
 	}
 
+	// waypoints added later, e.g. by the refinement, are connected without duplicating existing edges
+	let unconnected: HashSet<Entity> = query
+		.iter()
+		.filter(|(_, wp)| wp.1.is_empty())
+		.map(|(entity, _)| entity)
+		.collect();
+
 	let mut iter = query.iter_combinations_mut();
 	let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
 
 	while let Some([(e1, mut wp1), (e2, mut wp2)]) = iter.fetch_next() {
-		let pos = wp1.0 / rapier_params.scale;
-		let dir = (wp2.0 - wp1.0) / rapier_params.scale;
-		let ray = Ray::new(pos.into(), dir.into());
-
-		if let None = query_pipeline.cast_ray(
+		if !unconnected.contains(&e1) && !unconnected.contains(&e2) {
+			continue;
+		}
+		if edge_clear(
+			wp1.0,
+			wp2.0,
+			&query_pipeline,
 			&collider_set,
+			&rapier_params,
+			&physics_globals,
+		) {
+			let dist = wp2.0.distance(wp1.0);
+			wp1.1.push(WaypointEdge(Some(e2), dist));
+			wp2.1.push(WaypointEdge(Some(e1), dist));
+		}
+	}
+
+	// check and remove orphaned nodes
+	let orphans = query.iter().filter(|(_, wp)| wp.1.len() == 0);
+
+	for (entity, wp) in orphans {
+		debug!("Orphaned node removed at {}", wp.0);
+		commands.entity(entity).despawn();
+	}
+}
+
+/// Whether nothing blocks the straight line between two waypoints
+fn edge_clear(
+	from: Vec2,
+	to: Vec2,
+	query_pipeline: &QueryPipeline,
+	collider_set: &QueryPipelineColliderComponentsSet,
+	rapier_params: &RapierConfiguration,
+	physics_globals: &PhysicsGlobals,
+) -> bool {
+	let pos = from / rapier_params.scale;
+	let dir = (to - from) / rapier_params.scale;
+	let ray = Ray::new(pos.into(), dir.into());
+
+	query_pipeline
+		.cast_ray(
+			collider_set,
 			&ray,
 			1.0,
 			true,
 			InteractionGroups::new(u32::MAX, u32::MAX - physics_globals.enemy_mask),
 			None,
-		) {
+		)
+		.is_none()
+}
+
+/// Marks the graph dirty where walls are spawned, despawned or moved, at their old and new position
+fn track_obstacle_changes(
+	q_walls: Query<(Entity, &Transform, &ColliderShapeComponent), (With<Wall>, Changed<Transform>)>,
+	removed: RemovedComponents<Wall>,
+	rapier_params: Res<RapierConfiguration>,
+	mut dirty: ResMut<WaypointGraphDirty>,
+	mut known: Local<HashMap<Entity, (Vec2, Vec2)>>,
+) {
+	for (entity, transform, shape) in q_walls.iter() {
+		let half = shape.compute_local_aabb().half_extents();
+		let bounds = (
+			transform.translation.xy(),
+			Vec2::new(half.x, half.y) * rapier_params.scale,
+		);
+		let old = known.insert(entity, bounds);
+		if old == Some(bounds) {
+			continue;
+		}
+		if let Some((center, half_extents)) = old {
+			dirty.mark(center, half_extents);
+		}
+		dirty.mark(bounds.0, bounds.1);
+	}
+
+	for entity in removed.iter() {
+		if let Some((center, half_extents)) = known.remove(&entity) {
+			dirty.mark(center, half_extents);
+		}
+	}
+}
+
+/// Raycasts the edges crossing dirty regions again once the graph is built, adding edges that were
+/// unblocked and removing the ones that are blocked now
+fn rebake_edges(
+	mut query: Query<(Entity, &mut Waypoint)>,
+	query_pipeline: Res<QueryPipeline>,
+	collider_query: QueryPipelineColliderComponentsQuery,
+	rapier_params: Res<RapierConfiguration>,
+	state: Res<State<GameState>>,
+	clock: Res<GameClock>,
+	physics_globals: Res<PhysicsGlobals>,
+	params: Res<WaypointsParams>,
+	mut dirty: ResMut<WaypointGraphDirty>,
+) {
+	if !dirty.is_dirty() {
+		return;
+	}
+	// obstacles spawned with the arena are covered by the initial `construct_edges`
+	if *state.current() != GameState::Playing || !clock.warmed_up() {
+		dirty.regions.clear();
+		return;
+	}
+
+	let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+	let (mut added, mut removed) = (0, 0);
+	let mut iter = query.iter_combinations_mut();
+	while let Some([(e1, mut wp1), (e2, mut wp2)]) = iter.fetch_next() {
+		// unconnected waypoints get all their edges from `construct_edges`
+		if wp1.1.is_empty()
+			|| wp2.1.is_empty()
+			|| !dirty.crosses(wp1.0, wp2.0, params.rebake_margin)
+		{
+			continue;
+		}
+		let connected = wp1.1.iter().any(|WaypointEdge(e, _)| *e == Some(e2));
+		let clear = edge_clear(
+			wp1.0,
+			wp2.0,
+			&query_pipeline,
+			&collider_set,
+			&rapier_params,
+			&physics_globals,
+		);
+		if clear && !connected {
 			let dist = wp2.0.distance(wp1.0);
 			wp1.1.push(WaypointEdge(Some(e2), dist));
 			wp2.1.push(WaypointEdge(Some(e1), dist));
+			added += 1;
+		} else if !clear && connected {
+			wp1.1.retain(|WaypointEdge(e, _)| *e != Some(e2));
+			wp2.1.retain(|WaypointEdge(e, _)| *e != Some(e1));
+			removed += 1;
 		}
 	}
+	// waypoints left without edges are retried and removed as orphans by `construct_edges`
+	debug!(
+		"Waypoint graph re-baked in {} regions, {} edges added, {} removed",
+		dirty.regions.len(),
+		added,
+		removed
+	);
+	dirty.regions.clear();
+}
 
-	// check and remove orphaned nodes
-	let orphans = query.iter().filter(|(_, wp)| wp.1.len() == 0);
+/// Keeps the waypoint grid in sync with spawned and despawned waypoints
+fn update_waypoint_grid(
+	q_added: Query<(Entity, &Waypoint), Added<Waypoint>>,
+	removed: RemovedComponents<Waypoint>,
+	mut grid: ResMut<WaypointGrid>,
+) {
+	for entity in removed.iter() {
+		grid.remove(entity);
+	}
+	for (entity, waypoint) in q_added.iter() {
+		grid.insert(entity, waypoint.0);
+	}
+}
 
-	for (entity, wp) in orphans {
-		info!("Orphaned node removed at {}", wp.0);
+/// Orphans waypoints that ended up outside the arena after it shrunk, by removing every edge leading to them
+/// and despawning them, so paths are only built inside the arena
+fn remove_waypoints_outside_arena(
+	mut commands: Commands,
+	bounds: Res<ArenaBounds>,
+	mut q_waypoints: Query<(Entity, &mut Waypoint)>,
+) {
+	if !bounds.is_changed() {
+		return;
+	}
+
+	let outside: Vec<Entity> = q_waypoints
+		.iter()
+		.filter(|(_, wp)| !bounds.contains(wp.0))
+		.map(|(entity, _)| entity)
+		.collect();
+
+	if outside.is_empty() {
+		return;
+	}
+
+	for (entity, mut wp) in q_waypoints.iter_mut() {
+		if outside.contains(&entity) {
+			continue;
+		}
+		let had_edges = !wp.1.is_empty();
+		wp.1.retain(|WaypointEdge(e, _)| !e.map_or(false, |e| outside.contains(&e)));
+
+		// waypoints that were only reachable from outside are orphaned now
+		if had_edges && wp.1.is_empty() {
+			debug!("Orphaned node removed at {}", wp.0);
+			commands.entity(entity).despawn();
+		}
+	}
+
+	for entity in outside {
+		debug!("Waypoint outside arena removed");
 		commands.entity(entity).despawn();
 	}
 }
 
+/// Brings back the waypoints `remove_waypoints_outside_arena` despawned once the arena grows again, e.g.
+/// when a chaos event shrunk it for a while. Grid waypoints are spawned again and connected by
+/// `construct_edges`, the extra waypoints near obstacles are refined again. The edges blocked by the
+/// moving walls are re-baked, since the walls mark the graph dirty
+fn restore_waypoints_inside_arena(
+	mut commands: Commands,
+	bounds: Res<ArenaBounds>,
+	q_waypoints: Query<&Waypoint>,
+	window: Res<WindowDescriptor>,
+	params: Res<WaypointsParams>,
+	mut globals: ResMut<WaypointGlobals>,
+) {
+	if !bounds.is_changed() {
+		return;
+	}
+	let last = globals.arena_half_extents;
+	globals.arena_half_extents = bounds.half_extents;
+	if bounds.half_extents.x <= last.x && bounds.half_extents.y <= last.y {
+		return;
+	}
+
+	let mut restored = 0;
+	for pos in grid_positions(&window, &params) {
+		if bounds.contains(pos) && !q_waypoints.iter().any(|wp| wp.0.distance(pos) < 1.0) {
+			commands.spawn().insert(Waypoint::new(pos));
+			restored += 1;
+		}
+	}
+	debug!("{} waypoints restored inside the arena", restored);
+	globals.refined = false;
+}
+
 pub struct CreatePathEvent(pub Vec2, pub Vec2, pub Entity);
 
+/// Waypoint on the open list of the path search, ordered so the `BinaryHeap` pops the lowest estimate first
+struct OpenWaypoint {
+	/// Distance from the start plus the straight line distance to the destination
+	estimate: f32,
+	entity: Entity,
+}
+
+impl PartialEq for OpenWaypoint {
+	fn eq(&self, other: &Self) -> bool {
+		self.estimate == other.estimate
+	}
+}
+
+impl Eq for OpenWaypoint {}
+
+impl PartialOrd for OpenWaypoint {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for OpenWaypoint {
+	fn cmp(&self, other: &Self) -> Ordering {
+		other
+			.estimate
+			.partial_cmp(&self.estimate)
+			.unwrap_or(Ordering::Equal)
+	}
+}
+
 /// This system is responsible for generating paths between waypoints. It reacts to CreatePathEvent events
 /// fired by entities (mainly enemies) by attaching a WaypointPath component
 /// to the Entity (ideally an enemy) that send the event.
@@ -200,16 +782,34 @@ fn create_path_event_listener(
 	mut event_reader: EventReader<CreatePathEvent>,
 	q_waypoints: Query<(&Waypoint, Entity)>,
 	mut globals: ResMut<WaypointGlobals>,
+	grid: Res<WaypointGrid>,
+	(mut cache, q_changed, params): (
+		ResMut<PathCache>,
+		Query<(), Changed<Waypoint>>,
+		Res<WaypointsParams>,
+	),
+	log_params: Res<LogParams>,
 ) {
+	// any new, moved or reconnected waypoint can make cached paths outdated
+	if !q_changed.is_empty() || cache.paths.len() >= params.path_cache_size {
+		cache.paths.clear();
+	}
+
+	let nearest = |pos: &Vec2| {
+		grid.nearest(*pos)
+			.and_then(|entity| q_waypoints.get(entity).ok())
+	};
 	for CreatePathEvent(src, dst, sender_entity) in event_reader.iter() {
-		let wp_src = Waypoint::find_nearest(q_waypoints.iter(), src);
-		let wp_dst = Waypoint::find_nearest(q_waypoints.iter(), dst);
+		let wp_src = nearest(src);
+		let wp_dst = nearest(dst);
 
 		if wp_src.is_none() || wp_dst.is_none() {
-			info!(
-				"Failed to create path between {:?} and {:?}",
-				wp_src, wp_dst
-			);
+			if log_params.verbose {
+				info!(
+					"Failed to create path between {:?} and {:?}",
+					wp_src, wp_dst
+				);
+			}
 			return;
 		}
 
@@ -217,98 +817,78 @@ fn create_path_event_listener(
 			return;
 		}
 
-		let mut visited = vec![];
-		let total_wp = q_waypoints.iter().len();
-
-		// set starting node to 0 and other nodes to infinity
-		// loop over nodes
-		// if node not visited: 2 options:
-		// 1. totat dist < weight of node: update nodes weight to total dist
-		// 2. leave weight
-		// move to another unvisited node until all are visited
+		let (src_wp, src_entity) = wp_src.unwrap();
+		let (dst_wp, dst_entity) = wp_dst.unwrap();
 
-		globals.weights_cell = Arc::new(Mutex::new(HashMap::default()));
-
-		let (mut our_wp, mut our_entity) = wp_src.unwrap();
-		// mark starting node as visited and set weight to 0
-		visited.push(our_entity);
-		{
-			let mut weights = globals.weights_cell.lock().unwrap();
-			weights.insert(our_entity, 0.0);
+		if let Some(path) = cache.paths.get(&(src_entity, dst_entity)) {
+			commands
+				.entity(*sender_entity)
+				.insert(WaypointPath(path.clone()));
+			continue;
 		}
 
-		// search until all nodes visited
-		while visited.len() < total_wp {
-			let our_edges = &our_wp.1[..];
+		// A* with the straight line distance as heuristic, edges are never shorter than that.
+		// The search stops as soon as the destination is settled
+		let mut weights: HashMap<Entity, f32> = HashMap::default();
+		let mut came_from: HashMap<Entity, Entity> = HashMap::default();
+		let mut settled: HashSet<Entity> = HashSet::default();
+		let mut open = BinaryHeap::new();
+		weights.insert(src_entity, 0.0);
+		open.push(OpenWaypoint {
+			estimate: src_wp.0.distance(dst_wp.0),
+			entity: src_entity,
+		});
 
-			// loop over all unvisited nodes connectde to our starting node
-			for WaypointEdge(entity, dist) in our_edges {
-				let entity = entity.unwrap();
-				if visited.contains(&entity) {
-					continue;
-				} else {
-					let mut weights = globals.weights_cell.lock().unwrap();
-					let our_weight = weights.get(&our_entity).unwrap();
-					let total_dist = dist + *our_weight;
-					let weight = weights.entry(entity).or_insert(INFINITY);
-
-					// set to total distance when it's smaller than the node's weight
-					if total_dist < *weight {
-						*weight = total_dist;
+		while let Some(OpenWaypoint { entity, .. }) = open.pop() {
+			if !settled.insert(entity) {
+				continue;
+			}
+			if entity == dst_entity {
+				break;
+			}
+			let (Waypoint(_, edges), _) = match q_waypoints.get(entity) {
+				Ok(waypoint) => waypoint,
+				Err(_) => continue,
+			};
+			let weight = weights[&entity];
+			for WaypointEdge(next, dist) in edges.iter() {
+				let next = match next {
+					Some(next) if !settled.contains(next) => *next,
+					_ => continue,
+				};
+				let total_dist = weight + dist;
+				if total_dist < *weights.get(&next).unwrap_or(&INFINITY) {
+					weights.insert(next, total_dist);
+					came_from.insert(next, entity);
+					if let Ok((Waypoint(next_pos, _), _)) = q_waypoints.get(next) {
+						open.push(OpenWaypoint {
+							estimate: total_dist + next_pos.distance(dst_wp.0),
+							entity: next,
+						});
 					}
 				}
 			}
-
-			// mark our node as visited before moving on
-			visited.push(our_entity);
-
-			// move on to next node with smallest weight that isn't visted
-			let weights = globals.weights_cell.lock().unwrap();
-			if let Some((next_wp, next_entity)) = q_waypoints
-				.iter()
-				.filter(|(_, e)| !(&visited[..]).contains(e))
-				.min_by(|(_, e1), (_, e2)| {
-					weights
-						.get(e1)
-						.unwrap_or(&INFINITY)
-						.partial_cmp(weights.get(e2).unwrap_or(&INFINITY))
-						.unwrap()
-				}) {
-				our_wp = next_wp;
-				our_entity = next_entity;
-			}
 		}
 
 		// start from end waypoint and make our way down
-
-		let (mut our_wp, mut our_entity) = wp_dst.unwrap();
-		let (_, src_entity) = wp_src.unwrap();
-
-		let mut path = vec![(our_wp.clone(), our_entity)];
-
-		while our_entity != src_entity {
-			let n_wp = our_wp
-				.1
-				.iter()
-				.min_by(|WaypointEdge(e1, _), WaypointEdge(e2, _)| {
-					let weights = globals.weights_cell.lock().unwrap();
-					weights
-						.get(&e1.unwrap())
-						.partial_cmp(&weights.get(&e2.unwrap()))
-						.unwrap()
-				})
-				.unwrap();
-
-			if let WaypointEdge(Some(n_entity), _) = n_wp {
-				if let Ok((n_wp, _)) = q_waypoints.get(*n_entity) {
-					path.push((n_wp.clone().into(), *n_entity));
-					our_entity = *n_entity;
-					our_wp = n_wp;
-				}
+		if !settled.contains(&dst_entity) {
+			if log_params.verbose {
+				info!("No path between {:?} and {:?}", src, dst);
+			}
+			continue;
+		}
+		let mut path = vec![(dst_wp.clone(), dst_entity)];
+		let mut our_entity = dst_entity;
+		while let Some(prev_entity) = came_from.get(&our_entity) {
+			if let Ok((prev_wp, _)) = q_waypoints.get(*prev_entity) {
+				path.push((prev_wp.clone(), *prev_entity));
 			}
+			our_entity = *prev_entity;
 		}
+		*globals.weights_cell.lock().unwrap() = weights;
 
 		if path.len() > 0 {
+			cache.paths.insert((src_entity, dst_entity), path.clone());
 			commands.entity(*sender_entity).insert(WaypointPath(path));
 		}
 	}
@@ -318,22 +898,29 @@ fn set_next_waypoint(
 	mut commands: Commands,
 	q_path: Query<(Entity, &Transform, &WaypointPath)>,
 	mut q_next_wp: Query<&mut NextWaypoint>,
-	_time: Res<Time>,
+	grid: Res<WaypointGrid>,
 ) {
 	for (entity, transform, path) in q_path.iter() {
 		let pos = transform.translation.xy();
-		let (nearest_wp, nearest_id) = Waypoint::find_nearest_owned(path.0.iter(), &pos).unwrap();
+		let on_path: HashSet<Entity> = path.0.iter().map(|(_, path_id)| *path_id).collect();
+		let nearest_id = match grid.nearest_where(pos, |wp| on_path.contains(&wp)) {
+			Some(nearest_id) => nearest_id,
+			None => continue,
+		};
 
 		// if we arrive at end, stop
-		let (last_wp, _) = path.0.iter().next().unwrap();
-		if nearest_wp.0 == last_wp.0 {
+		if path
+			.0
+			.first()
+			.map_or(true, |(_, last_id)| *last_id == nearest_id)
+		{
 			continue;
 		}
 
 		for (i, &(_, path_id)) in path.0.iter().enumerate() {
 			if path_id == nearest_id {
 				// find the waypoint one index ahead from the nearest
-				let (next_wp, _) = path.0.iter().take(i).last().unwrap();
+				let (next_wp, _) = &path.0[i - 1];
 
 				// set the found index
 				if let Ok(mut wp) = q_next_wp.get_mut(entity) {
@@ -410,3 +997,71 @@ fn debug_render(
 		}
 	}
 }
+
+#[cfg(feature = "debug")]
+#[derive(Default)]
+struct PathDebug {
+	enabled: bool,
+}
+
+#[cfg(feature = "debug")]
+fn toggle_path_debug(
+	keyboard_input: Res<Input<KeyCode>>,
+	settings: Res<Settings>,
+	mut debug: ResMut<PathDebug>,
+) {
+	if settings.is_changed() {
+		debug.enabled = settings.debug_overlays;
+	}
+	if keyboard_input.just_pressed(KeyCode::F4) {
+		debug.enabled = !debug.enabled;
+	}
+}
+
+/// Draws the path of every enemy in its own color, toggled with F4. The line to the next waypoint is white
+#[cfg(feature = "debug")]
+fn draw_paths(
+	debug: Res<PathDebug>,
+	q_paths: Query<(Entity, &Transform, &WaypointPath, Option<&NextWaypoint>)>,
+	mut lines: ResMut<DebugLines>,
+) {
+	if !debug.enabled {
+		return;
+	}
+
+	for (entity, transform, WaypointPath(path), next_wp) in q_paths.iter() {
+		// spread the hues so neighbouring entity ids get clearly different colors
+		let color = Color::hsl((entity.id() * 67 % 360) as f32, 0.8, 0.6);
+		for pair in path.windows(2) {
+			let (from, to) = (pair[0].0 .0, pair[1].0 .0);
+			lines.line_colored(from.extend(0.0), to.extend(0.0), 0.0, color);
+		}
+		if let Some(NextWaypoint(Waypoint(next_pos, _))) = next_wp {
+			lines.line_colored(
+				transform.translation.xy().extend(0.0),
+				next_pos.extend(0.0),
+				0.0,
+				Color::WHITE,
+			);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn nearest_only_considers_accepted_waypoints() {
+		let mut world = World::new();
+		let near = world.spawn().id();
+		let far = world.spawn().id();
+		let mut grid = WaypointGrid::new(50.0);
+		grid.insert(near, Vec2::new(10.0, 0.0));
+		grid.insert(far, Vec2::new(300.0, 120.0));
+
+		assert_eq!(grid.nearest(Vec2::ZERO), Some(near));
+		assert_eq!(grid.nearest_where(Vec2::ZERO, |wp| wp != near), Some(far));
+		assert_eq!(grid.nearest_where(Vec2::ZERO, |_| false), None);
+	}
+}